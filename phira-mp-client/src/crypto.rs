@@ -0,0 +1,84 @@
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use phira_mp_common::RoomId;
+use sha2::Sha256;
+
+const PBKDF2_ROUNDS: u32 = 100_000;
+const NONCE_LEN: usize = 24;
+
+/// Key for [`Client::encrypted_chat`](crate::Client::encrypted_chat),
+/// derived from a password the room's members agreed on.
+///
+/// The room id salts the derivation, so the same password gives every room a
+/// different key. The sender's id is authenticated along with each message,
+/// so a member can't pass another's message off as their own.
+///
+/// Don't derive it from the room's join password. The server sees that one in
+/// plaintext, so the messages would be readable by whoever runs it.
+pub struct ChatKey {
+    room: RoomId,
+    cipher: XChaCha20Poly1305,
+}
+
+impl ChatKey {
+    /// Takes a noticeable moment on purpose, don't call it on a UI thread.
+    pub fn derive(password: &str, room: RoomId) -> Self {
+        let salt = format!("phira-mp chat {room}");
+        let mut key = [0; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(
+            password.as_bytes(),
+            salt.as_bytes(),
+            PBKDF2_ROUNDS,
+            &mut key,
+        );
+        Self {
+            room,
+            cipher: XChaCha20Poly1305::new(&key.into()),
+        }
+    }
+
+    pub fn room(&self) -> &RoomId {
+        &self.room
+    }
+
+    /// Returns the nonce followed by the ciphertext of `message`, sent by
+    /// the user `sender`.
+    pub fn encrypt(&self, sender: i32, message: &str) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut payload = nonce.to_vec();
+        payload.extend(
+            self.cipher
+                .encrypt(
+                    &nonce,
+                    Payload {
+                        msg: message.as_bytes(),
+                        aad: &sender.to_le_bytes(),
+                    },
+                )
+                .expect("message too long"),
+        );
+        payload
+    }
+
+    /// `None` if the payload was encrypted with another key, by someone else
+    /// than `sender`, or tampered with.
+    pub fn decrypt(&self, sender: i32, payload: &[u8]) -> Option<String> {
+        if payload.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        let plain = self
+            .cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &sender.to_le_bytes(),
+                },
+            )
+            .ok()?;
+        String::from_utf8(plain).ok()
+    }
+}
@@ -0,0 +1,29 @@
+use phira_mp_common::{GameEndReason, JudgeBatch, Message, RoomState, TouchFrame};
+use std::sync::Arc;
+
+/// What happened on the [`Client`](crate::Client), for frontends that would
+/// rather react than poll. See [`Client::subscribe`](crate::Client::subscribe).
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    RoomStateChanged(RoomState),
+    /// Whether we're the host now
+    HostChanged(bool),
+    /// Also kept for [`Client::blocking_take_messages`](crate::Client::blocking_take_messages)
+    Message(Message),
+    /// Of the current round, also kept for
+    /// [`Client::touches_for`](crate::Client::touches_for)
+    Touches {
+        player: i32,
+        frames: Arc<Vec<TouchFrame>>,
+    },
+    /// Of the current round, also kept for
+    /// [`Client::judges_for`](crate::Client::judges_for)
+    Judges {
+        player: i32,
+        judges: Arc<JudgeBatch>,
+    },
+    GameEnd {
+        round: u32,
+        reason: GameEndReason,
+    },
+}
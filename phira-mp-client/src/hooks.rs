@@ -0,0 +1,37 @@
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+use tracing::error;
+
+type Hook<T> = Mutex<Option<Arc<dyn Fn(T) + Send + Sync>>>;
+
+/// Callbacks registered on the [`Client`](crate::Client), for engines that
+/// would rather be called than poll.
+#[derive(Default)]
+pub(crate) struct Hooks {
+    pub disconnected: Hook<()>,
+    pub reconnected: Hook<()>,
+    pub kicked: Hook<()>,
+    pub host_changed: Hook<bool>,
+    pub start_countdown: Hook<u16>,
+    pub game_start: Hook<(u32, SystemTime)>,
+}
+
+impl Hooks {
+    /// Runs `hook` if one is set. A panicking hook is logged instead of
+    /// taking down the task that called it.
+    pub fn call<T>(name: &str, hook: &Hook<T>, arg: T) {
+        let Some(hook) = hook.lock().unwrap().clone() else {
+            return;
+        };
+        if catch_unwind(AssertUnwindSafe(|| hook(arg))).is_err() {
+            error!("{name} hook panicked");
+        }
+    }
+
+    pub fn set<T>(hook: &Hook<T>, f: impl Fn(T) + Send + Sync + 'static) {
+        *hook.lock().unwrap() = Some(Arc::new(f));
+    }
+}
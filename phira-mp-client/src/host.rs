@@ -0,0 +1,52 @@
+use anyhow::Result;
+use phira_mp_server::{Server, ServerConfig};
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::{net::TcpListener, task::JoinHandle};
+use tracing::{info, warn};
+
+/// A single-room server running in-process, so that players on the same
+/// network (e.g. a phone hotspot) can play without a dedicated server. The
+/// host connects to [`LocalHost::addr`] like everyone else.
+///
+/// Players still authenticate against the Phira API.
+pub struct LocalHost {
+    addr: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+impl LocalHost {
+    /// Listens on `port` (0 for any) and advertises the server on the local
+    /// network as `name`, which clients built with the `lan` feature find
+    /// with `Client::discover_lan`.
+    pub async fn start(name: impl Into<String>, port: u16) -> Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).await?;
+        let addr = listener.local_addr()?;
+        let server = Server::new(
+            ServerConfig {
+                lan_name: Some(name.into()),
+                max_rooms: Some(1),
+                ..ServerConfig::default()
+            },
+            listener,
+        )?;
+        info!("local host started on {addr}");
+        let handle = tokio::spawn(async move {
+            loop {
+                if let Err(err) = server.accept().await {
+                    warn!("failed to accept: {err:?}");
+                }
+            }
+        });
+        Ok(Self { addr, handle })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for LocalHost {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
@@ -1,32 +1,88 @@
-use anyhow::{Context, Error, Result};
+#[cfg(feature = "encrypted-chat")]
+mod crypto;
+#[cfg(feature = "encrypted-chat")]
+pub use crypto::*;
+mod event;
+pub use event::*;
+mod hooks;
+use hooks::Hooks;
+mod overlay;
+pub use overlay::*;
+mod snapshot;
+use snapshot::Snapshot;
+#[cfg(feature = "host")]
+mod host;
+#[cfg(feature = "host")]
+pub use host::*;
+#[cfg(feature = "tls")]
+pub use tokio_rustls::rustls;
+
+use anyhow::{bail, Context, Error, Result};
+use arc_swap::{ArcSwap, ArcSwapOption};
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
+#[cfg(feature = "lan")]
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+#[cfg(feature = "websocket")]
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+#[cfg(feature = "lan")]
+use phira_mp_common::LAN_SERVICE_TYPE;
+#[cfg(feature = "websocket")]
+use phira_mp_common::{connect_ws, WsTransport};
 use phira_mp_common::{
-    ClientCommand, ClientRoomState, JoinRoomResponse, JudgeEvent, Message, RoomId, RoomState,
-    ServerCommand, Stream, TouchFrame, UserInfo, HEARTBEAT_INTERVAL, HEARTBEAT_TIMEOUT,
+    decode_packet, encode_packet, unix_millis, Achievement, BinaryData, BinaryReader, BinaryWriter,
+    ChartFilter, ClientCommand, ClientRoomState, DraftAction, DraftState, DuelSeries, Features,
+    GameEndReason, JoinRoomResponse, JudgeEvent, MemberPhase, MemberStatus, Message, PlayedChart,
+    PlayerScore, ReadyCheck, Recording, ReliableCommand, RepeatPolicy, ReplayData, ReplayDirection,
+    ReplayWriter, RoomId, RoomListing, RoomMode, RoomState, RoundPhase, ServerCommand, ServerError,
+    ServerLimits, Stream, SyncStateResponse, TouchBatch, TouchFrame, TouchSpace, Transport,
+    UserInfo, Varchar, HEARTBEAT_INTERVAL, HEARTBEAT_TIMEOUT, LOG_HEARTBEAT, MIN_PROTOCOL_VERSION,
+    PROTOCOL_VERSION, RELIABLE_PENDING_MAX, RELIABLE_RESEND_AFTER, RESEND_JUDGES_MAX,
+    ROOM_MAX_PLAYERS, ROUTE_PREFIX, SCORE_UPDATE_INTERVAL,
 };
 use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fmt::Debug,
+    fs::File,
+    future::Future,
+    io::BufWriter,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    path::{Path, PathBuf},
+    pin::Pin,
     sync::{
-        atomic::{AtomicU8, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc, Mutex as StdMutex, RwLock as StdRwLock,
     },
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::{
+    io::AsyncWriteExt,
     net::TcpStream,
-    sync::{oneshot, Mutex, Notify, RwLock},
-    task::JoinHandle,
+    sync::{broadcast, mpsc, oneshot, watch, Mutex, Notify, RwLock},
+    task::{JoinHandle, JoinSet},
     time,
 };
-use tracing::{error, trace, warn};
+use tracing::{debug, error, info, trace, warn};
+use uuid::Uuid;
 
 type Callback<T> = Mutex<Option<oneshot::Sender<T>>>;
-type RCallback<T, E = String> = Mutex<Option<oneshot::Sender<Result<T, E>>>>;
+type RCallback<T, E = ServerError> = Mutex<Option<oneshot::Sender<Result<T, E>>>>;
+type ClientStream = Stream<ClientCommand, ServerCommand>;
 
 pub const TIMEOUT: Duration = Duration::from_secs(7);
+const OUTGOING_CHATS_KEPT: usize = 64;
 
 pub struct LivePlayer {
     pub touch_frames: Mutex<Vec<TouchFrame>>,
     pub judge_events: Mutex<Vec<JudgeEvent>>,
+    relay_stats: StdMutex<RelayStats>,
+    score: StdMutex<LiveScore>,
+}
+
+impl Default for LivePlayer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl LivePlayer {
@@ -34,17 +90,278 @@ impl LivePlayer {
         Self {
             touch_frames: Mutex::default(),
             judge_events: Mutex::default(),
+            relay_stats: StdMutex::default(),
+            score: StdMutex::default(),
+        }
+    }
+
+    pub fn relay_stats(&self) -> RelayStats {
+        self.relay_stats.lock().unwrap().clone()
+    }
+
+    pub fn score(&self) -> LiveScore {
+        self.score.lock().unwrap().clone()
+    }
+}
+
+/// Frames of a player relayed to us this round, told apart by their sequence
+/// numbers.
+///
+/// Lost touches are only counted. Missing judge batches are asked for again
+/// automatically, since they matter for the score.
+#[derive(Debug, Clone, Default)]
+pub struct RelayStats {
+    pub touches_received: u32,
+    /// Touch frames skipped over. When watching someone else, this includes
+    /// the frames of this player we weren't sent.
+    pub touches_lost: u32,
+    pub judges_received: u32,
+    /// Judge batches that arrived after having been found missing
+    pub judges_recovered: u32,
+    last_touch_seq: u32,
+    last_judge_seq: u32,
+    missing_judges: BTreeSet<u32>,
+}
+
+impl RelayStats {
+    /// Share of touch frames lost, between 0 and 1.
+    pub fn touch_loss(&self) -> f32 {
+        let total = self.touches_received + self.touches_lost;
+        if total == 0 {
+            0.
+        } else {
+            self.touches_lost as f32 / total as f32
+        }
+    }
+
+    /// Judge batches found missing that haven't arrived (yet).
+    pub fn judges_missing(&self) -> usize {
+        self.missing_judges.len()
+    }
+
+    fn on_touches(&mut self, seq: u32) {
+        self.touches_received += 1;
+        if seq > self.last_touch_seq {
+            self.touches_lost += seq - self.last_touch_seq - 1;
+            self.last_touch_seq = seq;
+        } else {
+            // Arrived late, was counted as lost
+            self.touches_lost = self.touches_lost.saturating_sub(1);
+        }
+    }
+
+    /// Returns the newly found gap before `seq`, or `None` if the batch was
+    /// received already.
+    fn on_judges(&mut self, seq: u32) -> Option<Vec<u32>> {
+        if seq > self.last_judge_seq {
+            let gap: Vec<_> = (self.last_judge_seq + 1..seq).collect();
+            self.missing_judges.extend(gap.iter().copied());
+            self.last_judge_seq = seq;
+            self.judges_received += 1;
+            Some(gap)
+        } else if self.missing_judges.remove(&seq) {
+            self.judges_received += 1;
+            self.judges_recovered += 1;
+            Some(Vec::new())
+        } else {
+            None
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct ServerLatency {
+    pub addr: SocketAddr,
+    pub delay: Duration,
+    pub region: Option<String>,
+}
+
+/// See [`Client::export_session`]. Contains the auth token, so it should be
+/// stored as carefully as the token itself.
+#[derive(Debug, Clone)]
+pub struct SessionSnapshot {
+    pub token: String,
+    pub user: i32,
+    pub room: Option<RoomId>,
+    /// See [`Client::resume_token`]
+    pub resume: Option<Uuid>,
+}
+
+impl SessionSnapshot {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        encode_packet(self, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        decode_packet(bytes)
+    }
+}
+
+impl BinaryData for SessionSnapshot {
+    fn read_binary(r: &mut BinaryReader<'_>) -> Result<Self> {
+        Ok(Self {
+            token: r.read()?,
+            user: r.read()?,
+            room: r.read()?,
+            // Not in snapshots from older versions
+            resume: r.read().unwrap_or_default(),
+        })
+    }
+
+    fn write_binary(&self, w: &mut BinaryWriter<'_>) -> Result<()> {
+        w.write(&self.token)?;
+        w.write_val(self.user)?;
+        w.write(&self.room)?;
+        w.write(&self.resume)?;
+        Ok(())
+    }
+}
+
+/// A server found by [`Client::discover_lan`].
+#[cfg(feature = "lan")]
+#[derive(Debug, Clone)]
+pub struct LanServer {
+    pub name: String,
+    pub addrs: Vec<SocketAddr>,
+    pub region: Option<String>,
+}
+
+/// Error of calls waiting for a response when [`Client::shutdown`] was
+/// called, and of those made afterwards.
+#[derive(Debug, Clone, Copy)]
+pub struct Shutdown;
+
+impl std::fmt::Display for Shutdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("client shut down")
+    }
+}
+
+impl std::error::Error for Shutdown {}
+
+/// Error of calls the server doesn't support, judging by the features agreed
+/// on in the handshake (see [`Client::features`]). Nothing is sent, as the
+/// server would leave it unanswered until the call times out.
+#[derive(Debug, Clone)]
+pub struct Unsupported {
+    /// Name of the command, e.g. `QueueChart`.
+    pub command: String,
+    pub missing: Features,
+}
+
+impl std::fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is not supported by the server", self.command)
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+/// Calls waiting for a response, see [`Client::pending_requests`].
+#[derive(Default)]
+struct PendingCalls {
+    next: AtomicU64,
+    names: StdMutex<BTreeMap<u64, String>>,
+}
+
+impl PendingCalls {
+    fn track(&self, cmd: &ClientCommand) -> PendingCall<'_> {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        self.names.lock().unwrap().insert(id, command_name(cmd));
+        PendingCall { calls: self, id }
+    }
+}
+
+// Only the variant's name, arguments may be secret like tokens
+fn command_name(cmd: &ClientCommand) -> String {
+    format!("{cmd:?}")
+        .chars()
+        .take_while(char::is_ascii_alphanumeric)
+        .collect()
+}
+
+/// Untracks the call once it's answered, failed or given up on.
+struct PendingCall<'a> {
+    calls: &'a PendingCalls,
+    id: u64,
+}
+
+impl Drop for PendingCall<'_> {
+    fn drop(&mut self) {
+        self.calls.names.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Delivery of a message sent with [`Client::send_chat`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChatStatus {
+    Pending,
+    Delivered,
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct OutgoingChat {
+    pub id: Uuid,
+    pub message: String,
+    pub status: ChatStatus,
+}
+
+/// Called with download progress, from 0 to 1.
+pub type ProgressCallback = Box<dyn Fn(f32) + Send + Sync>;
+
+/// Implemented by the game so that the client can get selected charts ready
+/// on its own, see [`Client::set_chart_provider`].
+pub trait ChartProvider: Send + Sync {
+    /// Whether the chart can be played without downloading it first.
+    fn is_available(&self, id: i32) -> bool;
+
+    fn download(
+        &self,
+        id: i32,
+        progress: ProgressCallback,
+    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+}
+
+/// Progress is only reported in steps at least this large.
+const CHART_PROGRESS_STEP: f32 = 0.05;
+/// Events a subscriber may fall behind by before it misses some.
+const EVENTS_CAPACITY: usize = 256;
+/// Latency is reported again once it changed by this much, or by a quarter
+/// if that's more.
+const LATENCY_REPORT_STEP: u16 = 20;
+/// Clock samples taken by [`Client::sync_clock`], the one with the shortest
+/// round trip is used.
+const CLOCK_SYNC_SAMPLES: usize = 5;
+
+/// A room member, see [`Client::members`].
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub info: UserInfo,
+    pub phase: MemberPhase,
+    /// `false` while the server waits for them to reconnect
+    pub connected: bool,
+    /// Their round-trip time to the server, unknown until they report it
+    pub latency: Option<Duration>,
+}
+
 struct State {
-    delay: Mutex<Option<Duration>>,
+    delay: ArcSwapOption<Duration>,
     ping_notify: Notify,
+    suspended: AtomicBool,
+    resume_notify: Notify,
 
-    me: RwLock<Option<UserInfo>>,
-    room: RwLock<Option<ClientRoomState>>,
+    me: Snapshot<Option<UserInfo>>,
+    token: RwLock<Option<String>>,
+    room: Snapshot<Option<ClientRoomState>>,
 
+    /// See [`Client::shutdown`]
+    shutdown: watch::Sender<bool>,
+    pending: PendingCalls,
+
+    cb_hello: RCallback<(u16, Features)>,
     cb_authenticate: RCallback<(UserInfo, Option<ClientRoomState>)>,
     cb_chat: RCallback<()>,
     cb_create_room: RCallback<()>,
@@ -53,17 +370,200 @@ struct State {
     cb_lock_room: RCallback<()>,
     cb_cycle_room: RCallback<()>,
     cb_select_chart: RCallback<()>,
+    cb_select_random_chart: RCallback<i32>,
     cb_request_start: RCallback<()>,
     cb_ready: RCallback<()>,
     cb_cancel_ready: RCallback<()>,
     cb_played: RCallback<()>,
     cb_abort: RCallback<()>,
+    cb_echo_test: RCallback<Vec<u8>>,
+    cb_watch_player: RCallback<()>,
+    cb_export_results: RCallback<String>,
+    cb_slow_mode: RCallback<()>,
+    cb_report_player: RCallback<()>,
+    cb_sync_state: RCallback<Option<SyncStateResponse>>,
+    cb_bridge_spectator_chat: RCallback<()>,
+    cb_force_cancel_start: RCallback<()>,
+    cb_set_recording: RCallback<()>,
+    cb_fetch_room_history: RCallback<Vec<PlayedChart>>,
+    cb_set_repeat_policy: RCallback<()>,
+    cb_set_room_mode: RCallback<()>,
+    cb_queue_chart: RCallback<()>,
+    cb_unqueue_chart: RCallback<()>,
+    cb_vote_chart: RCallback<()>,
+    cb_set_ready_check: RCallback<()>,
+    cb_sync_clock: RCallback<(u64, u64)>,
+    cb_set_duel: RCallback<()>,
+    cb_start_draft: RCallback<()>,
+    cb_cancel_draft: RCallback<()>,
+    cb_draft_turn: RCallback<()>,
+    cb_set_max_players: RCallback<()>,
+    cb_end_round: RCallback<Vec<i32>>,
+    cb_split_room: RCallback<()>,
+    cb_merge_room: RCallback<()>,
+    cb_list_rooms: RCallback<Vec<RoomListing>>,
+    cb_kick_player: RCallback<()>,
+    cb_ban_player: RCallback<()>,
+    cb_mute_player: RCallback<()>,
+    cb_timeout_player: RCallback<()>,
+    cb_transfer_host: RCallback<()>,
+    #[cfg(feature = "encrypted-chat")]
+    chat_key: StdMutex<Option<Arc<ChatKey>>>,
+    /// See [`Client::round`]
+    round: AtomicU32,
+    outgoing_chats: Mutex<VecDeque<OutgoingChat>>,
 
     live_players: DashMap<i32, Arc<LivePlayer>>,
     messages: Mutex<Vec<Message>>,
+    achievements: Mutex<Vec<(i32, Achievement)>>,
+    /// Tags of skipped commands, see [`Client::take_unknown_commands`]
+    unknown_commands: StdMutex<Vec<u8>>,
+    prefetch: Mutex<Vec<i32>>,
+    chart_provider: StdMutex<Option<Arc<dyn ChartProvider>>>,
+    /// Chart being made ready by the provider
+    chart_task: StdMutex<Option<(i32, JoinHandle<()>)>>,
+    /// Download progress of other members, 1 once ready
+    chart_progress: DashMap<i32, f32>,
+    expiry: ArcSwapOption<DateTime<Utc>>,
+    /// Latest [`ServerCommand::StartCountdown`] of the current start
+    start_countdown: StdMutex<Option<u16>>,
+    /// Server clock minus ours in milliseconds, see [`Client::sync_clock`]
+    clock_offset: StdMutex<Option<i64>>,
+    /// Latest [`ServerCommand::GameStart`], by our clock
+    game_start: StdMutex<Option<(u32, SystemTime)>>,
+    limits: StdMutex<Option<ServerLimits>>,
+    /// Negotiated with the server on the current connection
+    protocol: StdMutex<(u16, Features)>,
+    /// See [`Client::routing_token`]
+    route: StdMutex<Option<String>>,
+    /// See [`Client::resume_token`]
+    resume_token: StdMutex<Option<Uuid>>,
+    /// Status of room members by id, see [`Client::members`]
+    members: StdMutex<HashMap<i32, MemberStatus>>,
+    /// See [`Client::touch_space_of`]
+    touch_spaces: StdMutex<HashMap<i32, TouchSpace>>,
+    reported_latency: StdMutex<Option<u16>>,
+    recorder: StdMutex<Option<ReplayWriter<BufWriter<File>>>>,
+    /// The room doesn't allow recording, see [`Recording::Off`].
+    recording_off: AtomicBool,
+    /// See [`Client::set_send_threshold`], 0 if off
+    send_threshold: AtomicUsize,
+    /// When [`Client::update_score`] last sent one
+    last_score_update: StdMutex<Option<Instant>>,
+    reliable: StdMutex<ReliableQueue>,
+    /// Round and reason of the last game that ended
+    game_end: watch::Sender<Option<(u32, GameEndReason)>>,
+    /// See [`Client::subscribe`]
+    events: broadcast::Sender<ClientEvent>,
+    hooks: Hooks,
+    connection: StdMutex<ConnectionQuality>,
+    /// See [`Client::overlay_state`]
+    overlay: ArcSwap<OverlayState>,
 }
 
 impl State {
+    fn check_supported(&self, cmd: &ClientCommand) -> Result<()> {
+        let required = cmd.required_features();
+        let features = self.protocol.lock().unwrap().1;
+        if !features.contains(required) {
+            bail!(Unsupported {
+                command: command_name(cmd),
+                missing: required.difference(features),
+            });
+        }
+        Ok(())
+    }
+
+    /// Only builds the event if anyone listens.
+    fn emit(&self, event: impl FnOnce() -> ClientEvent) {
+        if self.events.receiver_count() > 0 {
+            let _ = self.events.send(event());
+        }
+    }
+
+    fn record(&self, direction: ReplayDirection, player: i32, data: ReplayData) {
+        if self.recording_off.load(Ordering::SeqCst) {
+            return;
+        }
+        let mut recorder = self.recorder.lock().unwrap();
+        if let Some(writer) = recorder.as_mut() {
+            if let Err(err) = writer.write(direction, player, data) {
+                error!("failed to record realtime data, recording stopped: {err:?}");
+                *recorder = None;
+            }
+        }
+    }
+
+    /// Interval and timeout of heartbeats, as told by the server.
+    fn heartbeat(&self) -> (Duration, Duration) {
+        match &*self.limits.lock().unwrap() {
+            Some(limits) => (limits.heartbeat_interval(), limits.heartbeat_timeout()),
+            None => (HEARTBEAT_INTERVAL, HEARTBEAT_TIMEOUT),
+        }
+    }
+
+    fn set_recording(&self, recording: Recording) {
+        self.recording_off
+            .store(recording == Recording::Off, Ordering::SeqCst);
+    }
+
+    /// Sets the phase of `user`, or of every player if `None`.
+    async fn set_phase(&self, user: Option<i32>, phase: MemberPhase) {
+        let room = self.room.read().await;
+        let Some(room) = room.as_ref() else {
+            return;
+        };
+        let mut members = self.members.lock().unwrap();
+        for info in room.users.values() {
+            if user.map_or(!info.monitor, |user| info.id == user) {
+                members
+                    .entry(info.id)
+                    .or_insert_with(|| new_member_status(info.id))
+                    .phase = phase;
+            }
+        }
+    }
+
+    fn members(&self, room: Option<&ClientRoomState>) -> Vec<Member> {
+        let Some(room) = room else {
+            return Vec::new();
+        };
+        let members = self.members.lock().unwrap();
+        room.users
+            .values()
+            .map(|info| {
+                let status = members
+                    .get(&info.id)
+                    .cloned()
+                    .unwrap_or_else(|| new_member_status(info.id));
+                Member {
+                    info: info.clone(),
+                    phase: status.phase,
+                    connected: status.connected,
+                    latency: status.latency_ms.map(|it| Duration::from_millis(it as u64)),
+                }
+            })
+            .collect()
+    }
+
+    /// Tells the room our latency if it changed noticeably since last time.
+    async fn report_latency(&self, stream: &ClientStream, delay: Duration) {
+        if self.room.read().await.is_none() {
+            return;
+        }
+        let ms = delay.as_millis().min(u16::MAX as u128) as u16;
+        {
+            let mut reported = self.reported_latency.lock().unwrap();
+            if reported.is_some_and(|it| it.abs_diff(ms) < LATENCY_REPORT_STEP.max(it / 4)) {
+                return;
+            }
+            *reported = Some(ms);
+        }
+        if let Err(err) = stream.send(ClientCommand::ReportLatency { ms }).await {
+            error!("failed to report latency: {err:?}");
+        }
+    }
+
     pub fn live_player(&self, player: i32) -> Arc<LivePlayer> {
         Arc::clone(
             &self
@@ -72,28 +572,113 @@ impl State {
                 .or_insert_with(|| Arc::new(LivePlayer::new())),
         )
     }
+
+    /// Takes a new snapshot for [`Client::overlay_state`].
+    async fn refresh_overlay(&self) {
+        let room = self.room.read().await;
+        let mut players: Vec<_> = self
+            .members(room.as_ref())
+            .into_iter()
+            .filter(|it| !it.info.monitor)
+            .map(|member| OverlayPlayer {
+                score: self
+                    .live_players
+                    .get(&member.info.id)
+                    .map(|it| it.score())
+                    .unwrap_or_default(),
+                id: member.info.id,
+                name: member.info.name,
+                phase: member.phase,
+                connected: member.connected,
+            })
+            .collect();
+        OverlayState::rank(&mut players);
+        self.overlay.store(Arc::new(OverlayState {
+            room_state: room.as_ref().map(|it| it.state),
+            round: self.round.load(Ordering::SeqCst),
+            players,
+            latency: self.delay.load().as_deref().copied(),
+            connection: *self.connection.lock().unwrap(),
+        }));
+    }
 }
 
 pub struct Client {
     state: Arc<State>,
 
-    stream: Arc<Stream<ClientCommand, ServerCommand>>,
+    /// Replaced when migrating to a new connection
+    stream: Arc<StdRwLock<Arc<ClientStream>>>,
+    security: Security,
+    peer: SocketAddr,
+    local_ip: StdMutex<IpAddr>,
 
     ping_fail_count: Arc<AtomicU8>,
     ping_task_handle: JoinHandle<()>,
+    resend_task_handle: JoinHandle<()>,
+}
+
+/// Judges and score updates sent as [`ClientCommand::Reliable`] that weren't
+/// acknowledged yet, by their `seq`.
+#[derive(Default)]
+struct ReliableQueue {
+    next_seq: u32,
+    pending: BTreeMap<u32, (ReliableCommand, Instant)>,
+}
+
+/// How connections to the server are set up, kept for migrating.
+#[derive(Clone)]
+enum Security {
+    Plain,
+    #[cfg(feature = "tls")]
+    Tls(rustls::ServerName, Arc<rustls::ClientConfig>),
+    #[cfg(feature = "websocket")]
+    WebSocket(String),
 }
 
 impl Client {
+    #[inline]
     pub async fn new(stream: TcpStream) -> Result<Self> {
-        stream.set_nodelay(true)?;
+        Self::start(stream, Security::Plain).await
+    }
+
+    /// Talks to the server over TLS, checking its certificate against
+    /// `domain`. Connections made when migrating use TLS as well.
+    #[cfg(feature = "tls")]
+    pub async fn new_tls(
+        domain: &str,
+        stream: TcpStream,
+        config: Arc<rustls::ClientConfig>,
+    ) -> Result<Self> {
+        let domain = rustls::ServerName::try_from(domain)?;
+        Self::start(stream, Security::Tls(domain, config)).await
+    }
+
+    /// Talks to the server's WebSocket listener, `url` being e.g.
+    /// `ws://example.com:12347/`. Connections made when migrating use
+    /// WebSocket as well.
+    #[cfg(feature = "websocket")]
+    pub async fn new_ws(url: &str, stream: TcpStream) -> Result<Self> {
+        Self::start(stream, Security::WebSocket(url.to_owned())).await
+    }
+
+    async fn start(stream: TcpStream, security: Security) -> Result<Self> {
+        let peer = stream.peer_addr()?;
+        let local_ip = stream.local_addr()?.ip();
 
         let state = Arc::new(State {
-            delay: Mutex::default(),
+            delay: ArcSwapOption::empty(),
             ping_notify: Notify::new(),
+            suspended: AtomicBool::new(false),
+            resume_notify: Notify::new(),
+
+            me: Snapshot::default(),
+            token: RwLock::default(),
+            room: Snapshot::default(),
 
-            me: RwLock::default(),
-            room: RwLock::default(),
+            shutdown: watch::channel(false).0,
+            pending: PendingCalls::default(),
 
+            cb_hello: Callback::default(),
             cb_authenticate: Callback::default(),
             cb_chat: Callback::default(),
             cb_create_room: Callback::default(),
@@ -102,26 +687,79 @@ impl Client {
             cb_lock_room: Callback::default(),
             cb_cycle_room: Callback::default(),
             cb_select_chart: Callback::default(),
+            cb_select_random_chart: Callback::default(),
             cb_request_start: Callback::default(),
             cb_ready: Callback::default(),
             cb_cancel_ready: Callback::default(),
             cb_played: Callback::default(),
             cb_abort: Callback::default(),
+            cb_echo_test: Callback::default(),
+            cb_watch_player: Callback::default(),
+            cb_export_results: Callback::default(),
+            cb_slow_mode: Callback::default(),
+            cb_report_player: Callback::default(),
+            cb_sync_state: Callback::default(),
+            cb_bridge_spectator_chat: Callback::default(),
+            cb_force_cancel_start: Callback::default(),
+            cb_set_recording: Callback::default(),
+            cb_fetch_room_history: Callback::default(),
+            cb_set_repeat_policy: Callback::default(),
+            cb_set_room_mode: Callback::default(),
+            cb_queue_chart: Callback::default(),
+            cb_unqueue_chart: Callback::default(),
+            cb_vote_chart: Callback::default(),
+            cb_set_ready_check: Callback::default(),
+            cb_sync_clock: Callback::default(),
+            cb_set_duel: Callback::default(),
+            cb_start_draft: Callback::default(),
+            cb_cancel_draft: Callback::default(),
+            cb_draft_turn: Callback::default(),
+            cb_set_max_players: Callback::default(),
+            cb_end_round: Callback::default(),
+            cb_split_room: Callback::default(),
+            cb_merge_room: Callback::default(),
+            cb_list_rooms: Callback::default(),
+            cb_kick_player: Callback::default(),
+            cb_ban_player: Callback::default(),
+            cb_mute_player: Callback::default(),
+            cb_timeout_player: Callback::default(),
+            cb_transfer_host: Callback::default(),
+            #[cfg(feature = "encrypted-chat")]
+            chat_key: StdMutex::default(),
+            round: AtomicU32::default(),
+            outgoing_chats: Mutex::default(),
 
             live_players: DashMap::new(),
             messages: Mutex::default(),
+            achievements: Mutex::default(),
+            unknown_commands: StdMutex::default(),
+            prefetch: Mutex::default(),
+            chart_provider: StdMutex::default(),
+            chart_task: StdMutex::default(),
+            chart_progress: DashMap::new(),
+            expiry: ArcSwapOption::empty(),
+            start_countdown: StdMutex::default(),
+            clock_offset: StdMutex::default(),
+            game_start: StdMutex::default(),
+            limits: StdMutex::default(),
+            protocol: StdMutex::new((PROTOCOL_VERSION, Features::EMPTY)),
+            route: StdMutex::default(),
+            resume_token: StdMutex::default(),
+            members: StdMutex::default(),
+            touch_spaces: StdMutex::default(),
+            reported_latency: StdMutex::default(),
+            recorder: StdMutex::default(),
+            recording_off: AtomicBool::new(false),
+            send_threshold: AtomicUsize::new(0),
+            last_score_update: StdMutex::default(),
+            reliable: StdMutex::default(),
+            game_end: watch::channel(None).0,
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            hooks: Hooks::default(),
+            connection: StdMutex::default(),
+            overlay: ArcSwap::default(),
         });
-        let stream = Arc::new(
-            Stream::new(
-                Some(1),
-                stream,
-                Box::new({
-                    let state = Arc::clone(&state);
-                    move |_send_tx, cmd| process(Arc::clone(&state), cmd)
-                }),
-            )
-            .await?,
-        );
+        let stream = Arc::new(StdRwLock::new(Self::open(&state, &security, stream).await?));
 
         let ping_fail_count = Arc::new(AtomicU8::default());
         let ping_task_handle = tokio::spawn({
@@ -130,23 +768,88 @@ impl Client {
             let stream = Arc::clone(&stream);
             async move {
                 loop {
-                    time::sleep(HEARTBEAT_INTERVAL).await;
+                    time::sleep(state.heartbeat().0).await;
+                    while state.suspended.load(Ordering::SeqCst) {
+                        state.resume_notify.notified().await;
+                    }
+                    let stream = Arc::clone(&*stream.read().unwrap());
 
                     let start = Instant::now();
-                    if let Err(err) = stream.send(ClientCommand::Ping).await {
-                        error!("failed to send heartbeat: {err:?}");
-                    } else if time::timeout(HEARTBEAT_TIMEOUT, state.ping_notify.notified())
+                    let failed = if let Err(err) = stream.send(ClientCommand::Ping).await {
+                        error!(target: LOG_HEARTBEAT, "failed to send heartbeat: {err:?}");
+                        true
+                    } else if time::timeout(state.heartbeat().1, state.ping_notify.notified())
                         .await
                         .is_err()
                     {
-                        warn!("heartbeat timeout");
-                        ping_fail_count.fetch_add(1, Ordering::Relaxed);
+                        warn!(target: LOG_HEARTBEAT, "heartbeat timeout");
+                        true
                     } else {
-                        ping_fail_count.store(0, Ordering::SeqCst);
+                        if ping_fail_count.swap(0, Ordering::SeqCst) != 0 {
+                            // Messages may have been lost while the connection was stalled
+                            warn!(target: LOG_HEARTBEAT, "heartbeat recovered, resyncing state");
+                            if let Err(err) = stream.send(ClientCommand::SyncState).await {
+                                error!("failed to resync state: {err:?}");
+                            }
+                            Hooks::call("reconnected", &state.hooks.reconnected, ());
+                        }
+                        false
+                    };
+                    if failed && ping_fail_count.fetch_add(1, Ordering::Relaxed) == 0 {
+                        Hooks::call("disconnected", &state.hooks.disconnected, ());
                     }
                     let delay = start.elapsed();
-                    *state.delay.lock().await = Some(delay);
-                    trace!("sent heartbeat, delay: {delay:?}");
+                    state.delay.store(Some(Arc::new(delay)));
+                    *state.connection.lock().unwrap() =
+                        ConnectionQuality::from_heartbeat(delay, failed);
+                    state.refresh_overlay().await;
+                    if !failed {
+                        state.report_latency(&stream, delay).await;
+                    }
+                    if stream.is_traced() {
+                        info!(target: LOG_HEARTBEAT, "sent heartbeat, delay: {delay:?}");
+                    } else {
+                        trace!(target: LOG_HEARTBEAT, "sent heartbeat, delay: {delay:?}");
+                    }
+                }
+            }
+        });
+
+        let resend_task_handle = tokio::spawn({
+            let state = Arc::clone(&state);
+            let stream = Arc::clone(&stream);
+            async move {
+                loop {
+                    time::sleep(RELIABLE_RESEND_AFTER / 2).await;
+                    while state.suspended.load(Ordering::SeqCst) {
+                        state.resume_notify.notified().await;
+                    }
+                    let overdue: Vec<_> = {
+                        let mut queue = state.reliable.lock().unwrap();
+                        queue
+                            .pending
+                            .iter_mut()
+                            .filter(|(_, (_, sent))| sent.elapsed() >= RELIABLE_RESEND_AFTER)
+                            .map(|(seq, (cmd, sent))| {
+                                *sent = Instant::now();
+                                ClientCommand::Reliable {
+                                    seq: *seq,
+                                    cmd: cmd.clone(),
+                                }
+                            })
+                            .collect()
+                    };
+                    if overdue.is_empty() {
+                        continue;
+                    }
+                    debug!("sending {} unacknowledged packets again", overdue.len());
+                    let stream = Arc::clone(&*stream.read().unwrap());
+                    for cmd in overdue {
+                        if let Err(err) = stream.send(cmd).await {
+                            warn!("failed to send again: {err:?}");
+                            break;
+                        }
+                    }
                 }
             }
         });
@@ -155,24 +858,183 @@ impl Client {
             state,
 
             stream,
+            security,
+            peer,
+            local_ip: StdMutex::new(local_ip),
 
             ping_fail_count,
             ping_task_handle,
+            resend_task_handle,
         })
     }
 
+    async fn open(
+        state: &Arc<State>,
+        security: &Security,
+        mut stream: TcpStream,
+    ) -> Result<Arc<ClientStream>> {
+        stream.set_nodelay(true)?;
+        let route = state.route.lock().unwrap().clone();
+        #[cfg(feature = "websocket")]
+        let websocket = matches!(security, Security::WebSocket(_));
+        #[cfg(not(feature = "websocket"))]
+        let websocket = false;
+        if let Some(route) = route.as_ref().filter(|_| !websocket) {
+            let mut line = ROUTE_PREFIX.to_vec();
+            line.extend_from_slice(route.as_bytes());
+            line.push(b'\n');
+            stream.write_all(&line).await?;
+        }
+        let stream: Box<dyn Transport> = match security {
+            Security::Plain => Box::new(stream),
+            #[cfg(feature = "tls")]
+            Security::Tls(domain, config) => Box::new(
+                tokio_rustls::TlsConnector::from(Arc::clone(config))
+                    .connect(domain.clone(), stream)
+                    .await?,
+            ),
+            #[cfg(feature = "websocket")]
+            Security::WebSocket(url) => {
+                let url = match &route {
+                    Some(route) => {
+                        let sep = if url.contains('?') { '&' } else { '?' };
+                        let route = utf8_percent_encode(route, NON_ALPHANUMERIC);
+                        format!("{url}{sep}route={route}")
+                    }
+                    None => url.clone(),
+                };
+                Box::new(WsTransport::new(connect_ws(&url, stream).await?.0))
+            }
+        };
+        let stream = Arc::new(
+            Stream::new(
+                Some(1),
+                stream,
+                Box::new({
+                    let state = Arc::clone(state);
+                    move |send_tx, cmd| process(Arc::clone(&state), send_tx, cmd)
+                }),
+            )
+            .await?,
+        );
+        let (version, features) = Self::rcall_on(
+            state,
+            &stream,
+            ClientCommand::Hello {
+                version: PROTOCOL_VERSION,
+                // Acknowledging and resending is wasted work on a
+                // transport that already delivers everything
+                features: if stream.is_lossy() {
+                    Features::ALL
+                } else {
+                    Features::ALL.difference(Features::RELIABLE_REALTIME)
+                },
+            },
+            &state.cb_hello,
+        )
+        .await
+        .context("protocol handshake failed, the server may be too old")?;
+        if version < MIN_PROTOCOL_VERSION {
+            bail!("unsupported server protocol version {version}");
+        }
+        *state.protocol.lock().unwrap() = (version, features);
+        if features.contains(Features::CLOCK_SYNC) {
+            tokio::spawn({
+                let state = Arc::clone(state);
+                let stream = Arc::clone(&stream);
+                async move {
+                    if let Err(err) = Self::sync_clock_on(&state, &stream).await {
+                        warn!("failed to synchronize clock: {err:?}");
+                    }
+                }
+            });
+        }
+        Ok(stream)
+    }
+
+    fn stream(&self) -> Arc<ClientStream> {
+        Arc::clone(&self.stream.read().unwrap())
+    }
+
+    /// Probes candidate servers concurrently. Unreachable servers are left
+    /// out; the rest are sorted by round-trip time.
+    pub async fn measure(addrs: impl IntoIterator<Item = SocketAddr>) -> Vec<ServerLatency> {
+        let mut tasks = JoinSet::new();
+        for addr in addrs {
+            tasks.spawn(async move { (addr, time::timeout(TIMEOUT, probe(addr)).await) });
+        }
+        let mut result = Vec::new();
+        while let Some(res) = tasks.join_next().await {
+            match res {
+                Ok((_, Ok(Ok(latency)))) => result.push(latency),
+                Ok((addr, Ok(Err(err)))) => warn!("failed to probe {addr}: {err:?}"),
+                Ok((addr, Err(_))) => warn!("probing {addr} timed out"),
+                Err(err) => error!("probe task failed: {err:?}"),
+            }
+        }
+        result.sort_by_key(|it| it.delay);
+        result
+    }
+
+    /// Looks for servers advertised on the local network via mDNS, listening
+    /// for `timeout`. The result can be passed to [`Client::measure`].
+    #[cfg(feature = "lan")]
+    pub async fn discover_lan(timeout: Duration) -> Result<Vec<LanServer>> {
+        let daemon = ServiceDaemon::new()?;
+        let events = daemon.browse(LAN_SERVICE_TYPE)?;
+        let mut servers = HashMap::new();
+        let deadline = time::Instant::now() + timeout;
+        while let Ok(Ok(event)) = time::timeout_at(deadline, events.recv_async()).await {
+            match event {
+                ServiceEvent::ServiceResolved(info) => {
+                    let name = info
+                        .get_fullname()
+                        .strip_suffix(LAN_SERVICE_TYPE)
+                        .unwrap_or(info.get_fullname())
+                        .trim_end_matches('.')
+                        .to_owned();
+                    let port = info.get_port();
+                    servers.insert(
+                        info.get_fullname().to_owned(),
+                        LanServer {
+                            name,
+                            addrs: info
+                                .get_addresses()
+                                .iter()
+                                .map(|ip| SocketAddr::new(*ip, port))
+                                .collect(),
+                            region: info.get_property_val_str("region").map(str::to_owned),
+                        },
+                    );
+                }
+                ServiceEvent::ServiceRemoved(_, fullname) => {
+                    servers.remove(&fullname);
+                }
+                _ => {}
+            }
+        }
+        let _ = daemon.shutdown();
+        Ok(servers.into_values().collect())
+    }
+
     pub fn me(&self) -> Option<UserInfo> {
-        self.state.me.blocking_read().clone()
+        (**self.state.me.load()).clone()
     }
 
     pub fn user_name(&self, id: i32) -> String {
         self.user_name_opt(id).unwrap_or_else(|| "?".to_owned())
     }
 
+    /// Members of our room, and the server's bot as
+    /// [`BOT_USER_ID`](phira_mp_common::BOT_USER_ID).
     pub fn user_name_opt(&self, id: i32) -> Option<String> {
+        if id == phira_mp_common::BOT_USER_ID {
+            return self.limits().and_then(|it| it.bot_name);
+        }
         self.state
             .room
-            .blocking_read()
+            .load()
+            .as_ref()
             .as_ref()
             .and_then(|it| it.users.get(&id).map(|it| it.name.clone()))
     }
@@ -181,20 +1043,136 @@ impl Client {
         self.state.messages.blocking_lock().drain(..).collect()
     }
 
+    /// Achievements unlocked by room members (including us) since the last
+    /// call, as `(user, achievement)`.
+    pub fn blocking_take_achievements(&self) -> Vec<(i32, Achievement)> {
+        self.state.achievements.blocking_lock().drain(..).collect()
+    }
+
+    /// Tags of commands from the server this build doesn't know, skipped
+    /// since the last call. Any means the server is newer, so updating may
+    /// be worth suggesting.
+    pub fn take_unknown_commands(&self) -> Vec<u8> {
+        std::mem::take(&mut self.state.unknown_commands.lock().unwrap())
+    }
+
+    /// Charts the host hinted at since the last call, most recent last.
+    /// Downloading them in the background shortens the wait once one is
+    /// selected.
+    pub fn blocking_take_prefetch(&self) -> Vec<i32> {
+        self.state.prefetch.blocking_lock().drain(..).collect()
+    }
+
+    /// Everyone in the room with where they are in the current round,
+    /// whether they're connected and their latency.
+    pub async fn members(&self) -> Vec<Member> {
+        self.state.members(self.state.room.read().await.as_ref())
+    }
+
+    pub fn blocking_members(&self) -> Vec<Member> {
+        self.state.members(self.state.room.load().as_ref().as_ref())
+    }
+
     pub fn blocking_state(&self) -> Option<ClientRoomState> {
-        self.state.room.blocking_read().clone()
+        (**self.state.room.load()).clone()
+    }
+
+    pub async fn room_id(&self) -> Option<RoomId> {
+        self.state
+            .room
+            .read()
+            .await
+            .as_ref()
+            .map(|it| it.id.clone())
     }
 
     pub fn blocking_room_id(&self) -> Option<RoomId> {
         self.state
             .room
-            .blocking_read()
+            .load()
+            .as_ref()
             .as_ref()
             .map(|it| it.id.clone())
     }
 
+    /// Seconds left to get ready before the room's [`ReadyCheck`] runs out,
+    /// as of the latest tick. `None` while there's no countdown.
+    pub fn start_countdown(&self) -> Option<u16> {
+        *self.state.start_countdown.lock().unwrap()
+    }
+
+    /// Milliseconds the server's clock is ahead of ours, once measured. Done
+    /// on connecting to servers with [`Features::CLOCK_SYNC`].
+    pub fn clock_offset(&self) -> Option<i64> {
+        *self.state.clock_offset.lock().unwrap()
+    }
+
+    /// When the chart of `round` should begin, by our clock, as scheduled
+    /// by the server in [`ServerCommand::GameStart`]. `None` for servers
+    /// without [`Features::CLOCK_SYNC`], which expect the chart to begin on
+    /// [`Message::StartPlaying`], and while [`Self::clock_offset`] is yet to
+    /// be measured.
+    pub fn game_start(&self) -> Option<(u32, SystemTime)> {
+        *self.state.game_start.lock().unwrap()
+    }
+
+    /// When the room will be closed for inactivity, if that's near. Any
+    /// room action (e.g. chatting) keeps it open.
+    pub fn blocking_room_expiry(&self) -> Option<DateTime<Utc>> {
+        self.state.expiry.load().as_deref().copied()
+    }
+
+    /// Limits advertised by the server, known once authenticated.
+    pub fn limits(&self) -> Option<ServerLimits> {
+        self.state.limits.lock().unwrap().clone()
+    }
+
+    /// The protocol version spoken with the server.
+    pub fn protocol_version(&self) -> u16 {
+        self.state.protocol.lock().unwrap().0
+    }
+
+    /// Optional parts of the protocol both the server and we support.
+    pub fn features(&self) -> Features {
+        self.state.protocol.lock().unwrap().1
+    }
+
+    /// Given by servers run in a cluster, and written first on every later
+    /// connection (see [`Self::migrate`] and [`ROUTE_PREFIX`]) so that proxies
+    /// route it back to the same server. WebSocket connections carry it in
+    /// their URL instead, as the `route` query parameter.
+    pub fn routing_token(&self) -> Option<String> {
+        self.state.route.lock().unwrap().clone()
+    }
+
+    /// Picks up the session on a new connection without the auth token,
+    /// see [`Self::resume_session`]. Works only once, a new one is sent afterwards.
+    pub fn resume_token(&self) -> Option<Uuid> {
+        *self.state.resume_token.lock().unwrap()
+    }
+
+    /// Whether the server is a sandbox for testing clients against, where
+    /// results aren't submitted. Known once authenticated.
+    pub fn is_sandbox(&self) -> bool {
+        self.state
+            .limits
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|it| it.sandbox)
+    }
+
+    fn check_chat(&self, message: &str) -> Result<()> {
+        if let Some(limits) = self.limits() {
+            if message.len() > limits.chat_max_len as usize {
+                bail!("chat message longer than {} bytes", limits.chat_max_len);
+            }
+        }
+        Ok(())
+    }
+
     pub fn blocking_room_state(&self) -> Option<RoomState> {
-        self.state.room.blocking_read().as_ref().map(|it| it.state)
+        self.state.room.load().as_ref().as_ref().map(|it| it.state)
     }
 
     pub async fn room_state(&self) -> Option<RoomState> {
@@ -204,61 +1182,343 @@ impl Client {
     pub fn blocking_is_host(&self) -> Option<bool> {
         self.state
             .room
-            .blocking_read()
+            .load()
+            .as_ref()
             .as_ref()
             .map(|it| it.is_host)
     }
 
+    /// See [`Self::set_max_players`].
+    pub fn blocking_max_players(&self) -> Option<u8> {
+        self.state
+            .room
+            .load()
+            .as_ref()
+            .as_ref()
+            .map(|it| it.max_players)
+    }
+
+    /// The duel being played in the room, see [`Self::set_duel`].
+    pub fn blocking_duel(&self) -> Option<DuelSeries> {
+        self.state
+            .room
+            .load()
+            .as_ref()
+            .as_ref()
+            .and_then(|it| it.duel.clone())
+    }
+
+    /// The draft choosing the next chart, see [`Self::start_draft`].
+    pub fn blocking_draft(&self) -> Option<DraftState> {
+        self.state
+            .room
+            .load()
+            .as_ref()
+            .as_ref()
+            .and_then(|it| it.draft.clone())
+    }
+
     pub fn blocking_is_ready(&self) -> Option<bool> {
         self.state
             .room
-            .blocking_read()
+            .load()
+            .as_ref()
             .as_ref()
             .map(|it| it.is_ready)
     }
 
     pub async fn ping(&self) -> Result<Duration> {
         let start = Instant::now();
-        self.stream.send(ClientCommand::Ping).await?;
-        time::timeout(HEARTBEAT_TIMEOUT, self.state.ping_notify.notified())
+        self.stream().send(ClientCommand::Ping).await?;
+        time::timeout(self.state.heartbeat().1, self.state.ping_notify.notified())
             .await
             .context("heartbeat timeout")?;
         let delay = start.elapsed();
-        *self.state.delay.lock().await = Some(delay);
+        self.state.delay.store(Some(Arc::new(delay)));
         Ok(delay)
     }
 
-    pub fn delay(&self) -> Option<Duration> {
-        *self.state.delay.blocking_lock()
+    /// Stops heartbeats, e.g. when the app is sent to background and the OS
+    /// is about to suspend it, so that the time asleep isn't counted as
+    /// failed pings.
+    pub fn suspend(&self) {
+        self.state.suspended.store(true, Ordering::SeqCst);
     }
 
-    async fn rcall<R>(&self, payload: ClientCommand, cb: &RCallback<R>) -> Result<R> {
-        self.stream.send(payload).await?;
-        let (tx, rx) = oneshot::channel();
-        *cb.lock().await = Some(tx);
-        time::timeout(TIMEOUT, rx)
-            .await
-            .context("timeout")??
-            .map_err(Error::msg)
+    /// Restarts heartbeats after [`Client::suspend`] and brings the room state
+    /// up to date. If this fails the server has most likely dropped the
+    /// connection already; see [`Client::restore_session`].
+    pub async fn resume(&self) -> Result<()> {
+        self.state.suspended.store(false, Ordering::SeqCst);
+        self.state.resume_notify.notify_one();
+        self.ping().await?;
+        self.reset_ping_fail_count();
+        self.sync_state().await?;
+        Ok(())
+    }
+
+    /// Replaces the local room state with the server's. Done automatically
+    /// when heartbeats recover after timing out.
+    pub async fn sync_state(&self) -> Result<Option<SyncStateResponse>> {
+        self.rcall(ClientCommand::SyncState, &self.state.cb_sync_state)
+            .await
+    }
+
+    pub fn delay(&self) -> Option<Duration> {
+        self.state.delay.load().as_deref().copied()
+    }
+
+    /// Ranked live scores, round phase and connection quality in one
+    /// snapshot. Takes no locks, so a HUD can read it every frame; it's
+    /// updated as messages and judges arrive.
+    pub fn overlay_state(&self) -> Arc<OverlayState> {
+        self.state.overlay.load_full()
+    }
+
+    async fn rcall<R, E>(&self, payload: ClientCommand, cb: &RCallback<R, E>) -> Result<R>
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        Self::rcall_on(&self.state, &self.stream(), payload, cb).await
+    }
+
+    async fn rcall_on<R, E>(
+        state: &State,
+        stream: &ClientStream,
+        payload: ClientCommand,
+        cb: &RCallback<R, E>,
+    ) -> Result<R>
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let mut shutdown = state.shutdown.subscribe();
+        if *shutdown.borrow() {
+            bail!(Shutdown);
+        }
+        state.check_supported(&payload)?;
+        let _pending = state.pending.track(&payload);
+        // Registered first, the response may arrive before `send` returns
+        let (tx, rx) = oneshot::channel();
+        *cb.lock().await = Some(tx);
+        stream.send(payload).await?;
+        tokio::select! {
+            res = time::timeout(TIMEOUT, rx) => res.context("timeout")??.map_err(Error::new),
+            _ = shutdown.wait_for(|it| *it) => bail!(Shutdown),
+        }
+    }
+
+    /// Names of the commands sent that still wait for a response, oldest
+    /// first, e.g. `JoinRoom`.
+    pub fn pending_requests(&self) -> Vec<String> {
+        self.state
+            .pending
+            .names
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Tears the client down: stops heartbeats and chart loading, fails
+    /// calls waiting for a response (and any made afterwards) with
+    /// [`Shutdown`], says [`ClientCommand::Bye`] so that the server lets us
+    /// go right away, and waits for it to close the connection. Unlike
+    /// dropping the client, this leaves nothing running in the background.
+    pub async fn shutdown(&self) -> Result<()> {
+        self.ping_task_handle.abort();
+        self.resend_task_handle.abort();
+        if let Some((_, handle)) = self.state.chart_task.lock().unwrap().take() {
+            handle.abort();
+        }
+        self.state.shutdown.send_replace(true);
+        let stream = self.stream();
+        // Fails only if the connection is gone already
+        let _ = stream.send(ClientCommand::Bye).await;
+        time::timeout(TIMEOUT, stream.closed())
+            .await
+            .context("server didn't close the connection")?;
+        Ok(())
     }
 
     #[inline]
     pub async fn authenticate(&self, token: impl Into<String>) -> Result<()> {
+        let token = token.into();
         let (me, room) = self
             .rcall(
                 ClientCommand::Authenticate {
-                    token: token.into().try_into()?,
+                    token: token.clone().try_into()?,
                 },
                 &self.state.cb_authenticate,
             )
             .await?;
         *self.state.me.write().await = Some(me);
+        *self.state.token.write().await = Some(token);
+        self.state
+            .set_recording(room.as_ref().map(|it| it.recording).unwrap_or_default());
         *self.state.room.write().await = room;
+        self.state.refresh_overlay().await;
         Ok(())
     }
 
-    #[inline]
+    /// Authenticates as the session `session` was given for as its
+    /// [`resume token`](Self::resume_token), taking over its room, even in the
+    /// middle of a round, as long as the server's grace period for it hasn't
+    /// passed yet. The auth token stays unknown though, so there's no
+    /// [`Self::export_session`] afterwards.
+    pub async fn resume_session(&self, session: Uuid) -> Result<()> {
+        let (me, room) = self
+            .rcall(
+                ClientCommand::Resume { session },
+                &self.state.cb_authenticate,
+            )
+            .await?;
+        *self.state.me.write().await = Some(me);
+        self.state
+            .set_recording(room.as_ref().map(|it| it.recording).unwrap_or_default());
+        *self.state.room.write().await = room;
+        self.state.refresh_overlay().await;
+        Ok(())
+    }
+
+    /// What's needed to pick the session up again from a new connection,
+    /// e.g. after the app was killed while in background. `None` if not
+    /// authenticated.
+    pub async fn export_session(&self) -> Option<SessionSnapshot> {
+        Some(SessionSnapshot {
+            token: self.state.token.read().await.clone()?,
+            user: self.state.me.read().await.as_ref()?.id,
+            room: self
+                .state
+                .room
+                .read()
+                .await
+                .as_ref()
+                .map(|it| it.id.clone()),
+            resume: self.resume_token(),
+        })
+    }
+
+    /// Authenticates this (new) connection as the snapshot's session.
+    ///
+    /// Returns whether the room was kept, which is only the case if the
+    /// server's reconnect grace period hasn't passed yet. The snapshot's
+    /// resume token is tried first, the auth token if that's gone.
+    pub async fn restore_session(&self, snapshot: &SessionSnapshot) -> Result<bool> {
+        let resumed = match snapshot.resume {
+            Some(session) => match self.resume_session(session).await {
+                Ok(()) => true,
+                Err(err) => {
+                    warn!("failed to resume session: {err:?}");
+                    false
+                }
+            },
+            None => false,
+        };
+        if resumed {
+            *self.state.token.write().await = Some(snapshot.token.clone());
+        } else {
+            self.authenticate(snapshot.token.clone()).await?;
+        }
+        if self.state.me.read().await.as_ref().map(|it| it.id) != Some(snapshot.user) {
+            bail!("session belongs to another user");
+        }
+        let room = self
+            .state
+            .room
+            .read()
+            .await
+            .as_ref()
+            .map(|it| it.id.clone());
+        Ok(snapshot.room.is_some() && room == snapshot.room)
+    }
+
+    /// Moves the session onto `stream`, a new connection to the same server,
+    /// e.g. after switching from Wi-Fi to cellular. The server swaps it in
+    /// for the old connection, which is closed, without leaving the room,
+    /// even in the middle of a round.
+    pub async fn migrate(&self, stream: TcpStream) -> Result<()> {
+        let token = self
+            .state
+            .token
+            .read()
+            .await
+            .clone()
+            .context("not authenticated")?;
+        let local_ip = stream.local_addr()?.ip();
+        let new = Self::open(&self.state, &self.security, stream).await?;
+        // Nothing else may be sent on the new connection before it's accepted
+        let cmd = match self.resume_token() {
+            Some(session) => ClientCommand::Resume { session },
+            None => ClientCommand::Authenticate {
+                token: token.try_into()?,
+            },
+        };
+        let (me, room) =
+            Self::rcall_on(&self.state, &new, cmd, &self.state.cb_authenticate).await?;
+        if self.state.me.read().await.as_ref().map(|it| it.id) != Some(me.id) {
+            bail!("session belongs to another user");
+        }
+        new.set_trace(self.stream().is_traced());
+        let old = std::mem::replace(&mut *self.stream.write().unwrap(), new);
+        info!(
+            "migrated connection from {}",
+            *self.local_ip.lock().unwrap()
+        );
+        *self.local_ip.lock().unwrap() = local_ip;
+        drop(old);
+        // The new connection numbers them anew, and the old one may have got them
+        let lost = std::mem::take(&mut *self.state.reliable.lock().unwrap())
+            .pending
+            .len();
+        if lost != 0 {
+            warn!("{lost} unacknowledged packets left behind");
+        }
+
+        *self.state.me.write().await = Some(me);
+        self.state
+            .set_recording(room.as_ref().map(|it| it.recording).unwrap_or_default());
+        *self.state.room.write().await = room;
+        self.state.refresh_overlay().await;
+        self.reset_ping_fail_count();
+        Ok(())
+    }
+
+    /// Whether traffic to the server would now leave through another local
+    /// address than the connection's, i.e. the device switched networks.
+    /// Nothing is sent.
+    pub fn network_changed(&self) -> Result<bool> {
+        let socket = UdpSocket::bind(SocketAddr::new(
+            if self.peer.is_ipv4() {
+                IpAddr::from([0; 4])
+            } else {
+                IpAddr::from([0u16; 8])
+            },
+            0,
+        ))?;
+        socket.connect(self.peer)?;
+        Ok(socket.local_addr()?.ip() != *self.local_ip.lock().unwrap())
+    }
+
+    /// Migrates to a new connection if [`Self::network_changed`]. Meant to
+    /// be called on the platform's network change events, or periodically.
+    /// Returns whether it migrated.
+    pub async fn check_network(&self) -> Result<bool> {
+        if !self.network_changed()? {
+            return Ok(false);
+        }
+        let stream = time::timeout(TIMEOUT, TcpStream::connect(self.peer))
+            .await
+            .context("timeout")??;
+        self.migrate(stream).await?;
+        Ok(true)
+    }
+
+    /// Fails with [`ServerError::RateLimited`] if the room is in slow mode or
+    /// the server's chat rate limit is hit, and the last message was sent too
+    /// recently.
     pub async fn chat(&self, message: String) -> Result<()> {
+        self.check_chat(&message)?;
         self.rcall(
             ClientCommand::Chat {
                 message: message.try_into()?,
@@ -268,13 +1528,181 @@ impl Client {
         .await
     }
 
+    /// Only `target`, who must be in the same room, receives it as a
+    /// [`Message::Whisper`]. So do we, once it's delivered.
+    pub async fn whisper(&self, target: i32, message: String) -> Result<()> {
+        self.check_chat(&message)?;
+        self.rcall(
+            ClientCommand::Whisper {
+                target,
+                message: message.try_into()?,
+            },
+            &self.state.cb_chat,
+        )
+        .await
+    }
+
+    /// Uses the key from [`Self::set_chat_password`], which must have been set
+    /// in the current room. Others receive a [`Message::EncryptedChat`], to be
+    /// read with [`Self::decrypt_chat`].
+    #[cfg(feature = "encrypted-chat")]
+    pub async fn encrypted_chat(&self, message: String) -> Result<()> {
+        self.check_chat(&message)?;
+        let room = self.room_id().await;
+        let Some(key) = self
+            .chat_key()
+            .filter(|it| Some(it.room()) == room.as_ref())
+        else {
+            bail!("no chat password set for this room");
+        };
+        let Some(me) = self.me() else {
+            bail!("not authenticated");
+        };
+        self.rcall(
+            ClientCommand::EncryptedChat {
+                payload: key.encrypt(me.id, &message),
+            },
+            &self.state.cb_chat,
+        )
+        .await
+    }
+
+    /// Derives the key for encrypted chat in the current room from a password
+    /// shared among its members, or forgets it. See [`ChatKey::derive`].
+    ///
+    /// This must not be the room's join password: the server receives that one
+    /// in plaintext, so reusing it hands the key to whoever runs the server.
+    #[cfg(feature = "encrypted-chat")]
+    pub async fn set_chat_password(&self, password: Option<&str>) -> Result<()> {
+        let key = match password {
+            Some(password) => {
+                let Some(room) = self.room_id().await else {
+                    bail!("not in a room");
+                };
+                let password = password.to_owned();
+                let key =
+                    tokio::task::spawn_blocking(move || ChatKey::derive(&password, room)).await?;
+                Some(Arc::new(key))
+            }
+            None => None,
+        };
+        *self.state.chat_key.lock().unwrap() = key;
+        Ok(())
+    }
+
+    #[cfg(feature = "encrypted-chat")]
+    fn chat_key(&self) -> Option<Arc<ChatKey>> {
+        self.state.chat_key.lock().unwrap().clone()
+    }
+
+    /// The content of an encrypted chat payload from `user`, or `None` if it
+    /// can't be read with the current key or wasn't sent by them.
+    #[cfg(feature = "encrypted-chat")]
+    pub fn decrypt_chat(&self, user: i32, payload: &[u8]) -> Option<String> {
+        self.chat_key()?.decrypt(user, payload)
+    }
+
+    /// Sends a chat message without waiting for the server. Its delivery can
+    /// be followed through [`Self::blocking_outgoing_chats`].
+    pub async fn send_chat(&self, message: String) -> Result<Uuid> {
+        let id = Uuid::new_v4();
+        self.send_chat_with_id(id, message).await?;
+        Ok(id)
+    }
+
+    /// Sends (again) a chat message under the given id. The server delivers
+    /// each id only once, so this is safe to call when the acknowledgement
+    /// was lost, also on a new connection after [`Self::restore_session`].
+    pub async fn send_chat_with_id(&self, id: Uuid, message: String) -> Result<()> {
+        self.check_chat(&message)?;
+        let payload = ClientCommand::ChatWithId {
+            id,
+            message: message.clone().try_into()?,
+        };
+        {
+            let mut chats = self.state.outgoing_chats.lock().await;
+            if let Some(chat) = chats.iter_mut().find(|it| it.id == id) {
+                chat.status = ChatStatus::Pending;
+            } else {
+                if chats.len() == OUTGOING_CHATS_KEPT {
+                    match chats
+                        .iter()
+                        .position(|it| it.status == ChatStatus::Delivered)
+                    {
+                        Some(index) => chats.remove(index),
+                        None => chats.pop_front(),
+                    };
+                }
+                chats.push_back(OutgoingChat {
+                    id,
+                    message,
+                    status: ChatStatus::Pending,
+                });
+            }
+        }
+        if let Err(err) = self.send(payload).await {
+            set_chat_status(&self.state, id, ChatStatus::Failed(err.to_string())).await;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Resends a message that failed or is still pending.
+    pub async fn retry_chat(&self, id: Uuid) -> Result<()> {
+        let message = self
+            .state
+            .outgoing_chats
+            .lock()
+            .await
+            .iter()
+            .find(|it| it.id == id)
+            .map(|it| it.message.clone());
+        let Some(message) = message else {
+            bail!("unknown chat message");
+        };
+        self.send_chat_with_id(id, message).await
+    }
+
+    /// The latest messages sent with [`Self::send_chat`], oldest first.
+    pub fn blocking_outgoing_chats(&self) -> Vec<OutgoingChat> {
+        self.state
+            .outgoing_chats
+            .blocking_lock()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
     #[inline]
     pub async fn create_room(&self, id: RoomId) -> Result<()> {
+        self.create_room_with(id, None, ROOM_MAX_PLAYERS).await
+    }
+
+    /// Creates a room only those who know `password` can join.
+    #[inline]
+    pub async fn create_room_with_password(&self, id: RoomId, password: String) -> Result<()> {
+        self.create_room_with(id, Some(password), ROOM_MAX_PLAYERS)
+            .await
+    }
+
+    /// Creates a room with room for at most `max_players` players, see
+    /// [`Self::set_max_players`].
+    pub async fn create_room_with(
+        &self,
+        id: RoomId,
+        password: Option<String>,
+        max_players: u8,
+    ) -> Result<()> {
         self.rcall(
-            ClientCommand::CreateRoom { id: id.clone() },
+            ClientCommand::CreateRoom {
+                id: id.clone(),
+                password: password.map(Varchar::try_from).transpose()?,
+                max_players,
+            },
             &self.state.cb_create_room,
         )
         .await?;
+        self.state.set_recording(Recording::default());
         let me = self.state.me.read().await.clone().unwrap();
         *self.state.room.write().await = Some(ClientRoomState {
             id,
@@ -282,34 +1710,93 @@ impl Client {
             live: false,
             locked: false,
             cycle: false,
+            recording: Recording::default(),
             is_host: true,
             is_ready: false,
+            is_spectator: false,
             users: std::iter::once((me.id, me)).collect(),
+            test: self.is_sandbox(),
+            duel: None,
+            draft: None,
+            max_players,
+            last_results: Vec::new(),
+            repeat_policy: RepeatPolicy::default(),
+            mode: RoomMode::default(),
+            queue: Vec::new(),
+            ready_check: None,
         });
+        self.state.refresh_overlay().await;
         Ok(())
     }
 
+    /// Fails with [`ServerError::PasswordRequired`] if the room has a
+    /// password, see [`Self::join_room_with_password`].
     #[inline]
     pub async fn join_room(&self, id: RoomId, monitor: bool) -> Result<()> {
+        self.join_room_with(id, monitor, false, None).await
+    }
+
+    /// Joins to watch others play, receiving their touches and judges. Works
+    /// in rooms that are full of players too.
+    #[inline]
+    pub async fn join_as_spectator(&self, id: RoomId) -> Result<()> {
+        self.join_room_with(id, false, true, None).await
+    }
+
+    /// Fails with [`ServerError::WrongPassword`] if `password` isn't the
+    /// room's. Rooms without one can be joined with any password.
+    #[inline]
+    pub async fn join_room_with_password(
+        &self,
+        id: RoomId,
+        monitor: bool,
+        password: String,
+    ) -> Result<()> {
+        self.join_room_with(id, monitor, false, Some(password.try_into()?))
+            .await
+    }
+
+    async fn join_room_with(
+        &self,
+        id: RoomId,
+        monitor: bool,
+        spectator: bool,
+        password: Option<Varchar<64>>,
+    ) -> Result<()> {
         let resp = self
             .rcall(
                 ClientCommand::JoinRoom {
                     id: id.clone(),
                     monitor,
+                    spectator,
+                    password,
                 },
                 &self.state.cb_join_room,
             )
             .await?;
+        self.state.set_recording(resp.recording);
         *self.state.room.write().await = Some(ClientRoomState {
             id,
             state: resp.state,
             live: resp.live,
             locked: false,
-            cycle: false,
-            is_host: false,
+            cycle: resp.mode == RoomMode::Relay,
+            recording: resp.recording,
+            is_host: resp.is_host,
             is_ready: false,
+            is_spectator: spectator,
             users: resp.users.into_iter().map(|it| (it.id, it)).collect(),
+            test: self.is_sandbox(),
+            duel: resp.duel,
+            draft: resp.draft,
+            max_players: resp.max_players,
+            last_results: resp.last_results,
+            repeat_policy: resp.repeat_policy,
+            mode: resp.mode,
+            queue: resp.queue,
+            ready_check: resp.ready_check,
         });
+        self.state.refresh_overlay().await;
         Ok(())
     }
 
@@ -318,6 +1805,8 @@ impl Client {
         self.rcall(ClientCommand::LeaveRoom, &self.state.cb_leave_room)
             .await?;
         *self.state.room.write().await = None;
+        self.state.expiry.store(None);
+        self.state.refresh_overlay().await;
         Ok(())
     }
 
@@ -336,6 +1825,222 @@ impl Client {
         .await
     }
 
+    /// Limits everyone but the host to one chat message per `secs` seconds,
+    /// 0 to turn it off.
+    #[inline]
+    pub async fn slow_mode(&self, secs: u16) -> Result<()> {
+        self.rcall(ClientCommand::SlowMode { secs }, &self.state.cb_slow_mode)
+            .await
+    }
+
+    /// Sets whether results and replays of the following rounds are kept,
+    /// and who may access them. Can't be changed during a game.
+    #[inline]
+    pub async fn set_recording(&self, recording: Recording) -> Result<()> {
+        self.rcall(
+            ClientCommand::SetRecording { recording },
+            &self.state.cb_set_recording,
+        )
+        .await
+    }
+
+    /// Starts a best-of-`best_of` duel against the other player in the
+    /// room, or calls off the current one with `None`. Players take turns
+    /// selecting the chart, starting with us; the winner is announced with
+    /// [`Message::DuelWon`]. Only for the host, while choosing a chart.
+    #[inline]
+    pub async fn set_duel(&self, best_of: Option<u8>) -> Result<()> {
+        self.rcall(ClientCommand::SetDuel { best_of }, &self.state.cb_set_duel)
+            .await
+    }
+
+    /// Lets at most `max_players` play in the room, which must be at least
+    /// as many as are in it. Only for the host.
+    #[inline]
+    pub async fn set_max_players(&self, max_players: u8) -> Result<()> {
+        self.rcall(
+            ClientCommand::SetMaxPlayers { max_players },
+            &self.state.cb_set_max_players,
+        )
+        .await?;
+        if let Some(room) = self.state.room.write().await.as_mut() {
+            room.max_players = max_players;
+        }
+        Ok(())
+    }
+
+    /// Charts played in the room so far, oldest first.
+    #[inline]
+    pub async fn fetch_room_history(&self) -> Result<Vec<PlayedChart>> {
+        self.rcall(
+            ClientCommand::FetchRoomHistory,
+            &self.state.cb_fetch_room_history,
+        )
+        .await
+    }
+
+    /// Whether charts played within the last
+    /// [`RECENT_CHARTS`](phira_mp_common::RECENT_CHARTS) rounds can
+    /// be selected again. Only for the host.
+    #[inline]
+    pub async fn set_repeat_policy(&self, policy: RepeatPolicy) -> Result<()> {
+        self.rcall(
+            ClientCommand::SetRepeatPolicy { policy },
+            &self.state.cb_set_repeat_policy,
+        )
+        .await
+    }
+
+    /// Only for the host while choosing a chart. [`cycle_room`](Self::cycle_room)
+    /// switches between [`RoomMode::Versus`] and [`RoomMode::Relay`] as well.
+    #[inline]
+    pub async fn set_room_mode(&self, mode: RoomMode) -> Result<()> {
+        self.rcall(
+            ClientCommand::SetRoomMode { mode },
+            &self.state.cb_set_room_mode,
+        )
+        .await
+    }
+
+    /// Adds a chart to the room's queue, see
+    /// [`QueuedChart`](phira_mp_common::QueuedChart). Queued charts are in
+    /// [`ClientRoomState::queue`].
+    #[inline]
+    pub async fn queue_chart(&self, id: i32) -> Result<()> {
+        self.rcall(ClientCommand::QueueChart { id }, &self.state.cb_queue_chart)
+            .await
+    }
+
+    /// Only for the host and who queued the chart.
+    #[inline]
+    pub async fn unqueue_chart(&self, id: i32) -> Result<()> {
+        self.rcall(
+            ClientCommand::UnqueueChart { id },
+            &self.state.cb_unqueue_chart,
+        )
+        .await
+    }
+
+    /// Gives players `check.secs` seconds to get ready once we request a
+    /// start, or as long as they need if `None`. Only for the host.
+    #[inline]
+    pub async fn set_ready_check(&self, check: Option<ReadyCheck>) -> Result<()> {
+        self.rcall(
+            ClientCommand::SetReadyCheck { check },
+            &self.state.cb_set_ready_check,
+        )
+        .await
+    }
+
+    /// Measures again how far the server's clock is off from ours, e.g.
+    /// after the system clock was changed, and returns the offset in
+    /// milliseconds. See [`Self::clock_offset`].
+    pub async fn sync_clock(&self) -> Result<i64> {
+        Self::sync_clock_on(&self.state, &self.stream()).await
+    }
+
+    async fn sync_clock_on(state: &State, stream: &ClientStream) -> Result<i64> {
+        let mut best = (u64::MAX, 0);
+        for _ in 0..CLOCK_SYNC_SAMPLES {
+            // The system clock may jump in between
+            let start = Instant::now();
+            let (sent, server_time) = Self::rcall_on(
+                state,
+                stream,
+                ClientCommand::SyncClock {
+                    client_time: unix_millis(),
+                },
+                &state.cb_sync_clock,
+            )
+            .await?;
+            let rtt = start.elapsed().as_millis() as u64;
+            let offset = server_time as i64 - (sent + rtt / 2) as i64;
+            if rtt < best.0 {
+                best = (rtt, offset);
+            }
+        }
+        let (rtt, offset) = best;
+        debug!("clock offset {offset} ms, round trip {rtt} ms");
+        *state.clock_offset.lock().unwrap() = Some(offset);
+        Ok(offset)
+    }
+
+    /// Votes for the queued chart `id` to be played next, or withdraws our
+    /// vote.
+    #[inline]
+    pub async fn vote_chart(&self, id: Option<i32>) -> Result<()> {
+        self.rcall(ClientCommand::VoteChart { id }, &self.state.cb_vote_chart)
+            .await
+    }
+
+    /// Lets the players choose the next chart from `pool`: taking turns,
+    /// starting with us (or the duel's picker), they ban `bans` charts with
+    /// [`Self::draft_turn`] and then pick one of the rest. Players who don't
+    /// act within `turn_secs` get a random chart banned or picked for them.
+    /// Only for the host, while choosing a chart.
+    #[inline]
+    pub async fn start_draft(&self, pool: Vec<i32>, bans: u8, turn_secs: u16) -> Result<()> {
+        self.rcall(
+            ClientCommand::StartDraft {
+                pool,
+                bans,
+                turn_secs,
+            },
+            &self.state.cb_start_draft,
+        )
+        .await
+    }
+
+    #[inline]
+    pub async fn cancel_draft(&self) -> Result<()> {
+        self.rcall(ClientCommand::CancelDraft, &self.state.cb_cancel_draft)
+            .await
+    }
+
+    /// Takes our turn in the draft, see [`DraftState::turn`].
+    #[inline]
+    pub async fn draft_turn(&self, action: DraftAction, chart: i32) -> Result<()> {
+        self.rcall(
+            ClientCommand::DraftTurn { action, chart },
+            &self.state.cb_draft_turn,
+        )
+        .await
+    }
+
+    /// Shows chat from spectators to players as well. Spectator chat is
+    /// received as [`Message::SpectatorChat`].
+    #[inline]
+    pub async fn bridge_spectator_chat(&self, bridge: bool) -> Result<()> {
+        self.rcall(
+            ClientCommand::BridgeSpectatorChat { bridge },
+            &self.state.cb_bridge_spectator_chat,
+        )
+        .await
+    }
+
+    /// Makes the client get each newly selected chart ready on its own:
+    /// unless `provider` has it already, it's downloaded while progress is
+    /// reported to the room, then other members are told it's ready.
+    pub fn set_chart_provider(&self, provider: impl ChartProvider + 'static) {
+        *self.state.chart_provider.lock().unwrap() = Some(Arc::new(provider));
+    }
+
+    /// Download progress of other members for the selected chart, from 0 to
+    /// 1. Only tracked with a [`ChartProvider`] set.
+    pub fn chart_progress(&self) -> HashMap<i32, f32> {
+        self.state
+            .chart_progress
+            .iter()
+            .map(|it| (*it.key(), *it.value()))
+            .collect()
+    }
+
+    /// Hints other members to download charts that may be selected next,
+    /// e.g. while browsing. Only the host's hints are relayed.
+    pub async fn prefetch(&self, chart_ids: Vec<i32>) -> Result<()> {
+        self.send(ClientCommand::Prefetch { chart_ids }).await
+    }
+
     #[inline]
     pub async fn select_chart(&self, id: i32) -> Result<()> {
         self.rcall(
@@ -345,6 +2050,20 @@ impl Client {
         .await
     }
 
+    /// Lets the server select a random chart matching `filter`, never one
+    /// of the last [`RECENT_CHARTS`](phira_mp_common::RECENT_CHARTS)
+    /// played. Returns its id; like any selection it's also announced with
+    /// [`Message::SelectChart`], which is all there is should this time out
+    /// while the server still looks.
+    #[inline]
+    pub async fn select_random_chart(&self, filter: ChartFilter) -> Result<i32> {
+        self.rcall(
+            ClientCommand::SelectRandomChart { filter },
+            &self.state.cb_select_random_chart,
+        )
+        .await
+    }
+
     #[inline]
     pub async fn request_start(&self) -> Result<()> {
         self.rcall(ClientCommand::RequestStart, &self.state.cb_request_start)
@@ -353,6 +2072,17 @@ impl Client {
         Ok(())
     }
 
+    /// Goes back to chart selection when players can't get ready, e.g.
+    /// because one of them crashed. Host only.
+    #[inline]
+    pub async fn force_cancel_start(&self) -> Result<()> {
+        self.rcall(
+            ClientCommand::ForceCancelStart,
+            &self.state.cb_force_cancel_start,
+        )
+        .await
+    }
+
     #[inline]
     pub async fn ready(&self) -> Result<()> {
         self.rcall(ClientCommand::Ready, &self.state.cb_ready)
@@ -369,42 +2099,515 @@ impl Client {
         Ok(())
     }
 
+    /// Moves `users` into a new room `id`, hosted by the first of them who
+    /// may host. Only for the host, while choosing a chart.
     #[inline]
-    pub async fn played(&self, id: i32) -> Result<()> {
-        self.rcall(ClientCommand::Played { id }, &self.state.cb_played)
-            .await
+    pub async fn split_room(&self, id: RoomId, users: Vec<i32>) -> Result<()> {
+        self.rcall(
+            ClientCommand::SplitRoom { id, users },
+            &self.state.cb_split_room,
+        )
+        .await
     }
 
+    /// Moves everyone here, us included, into the room `into` and closes this
+    /// one. Only for the host, while choosing a chart.
     #[inline]
-    pub async fn abort(&self) -> Result<()> {
-        self.rcall(ClientCommand::Abort, &self.state.cb_abort).await
+    pub async fn merge_room(&self, into: RoomId) -> Result<()> {
+        self.rcall(ClientCommand::MergeRoom { into }, &self.state.cb_merge_room)
+            .await
     }
 
-    pub fn ping_fail_count(&self) -> u8 {
-        self.ping_fail_count.load(Ordering::Relaxed)
+    /// Removes a member from the room. Only for the host.
+    #[inline]
+    pub async fn kick_player(&self, id: i32) -> Result<()> {
+        self.rcall(ClientCommand::KickPlayer { id }, &self.state.cb_kick_player)
+            .await
     }
 
-    pub async fn send(&self, payload: ClientCommand) -> Result<()> {
-        self.stream.send(payload).await
+    /// Removes a member from the room for good: they can't join again while
+    /// the room exists. Only for the host.
+    #[inline]
+    pub async fn ban_player(&self, id: i32) -> Result<()> {
+        self.rcall(ClientCommand::BanPlayer { id }, &self.state.cb_ban_player)
+            .await
     }
 
-    pub fn blocking_send(&self, payload: ClientCommand) -> Result<()> {
-        self.stream.blocking_send(payload)
+    /// Keeps a member from chatting for `secs` seconds, or lets them chat
+    /// again with 0. Everyone is told with [`Message::Muted`], also once
+    /// the mute runs out. Only for the host.
+    #[inline]
+    pub async fn mute_player(&self, user_id: i32, secs: u32) -> Result<()> {
+        self.rcall(
+            ClientCommand::MutePlayer { user_id, secs },
+            &self.state.cb_mute_player,
+        )
+        .await
+    }
+
+    /// Removes a member from the room and keeps them from joining again for
+    /// `secs` seconds. Only for the host.
+    #[inline]
+    pub async fn timeout_player(&self, user_id: i32, secs: u32) -> Result<()> {
+        self.rcall(
+            ClientCommand::TimeoutPlayer { user_id, secs },
+            &self.state.cb_timeout_player,
+        )
+        .await
+    }
+
+    /// Hands the room to another player. Only for the host.
+    #[inline]
+    pub async fn transfer_host(&self, user_id: i32) -> Result<()> {
+        self.rcall(
+            ClientCommand::TransferHost { target: user_id },
+            &self.state.cb_transfer_host,
+        )
+        .await
+    }
+
+    /// Rooms open on the server, those with the most players first. Works
+    /// without being in a room.
+    #[inline]
+    pub async fn list_rooms(&self) -> Result<Vec<RoomListing>> {
+        self.rcall(ClientCommand::ListRooms, &self.state.cb_list_rooms)
+            .await
+    }
+
+    /// Ends the game for everyone, e.g. when one player's client hung. The
+    /// players still playing are treated as aborted. Without `confirm`
+    /// nothing happens, which tells who would be affected.
+    #[inline]
+    pub async fn end_round(&self, confirm: bool) -> Result<Vec<i32>> {
+        self.rcall(
+            ClientCommand::EndRound { confirm },
+            &self.state.cb_end_round,
+        )
+        .await
+    }
+
+    #[inline]
+    pub async fn played(&self, id: i32) -> Result<()> {
+        self.rcall(ClientCommand::Played { id }, &self.state.cb_played)
+            .await
+    }
+
+    #[inline]
+    pub async fn abort(&self) -> Result<()> {
+        self.rcall(ClientCommand::Abort, &self.state.cb_abort).await
+    }
+
+    /// Asks the server to only relay touches of `user_id` (judges are still
+    /// relayed for everyone). `None` restores full relaying.
+    #[inline]
+    pub async fn watch_player(&self, user_id: Option<i32>) -> Result<()> {
+        self.rcall(
+            ClientCommand::WatchPlayer { user_id },
+            &self.state.cb_watch_player,
+        )
+        .await
+    }
+
+    /// Standings of a finished round (the latest if `None`) as JSON. Host only.
+    #[inline]
+    pub async fn export_results(&self, round: Option<u32>) -> Result<String> {
+        self.rcall(
+            ClientCommand::ExportResults { round },
+            &self.state.cb_export_results,
+        )
+        .await
+    }
+
+    #[inline]
+    pub async fn report_player(&self, user_id: i32, reason: String) -> Result<()> {
+        self.rcall(
+            ClientCommand::ReportPlayer {
+                user_id,
+                reason: reason.try_into()?,
+            },
+            &self.state.cb_report_player,
+        )
+        .await
+    }
+
+    /// Round-trips `payload` through the server. Unlike [`Client::ping`], the
+    /// payload size is chosen by the caller, so repeated calls can be used to
+    /// estimate throughput and jitter.
+    pub async fn echo_test(&self, payload: Vec<u8>) -> Result<Duration> {
+        let start = Instant::now();
+        let echoed = self
+            .rcall(
+                ClientCommand::EchoTest {
+                    payload: payload.clone(),
+                },
+                &self.state.cb_echo_test,
+            )
+            .await?;
+        let delay = start.elapsed();
+        if echoed != payload {
+            bail!("echoed payload mismatch");
+        }
+        Ok(delay)
+    }
+
+    /// Why the connection was closed locally (e.g. the server stopped
+    /// reading), if it was.
+    pub fn close_reason(&self) -> Option<String> {
+        self.stream().close_reason().map(str::to_owned)
+    }
+
+    /// Logs every frame and heartbeat of this connection at info level, for
+    /// debugging a session without restarting it with a different filter.
+    pub fn set_trace(&self, trace: bool) {
+        self.stream().set_trace(trace);
+    }
+
+    pub fn ping_fail_count(&self) -> u8 {
+        self.ping_fail_count.load(Ordering::Relaxed)
+    }
+
+    fn reset_ping_fail_count(&self) {
+        if self.ping_fail_count.swap(0, Ordering::SeqCst) != 0 {
+            Hooks::call("reconnected", &self.state.hooks.reconnected, ());
+        }
+    }
+
+    /// Called once heartbeats start failing, e.g. when the network dropped.
+    ///
+    /// Hooks are run on the client's own tasks, so they should return
+    /// quickly, e.g. by handing over to the game's main loop. A panicking
+    /// hook is logged and otherwise ignored.
+    pub fn on_disconnected(&self, f: impl Fn() + Send + Sync + 'static) {
+        Hooks::set(&self.state.hooks.disconnected, move |()| f());
+    }
+
+    /// Called once heartbeats succeed again after [`Self::on_disconnected`],
+    /// or the connection was resumed or migrated.
+    pub fn on_reconnected(&self, f: impl Fn() + Send + Sync + 'static) {
+        Hooks::set(&self.state.hooks.reconnected, move |()| f());
+    }
+
+    /// Called when the host kicked us out of the room, or the server operator
+    /// off the server, which closes the connection right after.
+    pub fn on_kicked(&self, f: impl Fn() + Send + Sync + 'static) {
+        Hooks::set(&self.state.hooks.kicked, move |()| f());
+    }
+
+    /// Called with whether we're the host whenever that changes.
+    pub fn on_host_changed(&self, f: impl Fn(bool) + Send + Sync + 'static) {
+        Hooks::set(&self.state.hooks.host_changed, f);
+    }
+
+    /// Called every second with the time left to get ready, while the
+    /// room's [`ReadyCheck`] runs.
+    pub fn on_start_countdown(&self, f: impl Fn(u16) + Send + Sync + 'static) {
+        Hooks::set(&self.state.hooks.start_countdown, f);
+    }
+
+    /// Called with the round and when its chart should begin, by our
+    /// clock, see [`Self::game_start`].
+    pub fn on_game_start(&self, f: impl Fn((u32, SystemTime)) + Send + Sync + 'static) {
+        Hooks::set(&self.state.hooks.game_start, f);
+    }
+
+    /// Packets waiting to be sent. A growing queue means the uplink can't
+    /// keep up, e.g. with touches.
+    pub fn send_queue_len(&self) -> usize {
+        self.stream().queue_len()
+    }
+
+    /// Makes [`Self::send`] wait until fewer than `threshold` packets are
+    /// queued, or never wait if `None`. [`Self::blocking_send`] isn't
+    /// affected.
+    pub fn set_send_threshold(&self, threshold: Option<usize>) {
+        self.state
+            .send_threshold
+            .store(threshold.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Judges and score updates are sent as [`ClientCommand::Reliable`] if
+    /// the server supports it, and again until it acknowledges them.
+    pub async fn send(&self, payload: ClientCommand) -> Result<()> {
+        self.state.check_supported(&payload)?;
+        if let Some(data) = outgoing_replay_data(&payload) {
+            let me = self.state.me.read().await.as_ref().map_or(-1, |it| it.id);
+            self.state.record(ReplayDirection::Outgoing, me, data);
+        }
+        let payload = self.reliable(payload);
+        match self.state.send_threshold.load(Ordering::Relaxed) {
+            0 => self.stream().send(payload).await,
+            threshold => self.stream().send_throttled(payload, threshold).await,
+        }
+    }
+
+    pub fn blocking_send(&self, payload: ClientCommand) -> Result<()> {
+        self.state.check_supported(&payload)?;
+        if let Some(data) = outgoing_replay_data(&payload) {
+            let me = self
+                .state
+                .me
+                .load()
+                .as_ref()
+                .as_ref()
+                .map_or(-1, |it| it.id);
+            self.state.record(ReplayDirection::Outgoing, me, data);
+        }
+        self.stream().blocking_send(self.reliable(payload))
+    }
+
+    fn reliable(&self, payload: ClientCommand) -> ClientCommand {
+        if !self.features().contains(Features::RELIABLE_REALTIME) {
+            return payload;
+        }
+        let cmd = match ReliableCommand::try_from(payload) {
+            Ok(cmd) => cmd,
+            Err(payload) => return payload,
+        };
+        let mut queue = self.state.reliable.lock().unwrap();
+        if matches!(cmd, ReliableCommand::ScoreUpdate { .. }) {
+            // Only the latest score matters
+            queue
+                .pending
+                .retain(|_, (it, _)| !matches!(it, ReliableCommand::ScoreUpdate { .. }));
+        }
+        if queue.pending.len() >= RELIABLE_PENDING_MAX {
+            warn!("giving up on an unacknowledged packet");
+            queue.pending.pop_first();
+        }
+        let seq = queue.next_seq;
+        queue.next_seq += 1;
+        queue.pending.insert(seq, (cmd.clone(), Instant::now()));
+        ClientCommand::Reliable { seq, cmd }
+    }
+
+    /// Tells the room how we're doing in the current round, for live
+    /// scoreboards. Meant to be called every frame: at most one update is
+    /// sent per [`SCORE_UPDATE_INTERVAL`], and none if the server doesn't
+    /// support it.
+    pub async fn update_score(&self, score: i32, accuracy: f32, combo: u32) -> Result<()> {
+        match self.score_update(score, accuracy, combo) {
+            Some(cmd) => self.send(cmd).await,
+            None => Ok(()),
+        }
+    }
+
+    pub fn blocking_update_score(&self, score: i32, accuracy: f32, combo: u32) -> Result<()> {
+        match self.score_update(score, accuracy, combo) {
+            Some(cmd) => self.blocking_send(cmd),
+            None => Ok(()),
+        }
+    }
+
+    fn score_update(&self, score: i32, accuracy: f32, combo: u32) -> Option<ClientCommand> {
+        if !self.features().contains(Features::LIVE_SCORES) {
+            return None;
+        }
+        let mut last = self.state.last_score_update.lock().unwrap();
+        if last.is_some_and(|it| it.elapsed() < SCORE_UPDATE_INTERVAL) {
+            return None;
+        }
+        *last = Some(Instant::now());
+        Some(ClientCommand::ScoreUpdate {
+            round: self.round(),
+            score,
+            accuracy,
+            combo,
+        })
+    }
+
+    /// Sends our touches of the current round, without their phases if the
+    /// server doesn't support them.
+    pub async fn send_touches(&self, frames: Vec<TouchFrame>) -> Result<()> {
+        self.send(self.touches_command(frames)).await
+    }
+
+    pub fn blocking_send_touches(&self, frames: Vec<TouchFrame>) -> Result<()> {
+        self.blocking_send(self.touches_command(frames))
+    }
+
+    fn touches_command(&self, frames: Vec<TouchFrame>) -> ClientCommand {
+        let round = self.round();
+        let features = self.features();
+        if features.contains(Features::TOUCH_DELTA) {
+            let compress = features.contains(Features::TOUCH_ZSTD);
+            ClientCommand::PackedTouches {
+                round,
+                frames: Arc::new(TouchBatch::new(frames, compress)),
+            }
+        } else if features.contains(Features::TOUCH_PHASES) {
+            ClientCommand::PhasedTouches {
+                round,
+                frames: Arc::new(frames),
+            }
+        } else {
+            ClientCommand::Touches {
+                round,
+                frames: Arc::new(frames.iter().map(Into::into).collect()),
+            }
+        }
+    }
+
+    /// Scores the room's players reported this round, highest first.
+    pub fn live_scores(&self) -> Vec<PlayerScore> {
+        let mut scores: Vec<_> = self
+            .state
+            .live_players
+            .iter()
+            .filter_map(|it| it.score.lock().unwrap().reported.clone())
+            .collect();
+        scores.sort_by_key(|it| std::cmp::Reverse(it.score));
+        scores
+    }
+
+    /// Starts writing realtime data sent and received by this client to a
+    /// new file in `dir`, in the format read by
+    /// [`phira_mp_common::ReplayReader`]. Returns the path of the file.
+    /// Nothing is written while the room has [`Recording::Off`].
+    pub fn start_recording(&self, dir: impl AsRef<Path>) -> Result<PathBuf> {
+        let path = dir.as_ref().join(format!(
+            "phira-mp-{}.pmr",
+            Utc::now().format("%Y%m%d-%H%M%S%.3f")
+        ));
+        let writer = ReplayWriter::new(BufWriter::new(File::create(&path)?))?;
+        *self.state.recorder.lock().unwrap() = Some(writer);
+        Ok(path)
+    }
+
+    pub fn stop_recording(&self) -> Result<()> {
+        if let Some(mut writer) = self.state.recorder.lock().unwrap().take() {
+            writer.flush()?;
+        }
+        Ok(())
     }
 
     #[inline]
     pub fn live_player(&self, player: i32) -> Arc<LivePlayer> {
         self.state.live_player(player)
     }
+
+    /// Round of the current (or last) game, which touches and judges sent
+    /// must be tagged with. Data of other rounds is dropped.
+    pub fn round(&self) -> u32 {
+        self.state.round.load(Ordering::SeqCst)
+    }
+
+    /// Waits until the game of the current round ends, returning at once if
+    /// it already has.
+    pub async fn wait_game_end(&self) -> Result<GameEndReason> {
+        let mut rx = self.state.game_end.subscribe();
+        loop {
+            if let Some((round, reason)) = *rx.borrow_and_update() {
+                if round == self.round() {
+                    return Ok(reason);
+                }
+            }
+            rx.changed().await?;
+        }
+    }
+
+    /// Events from now on, as an alternative to polling. Subscribers falling
+    /// more than a few hundred events behind miss the oldest ones, see
+    /// [`broadcast::error::RecvError::Lagged`]. Polling accessors keep
+    /// working either way.
+    pub fn subscribe(&self) -> broadcast::Receiver<ClientEvent> {
+        self.state.events.subscribe()
+    }
+
+    /// Gaps in the realtime data of `player` this round, see [`RelayStats`].
+    pub fn relay_stats(&self, player: i32) -> Option<RelayStats> {
+        self.state
+            .live_players
+            .get(&player)
+            .map(|it| it.relay_stats())
+    }
+
+    /// Players we have received realtime data of.
+    pub fn live_player_ids(&self) -> Vec<i32> {
+        self.state.live_players.iter().map(|it| *it.key()).collect()
+    }
+
+    /// Touch frames of `user_id` received since the last call, oldest first.
+    pub async fn touches_for(&self, user_id: i32) -> Vec<TouchFrame> {
+        std::mem::take(&mut *self.live_player(user_id).touch_frames.lock().await)
+    }
+
+    /// Like [`Self::touches_for`], but moved onto `space`, our own screen.
+    /// Left as they are if `user_id` didn't tell their screen.
+    pub async fn touches_in(&self, user_id: i32, space: TouchSpace) -> Vec<TouchFrame> {
+        let mut frames = self.touches_for(user_id).await;
+        if let Some(from) = self.touch_space_of(user_id) {
+            for frame in &mut frames {
+                for point in &mut frame.points {
+                    point.pos = space.map_from(&from, point.pos.clone());
+                }
+            }
+        }
+        frames
+    }
+
+    /// Tells the room what screen our touch frames are relative to, so that
+    /// they're drawn right on differently shaped ones. Kept by the server
+    /// until changed, also across reconnects.
+    #[inline]
+    pub async fn set_touch_space(&self, space: TouchSpace) -> Result<()> {
+        self.send(ClientCommand::SetTouchSpace { space }).await
+    }
+
+    /// The screen `player`'s touch frames are relative to, if they told.
+    pub fn touch_space_of(&self, player: i32) -> Option<TouchSpace> {
+        self.state
+            .touch_spaces
+            .lock()
+            .unwrap()
+            .get(&player)
+            .copied()
+    }
+
+    /// Judge events of `user_id` received since the last call, oldest first.
+    pub async fn judges_for(&self, user_id: i32) -> Vec<JudgeEvent> {
+        std::mem::take(&mut *self.live_player(user_id).judge_events.lock().await)
+    }
+
+    pub fn blocking_touches_for(&self, user_id: i32) -> Vec<TouchFrame> {
+        std::mem::take(&mut *self.live_player(user_id).touch_frames.blocking_lock())
+    }
+
+    pub fn blocking_judges_for(&self, user_id: i32) -> Vec<JudgeEvent> {
+        std::mem::take(&mut *self.live_player(user_id).judge_events.blocking_lock())
+    }
 }
 
 impl Drop for Client {
     fn drop(&mut self) {
         self.ping_task_handle.abort();
+        self.resend_task_handle.abort();
+        if let Some((_, handle)) = self.state.chart_task.lock().unwrap().take() {
+            handle.abort();
+        }
     }
 }
 
-async fn process(state: Arc<State>, cmd: ServerCommand) {
+async fn process(state: Arc<State>, send_tx: Arc<mpsc::Sender<ClientCommand>>, cmd: ServerCommand) {
+    // Touches come too often, and the ping task takes care of heartbeats
+    let refresh_overlay = !matches!(
+        cmd,
+        ServerCommand::Touches { .. }
+            | ServerCommand::PhasedTouches { .. }
+            | ServerCommand::PackedTouches { .. }
+            | ServerCommand::Ack { .. }
+            | ServerCommand::Pong
+    );
+    process_command(&state, send_tx, cmd).await;
+    if refresh_overlay {
+        state.refresh_overlay().await;
+    }
+}
+
+async fn process_command(
+    state: &Arc<State>,
+    send_tx: Arc<mpsc::Sender<ClientCommand>>,
+    cmd: ServerCommand,
+) {
     async fn cb<T>(cb: &Callback<T>, res: T) {
         let _ = cb.lock().await.take().unwrap().send(res);
     }
@@ -412,24 +2615,100 @@ async fn process(state: Arc<State>, cmd: ServerCommand) {
         ServerCommand::Pong => {
             state.ping_notify.notify_one();
         }
+        ServerCommand::Ack { seq } => {
+            state.reliable.lock().unwrap().pending.remove(&seq);
+        }
         ServerCommand::Authenticate(res) => {
             cb(&state.cb_authenticate, res).await;
         }
+        ServerCommand::Limits(limits) => {
+            *state.limits.lock().unwrap() = Some(limits);
+        }
         ServerCommand::Chat(res) => {
             cb(&state.cb_chat, res).await;
         }
-        ServerCommand::Touches { player, frames } => {
-            state
-                .live_player(player)
-                .touch_frames
-                .lock()
-                .await
-                .extend(frames.iter().cloned());
+        ServerCommand::Touches {
+            player,
+            round,
+            seq,
+            frames,
+        } => {
+            let frames = Arc::new(frames.iter().map(Into::into).collect());
+            on_touches(state, player, round, seq, frames).await;
+        }
+        ServerCommand::PhasedTouches {
+            player,
+            round,
+            seq,
+            frames,
+        } => {
+            on_touches(state, player, round, seq, frames).await;
+        }
+        ServerCommand::PackedTouches {
+            player,
+            round,
+            seq,
+            frames,
+        } => {
+            let frames = Arc::try_unwrap(frames).unwrap_or_else(|it| (*it).clone());
+            on_touches(state, player, round, seq, Arc::new(frames.into_inner())).await;
+        }
+        ServerCommand::MessageHistory(history) => {
+            state.messages.lock().await.extend(history);
         }
-        ServerCommand::Judges { player, judges } => {
-            state
-                .live_player(player)
-                .judge_events
+        ServerCommand::Scores { round, scores } => {
+            if round != state.round.load(Ordering::SeqCst) {
+                trace!("dropped scores of stale round {round}");
+                return;
+            }
+            for score in scores {
+                let live = state.live_player(score.user);
+                live.score.lock().unwrap().reported = Some(score);
+            }
+        }
+        ServerCommand::Judges {
+            player,
+            round,
+            seq,
+            judges,
+        } => {
+            if round != state.round.load(Ordering::SeqCst) {
+                trace!("dropped judge events of stale round {round}");
+                return;
+            }
+            let live = state.live_player(player);
+            let Some(mut gap) = live.relay_stats.lock().unwrap().on_judges(seq) else {
+                trace!("dropped duplicate judge events {seq} of {player}");
+                return;
+            };
+            if !gap.is_empty() {
+                // Older batches are the likeliest to be gone from the server
+                let seqs = gap.split_off(gap.len().saturating_sub(RESEND_JUDGES_MAX));
+                warn!("missing {} judge batches of {player}", seqs.len());
+                let _ = send_tx.try_send(ClientCommand::ResendJudges {
+                    player,
+                    round,
+                    seqs,
+                });
+            }
+            state.record(
+                ReplayDirection::Incoming,
+                player,
+                ReplayData::Judges {
+                    judges: Arc::clone(&judges),
+                },
+            );
+            state.emit(|| ClientEvent::Judges {
+                player,
+                judges: Arc::clone(&judges),
+            });
+            {
+                let mut score = live.score.lock().unwrap();
+                for judge in judges.iter() {
+                    score.on_judge(judge.judgement);
+                }
+            }
+            live.judge_events
                 .lock()
                 .await
                 .extend(judges.iter().cloned());
@@ -439,25 +2718,197 @@ async fn process(state: Arc<State>, cmd: ServerCommand) {
                 Message::LockRoom { lock } => {
                     state.room.write().await.as_mut().unwrap().locked = lock;
                 }
+                Message::StartPlaying { round } => {
+                    if state
+                        .protocol
+                        .lock()
+                        .unwrap()
+                        .1
+                        .contains(Features::START_ACK)
+                    {
+                        let _ = send_tx.send(ClientCommand::AckStart { round }).await;
+                    }
+                    state.round.store(round, Ordering::SeqCst);
+                    state.live_players.clear();
+                    state.set_phase(None, MemberPhase::Playing).await;
+                }
+                Message::GameStart { user } => {
+                    state.set_phase(None, MemberPhase::Idle).await;
+                    state.set_phase(Some(user), MemberPhase::Ready).await;
+                }
+                Message::Ready { user } => {
+                    state.set_phase(Some(user), MemberPhase::Ready).await;
+                }
+                Message::CancelReady { user } => {
+                    state.set_phase(Some(user), MemberPhase::Idle).await;
+                }
+                Message::Played {
+                    user,
+                    score,
+                    accuracy,
+                    full_combo,
+                } => {
+                    state.live_player(user).score.lock().unwrap().result =
+                        Some((score, accuracy, full_combo));
+                    state.set_phase(Some(user), MemberPhase::Finished).await;
+                }
+                Message::Abort { user } => {
+                    state.set_phase(Some(user), MemberPhase::Aborted).await;
+                }
+                Message::GameEnd {
+                    round,
+                    reason,
+                    ref results,
+                } => {
+                    if let Some(room) = state.room.write().await.as_mut() {
+                        room.last_results = results.clone();
+                    }
+                    state.game_end.send_replace(Some((round, reason)));
+                    state.emit(|| ClientEvent::GameEnd { round, reason });
+                }
                 Message::CycleRoom { cycle } => {
-                    state.room.write().await.as_mut().unwrap().cycle = cycle;
+                    let mut guard = state.room.write().await;
+                    let room = guard.as_mut().unwrap();
+                    room.cycle = cycle;
+                    room.mode = match (cycle, room.mode) {
+                        (true, _) => RoomMode::Relay,
+                        (false, RoomMode::Relay) => RoomMode::Versus,
+                        (false, mode) => mode,
+                    };
+                }
+                Message::Recording { recording } => {
+                    if let Some(room) = state.room.write().await.as_mut() {
+                        room.recording = recording;
+                        state.set_recording(recording);
+                    }
                 }
                 Message::LeaveRoom { user, .. } => {
-                    state
-                        .room
-                        .write()
-                        .await
-                        .as_mut()
-                        .unwrap()
-                        .users
-                        .remove(&user);
+                    let me = state.me.read().await.as_ref().map(|it| it.id);
+                    let mut guard = state.room.write().await;
+                    if me == Some(user) {
+                        // Removed by the server, e.g. for inactivity
+                        *guard = None;
+                        state.expiry.store(None);
+                        state.members.lock().unwrap().clear();
+                    } else if let Some(room) = guard.as_mut() {
+                        room.users.remove(&user);
+                        for queued in &mut room.queue {
+                            queued.votes.retain(|it| *it != user);
+                        }
+                        state.members.lock().unwrap().remove(&user);
+                    }
+                }
+                Message::MembersChurn { ref left, .. } => {
+                    if let Some(room) = state.room.write().await.as_mut() {
+                        let mut members = state.members.lock().unwrap();
+                        for &user in left {
+                            room.users.remove(&user);
+                            for queued in &mut room.queue {
+                                queued.votes.retain(|it| *it != user);
+                            }
+                            members.remove(&user);
+                        }
+                    }
+                }
+                Message::SelectChart { id, .. } => {
+                    if let Some(room) = state.room.write().await.as_mut() {
+                        room.queue.retain(|it| it.chart != id);
+                    }
+                }
+                Message::ChartQueued { ref chart } => {
+                    if let Some(room) = state.room.write().await.as_mut() {
+                        room.queue.push(chart.clone());
+                    }
+                }
+                Message::ChartUnqueued { chart, .. } => {
+                    if let Some(room) = state.room.write().await.as_mut() {
+                        room.queue.retain(|it| it.chart != chart);
+                    }
+                }
+                Message::ReadyCheck { check } => {
+                    if let Some(room) = state.room.write().await.as_mut() {
+                        room.ready_check = check;
+                    }
+                }
+                Message::ChartVoted { user, chart } => {
+                    if let Some(room) = state.room.write().await.as_mut() {
+                        for queued in &mut room.queue {
+                            queued.votes.retain(|it| *it != user);
+                            if Some(queued.chart) == chart {
+                                queued.votes.push(user);
+                            }
+                        }
+                    }
+                }
+                Message::Duel { ref series } => {
+                    if let Some(room) = state.room.write().await.as_mut() {
+                        room.duel = series.clone();
+                    }
+                }
+                Message::DuelWon { .. } => {
+                    if let Some(room) = state.room.write().await.as_mut() {
+                        room.duel = None;
+                    }
+                }
+                Message::DraftStarted { ref draft } => {
+                    if let Some(room) = state.room.write().await.as_mut() {
+                        room.draft = Some(draft.clone());
+                    }
+                }
+                Message::DraftTurn {
+                    action,
+                    chart,
+                    deadline,
+                    ..
+                } => {
+                    if let Some(room) = state.room.write().await.as_mut() {
+                        match (action, deadline, room.draft.as_mut()) {
+                            (DraftAction::Ban, Some(deadline), Some(draft)) => {
+                                draft.banned.push(chart);
+                                draft.deadline = deadline;
+                            }
+                            _ => room.draft = None,
+                        }
+                    }
+                }
+                Message::DraftCancelled => {
+                    if let Some(room) = state.room.write().await.as_mut() {
+                        room.draft = None;
+                    }
+                }
+                Message::MaxPlayers { max_players } => {
+                    if let Some(room) = state.room.write().await.as_mut() {
+                        room.max_players = max_players;
+                    }
+                }
+                Message::RepeatPolicy { policy } => {
+                    if let Some(room) = state.room.write().await.as_mut() {
+                        room.repeat_policy = policy;
+                    }
+                }
+                Message::RoomMode { mode } => {
+                    if let Some(room) = state.room.write().await.as_mut() {
+                        room.mode = mode;
+                        room.cycle = mode == RoomMode::Relay;
+                    }
                 }
                 _ => {}
             }
+            state.emit(|| ClientEvent::Message(msg.clone()));
             state.messages.lock().await.push(msg);
         }
         ServerCommand::ChangeState(room) => {
             state.live_players.clear();
+            if !matches!(room, RoomState::WaitingForReady) {
+                *state.start_countdown.lock().unwrap() = None;
+            }
+            if let RoomState::SelectChart(_) = room {
+                state.set_phase(None, MemberPhase::Idle).await;
+            }
+            if let RoomState::SelectChart(Some(id)) = room {
+                prepare_chart(state, send_tx, id);
+            }
+            state.emit(|| ClientEvent::RoomStateChanged(room));
             let mut guard = state.room.write().await;
             let state = guard.as_mut().unwrap();
             state.state = room;
@@ -465,6 +2916,51 @@ async fn process(state: Arc<State>, cmd: ServerCommand) {
         }
         ServerCommand::ChangeHost(me_is_host) => {
             state.room.write().await.as_mut().unwrap().is_host = me_is_host;
+            state.emit(|| ClientEvent::HostChanged(me_is_host));
+            Hooks::call("host_changed", &state.hooks.host_changed, me_is_host);
+        }
+        ServerCommand::Kicked { room, .. } => {
+            {
+                let mut guard = state.room.write().await;
+                if room.is_none() || guard.as_ref().map(|it| &it.id) == room.as_ref() {
+                    *guard = None;
+                    state.members.lock().unwrap().clear();
+                }
+            }
+            Hooks::call("kicked", &state.hooks.kicked, ());
+        }
+        ServerCommand::KickPlayer(res) => {
+            cb(&state.cb_kick_player, res).await;
+        }
+        ServerCommand::BanPlayer(res) => {
+            cb(&state.cb_ban_player, res).await;
+        }
+        ServerCommand::MutePlayer(res) => {
+            cb(&state.cb_mute_player, res).await;
+        }
+        ServerCommand::TimeoutPlayer(res) => {
+            cb(&state.cb_timeout_player, res).await;
+        }
+        ServerCommand::TransferHost(res) => {
+            cb(&state.cb_transfer_host, res).await;
+        }
+        ServerCommand::Hello(res) => {
+            cb(&state.cb_hello, res).await;
+        }
+        ServerCommand::Routing { token } => {
+            *state.route.lock().unwrap() = Some(token);
+        }
+        ServerCommand::TouchSpace { player, space } => {
+            state.touch_spaces.lock().unwrap().insert(player, space);
+        }
+        ServerCommand::ResumeToken(token) => {
+            *state.resume_token.lock().unwrap() = Some(token);
+        }
+        ServerCommand::Members(members) => {
+            *state.members.lock().unwrap() = members.into_iter().map(|it| (it.user, it)).collect();
+        }
+        ServerCommand::MemberUpdate(status) => {
+            state.members.lock().unwrap().insert(status.user, status);
         }
 
         ServerCommand::CreateRoom(res) => {
@@ -491,6 +2987,9 @@ async fn process(state: Arc<State>, cmd: ServerCommand) {
         ServerCommand::SelectChart(res) => {
             cb(&state.cb_select_chart, res).await;
         }
+        ServerCommand::SelectRandomChart(res) => {
+            cb(&state.cb_select_random_chart, res).await;
+        }
         ServerCommand::RequestStart(res) => {
             cb(&state.cb_request_start, res).await;
         }
@@ -506,5 +3005,320 @@ async fn process(state: Arc<State>, cmd: ServerCommand) {
         ServerCommand::Abort(res) => {
             cb(&state.cb_abort, res).await;
         }
+
+        ServerCommand::Region(_) => {}
+        ServerCommand::EchoTest(res) => {
+            cb(&state.cb_echo_test, res).await;
+        }
+        ServerCommand::WatchPlayer(res) => {
+            cb(&state.cb_watch_player, res).await;
+        }
+        ServerCommand::ExportResults(res) => {
+            cb(&state.cb_export_results, res).await;
+        }
+
+        ServerCommand::Achievement { user, achievement } => {
+            state.achievements.lock().await.push((user, achievement));
+        }
+        ServerCommand::StartCountdown { secs } => {
+            *state.start_countdown.lock().unwrap() = Some(secs);
+            Hooks::call("start_countdown", &state.hooks.start_countdown, secs);
+        }
+        ServerCommand::SetReadyCheck(res) => {
+            cb(&state.cb_set_ready_check, res).await;
+        }
+        ServerCommand::SyncClock {
+            client_time,
+            server_time,
+        } => {
+            cb(&state.cb_sync_clock, Ok((client_time, server_time))).await;
+        }
+        ServerCommand::GameStart { round, start_at } => {
+            let offset = *state.clock_offset.lock().unwrap();
+            if let Some(offset) = offset {
+                let local = (start_at as i64 - offset).max(0) as u64;
+                let at = UNIX_EPOCH + Duration::from_millis(local);
+                *state.game_start.lock().unwrap() = Some((round, at));
+                Hooks::call("game_start", &state.hooks.game_start, (round, at));
+            } else {
+                warn!("start of round {round} scheduled before our clock was synchronized");
+            }
+        }
+        ServerCommand::UnknownCommand { tag } => {
+            state.unknown_commands.lock().unwrap().push(tag);
+        }
+
+        ServerCommand::SlowMode(res) => {
+            cb(&state.cb_slow_mode, res).await;
+        }
+        ServerCommand::ReportPlayer(res) => {
+            cb(&state.cb_report_player, res).await;
+        }
+        ServerCommand::SyncState(res) => {
+            if let Ok(resp) = &res {
+                if let Some(RoundPhase::Playing { round, .. }) = resp.as_ref().map(|it| &it.phase) {
+                    state.round.store(*round, Ordering::SeqCst);
+                }
+                state.set_recording(
+                    resp.as_ref()
+                        .map(|it| it.room.recording)
+                        .unwrap_or_default(),
+                );
+                *state.room.write().await = resp.as_ref().map(|it| it.room.clone());
+            }
+            // Not requested through `Client::sync_state` if sent by the ping task
+            if let Some(tx) = state.cb_sync_state.lock().await.take() {
+                let _ = tx.send(res);
+            }
+        }
+        ServerCommand::ChatAck { id, result } => {
+            let status = match result {
+                Ok(()) => ChatStatus::Delivered,
+                Err(err) => ChatStatus::Failed(err.to_string()),
+            };
+            set_chat_status(state, id, status).await;
+        }
+        ServerCommand::ForceCancelStart(res) => {
+            cb(&state.cb_force_cancel_start, res).await;
+        }
+        ServerCommand::SetRecording(res) => {
+            cb(&state.cb_set_recording, res).await;
+        }
+        ServerCommand::SetDuel(res) => {
+            cb(&state.cb_set_duel, res).await;
+        }
+        ServerCommand::StartDraft(res) => {
+            cb(&state.cb_start_draft, res).await;
+        }
+        ServerCommand::CancelDraft(res) => {
+            cb(&state.cb_cancel_draft, res).await;
+        }
+        ServerCommand::DraftTurn(res) => {
+            cb(&state.cb_draft_turn, res).await;
+        }
+        ServerCommand::SetMaxPlayers(res) => {
+            cb(&state.cb_set_max_players, res).await;
+        }
+        ServerCommand::FetchRoomHistory(res) => {
+            cb(&state.cb_fetch_room_history, res).await;
+        }
+        ServerCommand::SetRepeatPolicy(res) => {
+            cb(&state.cb_set_repeat_policy, res).await;
+        }
+        ServerCommand::SetRoomMode(res) => {
+            cb(&state.cb_set_room_mode, res).await;
+        }
+        ServerCommand::QueueChart(res) => {
+            cb(&state.cb_queue_chart, res).await;
+        }
+        ServerCommand::UnqueueChart(res) => {
+            cb(&state.cb_unqueue_chart, res).await;
+        }
+        ServerCommand::VoteChart(res) => {
+            cb(&state.cb_vote_chart, res).await;
+        }
+        ServerCommand::EndRound(res) => {
+            cb(&state.cb_end_round, res).await;
+        }
+        ServerCommand::SplitRoom(res) => {
+            cb(&state.cb_split_room, res).await;
+        }
+        ServerCommand::MergeRoom(res) => {
+            cb(&state.cb_merge_room, res).await;
+        }
+        ServerCommand::RoomList(res) => {
+            cb(&state.cb_list_rooms, res).await;
+        }
+        ServerCommand::UserRenamed { user, name } => {
+            if let Some(me) = state.me.write().await.as_mut().filter(|it| it.id == user) {
+                me.name = name.clone();
+            }
+            if let Some(info) = state
+                .room
+                .write()
+                .await
+                .as_mut()
+                .and_then(|it| it.users.get_mut(&user))
+            {
+                info.name = name;
+            }
+        }
+        ServerCommand::MovedRoom(room) => {
+            state.set_recording(room.recording);
+            state.live_players.clear();
+            if let RoomState::SelectChart(Some(id)) = room.state {
+                prepare_chart(state, send_tx, id);
+            }
+            state.expiry.store(None);
+            *state.room.write().await = Some(room);
+        }
+        ServerCommand::BridgeSpectatorChat(res) => {
+            cb(&state.cb_bridge_spectator_chat, res).await;
+        }
+        ServerCommand::RoomExpiry { at } => {
+            state.expiry.store(at.map(Arc::new));
+        }
+        ServerCommand::Prefetch { chart_ids } => {
+            let mut prefetch = state.prefetch.lock().await;
+            prefetch.retain(|it| !chart_ids.contains(it));
+            prefetch.extend(chart_ids);
+        }
+        ServerCommand::ChartProgress { user, id, progress } => {
+            if state.chart_task.lock().unwrap().as_ref().map(|it| it.0) == Some(id) {
+                state.chart_progress.insert(user, progress);
+            }
+        }
+        ServerCommand::ChartReady { user, id } => {
+            if state.chart_task.lock().unwrap().as_ref().map(|it| it.0) == Some(id) {
+                state.chart_progress.insert(user, 1.);
+            }
+        }
+    }
+}
+
+/// Status of a member the server hasn't told about (yet).
+fn new_member_status(user: i32) -> MemberStatus {
+    MemberStatus {
+        user,
+        phase: MemberPhase::Idle,
+        connected: true,
+        latency_ms: None,
+    }
+}
+
+/// Gets a newly selected chart ready through the registered provider,
+/// reporting progress to the room.
+fn prepare_chart(state: &State, send_tx: Arc<mpsc::Sender<ClientCommand>>, id: i32) {
+    let Some(provider) = state.chart_provider.lock().unwrap().clone() else {
+        return;
+    };
+    let mut task = state.chart_task.lock().unwrap();
+    if task.as_ref().map(|it| it.0) == Some(id) {
+        return;
+    }
+    if let Some((_, handle)) = task.take() {
+        handle.abort();
     }
+    state.chart_progress.clear();
+    let handle = tokio::spawn(async move {
+        if !provider.is_available(id) {
+            let last = StdMutex::new(0.);
+            let progress = {
+                let send_tx = Arc::clone(&send_tx);
+                Box::new(move |progress: f32| {
+                    let mut last = last.lock().unwrap();
+                    if progress - *last >= CHART_PROGRESS_STEP {
+                        *last = progress;
+                        // Progress is only informative, skip it if the queue is full
+                        let _ = send_tx.try_send(ClientCommand::ChartProgress { id, progress });
+                    }
+                })
+            };
+            if let Err(err) = provider.download(id, progress).await {
+                error!("failed to download chart {id}: {err:?}");
+                return;
+            }
+        }
+        let _ = send_tx.send(ClientCommand::ChartReady { id }).await;
+    });
+    *task = Some((id, handle));
+}
+
+async fn set_chat_status(state: &State, id: Uuid, status: ChatStatus) {
+    if let Some(chat) = state
+        .outgoing_chats
+        .lock()
+        .await
+        .iter_mut()
+        .find(|it| it.id == id)
+    {
+        chat.status = status;
+    }
+}
+
+async fn on_touches(
+    state: &State,
+    player: i32,
+    round: u32,
+    seq: u32,
+    frames: Arc<Vec<TouchFrame>>,
+) {
+    if round != state.round.load(Ordering::SeqCst) {
+        trace!("dropped touch events of stale round {round}");
+        return;
+    }
+    let live = state.live_player(player);
+    live.relay_stats.lock().unwrap().on_touches(seq);
+    state.record(
+        ReplayDirection::Incoming,
+        player,
+        ReplayData::Touches {
+            frames: Arc::clone(&frames),
+        },
+    );
+    state.emit(|| ClientEvent::Touches {
+        player,
+        frames: Arc::clone(&frames),
+    });
+    live.touch_frames
+        .lock()
+        .await
+        .extend(frames.iter().cloned());
+}
+
+fn outgoing_replay_data(payload: &ClientCommand) -> Option<ReplayData> {
+    match payload {
+        ClientCommand::Touches { frames, .. } => Some(ReplayData::Touches {
+            frames: Arc::new(frames.iter().map(Into::into).collect()),
+        }),
+        ClientCommand::PhasedTouches { frames, .. } => Some(ReplayData::Touches {
+            frames: Arc::clone(frames),
+        }),
+        ClientCommand::PackedTouches { frames, .. } => Some(ReplayData::Touches {
+            frames: Arc::new(frames.to_vec()),
+        }),
+        ClientCommand::Judges { judges, .. } => Some(ReplayData::Judges {
+            judges: Arc::clone(judges),
+        }),
+        _ => None,
+    }
+}
+
+async fn probe(addr: SocketAddr) -> Result<ServerLatency> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+    let stream = Stream::<ClientCommand, ServerCommand>::new(
+        Some(1),
+        stream,
+        Box::new(move |_send_tx, cmd| {
+            let _ = tx.send(cmd);
+            std::future::ready(())
+        }),
+    )
+    .await?;
+
+    let start = Instant::now();
+    stream.send(ClientCommand::Ping).await?;
+    while !matches!(
+        rx.recv().await.context("connection closed")?,
+        ServerCommand::Pong
+    ) {}
+    let delay = start.elapsed();
+
+    // Older servers don't know about regions and close the connection instead
+    stream.send(ClientCommand::Region).await?;
+    let region = loop {
+        match rx.recv().await {
+            Some(ServerCommand::Region(region)) => break region,
+            Some(_) => {}
+            None => break None,
+        }
+    };
+
+    Ok(ServerLatency {
+        addr,
+        delay,
+        region,
+    })
 }
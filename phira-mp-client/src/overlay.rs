@@ -0,0 +1,130 @@
+use phira_mp_common::{Judgement, MemberPhase, PlayerScore, RoomState};
+use std::{cmp::Ordering, time::Duration};
+
+/// Round trip above which the connection is no longer [`ConnectionQuality::Good`].
+const FAIR_LATENCY: Duration = Duration::from_millis(100);
+/// Round trip above which the connection is [`ConnectionQuality::Poor`].
+const POOR_LATENCY: Duration = Duration::from_millis(250);
+
+/// How a player is doing this round, tallied from the judges relayed to us.
+#[derive(Debug, Clone, Default)]
+pub struct LiveScore {
+    /// Notes judged so far
+    pub judged: u32,
+    pub combo: u32,
+    pub max_combo: u32,
+    /// Perfect notes count fully and good ones 65%, as in results
+    points: f32,
+    /// As last reported by the player themselves, see
+    /// [`Client::update_score`](crate::Client::update_score)
+    pub reported: Option<PlayerScore>,
+    /// Score, accuracy and full combo, once they finished
+    pub result: Option<(i32, f32, bool)>,
+}
+
+impl LiveScore {
+    /// The final score once they finished, or the one they reported last.
+    pub fn score(&self) -> Option<i32> {
+        self.result
+            .map(|it| it.0)
+            .or_else(|| self.reported.as_ref().map(|it| it.score))
+    }
+
+    /// Between 0 and 1: the final accuracy once they finished, or the one
+    /// they reported last, or else tallied from judges.
+    pub fn accuracy(&self) -> f32 {
+        if let Some((_, accuracy, _)) = self.result {
+            return accuracy;
+        }
+        if let Some(reported) = &self.reported {
+            return reported.accuracy;
+        }
+        if self.judged == 0 {
+            1.
+        } else {
+            self.points / self.judged as f32
+        }
+    }
+
+    pub(crate) fn on_judge(&mut self, judgement: Judgement) {
+        self.judged += 1;
+        match judgement {
+            Judgement::Perfect | Judgement::HoldPerfect => self.points += 1.,
+            Judgement::Good | Judgement::HoldGood => self.points += 0.65,
+            Judgement::Bad | Judgement::Miss => {
+                self.combo = 0;
+                return;
+            }
+        }
+        self.combo += 1;
+        self.max_combo = self.max_combo.max(self.combo);
+    }
+}
+
+/// Judged from heartbeats, see [`Client::ping_fail_count`](crate::Client::ping_fail_count).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionQuality {
+    /// No heartbeat got through yet
+    #[default]
+    Unknown,
+    Good,
+    Fair,
+    Poor,
+    /// The last heartbeat failed
+    Lost,
+}
+
+impl ConnectionQuality {
+    pub(crate) fn from_heartbeat(delay: Duration, failed: bool) -> Self {
+        if failed {
+            Self::Lost
+        } else if delay > POOR_LATENCY {
+            Self::Poor
+        } else if delay > FAIR_LATENCY {
+            Self::Fair
+        } else {
+            Self::Good
+        }
+    }
+}
+
+/// A player in [`OverlayState::players`].
+#[derive(Debug, Clone)]
+pub struct OverlayPlayer {
+    pub id: i32,
+    pub name: String,
+    pub phase: MemberPhase,
+    /// `false` while the server waits for them to reconnect
+    pub connected: bool,
+    pub score: LiveScore,
+}
+
+/// Everything an in-game HUD shows, see [`Client::overlay_state`](crate::Client::overlay_state).
+#[derive(Debug, Clone, Default)]
+pub struct OverlayState {
+    /// `None` outside of rooms
+    pub room_state: Option<RoomState>,
+    /// See [`Client::round`](crate::Client::round)
+    pub round: u32,
+    /// Players of the room, leading ones first
+    pub players: Vec<OverlayPlayer>,
+    /// Our round trip to the server
+    pub latency: Option<Duration>,
+    pub connection: ConnectionQuality,
+}
+
+impl OverlayState {
+    /// Scores players reported or finished with come first. Otherwise,
+    /// since everyone plays the same chart, whoever has the most points
+    /// tallied from judges so far leads.
+    pub(crate) fn rank(players: &mut [OverlayPlayer]) {
+        players.sort_by(|a, b| {
+            b.score.score().cmp(&a.score.score()).then_with(|| {
+                b.score
+                    .points
+                    .partial_cmp(&a.score.points)
+                    .unwrap_or(Ordering::Equal)
+            })
+        });
+    }
+}
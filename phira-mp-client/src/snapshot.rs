@@ -0,0 +1,59 @@
+use arc_swap::{ArcSwap, Guard};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// A value changed by the network tasks and read by the game every frame.
+///
+/// Changes go through an async lock as usual. Once a write guard is dropped,
+/// a copy of the value is published for [`Self::load`], which never blocks.
+#[derive(Default)]
+pub(crate) struct Snapshot<T> {
+    value: RwLock<T>,
+    published: ArcSwap<T>,
+}
+
+impl<T: Clone> Snapshot<T> {
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.value.read().await
+    }
+
+    pub async fn write(&self) -> SnapshotWriteGuard<'_, T> {
+        SnapshotWriteGuard {
+            guard: self.value.write().await,
+            published: &self.published,
+        }
+    }
+
+    /// The value as of the last write.
+    pub fn load(&self) -> Guard<Arc<T>> {
+        self.published.load()
+    }
+}
+
+pub(crate) struct SnapshotWriteGuard<'a, T: Clone> {
+    guard: RwLockWriteGuard<'a, T>,
+    published: &'a ArcSwap<T>,
+}
+
+impl<T: Clone> Deref for SnapshotWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: Clone> DerefMut for SnapshotWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: Clone> Drop for SnapshotWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.published.store(Arc::new(self.guard.clone()));
+    }
+}
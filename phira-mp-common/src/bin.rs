@@ -9,6 +9,12 @@ use uuid::Uuid;
 pub trait BinaryData: Sized {
     fn read_binary(r: &mut BinaryReader<'_>) -> Result<Self>;
     fn write_binary(&self, w: &mut BinaryWriter<'_>) -> Result<()>;
+
+    /// Whether `tag`, the first byte of an encoded enum, names one of its
+    /// variants. Always `true` for anything else.
+    fn knows_tag(_tag: u8) -> bool {
+        true
+    }
 }
 
 pub struct BinaryReader<'a>(&'a [u8], usize);
@@ -80,6 +86,11 @@ impl<'a> BinaryWriter<'a> {
         v.write_binary(self)
     }
 
+    /// Appends `data` as is, e.g. something already encoded.
+    pub fn extend(&mut self, data: &[u8]) {
+        self.0.extend_from_slice(data);
+    }
+
     pub fn uleb(&mut self, mut v: u64) -> Result<()> {
         loop {
             let mut byte = (v & 0x7f) as u8;
@@ -1,12 +1,182 @@
-use crate::{BinaryData, BinaryReader, BinaryWriter};
+use crate::{BinaryData, BinaryReader, BinaryWriter, Packet};
 use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
 use half::f16;
 use phira_mp_macros::BinaryData;
-use std::{collections::HashMap, fmt::Display, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+    time::Duration,
+};
+use uuid::Uuid;
 
-type SResult<T> = Result<T, String>;
+type SResult<T> = Result<T, ServerError>;
+
+/// Protocol version spoken by this build, see [`ClientCommand::Hello`].
+///
+/// - 2: differs from version 1 in the layout of:
+///   - [`ClientCommand::CreateRoom`], which ends with `max_players`
+///   - [`ClientRoomState`] and [`JoinRoomResponse`], which end with `duel`,
+///     `draft`, `max_players`, `last_results`, `repeat_policy`, `mode`, `queue`
+///     and `ready_check`, in this order
+///   - [`Message::GameEnd`], which ends with `results`
+///   - [`ServerLimits`], which ends with `bot_name`
+///   - Every error: [`ServerError`] replaces the plain strings and, for
+///     [`ServerCommand::JoinRoom`], `JoinRoomError`
+///   - [`ServerCommand`]: `RateLimited` is gone, so every command from
+///     [`ServerCommand::ReportPlayer`] on has a tag one lower. Version 1
+///     peers misread even the [`ServerCommand::Hello`] turning them away
+pub const PROTOCOL_VERSION: u16 = 2;
+/// Oldest protocol version this build still talks to. Version 1 lays out
+/// rooms and several commands differently (see [`PROTOCOL_VERSION`]), which
+/// this build can't read or write.
+pub const MIN_PROTOCOL_VERSION: u16 = 2;
+
+/// Optional parts of the protocol a peer supports, exchanged in
+/// [`ClientCommand::Hello`]. Only those both sides support are used on a
+/// connection.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Features(u32);
+
+impl Features {
+    pub const EMPTY: Self = Self(0);
+    /// Acknowledges round starts with [`ClientCommand::AckStart`]
+    pub const START_ACK: Self = Self(1 << 0);
+    /// Understands [`ServerCommand::Members`] and [`ServerCommand::MemberUpdate`]
+    pub const MEMBER_STATUS: Self = Self(1 << 1);
+    /// Understands [`ServerCommand::Scores`]
+    pub const LIVE_SCORES: Self = Self(1 << 2);
+    /// Understands [`ServerCommand::MessageHistory`]
+    pub const MESSAGE_HISTORY: Self = Self(1 << 3);
+    /// Understands [`ServerCommand::Routing`]
+    pub const ROUTING: Self = Self(1 << 4);
+    /// Understands [`ServerCommand::ResumeToken`]
+    pub const RESUME: Self = Self(1 << 5);
+    /// Understands [`ServerCommand::TouchSpace`]
+    pub const TOUCH_SPACE: Self = Self(1 << 6);
+    /// Understands [`ServerCommand::PhasedTouches`]
+    pub const TOUCH_PHASES: Self = Self(1 << 7);
+    /// Understands [`ClientCommand::Reliable`]
+    pub const RELIABLE_REALTIME: Self = Self(1 << 8);
+    /// Handles [`ClientCommand::SetRoomMode`]
+    pub const ROOM_MODES: Self = Self(1 << 9);
+    /// Handles [`ClientCommand::QueueChart`] and the commands around it
+    pub const CHART_QUEUE: Self = Self(1 << 10);
+    /// Handles [`ClientCommand::SetReadyCheck`]
+    pub const READY_CHECK: Self = Self(1 << 11);
+    /// Handles [`ClientCommand::SyncClock`] and understands
+    /// [`ServerCommand::GameStart`]
+    pub const CLOCK_SYNC: Self = Self(1 << 12);
+    /// Understands [`Message::MembersChurn`]
+    pub const BATCHED_CHURN: Self = Self(1 << 13);
+    /// Handles [`ClientCommand::PackedTouches`] and understands
+    /// [`ServerCommand::PackedTouches`]
+    pub const TOUCH_DELTA: Self = Self(1 << 14);
+    /// Reads [`TouchBatch`]es compressed with zstd. Only in builds with the
+    /// `zstd` feature.
+    pub const TOUCH_ZSTD: Self = Self(1 << 15);
+    /// Handles [`ClientCommand::SetDuel`]
+    pub const DUELS: Self = Self(1 << 16);
+    /// Handles [`ClientCommand::StartDraft`] and the commands around it
+    pub const DRAFTS: Self = Self(1 << 17);
+    /// Handles [`ClientCommand::SetMaxPlayers`]
+    pub const MAX_PLAYERS: Self = Self(1 << 18);
+    /// Handles [`ClientCommand::MutePlayer`] and [`ClientCommand::TimeoutPlayer`]
+    pub const MODERATION: Self = Self(1 << 19);
+    /// Handles [`ClientCommand::Whisper`]
+    pub const WHISPER: Self = Self(1 << 20);
+    /// Handles [`ClientCommand::FetchRoomHistory`]
+    pub const ROOM_HISTORY: Self = Self(1 << 21);
+    /// Handles [`ClientCommand::SetRepeatPolicy`]
+    pub const REPEAT_POLICY: Self = Self(1 << 22);
+    /// Handles [`ClientCommand::SelectRandomChart`]
+    pub const RANDOM_CHART: Self = Self(1 << 23);
+    /// Understands [`Message::Bot`]
+    pub const BOT_MESSAGES: Self = Self(1 << 24);
+
+    /// Everything this build knows of.
+    pub const ALL: Self = Self(
+        Self::START_ACK.0
+            | Self::MEMBER_STATUS.0
+            | Self::LIVE_SCORES.0
+            | Self::MESSAGE_HISTORY.0
+            | Self::ROUTING.0
+            | Self::RESUME.0
+            | Self::TOUCH_SPACE.0
+            | Self::TOUCH_PHASES.0
+            | Self::RELIABLE_REALTIME.0
+            | Self::ROOM_MODES.0
+            | Self::CHART_QUEUE.0
+            | Self::READY_CHECK.0
+            | Self::CLOCK_SYNC.0
+            | Self::BATCHED_CHURN.0
+            | Self::TOUCH_DELTA.0
+            | Self::DUELS.0
+            | Self::DRAFTS.0
+            | Self::MAX_PLAYERS.0
+            | Self::MODERATION.0
+            | Self::WHISPER.0
+            | Self::ROOM_HISTORY.0
+            | Self::REPEAT_POLICY.0
+            | Self::RANDOM_CHART.0
+            | Self::BOT_MESSAGES.0
+            | if cfg!(feature = "zstd") {
+                Self::TOUCH_ZSTD.0
+            } else {
+                0
+            },
+    );
+
+    #[inline]
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    #[inline]
+    pub fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    /// Those of `self` that `other` lacks.
+    #[inline]
+    pub fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    #[inline]
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+impl std::ops::BitOr for Features {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+// Unknown bits are kept, so that newer peers' features pass through
+impl BinaryData for Features {
+    fn read_binary(r: &mut BinaryReader<'_>) -> Result<Self> {
+        Ok(Self(r.read()?))
+    }
+
+    fn write_binary(&self, w: &mut BinaryWriter<'_>) -> Result<()> {
+        w.write_val(self.0)
+    }
+}
 
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "(f32, f32)", into = "(f32, f32)")
+)]
 pub struct CompactPos {
     pub(crate) x: f16,
     pub(crate) y: f16,
@@ -27,6 +197,18 @@ impl BinaryData for CompactPos {
     }
 }
 
+impl From<(f32, f32)> for CompactPos {
+    fn from((x, y): (f32, f32)) -> Self {
+        Self::new(x, y)
+    }
+}
+
+impl From<CompactPos> for (f32, f32) {
+    fn from(pos: CompactPos) -> Self {
+        (pos.x(), pos.y())
+    }
+}
+
 impl CompactPos {
     pub fn new(x: f32, y: f32) -> Self {
         Self {
@@ -45,6 +227,11 @@ impl CompactPos {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String", into = "String")
+)]
 pub struct Varchar<const N: usize>(String);
 impl<const N: usize> Display for Varchar<N> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -61,6 +248,11 @@ impl<const N: usize> TryFrom<String> for Varchar<N> {
         Ok(Self(value))
     }
 }
+impl<const N: usize> From<Varchar<N>> for String {
+    fn from(value: Varchar<N>) -> Self {
+        value.0
+    }
+}
 impl<const N: usize> BinaryData for Varchar<N> {
     fn read_binary(r: &mut BinaryReader<'_>) -> Result<Self> {
         let len = r.uleb()? as usize;
@@ -76,6 +268,11 @@ impl<const N: usize> BinaryData for Varchar<N> {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String", into = "String")
+)]
 pub struct RoomId(Varchar<20>);
 impl RoomId {
     fn validate(self) -> Result<Self> {
@@ -128,14 +325,262 @@ impl<const N: usize> Varchar<N> {
     }
 }
 
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BinaryData)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TouchPhase {
+    /// The finger was just put down
+    Down,
+    Move,
+    /// The finger was lifted, this is its last position
+    Up,
+}
+
+/// A finger in a [`TouchFrame`]. Its `id` stays the same from `Down` to
+/// `Up`, and may only be taken by another finger after that.
+#[derive(Debug, Clone, BinaryData)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TouchPoint {
+    pub id: u8,
+    pub phase: TouchPhase,
+    pub pos: CompactPos,
+}
+
 #[derive(Debug, Clone, BinaryData)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TouchFrame {
+    pub time: f32,
+    pub points: Vec<TouchPoint>,
+}
+
+/// A [`TouchFrame`] as sent to and by peers without
+/// [`Features::TOUCH_PHASES`]: the fingers on the screen at `time`, without
+/// phases. Converting one gives `Move` for every finger.
+#[derive(Debug, Clone, BinaryData)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LegacyTouchFrame {
     pub time: f32,
     pub points: Vec<(i8, CompactPos)>,
 }
 
+impl From<&TouchFrame> for LegacyTouchFrame {
+    fn from(frame: &TouchFrame) -> Self {
+        Self {
+            time: frame.time,
+            // Lifted fingers are simply gone in the next frame
+            points: frame
+                .points
+                .iter()
+                .filter(|it| it.phase != TouchPhase::Up)
+                .map(|it| (it.id as i8, it.pos.clone()))
+                .collect(),
+        }
+    }
+}
+
+impl From<&LegacyTouchFrame> for TouchFrame {
+    fn from(frame: &LegacyTouchFrame) -> Self {
+        Self {
+            time: frame.time,
+            points: frame
+                .points
+                .iter()
+                .map(|(id, pos)| TouchPoint {
+                    id: *id as u8,
+                    phase: TouchPhase::Move,
+                    pos: pos.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Largest decompressed [`TouchBatch`] accepted, in bytes.
+#[cfg(feature = "zstd")]
+const TOUCH_BATCH_MAX_SIZE: usize = 1 << 20;
+
+fn zigzag(v: i32) -> u64 {
+    ((v << 1) ^ (v >> 31)) as u32 as u64
+}
+
+fn unzigzag(v: u64) -> i32 {
+    (v >> 1) as i32 ^ -((v & 1) as i32)
+}
+
+/// Touch frames in a compact encoding: timestamps are stored as the
+/// difference of their bits to the previous frame's, and positions as that
+/// to the same finger's previous one, which stays small while it moves.
+/// Both are lossless. Batches made with `compress` are compressed with zstd
+/// as a whole as well if that makes them smaller, which only peers with
+/// [`Features::TOUCH_ZSTD`] can read.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct TouchBatch {
+    frames: Vec<TouchFrame>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    compress: bool,
+}
+
+impl TouchBatch {
+    pub fn new(frames: Vec<TouchFrame>, compress: bool) -> Self {
+        Self { frames, compress }
+    }
+
+    pub fn into_inner(self) -> Vec<TouchFrame> {
+        self.frames
+    }
+
+    fn read_frames(r: &mut BinaryReader<'_>) -> Result<Vec<TouchFrame>> {
+        let len = r.uleb()? as usize;
+        let mut frames = Vec::with_capacity(len.min(1024));
+        let mut time = 0i32;
+        let mut last = [(0u16, 0u16); 256];
+        for _ in 0..len {
+            time = time.wrapping_add(unzigzag(r.uleb()?));
+            let count = r.uleb()? as usize;
+            let mut points = Vec::with_capacity(count.min(16));
+            for _ in 0..count {
+                let id: u8 = r.read()?;
+                let phase = r.read()?;
+                let prev = &mut last[id as usize];
+                prev.0 = prev.0.wrapping_add(unzigzag(r.uleb()?) as u16);
+                prev.1 = prev.1.wrapping_add(unzigzag(r.uleb()?) as u16);
+                points.push(TouchPoint {
+                    id,
+                    phase,
+                    pos: CompactPos {
+                        x: f16::from_bits(prev.0),
+                        y: f16::from_bits(prev.1),
+                    },
+                });
+            }
+            frames.push(TouchFrame {
+                time: f32::from_bits(time as u32),
+                points,
+            });
+        }
+        Ok(frames)
+    }
+
+    fn write_frames(&self, w: &mut BinaryWriter<'_>) -> Result<()> {
+        w.uleb(self.frames.len() as _)?;
+        let mut time = 0i32;
+        let mut last = [(0u16, 0u16); 256];
+        for frame in &self.frames {
+            let bits = frame.time.to_bits() as i32;
+            w.uleb(zigzag(bits.wrapping_sub(time)))?;
+            time = bits;
+            w.uleb(frame.points.len() as _)?;
+            for point in &frame.points {
+                w.write_val(point.id)?;
+                w.write_val(point.phase)?;
+                let prev = &mut last[point.id as usize];
+                let (x, y) = (point.pos.x.to_bits(), point.pos.y.to_bits());
+                w.uleb(zigzag(x.wrapping_sub(prev.0) as i16 as i32))?;
+                w.uleb(zigzag(y.wrapping_sub(prev.1) as i16 as i32))?;
+                *prev = (x, y);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Deref for TouchBatch {
+    type Target = Vec<TouchFrame>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.frames
+    }
+}
+
+impl From<Vec<TouchFrame>> for TouchBatch {
+    fn from(frames: Vec<TouchFrame>) -> Self {
+        Self::new(frames, false)
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn compress_touches(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let compressed = zstd::bulk::compress(data, 3)?;
+    Ok((compressed.len() < data.len()).then_some(compressed))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn compress_touches(_data: &[u8]) -> Result<Option<Vec<u8>>> {
+    Ok(None)
+}
+
+#[cfg(feature = "zstd")]
+fn decompress_touches(data: &[u8]) -> Result<Vec<u8>> {
+    Ok(zstd::bulk::decompress(data, TOUCH_BATCH_MAX_SIZE)?)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress_touches(_data: &[u8]) -> Result<Vec<u8>> {
+    bail!("received compressed touches, but built without zstd")
+}
+
+// Starts with 0 if the frames follow as they are, 1 if they're compressed
+impl BinaryData for TouchBatch {
+    fn read_binary(r: &mut BinaryReader<'_>) -> Result<Self> {
+        match r.read::<u8>()? {
+            0 => Ok(Self::new(Self::read_frames(r)?, false)),
+            1 => {
+                let len = r.uleb()? as usize;
+                let data = decompress_touches(r.take(len)?)?;
+                Ok(Self::new(
+                    Self::read_frames(&mut BinaryReader::new(&data))?,
+                    true,
+                ))
+            }
+            it => bail!("unknown touch batch encoding: {it}"),
+        }
+    }
+
+    fn write_binary(&self, w: &mut BinaryWriter<'_>) -> Result<()> {
+        if self.compress {
+            let mut data = Vec::new();
+            self.write_frames(&mut BinaryWriter::new(&mut data))?;
+            if let Some(compressed) = compress_touches(&data)? {
+                w.write_val(1u8)?;
+                w.uleb(compressed.len() as _)?;
+                w.extend(&compressed);
+            } else {
+                w.write_val(0u8)?;
+                w.extend(&data);
+            }
+            return Ok(());
+        }
+        w.write_val(0u8)?;
+        self.write_frames(w)
+    }
+}
+
+/// The screen touch positions of a player are given in: x spans -1 to 1
+/// across its width, y spans -1 / `aspect_ratio` to 1 / `aspect_ratio`
+/// across its height. See [`ClientCommand::SetTouchSpace`].
+#[derive(Debug, Clone, Copy, PartialEq, BinaryData)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TouchSpace {
+    /// Width divided by height
+    pub aspect_ratio: f32,
+}
+
+impl TouchSpace {
+    /// Moves `pos`, given in `from`, to the same place relative to this
+    /// screen, e.g. to draw another player's touches on our own.
+    pub fn map_from(&self, from: &TouchSpace, pos: CompactPos) -> CompactPos {
+        CompactPos::new(pos.x(), pos.y() * from.aspect_ratio / self.aspect_ratio)
+    }
+}
+
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, BinaryData)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BinaryData)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Judgement {
     Perfect,
     Good,
@@ -146,6 +591,7 @@ pub enum Judgement {
 }
 
 #[derive(Debug, Clone, BinaryData)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct JudgeEvent {
     pub time: f32,
     pub line_id: u32,
@@ -153,31 +599,501 @@ pub struct JudgeEvent {
     pub judgement: Judgement,
 }
 
+/// Judge events in a compact encoding: runs of the same judgement share it,
+/// and timestamps are stored as the difference of their bits to the previous
+/// one. Both are lossless, and take little space since judges come in order.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+pub struct JudgeBatch(pub Vec<JudgeEvent>);
+
+impl JudgeBatch {
+    pub fn into_inner(self) -> Vec<JudgeEvent> {
+        self.0
+    }
+}
+
+impl Deref for JudgeBatch {
+    type Target = Vec<JudgeEvent>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for JudgeBatch {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl From<Vec<JudgeEvent>> for JudgeBatch {
+    fn from(judges: Vec<JudgeEvent>) -> Self {
+        Self(judges)
+    }
+}
+
+impl BinaryData for JudgeBatch {
+    fn read_binary(r: &mut BinaryReader<'_>) -> Result<Self> {
+        let len = r.uleb()? as usize;
+        let mut judges = Vec::with_capacity(len.min(1024));
+        let mut time = 0i32;
+        while judges.len() < len {
+            let judgement = r.read()?;
+            let run = r.uleb()? as usize;
+            if run == 0 || run > len - judges.len() {
+                bail!("invalid judge run length: {run}");
+            }
+            for _ in 0..run {
+                let delta = r.uleb()?;
+                time = time.wrapping_add((delta >> 1) as i32 ^ -((delta & 1) as i32));
+                judges.push(JudgeEvent {
+                    time: f32::from_bits(time as u32),
+                    line_id: u32::try_from(r.uleb()?)?,
+                    note_id: u32::try_from(r.uleb()?)?,
+                    judgement,
+                });
+            }
+        }
+        Ok(Self(judges))
+    }
+
+    fn write_binary(&self, w: &mut BinaryWriter<'_>) -> Result<()> {
+        w.uleb(self.0.len() as _)?;
+        let mut time = 0i32;
+        let mut rest = &self.0[..];
+        while let Some(first) = rest.first() {
+            let len = rest
+                .iter()
+                .position(|it| it.judgement != first.judgement)
+                .unwrap_or(rest.len());
+            let (run, next) = rest.split_at(len);
+            rest = next;
+            w.write_val(first.judgement)?;
+            w.uleb(run.len() as _)?;
+            for judge in run {
+                let bits = judge.time.to_bits() as i32;
+                let delta = bits.wrapping_sub(time);
+                time = bits;
+                w.uleb(((delta << 1) ^ (delta >> 31)) as u32 as u64)?;
+                w.uleb(judge.line_id as _)?;
+                w.uleb(judge.note_id as _)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, BinaryData)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ClientCommand {
     Ping,
 
-    Authenticate { token: Varchar<32> },
-    Chat { message: Varchar<200> },
+    Authenticate {
+        token: Varchar<32>,
+    },
+    Chat {
+        message: Varchar<200>,
+    },
 
-    Touches { frames: Arc<Vec<TouchFrame>> },
-    Judges { judges: Arc<Vec<JudgeEvent>> },
+    /// Our touches for peers without [`Features::TOUCH_PHASES`], see
+    /// `PhasedTouches`.
+    Touches {
+        round: u32,
+        frames: Arc<Vec<LegacyTouchFrame>>,
+    },
+    Judges {
+        round: u32,
+        judges: Arc<JudgeBatch>,
+    },
 
-    CreateRoom { id: RoomId },
-    JoinRoom { id: RoomId, monitor: bool },
+    /// With a `password`, only those who know it may join. `max_players`
+    /// is at most [`ROOM_MAX_PLAYERS`](crate::ROOM_MAX_PLAYERS).
+    CreateRoom {
+        id: RoomId,
+        password: Option<Varchar<64>>,
+        max_players: u8,
+    },
+    /// Spectators watch like monitors, but anyone may join as one, and
+    /// they don't take part in the game.
+    JoinRoom {
+        id: RoomId,
+        monitor: bool,
+        spectator: bool,
+        password: Option<Varchar<64>>,
+    },
     LeaveRoom,
-    LockRoom { lock: bool },
-    CycleRoom { cycle: bool },
+    LockRoom {
+        lock: bool,
+    },
+    CycleRoom {
+        cycle: bool,
+    },
 
-    SelectChart { id: i32 },
+    SelectChart {
+        id: i32,
+    },
     RequestStart,
     Ready,
     CancelReady,
-    Played { id: i32 },
+    Played {
+        id: i32,
+    },
     Abort,
+
+    Region,
+    EchoTest {
+        payload: Vec<u8>,
+    },
+    WatchPlayer {
+        user_id: Option<i32>,
+    },
+    ExportResults {
+        round: Option<u32>,
+    },
+    SlowMode {
+        secs: u16,
+    },
+    ReportPlayer {
+        user_id: i32,
+        reason: Varchar<200>,
+    },
+    SyncState,
+    Prefetch {
+        chart_ids: Vec<i32>,
+    },
+    BridgeSpectatorChat {
+        bridge: bool,
+    },
+    ChatWithId {
+        id: Uuid,
+        message: Varchar<200>,
+    },
+    /// Chat encrypted with a key only the room members know, relayed as is.
+    /// Answered with `ServerCommand::Chat`.
+    EncryptedChat {
+        payload: Vec<u8>,
+    },
+    ForceCancelStart,
+    SetRecording {
+        recording: Recording,
+    },
+    /// Asks for judge batches of `player` that didn't arrive, by their `seq`.
+    /// Batches still kept by the server are sent again.
+    ResendJudges {
+        player: i32,
+        round: u32,
+        seqs: Vec<u32>,
+    },
+    /// Moves `users` of the room into a new room `id`, hosted by the first of
+    /// them who may host.
+    SplitRoom {
+        id: RoomId,
+        users: Vec<i32>,
+    },
+    /// Moves everyone in the room into `into`, which must be unlocked and
+    /// have space for all players. The room is closed afterwards.
+    MergeRoom {
+        into: RoomId,
+    },
+    /// Without `confirm`, only asks who is still playing.
+    EndRound {
+        confirm: bool,
+    },
+    /// Download progress of the selected chart, from 0 to 1.
+    ChartProgress {
+        id: i32,
+        progress: f32,
+    },
+    /// The selected chart is available locally.
+    ChartReady {
+        id: i32,
+    },
+    /// Asks for the open rooms, see [`RoomListing`].
+    ListRooms,
+    /// Removes a member from the room. Only for the host.
+    KickPlayer {
+        id: i32,
+    },
+    /// Like `KickPlayer`, and keeps them from joining again for as long as
+    /// the room exists.
+    BanPlayer {
+        id: i32,
+    },
+    /// Makes another player the host. Only for the host.
+    TransferHost {
+        target: i32,
+    },
+    /// Our round-trip time to the server, shown to the other members. Only
+    /// worth sending when it changed noticeably.
+    ReportLatency {
+        ms: u16,
+    },
+    /// Sent on [`Message::StartPlaying`]. If a player doesn't within a few
+    /// seconds, the round is rolled back for everyone.
+    AckStart {
+        round: u32,
+    },
+    /// Must come before [`ClientCommand::Authenticate`], which fails
+    /// otherwise. `version` is the [`PROTOCOL_VERSION`] of the client.
+    Hello {
+        version: u16,
+        features: Features,
+    },
+    /// Starts a best-of-`best_of` duel between the room's two players, or
+    /// calls off the current one with `None`. Only for the host, while
+    /// choosing a chart.
+    SetDuel {
+        best_of: Option<u8>,
+    },
+    /// Lets the players choose the next chart from `pool` by taking turns,
+    /// banning `bans` charts and then picking one, see [`DraftState`]. Only
+    /// for the host, while choosing a chart.
+    StartDraft {
+        pool: Vec<i32>,
+        bans: u8,
+        /// Time each player has for their turn, after which a random chart
+        /// is chosen for them
+        turn_secs: u16,
+    },
+    /// Only for the host.
+    CancelDraft,
+    /// Bans or picks `chart` of the pool. `action` must be the one
+    /// expected of us this turn.
+    DraftTurn {
+        action: DraftAction,
+        chart: i32,
+    },
+    /// Only for the host. Can't be lower than the number of players in
+    /// the room.
+    SetMaxPlayers {
+        max_players: u8,
+    },
+    /// How we're doing in the game of `round`, sent every
+    /// [`SCORE_UPDATE_INTERVAL`](crate::SCORE_UPDATE_INTERVAL) while
+    /// playing. Updates coming more often are dropped.
+    ScoreUpdate {
+        round: u32,
+        score: i32,
+        accuracy: f32,
+        combo: u32,
+    },
+    /// Keeps a member from chatting for `secs` seconds, or lets them chat
+    /// again with 0. Only for the host.
+    MutePlayer {
+        user_id: i32,
+        secs: u32,
+    },
+    /// Removes a member from the room and keeps them from joining again
+    /// for `secs` seconds. Only for the host.
+    TimeoutPlayer {
+        user_id: i32,
+        secs: u32,
+    },
+    /// Sent first on every later connection of a session that was given a
+    /// [`ServerCommand::Routing`] token by clients predating
+    /// [`ROUTE_PREFIX`](crate::ROUTE_PREFIX), which proxies can read while
+    /// this can't be. Not answered.
+    Route {
+        token: Varchar<64>,
+    },
+    /// Chat only `target`, who must be in the same room, gets to read.
+    /// Answered with [`ServerCommand::Chat`].
+    Whisper {
+        target: i32,
+        message: Varchar<200>,
+    },
+    /// Charts played in the room so far, see [`PlayedChart`].
+    FetchRoomHistory,
+    /// Only for the host.
+    SetRepeatPolicy {
+        policy: RepeatPolicy,
+    },
+    /// Instead of [`ClientCommand::Authenticate`], picks up the session
+    /// given `session` as its [`ServerCommand::ResumeToken`], as long as the
+    /// server keeps it, e.g. while it waits for a player who lost connection
+    /// to come back. Answered with `ServerCommand::Authenticate`.
+    Resume {
+        session: Uuid,
+    },
+    /// Selects a random chart of the Phira catalog matching `filter`, with
+    /// the same rules as [`ClientCommand::SelectChart`]. Answered with the
+    /// chart's id.
+    SelectRandomChart {
+        filter: ChartFilter,
+    },
+    /// The screen our touch frames are relative to, shown to the room as
+    /// [`ServerCommand::TouchSpace`] and kept until changed. Not answered.
+    SetTouchSpace {
+        space: TouchSpace,
+    },
+    /// Sent last before closing the connection on purpose. The server lets
+    /// the user go right away instead of waiting for them to reconnect. Not
+    /// answered.
+    Bye,
+    /// Our touches during `round`, relayed to monitors. Not answered.
+    PhasedTouches {
+        round: u32,
+        frames: Arc<Vec<TouchFrame>>,
+    },
+    /// Wraps judges and score updates, which the live leaderboard can't do
+    /// without, on connections that may lose packets. Acknowledged with
+    /// [`ServerCommand::Ack`] and sent again with the same `seq` until then;
+    /// `cmd` is only handled the first time it arrives. `seq` counts up from
+    /// 0 on each connection.
+    Reliable {
+        seq: u32,
+        cmd: ReliableCommand,
+    },
+    /// Only for the host, while choosing a chart. Duels are only held in
+    /// `Versus` rooms. `CycleRoom` switches between `Versus` and `Relay`.
+    SetRoomMode {
+        mode: RoomMode,
+    },
+    /// Adds a chart to the end of the room's queue, see [`QueuedChart`].
+    /// Members may have up to [`CHART_QUEUE_PER_USER`](crate::CHART_QUEUE_PER_USER)
+    /// charts queued at once, and charts can't be queued twice. Not
+    /// possible in quickplay rooms.
+    QueueChart {
+        id: i32,
+    },
+    /// Only for the host and the member who queued the chart.
+    UnqueueChart {
+        id: i32,
+    },
+    /// Moves our vote to the queued chart `id`, or withdraws it if `None`.
+    VoteChart {
+        id: Option<i32>,
+    },
+    /// Never sent. Stands in for a command with tag `tag` this build doesn't
+    /// know, from a newer client, which was skipped. Its own tag is never
+    /// read either, and counts as unknown.
+    #[binary(skip)]
+    UnknownCommand {
+        tag: u8,
+    },
+    /// Only for the host. Applies from the next `RequestStart` on.
+    SetReadyCheck {
+        check: Option<ReadyCheck>,
+    },
+    /// One sample of the server's clock, taken when sent at `client_time`
+    /// by ours, see [`unix_millis`](crate::unix_millis). Answered right away
+    /// with [`ServerCommand::SyncClock`].
+    SyncClock {
+        client_time: u64,
+    },
+    /// Replaces `PhasedTouches` with [`Features::TOUCH_DELTA`], taking less
+    /// space. Not answered.
+    PackedTouches {
+        round: u32,
+        frames: Arc<TouchBatch>,
+    },
+}
+
+impl ClientCommand {
+    /// What the server must support for this command to be handled. Servers
+    /// without it skip commands they don't know without answering them, or
+    /// close the connection if they're older than
+    /// [`ClientCommand::UnknownCommand`].
+    pub fn required_features(&self) -> Features {
+        match self {
+            Self::AckStart { .. } => Features::START_ACK,
+            Self::Route { .. } => Features::ROUTING,
+            Self::Resume { .. } => Features::RESUME,
+            Self::SetTouchSpace { .. } => Features::TOUCH_SPACE,
+            Self::PhasedTouches { .. } => Features::TOUCH_PHASES,
+            Self::Reliable { .. } => Features::RELIABLE_REALTIME,
+            Self::SetRoomMode { .. } => Features::ROOM_MODES,
+            Self::QueueChart { .. } | Self::UnqueueChart { .. } | Self::VoteChart { .. } => {
+                Features::CHART_QUEUE
+            }
+            Self::SetReadyCheck { .. } => Features::READY_CHECK,
+            Self::SyncClock { .. } => Features::CLOCK_SYNC,
+            Self::PackedTouches { .. } => Features::TOUCH_DELTA,
+            Self::SetDuel { .. } => Features::DUELS,
+            Self::StartDraft { .. } | Self::CancelDraft | Self::DraftTurn { .. } => {
+                Features::DRAFTS
+            }
+            Self::SetMaxPlayers { .. } => Features::MAX_PLAYERS,
+            Self::MutePlayer { .. } | Self::TimeoutPlayer { .. } => Features::MODERATION,
+            Self::Whisper { .. } => Features::WHISPER,
+            Self::FetchRoomHistory => Features::ROOM_HISTORY,
+            Self::SetRepeatPolicy { .. } => Features::REPEAT_POLICY,
+            Self::SelectRandomChart { .. } => Features::RANDOM_CHART,
+            _ => Features::EMPTY,
+        }
+    }
 }
 
+impl Packet for ClientCommand {
+    fn unknown(tag: u8) -> Self {
+        Self::UnknownCommand { tag }
+    }
+}
+
+/// What [`ClientCommand::Reliable`] can carry, handled like the
+/// `ClientCommand` of the same name.
 #[derive(Clone, Debug, BinaryData)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ReliableCommand {
+    Judges {
+        round: u32,
+        judges: Arc<JudgeBatch>,
+    },
+    ScoreUpdate {
+        round: u32,
+        score: i32,
+        accuracy: f32,
+        combo: u32,
+    },
+}
+
+impl From<ReliableCommand> for ClientCommand {
+    fn from(cmd: ReliableCommand) -> Self {
+        match cmd {
+            ReliableCommand::Judges { round, judges } => Self::Judges { round, judges },
+            ReliableCommand::ScoreUpdate {
+                round,
+                score,
+                accuracy,
+                combo,
+            } => Self::ScoreUpdate {
+                round,
+                score,
+                accuracy,
+                combo,
+            },
+        }
+    }
+}
+
+impl TryFrom<ClientCommand> for ReliableCommand {
+    type Error = ClientCommand;
+
+    fn try_from(cmd: ClientCommand) -> Result<Self, ClientCommand> {
+        Ok(match cmd {
+            ClientCommand::Judges { round, judges } => Self::Judges { round, judges },
+            ClientCommand::ScoreUpdate {
+                round,
+                score,
+                accuracy,
+                combo,
+            } => Self::ScoreUpdate {
+                round,
+                score,
+                accuracy,
+                combo,
+            },
+            cmd => return Err(cmd),
+        })
+    }
+}
+
+#[derive(Clone, Debug, BinaryData)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Message {
     Chat {
         user: i32,
@@ -214,14 +1130,22 @@ pub enum Message {
     CancelGame {
         user: i32,
     },
-    StartPlaying,
+    /// Realtime data and results of this game are tagged with `round`.
+    StartPlaying {
+        round: u32,
+    },
     Played {
         user: i32,
         score: i32,
         accuracy: f32,
         full_combo: bool,
     },
-    GameEnd,
+    /// `results` are ordered by rank, and empty if nobody finished.
+    GameEnd {
+        round: u32,
+        reason: GameEndReason,
+        results: Vec<PlayerResult>,
+    },
     Abort {
         user: i32,
     },
@@ -231,9 +1155,349 @@ pub enum Message {
     CycleRoom {
         cycle: bool,
     },
+    SlowMode {
+        secs: u16,
+    },
+    /// Chat from a spectator, only delivered to other spectators unless the
+    /// host bridges the channels.
+    SpectatorChat {
+        user: i32,
+        content: String,
+    },
+    BridgeSpectatorChat {
+        bridge: bool,
+    },
+    /// From the server operator, sent to everyone, or a notice the operator
+    /// configured the server to give
+    Announcement {
+        content: String,
+    },
+    /// A start the players couldn't get through was cancelled by the host or
+    /// the server operator.
+    StartCancelled,
+    Recording {
+        recording: Recording,
+    },
+    /// See `ClientCommand::EncryptedChat`. Never kept in the chat log.
+    EncryptedChat {
+        user: i32,
+        channel: ChatChannel,
+        payload: Vec<u8>,
+    },
+    /// The host handed the room to `to`, unlike `NewHost` which is sent
+    /// when the server picks one.
+    HostTransferred {
+        from: i32,
+        to: i32,
+    },
+    /// `users` didn't acknowledge the start of the round in time, so the
+    /// room went back to getting ready, with nobody but the host ready.
+    StartFailed {
+        users: Vec<i32>,
+    },
+    /// A duel started, its score changed after a round, or it was called
+    /// off (`None`).
+    Duel {
+        series: Option<DuelSeries>,
+    },
+    /// `winner` took the duel, which is over now. Sent after the
+    /// [`Message::GameEnd`] of the deciding round.
+    DuelWon {
+        winner: i32,
+        series: DuelSeries,
+    },
+    DraftStarted {
+        draft: DraftState,
+    },
+    /// `user` took their turn in the draft, or had it taken for them when
+    /// `auto`. A pick ends the draft and is followed by
+    /// [`Message::SelectChart`]; after a ban, the next turn ends at
+    /// `deadline`.
+    DraftTurn {
+        user: i32,
+        action: DraftAction,
+        chart: i32,
+        auto: bool,
+        deadline: Option<DateTime<Utc>>,
+    },
+    /// The host called off the draft, or one of its players left.
+    DraftCancelled,
+    MaxPlayers {
+        max_players: u8,
+    },
+    /// `user` can't chat until `until`, or can again if `None`, either
+    /// because the host said so or the mute ran out.
+    Muted {
+        user: i32,
+        until: Option<DateTime<Utc>>,
+    },
+    /// `user` was removed from the room and can't join again until `until`.
+    TimedOut {
+        user: i32,
+        until: DateTime<Utc>,
+    },
+    /// Only delivered to `to`, and echoed back to `from`. Never kept in the
+    /// room's history.
+    Whisper {
+        from: i32,
+        to: i32,
+        content: String,
+    },
+    RepeatPolicy {
+        policy: RepeatPolicy,
+    },
+    /// The chart `user` selected was played `rounds_ago` rounds before, 1
+    /// being the last one. Sent after [`Message::SelectChart`] if the room
+    /// warns about repeats.
+    ChartRepeated {
+        user: i32,
+        chart: i32,
+        rounds_ago: u32,
+    },
+    RoomMode {
+        mode: RoomMode,
+    },
+    /// Added to the end of the queue. Queued charts are removed once
+    /// they're selected, see [`Message::SelectChart`].
+    ChartQueued {
+        chart: QueuedChart,
+    },
+    /// `user` removed `chart` from the queue.
+    ChartUnqueued {
+        user: i32,
+        chart: i32,
+    },
+    /// `user` now votes for `chart`, or for nothing. Members who leave
+    /// lose their vote without this.
+    ChartVoted {
+        user: i32,
+        chart: Option<i32>,
+    },
+    ReadyCheck {
+        check: Option<ReadyCheck>,
+    },
+    /// Sums up the joins and leaves of a burst, e.g. during a raid, beyond
+    /// the first few sent as `JoinRoom` and `LeaveRoom`. Only sent with
+    /// [`Features::BATCHED_CHURN`], and not kept in the message history.
+    MembersChurn {
+        joined: Vec<i32>,
+        left: Vec<i32>,
+    },
+    /// Chat from the server's bot, see [`ServerLimits::bot_name`]. Only sent
+    /// with [`Features::BOT_MESSAGES`], others get it as [`Message::Chat`]
+    /// from [`BOT_USER_ID`](crate::BOT_USER_ID).
+    Bot {
+        content: String,
+    },
+}
+
+impl Message {
+    pub fn channel(&self) -> ChatChannel {
+        match self {
+            Self::SpectatorChat { .. } => ChatChannel::Spectators,
+            Self::EncryptedChat { channel, .. } => *channel,
+            _ => ChatChannel::Room,
+        }
+    }
+}
+
+/// Why a game ended, see [`Message::GameEnd`].
+#[derive(Debug, BinaryData, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GameEndReason {
+    /// Every player finished, or aborted with some others finishing
+    AllPlayed,
+    /// Every player aborted, there are no results
+    Aborted,
+    /// Players still playing were given up on after a time limit, e.g. in
+    /// quickplay rooms
+    TimedOut,
+    /// The host (or the server operator) ended the game before everyone
+    /// finished
+    HostEnded,
+}
+
+/// Who a [`Message`] is delivered to.
+#[derive(Debug, BinaryData, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChatChannel {
+    /// Everyone in the room
+    Room,
+    /// Monitors only, or everyone if bridged
+    Spectators,
+}
+
+/// A best-of-N series between the two players of a room, see
+/// [`ClientCommand::SetDuel`]. Players take turns selecting the chart.
+#[derive(Debug, BinaryData, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DuelSeries {
+    pub best_of: u8,
+    /// Both players with the rounds they won, the one who picked first
+    /// coming first
+    pub wins: Vec<(i32, u8)>,
+    /// Who selects the chart of the next round
+    pub picker: i32,
+}
+
+impl DuelSeries {
+    /// Rounds a player must win to take the series.
+    pub fn wins_needed(&self) -> u8 {
+        self.best_of / 2 + 1
+    }
+
+    pub fn winner(&self) -> Option<i32> {
+        self.wins
+            .iter()
+            .find(|it| it.1 >= self.wins_needed())
+            .map(|it| it.0)
+    }
+}
+
+#[derive(Debug, BinaryData, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DraftAction {
+    Ban,
+    Pick,
+}
+
+/// A pick/ban phase choosing the next chart, see
+/// [`ClientCommand::StartDraft`]. Players take turns in `order`, the first
+/// `bans` turns banning a chart and the last one picking.
+#[derive(Debug, BinaryData, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DraftState {
+    pub pool: Vec<i32>,
+    /// In the order they were banned
+    pub banned: Vec<i32>,
+    /// Repeated from the start if there are more turns than players
+    pub order: Vec<i32>,
+    pub bans: u8,
+    /// End of the current turn
+    pub deadline: DateTime<Utc>,
+}
+
+impl DraftState {
+    /// Who takes the current turn, and what they do.
+    pub fn turn(&self) -> (i32, DraftAction) {
+        let turn = self.banned.len();
+        let action = if turn < self.bans as usize {
+            DraftAction::Ban
+        } else {
+            DraftAction::Pick
+        };
+        (self.order[turn % self.order.len()], action)
+    }
+
+    /// Charts not banned yet.
+    pub fn remaining(&self) -> impl Iterator<Item = i32> + '_ {
+        self.pool
+            .iter()
+            .copied()
+            .filter(|it| !self.banned.contains(it))
+    }
+}
+
+/// Whether results and replays of a room's games are kept, and who may
+/// access them.
+#[derive(Debug, Default, BinaryData, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Recording {
+    /// Nothing is kept, and clients don't record replays
+    Off,
+    /// Results are kept for members of the room only
+    Members,
+    /// Results are also submitted to the Phira API
+    #[default]
+    Public,
+}
+
+/// What happens when the host selects a chart played within the last
+/// [`RECENT_CHARTS`](crate::RECENT_CHARTS) rounds.
+#[derive(Debug, Default, BinaryData, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RepeatPolicy {
+    #[default]
+    Allow,
+    /// Everyone is told with [`Message::ChartRepeated`]
+    Warn,
+    /// The selection fails
+    Forbid,
+}
+
+/// How a room plays, see [`ClientCommand::SetRoomMode`].
+#[derive(Debug, Default, BinaryData, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RoomMode {
+    /// Players are ranked by their score
+    #[default]
+    Versus,
+    /// Players are a team: everyone who finishes shares the first rank, and
+    /// no wins are counted
+    Coop,
+    /// Like `Versus`, but the host passes to the next player after every
+    /// round, see [`ClientRoomState::cycle`]
+    Relay,
+}
+
+/// How long players have to get ready once the host requested a start, see
+/// [`ClientCommand::SetReadyCheck`]. The time left is counted down with
+/// [`ServerCommand::StartCountdown`].
+#[derive(Debug, BinaryData, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReadyCheck {
+    pub secs: u16,
+    pub on_timeout: ReadyTimeoutAction,
+}
+
+#[derive(Debug, Default, BinaryData, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ReadyTimeoutAction {
+    /// The round starts with whoever is ready, the others sit it out
+    #[default]
+    Start,
+    /// Back to choosing a chart, see [`Message::StartCancelled`]
+    Cancel,
+}
+
+/// What [`ClientCommand::SelectRandomChart`] may pick. Unset bounds don't
+/// restrict anything.
+#[derive(Debug, BinaryData, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChartFilter {
+    pub min_difficulty: Option<f32>,
+    pub max_difficulty: Option<f32>,
+    /// In seconds, up to the end of the last note
+    pub max_length: Option<u32>,
+    pub ranked_only: bool,
+}
+
+/// A round played in a room, see [`ClientCommand::FetchRoomHistory`].
+#[derive(Debug, BinaryData, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlayedChart {
+    pub round: u32,
+    pub chart: i32,
+    pub name: String,
+    pub finished_at: DateTime<Utc>,
+}
+
+/// A chart members would like to play next, see
+/// [`ClientCommand::QueueChart`]. When a round ends, the one with the most
+/// votes (the oldest on ties) is selected, except during duels and drafts.
+/// The host can select any chart in between as usual.
+#[derive(Debug, BinaryData, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueuedChart {
+    pub chart: i32,
+    pub name: String,
+    /// Who queued it
+    pub user: i32,
+    pub votes: Vec<i32>,
 }
 
 #[derive(Debug, BinaryData, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RoomState {
     SelectChart(Option<i32>),
     WaitingForReady,
@@ -247,6 +1511,7 @@ impl Default for RoomState {
 }
 
 #[derive(Clone, Debug, BinaryData)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UserInfo {
     pub id: i32,
     pub name: String,
@@ -254,38 +1519,314 @@ pub struct UserInfo {
 }
 
 #[derive(Debug, BinaryData, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClientRoomState {
     pub id: RoomId,
     pub state: RoomState,
     pub live: bool,
     pub locked: bool,
+    /// Whether `mode` is [`RoomMode::Relay`]
     pub cycle: bool,
+    pub recording: Recording,
     pub is_host: bool,
     pub is_ready: bool,
+    pub is_spectator: bool,
     pub users: HashMap<i32, UserInfo>,
+    /// Hosted by a sandbox server, see [`ServerLimits::sandbox`]
+    pub test: bool,
+    pub duel: Option<DuelSeries>,
+    pub draft: Option<DraftState>,
+    pub max_players: u8,
+    /// Of the last round played in the room, see [`Message::GameEnd`]
+    pub last_results: Vec<PlayerResult>,
+    pub repeat_policy: RepeatPolicy,
+    pub mode: RoomMode,
+    /// Oldest first
+    pub queue: Vec<QueuedChart>,
+    pub ready_check: Option<ReadyCheck>,
+}
+
+/// A room as shown in the lobby browser.
+#[derive(Debug, BinaryData, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RoomListing {
+    pub id: RoomId,
+    /// Display name of the host, which rooms are shown under. `None` for
+    /// quickplay rooms.
+    pub host: Option<String>,
+    /// Players only, monitors aren't counted
+    pub players: u8,
+    pub max_players: u8,
+    pub state: RoomState,
+    pub locked: bool,
+    /// Joining needs a password
+    pub password: bool,
+    /// Hosted by a sandbox server, see [`ServerLimits::sandbox`]
+    pub test: bool,
+}
+
+/// Where a member is in the current round.
+#[derive(Debug, Default, BinaryData, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MemberPhase {
+    /// Not taking part in a round, monitors always are
+    #[default]
+    Idle,
+    Ready,
+    Playing,
+    Finished,
+    Aborted,
+}
+
+#[derive(Debug, BinaryData, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemberStatus {
+    pub user: i32,
+    pub phase: MemberPhase,
+    /// `false` while the server waits for the member to reconnect
+    pub connected: bool,
+    /// Round-trip time to the server last reported by the member, in
+    /// milliseconds
+    pub latency_ms: Option<u16>,
+}
+
+/// A player's score as last reported, see [`ServerCommand::Scores`].
+#[derive(Debug, BinaryData, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlayerScore {
+    pub user: i32,
+    pub score: i32,
+    /// Between 0 and 1
+    pub accuracy: f32,
+    pub combo: u32,
+}
+
+/// A player's line in the results of a round, see [`Message::GameEnd`].
+#[derive(Debug, BinaryData, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PlayerResult {
+    pub user: i32,
+    /// From 1, players with the same score share a rank
+    pub rank: u32,
+    pub score: i32,
+    /// Between 0 and 1
+    pub accuracy: f32,
+    pub full_combo: bool,
+    pub max_combo: u32,
+    pub perfect: u32,
+    pub good: u32,
+    pub bad: u32,
+    pub miss: u32,
+}
+
+/// Limits enforced by the server, so that clients can check input before
+/// sending it. Sent right before a successful `Authenticate` response.
+#[derive(Debug, BinaryData, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServerLimits {
+    /// In bytes of UTF-8, like the 200 a chat message can take at most
+    pub chat_max_len: u16,
+    /// Most touch frames or judge events in a single batch
+    pub realtime_batch_max: u32,
+    /// Commands per second, realtime data excluded
+    pub command_rate: f32,
+    pub command_burst: f32,
+    /// Chat messages per second
+    pub chat_rate: f32,
+    pub chat_burst: f32,
+    /// Time between two heartbeats, in milliseconds
+    pub heartbeat_interval_ms: u32,
+    /// How long to wait for the response to a heartbeat, in milliseconds
+    pub heartbeat_timeout_ms: u32,
+    /// A server for testing clients against: any token is accepted, results
+    /// aren't submitted and rooms are closed after a while.
+    pub sandbox: bool,
+    /// Of the bot chatting in rooms with [`Message::Bot`], if the server has
+    /// one
+    pub bot_name: Option<String>,
+}
+
+impl ServerLimits {
+    pub fn heartbeat_interval(&self) -> Duration {
+        Duration::from_millis(self.heartbeat_interval_ms as u64)
+    }
+
+    pub fn heartbeat_timeout(&self) -> Duration {
+        Duration::from_millis(self.heartbeat_timeout_ms as u64)
+    }
 }
 
 #[derive(Debug, BinaryData, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct JoinRoomResponse {
     pub state: RoomState,
     pub users: Vec<UserInfo>,
     pub live: bool,
+    pub recording: Recording,
+    /// Set when a host who lost connection during a game got the room back
+    pub is_host: bool,
+    pub duel: Option<DuelSeries>,
+    pub draft: Option<DraftState>,
+    pub max_players: u8,
+    pub last_results: Vec<PlayerResult>,
+    pub repeat_policy: RepeatPolicy,
+    pub mode: RoomMode,
+    pub queue: Vec<QueuedChart>,
+    pub ready_check: Option<ReadyCheck>,
+}
+
+/// Why a command failed. Failures without a variant of their own come as
+/// a message in the user's language, and so does the `message` of those
+/// carrying one.
+///
+/// On the wire the description comes first, so that clients from before
+/// [`ClientCommand::Hello`] read it as the plain string they expect. Kinds
+/// this build doesn't know yet are read as [`Self::Other`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ServerError {
+    /// See [`ClientCommand::Hello`]
+    #[error("unsupported protocol version, please update the client")]
+    UnsupportedVersion,
+    #[error("invalid token")]
+    InvalidToken,
+    #[error("not in a room")]
+    NotInRoom,
+    #[error("already in room")]
+    AlreadyInRoom,
+    #[error("room not found")]
+    RoomNotFound,
+    #[error("{message}")]
+    RoomFull { message: String },
+    /// The room has a password, but none was given
+    #[error("password required")]
+    PasswordRequired,
+    #[error("wrong password")]
+    WrongPassword,
+    #[error("only host can do this")]
+    NotHost,
+    /// Not possible in the room's current state, e.g. during a game
+    #[error("invalid state")]
+    InvalidState,
+    #[error("chart not found")]
+    ChartNotFound,
+    /// Too many commands were sent, see [`ServerLimits`]
+    #[error("{message}")]
+    RateLimited {
+        retry_after_ms: u32,
+        message: String,
+    },
+    #[error("{0}")]
+    Other(String),
+}
+
+impl BinaryData for ServerError {
+    fn read_binary(r: &mut BinaryReader<'_>) -> Result<Self> {
+        let message: String = r.read()?;
+        Ok(match r.read::<u8>()? {
+            0 => Self::UnsupportedVersion,
+            1 => Self::InvalidToken,
+            2 => Self::NotInRoom,
+            3 => Self::AlreadyInRoom,
+            4 => Self::RoomNotFound,
+            5 => Self::RoomFull { message },
+            6 => Self::PasswordRequired,
+            7 => Self::WrongPassword,
+            8 => Self::NotHost,
+            9 => Self::InvalidState,
+            10 => Self::ChartNotFound,
+            11 => Self::RateLimited {
+                retry_after_ms: r.read()?,
+                message,
+            },
+            _ => Self::Other(message),
+        })
+    }
+
+    fn write_binary(&self, w: &mut BinaryWriter<'_>) -> Result<()> {
+        w.write(&self.to_string())?;
+        w.write_val::<u8>(match self {
+            Self::UnsupportedVersion => 0,
+            Self::InvalidToken => 1,
+            Self::NotInRoom => 2,
+            Self::AlreadyInRoom => 3,
+            Self::RoomNotFound => 4,
+            Self::RoomFull { .. } => 5,
+            Self::PasswordRequired => 6,
+            Self::WrongPassword => 7,
+            Self::NotHost => 8,
+            Self::InvalidState => 9,
+            Self::ChartNotFound => 10,
+            Self::RateLimited { .. } => 11,
+            Self::Other(_) => 12,
+        })?;
+        if let Self::RateLimited { retry_after_ms, .. } = self {
+            w.write_val(*retry_after_ms)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, BinaryData, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RoundPhase {
+    SelectChart,
+    WaitingForReady {
+        ready: Vec<i32>,
+    },
+    Playing {
+        round: u32,
+        finished: Vec<i32>,
+        aborted: Vec<i32>,
+    },
+}
+
+/// Everything a client needs to repair its view of the room.
+#[derive(Debug, BinaryData, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SyncStateResponse {
+    pub room: ClientRoomState,
+    pub host: Option<i32>,
+    pub phase: RoundPhase,
 }
 
 #[derive(Clone, Debug, BinaryData)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Achievement {
+    /// First round won today (UTC) against at least one other player.
+    FirstWinOfDay,
+    /// Finished this many rounds in a row without aborting.
+    Streak { rounds: u32 },
+    /// Played this many rounds in the same room.
+    Marathon { rounds: u32 },
+}
+
+#[derive(Clone, Debug, BinaryData)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ServerCommand {
     Pong,
 
     Authenticate(SResult<(UserInfo, Option<ClientRoomState>)>),
     Chat(SResult<()>),
 
+    /// Frames are attributed to `player`, who never receives them back.
+    /// Frames sent for any other than the current round are dropped.
+    ///
+    /// `seq` counts the frames relayed for `player` this round, starting at
+    /// 1, so receivers can tell how many went missing.
     Touches {
         player: i32,
-        frames: Arc<Vec<TouchFrame>>,
+        round: u32,
+        seq: u32,
+        frames: Arc<Vec<LegacyTouchFrame>>,
     },
+    /// See `Touches`. Missing batches can be asked for again with
+    /// `ClientCommand::ResendJudges`.
     Judges {
         player: i32,
-        judges: Arc<Vec<JudgeEvent>>,
+        round: u32,
+        seq: u32,
+        judges: Arc<JudgeBatch>,
     },
 
     Message(Message),
@@ -305,4 +1846,343 @@ pub enum ServerCommand {
     CancelReady(SResult<()>),
     Played(SResult<()>),
     Abort(SResult<()>),
+
+    Region(Option<String>),
+    EchoTest(SResult<Vec<u8>>),
+    WatchPlayer(SResult<()>),
+    ExportResults(SResult<String>),
+
+    Achievement {
+        user: i32,
+        achievement: Achievement,
+    },
+
+    SlowMode(SResult<()>),
+    ReportPlayer(SResult<()>),
+    SyncState(SResult<Option<SyncStateResponse>>),
+    /// Charts the host may select next, so that they can be downloaded
+    /// ahead of time.
+    Prefetch {
+        chart_ids: Vec<i32>,
+    },
+    /// When the room will be closed for inactivity, or `None` once activity
+    /// resumed. Only sent when the time is near.
+    RoomExpiry {
+        at: Option<DateTime<Utc>>,
+    },
+    BridgeSpectatorChat(SResult<()>),
+    /// Reply to `ChatWithId`. Retries of an already delivered message are
+    /// acknowledged without delivering it again.
+    ChatAck {
+        id: Uuid,
+        result: SResult<()>,
+    },
+    ForceCancelStart(SResult<()>),
+    Limits(ServerLimits),
+    SetRecording(SResult<()>),
+    /// Players who were (or are, if not confirmed) still playing
+    EndRound(SResult<Vec<i32>>),
+    SplitRoom(SResult<()>),
+    MergeRoom(SResult<()>),
+    /// A member of the room changed their display name.
+    UserRenamed {
+        user: i32,
+        name: String,
+    },
+    /// We were moved into another room by the host or the server operator,
+    /// after being told we left the old one.
+    MovedRoom(ClientRoomState),
+    /// Relayed from another member, see [`ClientCommand::ChartProgress`].
+    ChartProgress {
+        user: i32,
+        id: i32,
+        progress: f32,
+    },
+    ChartReady {
+        user: i32,
+        id: i32,
+    },
+    RoomList(SResult<Vec<RoomListing>>),
+    /// We were removed from `room` by its host, or kicked off the server by
+    /// the operator if `None`, in which case the connection is closed next.
+    Kicked {
+        room: Option<RoomId>,
+        banned: bool,
+    },
+    KickPlayer(SResult<()>),
+    BanPlayer(SResult<()>),
+    TransferHost(SResult<()>),
+    /// Everyone in the room, sent on joining it and whenever messages may
+    /// have been missed. Replaces what was known before.
+    Members(Vec<MemberStatus>),
+    /// A member lost or regained connection, or reported a new latency.
+    /// Phases are told by [`Message`]s instead.
+    MemberUpdate(MemberStatus),
+    /// The protocol version and the features used on this connection, or
+    /// why the client's version isn't supported.
+    Hello(SResult<(u16, Features)>),
+    SetDuel(SResult<()>),
+    StartDraft(SResult<()>),
+    CancelDraft(SResult<()>),
+    DraftTurn(SResult<()>),
+    SetMaxPlayers(SResult<()>),
+    /// Scores of the players who reported one this round, highest first.
+    /// Sent whenever one of them changes.
+    Scores {
+        round: u32,
+        scores: Vec<PlayerScore>,
+    },
+    MutePlayer(SResult<()>),
+    TimeoutPlayer(SResult<()>),
+    /// The latest messages of the room, oldest first, sent on joining it so
+    /// that the conversation can be followed. They were already acted upon
+    /// and don't change the room's state.
+    MessageHistory(Vec<Message>),
+    /// Sent right after a successful `Hello` by servers run in a cluster.
+    /// The token identifies the server, see [`ClientCommand::Route`].
+    Routing {
+        token: String,
+    },
+    /// Oldest first
+    FetchRoomHistory(SResult<Vec<PlayedChart>>),
+    SetRepeatPolicy(SResult<()>),
+    /// Sent after authenticating or resuming. Works for a single
+    /// [`ClientCommand::Resume`], and replaces the previous one.
+    ResumeToken(Uuid),
+    SelectRandomChart(SResult<i32>),
+    /// The screen `player`'s touch frames are relative to, sent when it's
+    /// set and for every other member on joining a room.
+    TouchSpace {
+        player: i32,
+        space: TouchSpace,
+    },
+    /// Replaces `Touches` with [`Features::TOUCH_PHASES`], numbered the same.
+    PhasedTouches {
+        player: i32,
+        round: u32,
+        seq: u32,
+        frames: Arc<Vec<TouchFrame>>,
+    },
+    /// See [`ClientCommand::Reliable`]
+    Ack {
+        seq: u32,
+    },
+    SetRoomMode(SResult<()>),
+    QueueChart(SResult<()>),
+    UnqueueChart(SResult<()>),
+    VoteChart(SResult<()>),
+    /// Never sent. Stands in for a command with tag `tag` this build doesn't
+    /// know, from a newer server, which was skipped. Its own tag is never
+    /// read either, and counts as unknown.
+    #[binary(skip)]
+    UnknownCommand {
+        tag: u8,
+    },
+    SetReadyCheck(SResult<()>),
+    /// Seconds left for players to get ready, sent every second while the
+    /// room's [`ReadyCheck`] runs.
+    StartCountdown {
+        secs: u16,
+    },
+    /// `client_time` is echoed, `server_time` was read when it arrived.
+    /// Half the round trip after `server_time` is roughly when the response
+    /// arrives, which gives the offset between both clocks.
+    SyncClock {
+        client_time: u64,
+        server_time: u64,
+    },
+    /// Sent along with [`Message::StartPlaying`] to those with
+    /// [`Features::CLOCK_SYNC`]: the chart begins at `start_at`, in
+    /// milliseconds since the Unix epoch by the server's clock, so that
+    /// everyone starts together regardless of latency.
+    GameStart {
+        round: u32,
+        start_at: u64,
+    },
+    /// Replaces `PhasedTouches` with [`Features::TOUCH_DELTA`], numbered the
+    /// same.
+    PackedTouches {
+        player: i32,
+        round: u32,
+        seq: u32,
+        frames: Arc<TouchBatch>,
+    },
+}
+
+impl Packet for ServerCommand {
+    fn unknown(tag: u8) -> Self {
+        Self::UnknownCommand { tag }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_packet, encode_packet};
+
+    fn round_trip<T: BinaryData>(value: &T) -> T {
+        let mut data = Vec::new();
+        encode_packet(value, &mut data).unwrap();
+        decode_packet(&data).unwrap()
+    }
+
+    /// Frames compared bit for bit, with each point as id, phase, x and y.
+    type FrameBits = (u32, Vec<(u8, TouchPhase, u16, u16)>);
+
+    fn frame_bits(frames: &[TouchFrame]) -> Vec<FrameBits> {
+        frames
+            .iter()
+            .map(|frame| {
+                let points = frame
+                    .points
+                    .iter()
+                    .map(|it| (it.id, it.phase, it.pos.x.to_bits(), it.pos.y.to_bits()))
+                    .collect();
+                (frame.time.to_bits(), points)
+            })
+            .collect()
+    }
+
+    fn judge_bits(judges: &[JudgeEvent]) -> Vec<(u32, u32, u32, Judgement)> {
+        judges
+            .iter()
+            .map(|it| (it.time.to_bits(), it.line_id, it.note_id, it.judgement))
+            .collect()
+    }
+
+    fn point(id: u8, phase: TouchPhase, x: f32, y: f32) -> TouchPoint {
+        TouchPoint {
+            id,
+            phase,
+            pos: CompactPos::new(x, y),
+        }
+    }
+
+    /// Exercises sign changes and wrapping in every delta.
+    fn touch_frames() -> Vec<TouchFrame> {
+        vec![
+            TouchFrame {
+                time: 0.5,
+                points: vec![point(0, TouchPhase::Down, -1., 1.)],
+            },
+            TouchFrame {
+                time: -3.25,
+                points: vec![
+                    point(0, TouchPhase::Move, 1., -1.),
+                    point(255, TouchPhase::Down, 0., 0.),
+                ],
+            },
+            TouchFrame {
+                time: f32::MAX,
+                points: Vec::new(),
+            },
+            TouchFrame {
+                time: f32::MIN_POSITIVE,
+                points: vec![
+                    point(255, TouchPhase::Up, 0.75, -0.5),
+                    point(0, TouchPhase::Up, 1., -1.),
+                ],
+            },
+        ]
+    }
+
+    fn judges() -> Vec<JudgeEvent> {
+        [
+            (0.1, 0, 0, Judgement::Perfect),
+            (0.2, 0, 1, Judgement::Perfect),
+            (-1.5, u32::MAX, u32::MAX, Judgement::Miss),
+            (f32::MAX, 3, 7, Judgement::HoldGood),
+            (0., 3, 8, Judgement::HoldGood),
+            (1e-30, 1, 2, Judgement::Bad),
+        ]
+        .into_iter()
+        .map(|(time, line_id, note_id, judgement)| JudgeEvent {
+            time,
+            line_id,
+            note_id,
+            judgement,
+        })
+        .collect()
+    }
+
+    #[test]
+    fn touch_batch_round_trips() {
+        let frames = touch_frames();
+        let decoded = round_trip(&TouchBatch::from(frames.clone()));
+        assert_eq!(frame_bits(&decoded), frame_bits(&frames));
+    }
+
+    #[test]
+    fn compressed_touch_batch_round_trips() {
+        // Repetitive enough for zstd to shrink; sent as is without it
+        let frames: Vec<_> = (0..1000).flat_map(|_| touch_frames()).collect();
+        let decoded = round_trip(&TouchBatch::new(frames.clone(), true));
+        assert_eq!(frame_bits(&decoded), frame_bits(&frames));
+    }
+
+    #[test]
+    fn empty_batches_round_trip() {
+        assert!(round_trip(&TouchBatch::default()).is_empty());
+        assert!(round_trip(&TouchBatch::new(Vec::new(), true)).is_empty());
+        assert!(round_trip(&JudgeBatch::default()).is_empty());
+    }
+
+    #[test]
+    fn large_batches_round_trip() {
+        let frames: Vec<_> = (0..=u8::MAX)
+            .map(|id| TouchFrame {
+                time: id as f32 / 60.,
+                points: (0..=u8::MAX)
+                    .map(|finger| point(finger, TouchPhase::Move, id as f32 / 255., -1.))
+                    .collect(),
+            })
+            .collect();
+        let decoded = round_trip(&TouchBatch::from(frames.clone()));
+        assert_eq!(frame_bits(&decoded), frame_bits(&frames));
+
+        let judges: Vec<_> = (0..u16::MAX as u32)
+            .map(|id| JudgeEvent {
+                time: id as f32,
+                line_id: id % 7,
+                note_id: id,
+                judgement: if id % 3 == 0 {
+                    Judgement::Good
+                } else {
+                    Judgement::Perfect
+                },
+            })
+            .collect();
+        let decoded = round_trip(&JudgeBatch::from(judges.clone()));
+        assert_eq!(judge_bits(&decoded), judge_bits(&judges));
+    }
+
+    #[test]
+    fn judge_batch_round_trips() {
+        let judges = judges();
+        let decoded = round_trip(&JudgeBatch::from(judges.clone()));
+        assert_eq!(judge_bits(&decoded), judge_bits(&judges));
+    }
+
+    #[test]
+    fn truncated_batches_fail() {
+        let mut touches = Vec::new();
+        encode_packet(&TouchBatch::from(touch_frames()), &mut touches).unwrap();
+        let mut judges = Vec::new();
+        encode_packet(&JudgeBatch::from(self::judges()), &mut judges).unwrap();
+        for len in 0..touches.len() {
+            assert!(decode_packet::<TouchBatch>(&touches[..len]).is_err());
+        }
+        for len in 0..judges.len() {
+            assert!(decode_packet::<JudgeBatch>(&judges[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_judge_runs_fail() {
+        // One judge announced, but an empty run and then one too long
+        assert!(decode_packet::<JudgeBatch>(&[1, 0, 0]).is_err());
+        assert!(decode_packet::<JudgeBatch>(&[1, 0, 2, 0, 0, 0, 0, 0, 0]).is_err());
+        // Unknown encoding of the touch batch
+        assert!(decode_packet::<TouchBatch>(&[2, 0]).is_err());
+    }
 }
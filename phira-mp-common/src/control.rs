@@ -0,0 +1,73 @@
+//! Protocol of the server's local control socket.
+//!
+//! Both sides exchange frames made of the encoded length as a little-endian
+//! `u32` followed by the encoded [`ControlCommand`] or [`ControlResponse`].
+//! Every command is answered by exactly one response.
+
+use crate::{decode_packet, encode_packet, BinaryData, RoomId, RoomState, UserInfo};
+use anyhow::{bail, Result};
+use phira_mp_macros::BinaryData;
+use std::io::ErrorKind;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const CONTROL_MAX_FRAME_SIZE: u32 = 2 * 1024 * 1024;
+
+#[derive(Debug, Clone, BinaryData)]
+pub enum ControlCommand {
+    ListRooms,
+    /// Disconnects the user, removing them from their room.
+    Kick {
+        user: i32,
+    },
+    /// Shows a message to every connected user.
+    Announce {
+        message: String,
+    },
+    /// Stops new rooms from being created, so that the server can be
+    /// restarted once the existing ones are gone.
+    Drain,
+}
+
+#[derive(Debug, Clone, BinaryData)]
+pub struct ControlRoom {
+    pub id: RoomId,
+    pub state: RoomState,
+    pub host: Option<i32>,
+    pub locked: bool,
+    pub users: Vec<UserInfo>,
+}
+
+#[derive(Debug, Clone, BinaryData)]
+pub enum ControlResponse {
+    Ok,
+    Rooms(Vec<ControlRoom>),
+    Error(String),
+}
+
+/// Reads a frame, `None` if the peer closed the connection in between.
+pub async fn read_control_frame<T: BinaryData>(
+    read: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<T>> {
+    let len = match read.read_u32_le().await {
+        Ok(len) => len,
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    if len > CONTROL_MAX_FRAME_SIZE {
+        bail!("control frame too large");
+    }
+    let mut buffer = vec![0; len as usize];
+    read.read_exact(&mut buffer).await?;
+    decode_packet(&buffer).map(Some)
+}
+
+pub async fn write_control_frame(
+    write: &mut (impl AsyncWrite + Unpin),
+    payload: &impl BinaryData,
+) -> Result<()> {
+    let mut buffer = Vec::new();
+    encode_packet(payload, &mut buffer)?;
+    write.write_u32_le(buffer.len() as u32).await?;
+    write.write_all(&buffer).await?;
+    Ok(())
+}
@@ -0,0 +1,177 @@
+//! Payload-free counterparts of protocol enums with stable names and codes.
+//!
+//! Codes match the tags used on the wire and never change once assigned, so
+//! they're safe to store in logs, dashboards and config files.
+
+use crate::{Message, RoomState};
+use anyhow::{anyhow, Error};
+use std::{fmt::Display, str::FromStr};
+
+macro_rules! stable_enum {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident = $code:literal => $str:literal,)* }) => {
+        $(#[$meta])*
+        #[repr(u8)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($variant = $code,)*
+        }
+
+        impl $name {
+            pub const ALL: &'static [Self] = &[$(Self::$variant,)*];
+
+            pub fn code(self) -> u8 {
+                self as u8
+            }
+
+            pub fn as_str(self) -> &'static str {
+                match self {
+                    $(Self::$variant => $str,)*
+                }
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    $($str => Ok(Self::$variant),)*
+                    _ => Err(anyhow!(concat!("invalid ", stringify!($name), ": {}"), s)),
+                }
+            }
+        }
+
+        impl TryFrom<u8> for $name {
+            type Error = Error;
+
+            fn try_from(code: u8) -> Result<Self, Self::Error> {
+                match code {
+                    $($code => Ok(Self::$variant),)*
+                    _ => Err(anyhow!(concat!("invalid ", stringify!($name), " code: {}"), code)),
+                }
+            }
+        }
+    };
+}
+
+stable_enum!(
+    RoomStateKind {
+        SelectChart = 0 => "select_chart",
+        WaitingForReady = 1 => "waiting_for_ready",
+        Playing = 2 => "playing",
+    }
+);
+
+stable_enum!(
+    MessageKind {
+        Chat = 0 => "chat",
+        CreateRoom = 1 => "create_room",
+        JoinRoom = 2 => "join_room",
+        LeaveRoom = 3 => "leave_room",
+        NewHost = 4 => "new_host",
+        SelectChart = 5 => "select_chart",
+        GameStart = 6 => "game_start",
+        Ready = 7 => "ready",
+        CancelReady = 8 => "cancel_ready",
+        CancelGame = 9 => "cancel_game",
+        StartPlaying = 10 => "start_playing",
+        Played = 11 => "played",
+        GameEnd = 12 => "game_end",
+        Abort = 13 => "abort",
+        LockRoom = 14 => "lock_room",
+        CycleRoom = 15 => "cycle_room",
+        SlowMode = 16 => "slow_mode",
+        SpectatorChat = 17 => "spectator_chat",
+        BridgeSpectatorChat = 18 => "bridge_spectator_chat",
+        Announcement = 19 => "announcement",
+        StartCancelled = 20 => "start_cancelled",
+        Recording = 21 => "recording",
+        EncryptedChat = 22 => "encrypted_chat",
+        HostTransferred = 23 => "host_transferred",
+        StartFailed = 24 => "start_failed",
+        Duel = 25 => "duel",
+        DuelWon = 26 => "duel_won",
+        DraftStarted = 27 => "draft_started",
+        DraftTurn = 28 => "draft_turn",
+        DraftCancelled = 29 => "draft_cancelled",
+        MaxPlayers = 30 => "max_players",
+        Muted = 31 => "muted",
+        TimedOut = 32 => "timed_out",
+        Whisper = 33 => "whisper",
+        RepeatPolicy = 34 => "repeat_policy",
+        ChartRepeated = 35 => "chart_repeated",
+        RoomMode = 36 => "room_mode",
+        ChartQueued = 37 => "chart_queued",
+        ChartUnqueued = 38 => "chart_unqueued",
+        ChartVoted = 39 => "chart_voted",
+        ReadyCheck = 40 => "ready_check",
+        MembersChurn = 41 => "members_churn",
+        Bot = 42 => "bot",
+    }
+);
+
+impl RoomState {
+    pub fn kind(&self) -> RoomStateKind {
+        match self {
+            Self::SelectChart(_) => RoomStateKind::SelectChart,
+            Self::WaitingForReady => RoomStateKind::WaitingForReady,
+            Self::Playing => RoomStateKind::Playing,
+        }
+    }
+}
+
+impl Message {
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            Self::Chat { .. } => MessageKind::Chat,
+            Self::CreateRoom { .. } => MessageKind::CreateRoom,
+            Self::JoinRoom { .. } => MessageKind::JoinRoom,
+            Self::LeaveRoom { .. } => MessageKind::LeaveRoom,
+            Self::NewHost { .. } => MessageKind::NewHost,
+            Self::SelectChart { .. } => MessageKind::SelectChart,
+            Self::GameStart { .. } => MessageKind::GameStart,
+            Self::Ready { .. } => MessageKind::Ready,
+            Self::CancelReady { .. } => MessageKind::CancelReady,
+            Self::CancelGame { .. } => MessageKind::CancelGame,
+            Self::StartPlaying { .. } => MessageKind::StartPlaying,
+            Self::Played { .. } => MessageKind::Played,
+            Self::GameEnd { .. } => MessageKind::GameEnd,
+            Self::Abort { .. } => MessageKind::Abort,
+            Self::LockRoom { .. } => MessageKind::LockRoom,
+            Self::CycleRoom { .. } => MessageKind::CycleRoom,
+            Self::SlowMode { .. } => MessageKind::SlowMode,
+            Self::SpectatorChat { .. } => MessageKind::SpectatorChat,
+            Self::BridgeSpectatorChat { .. } => MessageKind::BridgeSpectatorChat,
+            Self::Announcement { .. } => MessageKind::Announcement,
+            Self::StartCancelled => MessageKind::StartCancelled,
+            Self::Recording { .. } => MessageKind::Recording,
+            Self::EncryptedChat { .. } => MessageKind::EncryptedChat,
+            Self::HostTransferred { .. } => MessageKind::HostTransferred,
+            Self::StartFailed { .. } => MessageKind::StartFailed,
+            Self::Duel { .. } => MessageKind::Duel,
+            Self::DuelWon { .. } => MessageKind::DuelWon,
+            Self::DraftStarted { .. } => MessageKind::DraftStarted,
+            Self::DraftTurn { .. } => MessageKind::DraftTurn,
+            Self::DraftCancelled => MessageKind::DraftCancelled,
+            Self::MaxPlayers { .. } => MessageKind::MaxPlayers,
+            Self::Muted { .. } => MessageKind::Muted,
+            Self::TimedOut { .. } => MessageKind::TimedOut,
+            Self::Whisper { .. } => MessageKind::Whisper,
+            Self::RepeatPolicy { .. } => MessageKind::RepeatPolicy,
+            Self::ChartRepeated { .. } => MessageKind::ChartRepeated,
+            Self::RoomMode { .. } => MessageKind::RoomMode,
+            Self::ChartQueued { .. } => MessageKind::ChartQueued,
+            Self::ChartUnqueued { .. } => MessageKind::ChartUnqueued,
+            Self::ChartVoted { .. } => MessageKind::ChartVoted,
+            Self::ReadyCheck { .. } => MessageKind::ReadyCheck,
+            Self::MembersChurn { .. } => MessageKind::MembersChurn,
+            Self::Bot { .. } => MessageKind::Bot,
+        }
+    }
+}
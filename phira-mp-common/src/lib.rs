@@ -4,22 +4,113 @@ pub use bin::*;
 mod command;
 pub use command::*;
 
-use anyhow::{bail, Error, Result};
-use std::{future::Future, marker::PhantomData, sync::Arc, time::Duration};
+mod control;
+pub use control::*;
+
+mod kind;
+pub use kind::*;
+
+#[cfg(feature = "netsim")]
+mod netsim;
+#[cfg(feature = "netsim")]
+pub use netsim::*;
+
+mod replay;
+pub use replay::*;
+
+#[cfg(feature = "websocket")]
+mod ws;
+#[cfg(feature = "websocket")]
+pub use ws::*;
+
+use anyhow::{anyhow, bail, Error, Result};
+use std::{
+    future::Future,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-    sync::mpsc,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, watch, Notify},
     task::JoinHandle,
+    time,
 };
-use tracing::{error, trace, warn};
+use tracing::{debug, error, info, trace, warn};
 
+/// Defaults, servers may tell clients otherwise through [`ServerLimits`].
 pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(3);
 pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(2);
 pub const HEARTBEAT_DISCONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 
-pub fn encode_packet(payload: &impl BinaryData, vec: &mut Vec<u8>) {
-    BinaryWriter::new(vec).write(payload).unwrap();
+/// If a single packet can't be written within this duration, the peer is
+/// considered to have stopped reading and the connection is closed.
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bytes an encrypted chat payload may have on top of the UTF-8 message:
+/// a 24-byte nonce and a 16-byte tag.
+pub const ENCRYPTED_CHAT_OVERHEAD: usize = 40;
+
+/// Most judge batches resent for one `ClientCommand::ResendJudges`.
+pub const RESEND_JUDGES_MAX: usize = 64;
+
+/// How long a `ClientCommand::Reliable` waits for its `ServerCommand::Ack`
+/// before it's sent again.
+pub const RELIABLE_RESEND_AFTER: Duration = Duration::from_millis(500);
+
+/// Most `ClientCommand::Reliable` a client keeps waiting for their `Ack`.
+/// Older ones are given up on beyond this.
+pub const RELIABLE_PENDING_MAX: usize = 256;
+
+/// Time between two `ClientCommand::ScoreUpdate`s of a player.
+pub const SCORE_UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Most players a room may have, monitors and spectators aside. Rooms can
+/// be limited to fewer with `ClientCommand::SetMaxPlayers`.
+pub const ROOM_MAX_PLAYERS: u8 = 8;
+
+/// Charts of this many rounds before count as played recently, see
+/// `RepeatPolicy`.
+pub const RECENT_CHARTS: usize = 3;
+
+/// Most charts a room's queue holds, see `QueuedChart`.
+pub const CHART_QUEUE_MAX: usize = 20;
+
+/// Most charts a member may have in a room's queue at once.
+pub const CHART_QUEUE_PER_USER: usize = 3;
+
+/// Chat from this user comes from the server's bot, named in
+/// `ServerLimits::bot_name`, for clients without `Features::BOT_MESSAGES`.
+/// Never a real user.
+pub const BOT_USER_ID: i32 = 0;
+
+/// Log targets of subsystems whose verbosity can be raised at runtime.
+pub const LOG_PROTOCOL: &str = "phira_mp::protocol";
+pub const LOG_HEARTBEAT: &str = "phira_mp::heartbeat";
+
+/// Starts the line `ROUTE <token>\n` that clients given a routing token
+/// write first on later connections, ahead of TLS if any, so that L4 proxies
+/// can read it in the clear. WebSocket connections carry the token in their
+/// URL instead.
+pub const ROUTE_PREFIX: &[u8] = b"ROUTE ";
+
+/// DNS-SD service type servers advertise on the local network.
+pub const LAN_SERVICE_TYPE: &str = "_phira-mp._tcp.local.";
+
+/// Milliseconds since the Unix epoch by the local clock, as used for
+/// [`ServerCommand::SyncClock`].
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |it| it.as_millis() as u64)
+}
+
+/// Fails for variants that are never sent, see `#[binary(skip)]`.
+pub fn encode_packet(payload: &impl BinaryData, vec: &mut Vec<u8>) -> Result<()> {
+    BinaryWriter::new(vec).write(payload)
 }
 
 pub fn decode_packet<T>(data: &[u8]) -> Result<T>
@@ -29,10 +120,33 @@ where
     BinaryReader::new(data).read()
 }
 
+/// What a [`Stream`] receives. Packets whose tag this build doesn't know,
+/// e.g. commands added in a newer version of the peer, are skipped and
+/// handed over as [`Self::unknown`] instead of closing the connection.
+pub trait Packet: BinaryData {
+    fn unknown(tag: u8) -> Self;
+}
+
+/// Connection a [`Stream`] runs on, e.g. a `TcpStream` or a TLS stream on top
+/// of one.
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Transport for T {}
+
+type SentHook<S> = Box<dyn Fn(&S, usize) + Send + Sync>;
+
 pub struct Stream<S, R> {
     version: u8,
 
     send_tx: Arc<mpsc::Sender<S>>,
+    /// Notified whenever the send loop has written a packet, or stopped
+    sent: Arc<Notify>,
+    close_reason: Arc<OnceLock<String>>,
+    trace: Arc<AtomicBool>,
+    on_sent: Arc<OnceLock<SentHook<S>>>,
+    lossy: bool,
+    /// Set once the receive loop stopped, see [`Self::closed`]
+    closed: watch::Receiver<bool>,
 
     send_task_handle: JoinHandle<()>,
     recv_task_handle: JoinHandle<Result<()>>,
@@ -43,35 +157,67 @@ pub struct Stream<S, R> {
 impl<S, R> Stream<S, R>
 where
     S: BinaryData + std::fmt::Debug + Send + Sync + 'static,
-    R: BinaryData + std::fmt::Debug + Send + 'static,
+    R: Packet + std::fmt::Debug + Send + 'static,
 {
     pub async fn new<F>(
         version: Option<u8>,
-        stream: TcpStream,
+        stream: impl Transport,
         mut handler: Box<dyn FnMut(Arc<mpsc::Sender<S>>, R) -> F + Send + Sync>,
     ) -> Result<Self>
     where
         F: Future<Output = ()> + Send + 'static,
     {
-        stream.set_nodelay(true)?;
-        let (mut read, mut write) = stream.into_split();
+        let (mut read, mut write) = tokio::io::split(stream);
         let version = if let Some(version) = version {
             write.write_u8(version).await?;
+            write.flush().await?;
             version
         } else {
             read.read_u8().await?
         };
 
-        let (send_tx, mut send_rx) = mpsc::channel(1024);
+        let (send_tx, send_rx) = mpsc::channel(1024);
+        #[cfg(feature = "netsim")]
+        let (send_rx, lossy) = match NetSim::current() {
+            Some(sim) => {
+                let lossy = sim.loss > 0.;
+                (sim.wrap(send_rx), lossy)
+            }
+            None => (send_rx, false),
+        };
+        #[cfg(not(feature = "netsim"))]
+        let lossy = false;
+        let mut send_rx = send_rx;
         let send_tx = Arc::new(send_tx);
+        let sent = Arc::new(Notify::new());
+        let close_reason = Arc::new(OnceLock::new());
+        let stop_recv = Arc::new(Notify::new());
+        let trace = Arc::new(AtomicBool::new(false));
+        let on_sent = Arc::new(OnceLock::<SentHook<S>>::new());
         let send_task_handle = tokio::spawn({
+            let close_reason = Arc::clone(&close_reason);
+            let trace = Arc::clone(&trace);
+            let on_sent = Arc::clone(&on_sent);
+            let stop_recv = Arc::clone(&stop_recv);
+            let sent = Arc::clone(&sent);
             async move {
                 let mut buffer = Vec::new();
                 let mut len_buf = [0u8; 5];
                 while let Some(payload) = send_rx.recv().await {
                     buffer.clear();
-                    encode_packet(&payload, &mut buffer);
-                    trace!("sending {} bytes ({payload:?}): {buffer:?}", buffer.len());
+                    if let Err(err) = encode_packet(&payload, &mut buffer) {
+                        error!("failed to encode {payload:?}, not sent: {err:?}");
+                        continue;
+                    }
+                    if trace.load(Ordering::Relaxed) {
+                        info!(target: LOG_PROTOCOL, "sending {} bytes: {payload:?}", buffer.len());
+                    } else {
+                        trace!(
+                            target: LOG_PROTOCOL,
+                            "sending {} bytes ({payload:?}): {buffer:?}",
+                            buffer.len()
+                        );
+                    }
 
                     let mut x = buffer.len() as u32;
                     let mut n = 0;
@@ -86,58 +232,81 @@ where
                         }
                     }
 
-                    if let Err(err) = async {
+                    let err = match time::timeout(SEND_TIMEOUT, async {
                         write.write_all(&len_buf[..n]).await?;
                         write.write_all(&buffer).await?;
+                        // Buffered transports like WebSocket hold on to it
+                        // otherwise
+                        write.flush().await?;
                         Ok::<_, Error>(())
-                    }
+                    })
                     .await
                     {
-                        error!("failed to send: {err:?}");
-                    }
+                        Ok(Ok(())) => {
+                            if let Some(hook) = on_sent.get() {
+                                hook(&payload, n + buffer.len());
+                            }
+                            sent.notify_waiters();
+                            continue;
+                        }
+                        Ok(Err(err)) => err.context("failed to send"),
+                        Err(_) => anyhow!("peer stopped reading, send timed out"),
+                    };
+                    error!("closing connection: {err:?}");
+                    let _ = close_reason.set(err.to_string());
+                    stop_recv.notify_one();
+                    break;
                 }
+                // Closes the channel before waking those waiting on it
+                drop(send_rx);
+                sent.notify_waiters();
             }
         });
 
+        let (closed_tx, closed) = watch::channel(false);
         let recv_task_handle = tokio::spawn({
             let send_tx = Arc::clone(&send_tx);
-            #[allow(clippy::read_zero_byte_vec)]
-            async move {
+            let trace = Arc::clone(&trace);
+            let recv = async move {
                 let mut buffer = Vec::new();
                 loop {
-                    let mut len = 0u32;
-                    let mut pos = 0;
-                    loop {
-                        let byte = read.read_u8().await?;
-                        len |= ((byte & 0x7f) as u32) << pos;
-                        pos += 7;
-                        if byte & 0x80 == 0 {
-                            break;
-                        }
-                        if pos > 32 {
-                            bail!("invalid length");
-                        }
-                    }
-                    if len > 2 * 1024 * 1024 {
-                        bail!("data packet too large");
+                    tokio::select! {
+                        biased;
+                        _ = stop_recv.notified() => break,
+                        res = read_frame(&mut read, &mut buffer) => res?,
                     }
-                    let len = len as usize;
-
-                    buffer.resize(len, 0);
-                    read.read_exact(&mut buffer).await?;
-                    trace!("received {} bytes: {buffer:?}", buffer.len());
+                    trace!(target: LOG_PROTOCOL, "received {} bytes: {buffer:?}", buffer.len());
 
                     let payload: R = match decode_packet(&buffer) {
                         Ok(val) => val,
+                        // Frames are length-prefixed, so nothing else is lost
+                        Err(_) if buffer.first().is_some_and(|it| !R::knows_tag(*it)) => {
+                            debug!(
+                                target: LOG_PROTOCOL,
+                                "skipped {} bytes with unknown tag {}",
+                                buffer.len(),
+                                buffer[0]
+                            );
+                            R::unknown(buffer[0])
+                        }
                         Err(err) => {
                             warn!("invalid packet: {err:?} {buffer:?}");
                             break;
                         }
                     };
-                    trace!("decodes to {payload:?}");
+                    if trace.load(Ordering::Relaxed) {
+                        info!(target: LOG_PROTOCOL, "received {} bytes: {payload:?}", buffer.len());
+                    } else {
+                        trace!(target: LOG_PROTOCOL, "decodes to {payload:?}");
+                    }
                     handler(Arc::clone(&send_tx), payload).await;
                 }
                 Ok(())
+            };
+            async move {
+                // Also when aborted by `close`
+                let _closed = ClosedGuard(closed_tx);
+                recv.await
             }
         });
 
@@ -145,11 +314,17 @@ where
             version,
 
             send_tx,
+            sent,
+            close_reason,
+            trace,
+            on_sent,
+            lossy,
+            closed,
 
             send_task_handle,
             recv_task_handle,
 
-            _marker: PhantomData::default(),
+            _marker: PhantomData,
         })
     }
 
@@ -157,15 +332,121 @@ where
         self.version
     }
 
+    /// Why the stream was closed by the send loop, if it was.
+    pub fn close_reason(&self) -> Option<&str> {
+        self.close_reason.get().map(String::as_str)
+    }
+
+    /// Stops sending and receiving, closing the connection. Packets still
+    /// queued are dropped.
+    pub fn close(&self) {
+        self.send_task_handle.abort();
+        self.recv_task_handle.abort();
+    }
+
+    /// Waits until nothing more is received, usually because the peer
+    /// closed the connection.
+    pub async fn closed(&self) {
+        let mut closed = self.closed.clone();
+        let _ = closed.wait_for(|it| *it).await;
+    }
+
+    /// Logs every frame at info level, regardless of the usual trace-level
+    /// filtering of [`LOG_PROTOCOL`].
+    pub fn set_trace(&self, trace: bool) {
+        self.trace.store(trace, Ordering::Relaxed);
+    }
+
+    pub fn is_traced(&self) -> bool {
+        self.trace.load(Ordering::Relaxed)
+    }
+
+    /// Calls `hook` with every packet written to the connection, along with
+    /// its size there. Only the first hook set is kept.
+    pub fn on_sent(&self, hook: impl Fn(&S, usize) + Send + Sync + 'static) {
+        let _ = self.on_sent.set(Box::new(hook));
+    }
+
+    /// Whether packets sent on this stream may never arrive. TCP and
+    /// WebSocket deliver everything, so only a simulated network (the
+    /// `netsim` feature) dropping packets makes a stream lossy.
+    pub fn is_lossy(&self) -> bool {
+        self.lossy
+    }
+
     pub async fn send(&self, payload: S) -> Result<()> {
-        self.send_tx.send(payload).await?;
+        if self.send_tx.send(payload).await.is_err() {
+            bail!(self.closed_error());
+        }
         Ok(())
     }
 
     pub fn blocking_send(&self, payload: S) -> Result<()> {
-        self.send_tx.blocking_send(payload)?;
+        if self.send_tx.blocking_send(payload).is_err() {
+            bail!(self.closed_error());
+        }
         Ok(())
     }
+
+    /// Packets waiting to be written to the connection.
+    pub fn queue_len(&self) -> usize {
+        self.send_tx.max_capacity() - self.send_tx.capacity()
+    }
+
+    /// Like [`Self::send`], but first waits until fewer than `threshold`
+    /// packets are queued, so that a saturated uplink slows the caller down
+    /// instead of adding latency.
+    pub async fn send_throttled(&self, payload: S, threshold: usize) -> Result<()> {
+        loop {
+            let sent = self.sent.notified();
+            tokio::pin!(sent);
+            sent.as_mut().enable();
+            if self.queue_len() < threshold || self.send_tx.is_closed() {
+                break;
+            }
+            sent.await;
+        }
+        self.send(payload).await
+    }
+
+    fn closed_error(&self) -> String {
+        match self.close_reason() {
+            Some(reason) => format!("connection closed: {reason}"),
+            None => "connection closed".to_owned(),
+        }
+    }
+}
+
+/// Marks a [`Stream`] as closed once its receive loop is gone.
+struct ClosedGuard(watch::Sender<bool>);
+
+impl Drop for ClosedGuard {
+    fn drop(&mut self) {
+        self.0.send_replace(true);
+    }
+}
+
+async fn read_frame(read: &mut (impl AsyncRead + Unpin), buffer: &mut Vec<u8>) -> Result<()> {
+    let mut len = 0u32;
+    let mut pos = 0;
+    loop {
+        let byte = read.read_u8().await?;
+        len |= ((byte & 0x7f) as u32) << pos;
+        pos += 7;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if pos > 32 {
+            bail!("invalid length");
+        }
+    }
+    if len > 2 * 1024 * 1024 {
+        bail!("data packet too large");
+    }
+
+    buffer.resize(len as usize, 0);
+    read.read_exact(buffer).await?;
+    Ok(())
 }
 
 impl<S, R> Drop for Stream<S, R> {
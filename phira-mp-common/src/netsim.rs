@@ -0,0 +1,184 @@
+//! Simulated bad networks, for development only.
+//!
+//! When a [`NetSim`] is installed (or configured through the environment),
+//! every [`Stream`](crate::Stream) created afterwards delays, reorders and
+//! drops the packets it sends. Only the sending side is affected, so to
+//! degrade both directions either enable it on both ends, or run the server
+//! in the same process. Random decisions come from a seeded generator, so a
+//! given configuration behaves the same on every run.
+
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc,
+    time::{self, Instant},
+};
+
+/// How long a packet held back for reordering waits for a successor before
+/// it's sent anyway.
+const REORDER_HOLD: Duration = Duration::from_millis(100);
+
+static INSTALLED: Mutex<Option<NetSim>> = Mutex::new(None);
+static FROM_ENV: OnceLock<Option<NetSim>> = OnceLock::new();
+static STREAMS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Default)]
+pub struct NetSim {
+    /// Base delay added to every packet
+    pub latency: Duration,
+    /// Maximum random deviation from `latency`, in both directions
+    pub jitter: Duration,
+    /// Probability of a packet being dropped
+    pub loss: f32,
+    /// Probability of a packet being sent after the next one
+    pub reorder: f32,
+    pub seed: u64,
+}
+
+impl NetSim {
+    /// Reads `PHIRA_MP_NETSIM_LATENCY_MS`, `PHIRA_MP_NETSIM_JITTER_MS`,
+    /// `PHIRA_MP_NETSIM_LOSS`, `PHIRA_MP_NETSIM_REORDER` and
+    /// `PHIRA_MP_NETSIM_SEED`. Returns `None` if none of them is set.
+    pub fn from_env() -> Option<Self> {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            env::var(name).ok().and_then(|it| it.parse().ok())
+        }
+        let latency = var::<u64>("PHIRA_MP_NETSIM_LATENCY_MS");
+        let jitter = var::<u64>("PHIRA_MP_NETSIM_JITTER_MS");
+        let loss = var::<f32>("PHIRA_MP_NETSIM_LOSS");
+        let reorder = var::<f32>("PHIRA_MP_NETSIM_REORDER");
+        if latency.is_none() && jitter.is_none() && loss.is_none() && reorder.is_none() {
+            return None;
+        }
+        Some(Self {
+            latency: Duration::from_millis(latency.unwrap_or_default()),
+            jitter: Duration::from_millis(jitter.unwrap_or_default()),
+            loss: loss.unwrap_or_default(),
+            reorder: reorder.unwrap_or_default(),
+            seed: var("PHIRA_MP_NETSIM_SEED").unwrap_or_default(),
+        })
+    }
+
+    /// Applies this configuration to streams created from now on, taking
+    /// precedence over the environment.
+    pub fn install(self) {
+        *INSTALLED.lock().unwrap() = Some(self);
+    }
+
+    /// Stops simulating for streams created from now on, including any
+    /// configuration from the environment.
+    pub fn uninstall() {
+        *INSTALLED.lock().unwrap() = Some(Self::default());
+    }
+
+    pub(crate) fn current() -> Option<Self> {
+        INSTALLED
+            .lock()
+            .unwrap()
+            .clone()
+            .or_else(|| FROM_ENV.get_or_init(Self::from_env).clone())
+            .filter(|it| !it.is_noop())
+    }
+
+    fn is_noop(&self) -> bool {
+        self.latency.is_zero() && self.jitter.is_zero() && self.loss <= 0. && self.reorder <= 0.
+    }
+
+    /// Returns a receiver yielding what's sent to `rx`, as it would arrive
+    /// over the simulated network.
+    pub(crate) fn wrap<T: Send + 'static>(self, mut rx: mpsc::Receiver<T>) -> mpsc::Receiver<T> {
+        // Each stream gets its own sequence, still determined by the seed.
+        let mut rng = SplitMix64(
+            self.seed
+                .wrapping_add(STREAMS.fetch_add(1, Ordering::Relaxed)),
+        );
+        let (delayed_tx, mut delayed_rx) = mpsc::unbounded_channel();
+        let (out_tx, out_rx) = mpsc::channel(1024);
+
+        // Packets are timestamped as soon as they're sent so that delays
+        // don't add up while earlier packets are still waiting.
+        tokio::spawn(async move {
+            while let Some(payload) = rx.recv().await {
+                if rng.next_f32() < self.loss {
+                    continue;
+                }
+                let offset = rng.next_f32() * 2. - 1.;
+                let delay = if offset >= 0. {
+                    self.latency + self.jitter.mul_f32(offset)
+                } else {
+                    self.latency.saturating_sub(self.jitter.mul_f32(-offset))
+                };
+                let reorder = rng.next_f32() < self.reorder;
+                if delayed_tx
+                    .send((Instant::now() + delay, reorder, payload))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        tokio::spawn(async move {
+            let mut held = None;
+            loop {
+                let item = if held.is_some() {
+                    match time::timeout(REORDER_HOLD, delayed_rx.recv()).await {
+                        Ok(item) => item,
+                        Err(_) => {
+                            if out_tx.send(held.take().unwrap()).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                    }
+                } else {
+                    delayed_rx.recv().await
+                };
+                let Some((deadline, reorder, payload)) = item else {
+                    break;
+                };
+                time::sleep_until(deadline).await;
+                if reorder && held.is_none() {
+                    held = Some(payload);
+                    continue;
+                }
+                if out_tx.send(payload).await.is_err() {
+                    break;
+                }
+                if let Some(payload) = held.take() {
+                    if out_tx.send(payload).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            if let Some(payload) = held {
+                let _ = out_tx.send(payload).await;
+            }
+        });
+
+        out_rx
+    }
+}
+
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
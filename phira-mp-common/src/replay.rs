@@ -0,0 +1,153 @@
+//! File format for recorded realtime data.
+//!
+//! A file starts with [`REPLAY_MAGIC`] and a [`ReplayHeader`], followed by
+//! [`ReplayEvent`]s, each prefixed by its encoded length as a little-endian
+//! `u32`. Files cut off in the middle of an event (e.g. after a crash) are
+//! read up to the last complete event.
+
+use crate::{decode_packet, encode_packet, JudgeBatch, TouchFrame};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use phira_mp_macros::BinaryData;
+use std::{
+    io::{ErrorKind, Read, Write},
+    sync::Arc,
+    time::Instant,
+};
+
+pub const REPLAY_MAGIC: &[u8; 4] = b"PMRP";
+pub const REPLAY_VERSION: u8 = 3;
+const REPLAY_MAX_EVENT_SIZE: u32 = 2 * 1024 * 1024;
+
+#[derive(Debug, Clone, BinaryData)]
+pub struct ReplayHeader {
+    pub version: u8,
+    pub started_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, BinaryData)]
+pub enum ReplayDirection {
+    Incoming,
+    Outgoing,
+}
+
+#[derive(Debug, Clone, BinaryData)]
+pub enum ReplayData {
+    Touches { frames: Arc<Vec<TouchFrame>> },
+    Judges { judges: Arc<JudgeBatch> },
+}
+
+#[derive(Debug, Clone, BinaryData)]
+pub struct ReplayEvent {
+    /// Milliseconds since the recording started
+    pub time: u32,
+    pub direction: ReplayDirection,
+    pub player: i32,
+    pub data: ReplayData,
+}
+
+pub struct ReplayWriter<W: Write> {
+    inner: W,
+    start: Instant,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> ReplayWriter<W> {
+    pub fn new(mut inner: W) -> Result<Self> {
+        let mut buffer = Vec::new();
+        encode_packet(
+            &ReplayHeader {
+                version: REPLAY_VERSION,
+                started_at: Utc::now(),
+            },
+            &mut buffer,
+        )?;
+        inner.write_all(REPLAY_MAGIC)?;
+        inner.write_all(&buffer)?;
+        Ok(Self {
+            inner,
+            start: Instant::now(),
+            buffer,
+        })
+    }
+
+    pub fn write(
+        &mut self,
+        direction: ReplayDirection,
+        player: i32,
+        data: ReplayData,
+    ) -> Result<()> {
+        self.buffer.clear();
+        encode_packet(
+            &ReplayEvent {
+                time: self.start.elapsed().as_millis() as u32,
+                direction,
+                player,
+                data,
+            },
+            &mut self.buffer,
+        )?;
+        self.inner
+            .write_all(&(self.buffer.len() as u32).to_le_bytes())?;
+        self.inner.write_all(&self.buffer)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.inner.flush()?;
+        Ok(())
+    }
+}
+
+pub struct ReplayReader<R: Read> {
+    inner: R,
+    header: ReplayHeader,
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> ReplayReader<R> {
+    pub fn new(mut inner: R) -> Result<Self> {
+        let mut magic = [0; 4];
+        inner.read_exact(&mut magic)?;
+        if &magic != REPLAY_MAGIC {
+            bail!("not a replay file");
+        }
+        // The header has a fixed size: version (1) + timestamp (8)
+        let mut buffer = vec![0; 9];
+        inner.read_exact(&mut buffer)?;
+        let header: ReplayHeader = decode_packet(&buffer)?;
+        if header.version != REPLAY_VERSION {
+            bail!("unsupported replay version: {}", header.version);
+        }
+        Ok(Self {
+            inner,
+            header,
+            buffer,
+        })
+    }
+
+    pub fn header(&self) -> &ReplayHeader {
+        &self.header
+    }
+
+    /// `None` at the end of the file.
+    pub fn next_event(&mut self) -> Result<Option<ReplayEvent>> {
+        let mut len = [0; 4];
+        match self.inner.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        let len = u32::from_le_bytes(len);
+        if len > REPLAY_MAX_EVENT_SIZE {
+            bail!("replay event too large");
+        }
+        self.buffer.resize(len as usize, 0);
+        match self.inner.read_exact(&mut self.buffer) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        decode_packet(&self.buffer).map(Some)
+    }
+}
@@ -0,0 +1,100 @@
+//! Carries the protocol over WebSocket for clients that can't open raw TCP
+//! connections, e.g. in browsers. The byte stream is sent as binary messages,
+//! so that the framing on top stays the same as over TCP.
+
+use futures_util::{ready, Sink, Stream as _};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::{
+    tungstenite::{Error as WsError, Message as WsMessage},
+    WebSocketStream,
+};
+
+pub use tokio_tungstenite::{accept_async as accept_ws, client_async as connect_ws};
+
+/// A WebSocket connection read and written as a byte stream, usable as a
+/// [`Transport`](crate::Transport).
+pub struct WsTransport<S> {
+    inner: WebSocketStream<S>,
+    /// Rest of the last message that didn't fit into the reader's buffer
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+impl<S> WsTransport<S> {
+    pub fn new(inner: WebSocketStream<S>) -> Self {
+        Self {
+            inner,
+            pending: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+fn to_io(err: WsError) -> io::Error {
+    match err {
+        WsError::Io(err) => err,
+        WsError::ConnectionClosed | WsError::AlreadyClosed => io::ErrorKind::BrokenPipe.into(),
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsTransport<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            if self.pos < self.pending.len() {
+                let len = buf.remaining().min(self.pending.len() - self.pos);
+                let pos = self.pos;
+                buf.put_slice(&self.pending[pos..pos + len]);
+                self.pos += len;
+                return Poll::Ready(Ok(()));
+            }
+            match ready!(Pin::new(&mut self.inner).poll_next(cx)) {
+                Some(Ok(WsMessage::Binary(data))) => {
+                    self.pending = data;
+                    self.pos = 0;
+                }
+                // Pings are answered by the WebSocket layer itself
+                Some(Ok(WsMessage::Ping(_) | WsMessage::Pong(_) | WsMessage::Frame(_))) => {}
+                Some(Ok(WsMessage::Text(_))) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unexpected text message",
+                    )))
+                }
+                Some(Ok(WsMessage::Close(_))) | None => return Poll::Ready(Ok(())),
+                Some(Err(err)) => return Poll::Ready(Err(to_io(err))),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsTransport<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(Pin::new(&mut self.inner).poll_ready(cx)).map_err(to_io)?;
+        Pin::new(&mut self.inner)
+            .start_send(WsMessage::Binary(buf.to_vec()))
+            .map_err(to_io)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx).map_err(to_io)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx).map_err(to_io)
+    }
+}
@@ -5,7 +5,7 @@ use syn::{
     PathArguments, Type, Variant,
 };
 
-#[proc_macro_derive(BinaryData)]
+#[proc_macro_derive(BinaryData, attributes(binary))]
 pub fn derive_model_ex(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let res = build_derive(input.ident, input.data);
@@ -95,10 +95,25 @@ fn build_derive_struct(name: Ident, fields: Fields) -> TokenStream {
     }
 }
 
+/// Whether the variant is marked `#[binary(skip)]`. It keeps its tag, so
+/// that those of later variants don't move, but is never read or written.
+fn is_skipped(variant: &Variant) -> bool {
+    variant.attrs.iter().any(|attr| {
+        attr.path.is_ident("binary") && attr.parse_args::<Ident>().is_ok_and(|it| it == "skip")
+    })
+}
+
 fn build_derive_enum(name: Ident, variants: Vec<Variant>) -> TokenStream {
+    let skipped: Vec<_> = variants
+        .iter()
+        .enumerate()
+        .filter(|(_, it)| is_skipped(it))
+        .map(|(i, _)| i as u8)
+        .collect();
     let read_arms = variants
         .iter()
         .enumerate()
+        .filter(|(_, it)| !is_skipped(it))
         .map(|(i, it)| {
             let i = i as u8;
             let name = &it.ident;
@@ -132,6 +147,16 @@ fn build_derive_enum(name: Ident, variants: Vec<Variant>) -> TokenStream {
     let write_arms = variants.iter().enumerate().map(|(i, it)| {
         let i = i as u8;
         let name = &it.ident;
+        if is_skipped(it) {
+            let pattern = match &it.fields {
+                Fields::Unit => quote! {},
+                Fields::Unnamed(_) => quote! { (..) },
+                Fields::Named(_) => quote! { { .. } },
+            };
+            return quote! {
+                Self::#name #pattern => anyhow::bail!("{} is never written", stringify!(#name))
+            };
+        }
         match &it.fields {
             Fields::Unit => quote! { Self::#name => w.write_val(#i)? },
             Fields::Unnamed(fields) => {
@@ -172,6 +197,7 @@ fn build_derive_enum(name: Ident, variants: Vec<Variant>) -> TokenStream {
             }
         }
     });
+    let count = variants.len() as u8;
     quote! {
         impl crate::BinaryData for #name {
             fn read_binary(r: &mut crate::BinaryReader<'_>) -> Result<Self> {
@@ -180,6 +206,11 @@ fn build_derive_enum(name: Ident, variants: Vec<Variant>) -> TokenStream {
                 })
             }
 
+            fn knows_tag(tag: u8) -> bool {
+                let skipped: &[u8] = &[#(#skipped),*];
+                tag < #count && !skipped.contains(&tag)
+            }
+
             fn write_binary(&self, w: &mut crate::BinaryWriter<'_>) -> Result<()> {
                 match self {
                     #(#write_arms,)*
@@ -199,7 +230,7 @@ fn field_read(name: &Option<Ident>, typ: &TypeInfo) -> TokenStream {
     let val = match (typ.is_arc, typ.is_vec) {
         (false, false) => quote! { r.read()? },
         (false, true) => quote! { r.array()? },
-        (true, false) => quote! { r.read()?.into() },
+        (true, false) => quote! { std::sync::Arc::new(r.read()?) },
         (true, true) => quote! { r.array()?.into() },
     };
     if let Some(name) = name {
@@ -237,6 +268,8 @@ fn struct_write(fields: &[(Option<Ident>, TypeInfo)], use_self: bool) -> TokenSt
 fn field_write(field: TokenStream, typ: &TypeInfo) -> TokenStream {
     if typ.is_vec {
         quote! { w.array(#field)?; }
+    } else if typ.is_arc {
+        quote! { w.write(&**#field)?; }
     } else {
         quote! { w.write(#field)?; }
     }
@@ -1,5 +1,7 @@
 
 create-id-occupied = Room ID is occupied
+create-draining = Server is about to restart, no new rooms can be created
+create-too-many-rooms = Too many rooms on this server
 
 join-game-ongoing = Game is ongoing
 join-room-full = Room is full
@@ -7,3 +9,87 @@ join-room-locked = Room is locked
 join-cant-monitor = Permission denied. You can't monitor this room.
 
 start-no-chart-selected = No chart selected
+start-nothing-to-cancel = There's no start to cancel
+
+echo-payload-too-large = Echo payload is too large (at most { $max } bytes)
+
+rate-limited = You're sending commands too fast, slow down
+
+watch-not-player = This user is not a player in the room
+
+export-round-not-found = Round not found
+
+chat-slow-mode = Slow mode is on, wait { $secs }s before sending another message
+
+report-self = You can't report yourself
+report-unknown-user = User not found
+report-duplicate = You have already reported this user
+
+trust-cannot-host = Your account is too new to host rooms
+trust-no-links = Your account is too new to send links
+chat-too-long = Chat messages may be at most { $max } bytes long
+recording-in-game = Recording settings cannot be changed during a game
+recording-off = This room does not keep results
+end-round-no-game = No game is in progress
+trust-no-encrypted-chat = Your account is too new to send encrypted chat
+notice-recording-public = Results of this room are recorded and published
+notice-recording-members = Results of this room are recorded for its members
+notice-spectated = Spectators are watching this room live
+merge-same-room = A room can't be merged into itself
+move-quickplay = Players can't be moved into or out of quickplay rooms
+move-game-ongoing = Players can only be moved while choosing a chart
+split-not-member = User { $user } is not in this room
+split-no-host = None of the moved players can host a room
+merge-password = Can't merge into a room with a password
+spectator-cannot-play = Spectators can't take part in the game
+kick-self = You can't kick yourself
+kick-not-member = User { $user } isn't in this room
+join-banned = You were banned from this room
+merge-banned = { $user } was banned from that room
+transfer-self = You're the host already
+transfer-not-player = User { $user } isn't playing in this room
+transfer-untrusted = This account is too new to host rooms
+duel-not-started = No duel is in progress
+duel-invalid-best-of = A duel must be best of an odd number of rounds, at most { $max }
+duel-needs-two-players = A duel needs exactly two players in the room
+duel-not-your-pick = It's your opponent's turn to pick the chart
+join-duel = A duel is in progress, you can only join as a spectator
+move-duel = Players can't be moved into or out of a duel
+draft-in-progress = The next chart is being drafted
+draft-not-started = No draft is in progress
+draft-invalid-pool = The pool must have more distinct charts than bans, at most { $max }
+draft-invalid-turn-time = Turns must last { $min } to { $max } seconds
+draft-needs-two-players = A draft needs at least two players
+draft-chart-unavailable = Chart { $chart } is unavailable
+draft-not-your-turn = It's not your turn
+draft-wrong-action = You can't do that this turn
+draft-not-in-pool = Chart { $chart } is not left in the pool
+max-players-invalid = Rooms can have 1 to { $max } players
+max-players-too-low = There are more players in the room than that
+mute-self = You can't mute yourself
+mute-not-muted = User { $user } isn't muted
+moderation-invalid-duration = Mutes and timeouts can last from 1 second to { $max } hours
+chat-muted = You're muted for another { $secs }s
+join-timed-out = You were timed out from this room, try again in { $secs }s
+whisper-self = You can't whisper to yourself
+whisper-not-in-room = User { $user } is not in this room
+chart-played-recently = This chart was played { $rounds } round(s) ago, pick another one
+random-chart-none = No chart matching the filter was found
+random-chart-no-length = This server can't tell chart lengths
+random-chart-fetch-failed = Failed to fetch charts, please try again later
+duel-needs-versus = Duels can only be held in versus rooms
+mode-duel-in-progress = Call off the duel before changing the room mode
+queue-quickplay = Quickplay rooms pick their charts themselves
+queue-duplicate = Chart { $chart } is queued already
+queue-full = The queue can hold at most { $max } charts
+queue-user-full = You can have at most { $max } charts queued
+queue-not-queued = Chart { $chart } isn't queued
+bot-round-summary = Round { $round } on { $chart }:
+bot-round-line = { $rank }. { $name } { $score } ({ $accuracy }%)
+bot-queue-next = Up next from the queue: { $chart } ({ $votes } votes)
+bot-room-closing = This room closes in { $secs } seconds
+bot-room-closing-idle = This room closes in { $secs } seconds unless someone does something
+bot-quickplay-countdown = { $chart } starts in { $secs } seconds, get ready!
+ready-check-invalid-time = Players can be given { $min } to { $max } seconds to get ready
+
+auth-banned = You are banned from this server
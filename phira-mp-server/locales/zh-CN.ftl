@@ -1,5 +1,7 @@
 
 create-id-occupied = 房间 ID 已被占用
+create-draining = 服务器即将重启，无法创建新房间
+create-too-many-rooms = 服务器上的房间数量已达上限
 
 join-game-ongoing = 游戏正在进行中
 join-room-full = 房间已满
@@ -7,3 +9,87 @@ join-room-locked = 房间已锁定
 join-cant-monitor = 权限不足，不能旁观房间
 
 start-no-chart-selected = 还没有选择谱面
+start-nothing-to-cancel = 当前没有可以取消的开始
+
+echo-payload-too-large = 回显数据过大（最多 { $max } 字节）
+
+rate-limited = 操作过于频繁，请稍后再试
+
+watch-not-player = 该用户不是房间中的玩家
+
+export-round-not-found = 找不到该轮游戏
+
+chat-slow-mode = 慢速模式已开启，请等待 { $secs } 秒后再发送消息
+
+report-self = 不能举报自己
+report-unknown-user = 用户不存在
+report-duplicate = 你已经举报过该用户
+
+trust-cannot-host = 你的账号太新，暂时无法创建房间
+trust-no-links = 你的账号太新，暂时无法发送链接
+chat-too-long = 聊天消息最多 { $max } 字节
+recording-in-game = 游戏中无法更改记录设置
+recording-off = 该房间不保留成绩
+end-round-no-game = 当前没有进行中的游戏
+trust-no-encrypted-chat = 你的账号太新，暂时无法发送加密聊天
+notice-recording-public = 本房间的成绩会被记录并公开
+notice-recording-members = 本房间的成绩会被记录，仅供房间成员查看
+notice-spectated = 有观众正在实时观看本房间
+merge-same-room = 不能将房间合并到其自身
+move-quickplay = 无法将玩家移入或移出快速游戏房间
+move-game-ongoing = 只有在选择谱面时才能移动玩家
+split-not-member = 用户 { $user } 不在此房间中
+split-no-host = 被移动的玩家中没有人可以担任房主
+merge-password = 无法合并到设有密码的房间
+spectator-cannot-play = 观战者无法参与游戏
+kick-self = 无法踢出自己
+kick-not-member = 用户 { $user } 不在此房间中
+join-banned = 你已被此房间封禁
+merge-banned = { $user } 已被该房间封禁
+transfer-self = 你已经是房主了
+transfer-not-player = 用户 { $user } 不是此房间的玩家
+transfer-untrusted = 该账号注册时间过短，无法成为房主
+duel-not-started = 当前没有进行中的对决
+duel-invalid-best-of = 对决局数须为奇数，且不超过 { $max }
+duel-needs-two-players = 对决需要房间中恰好有两名玩家
+duel-not-your-pick = 现在轮到对手选择谱面
+join-duel = 对决进行中，只能以观战者身份加入
+move-duel = 无法将玩家移入或移出对决
+draft-in-progress = 正在通过禁选决定下一张谱面
+draft-not-started = 当前没有进行中的禁选
+draft-invalid-pool = 谱面池中不同谱面的数量须多于禁用数，且不超过 { $max }
+draft-invalid-turn-time = 每轮时限须为 { $min } 到 { $max } 秒
+draft-needs-two-players = 禁选需要至少两名玩家
+draft-chart-unavailable = 谱面 { $chart } 不可用
+draft-not-your-turn = 现在不是你的回合
+draft-wrong-action = 本回合不能进行此操作
+draft-not-in-pool = 谱面 { $chart } 不在剩余的谱面池中
+max-players-invalid = 房间人数上限须在 1 到 { $max } 之间
+max-players-too-low = 房间内的玩家已超过该人数
+mute-self = 无法禁言自己
+mute-not-muted = 用户 { $user } 未被禁言
+moderation-invalid-duration = 禁言和暂时移出的时长须在 1 秒到 { $max } 小时之间
+chat-muted = 你已被禁言，剩余 { $secs } 秒
+join-timed-out = 你已被暂时移出此房间，请在 { $secs } 秒后重试
+whisper-self = 无法私聊自己
+whisper-not-in-room = 用户 { $user } 不在此房间中
+chart-played-recently = 该谱面在 { $rounds } 轮前刚玩过，请选择其他谱面
+random-chart-none = 没有找到符合条件的谱面
+random-chart-no-length = 该服务器无法获取谱面时长
+random-chart-fetch-failed = 获取谱面失败，请稍后再试
+duel-needs-versus = 对决只能在对战模式的房间中进行
+mode-duel-in-progress = 请先取消对决再更改房间模式
+queue-quickplay = 快速匹配房间会自行选择谱面
+queue-duplicate = 谱面 { $chart } 已在队列中
+queue-full = 队列最多只能容纳 { $max } 张谱面
+queue-user-full = 你最多只能在队列中加入 { $max } 张谱面
+queue-not-queued = 谱面 { $chart } 不在队列中
+bot-round-summary = 第 { $round } 轮「{ $chart }」结果：
+bot-round-line = { $rank }. { $name } { $score }（{ $accuracy }%）
+bot-queue-next = 队列中的下一张谱面：{ $chart }（{ $votes } 票）
+bot-room-closing = 房间将在 { $secs } 秒后关闭
+bot-room-closing-idle = 如果没有任何动静，房间将在 { $secs } 秒后关闭
+bot-quickplay-countdown = 「{ $chart }」将在 { $secs } 秒后开始，请准备！
+ready-check-invalid-time = 准备时间需在 { $min } 到 { $max } 秒之间
+
+auth-banned = 你已被此服务器封禁
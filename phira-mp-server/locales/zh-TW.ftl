@@ -1,5 +1,7 @@
 
 create-id-occupied = 房間 ID 已被佔用
+create-draining = 伺服器即將重新啟動，無法建立新房間
+create-too-many-rooms = 伺服器上的房間數量已達上限
 
 join-game-ongoing = 遊戲正在進行中
 join-room-full = 房間已滿
@@ -7,3 +9,87 @@ join-room-locked = 房間已鎖定
 join-cant-monitor = 權限不足，不能旁觀房間
 
 start-no-chart-selected = 還沒有選擇譜面
+start-nothing-to-cancel = 目前沒有可以取消的開始
+
+echo-payload-too-large = 回顯資料過大（最多 { $max } 位元組）
+
+rate-limited = 操作過於頻繁，請稍後再試
+
+watch-not-player = 該用戶不是房間中的玩家
+
+export-round-not-found = 找不到該輪遊戲
+
+chat-slow-mode = 慢速模式已開啟，請等待 { $secs } 秒後再傳送訊息
+
+report-self = 不能檢舉自己
+report-unknown-user = 使用者不存在
+report-duplicate = 你已經檢舉過該使用者
+
+trust-cannot-host = 你的帳號太新，暫時無法建立房間
+trust-no-links = 你的帳號太新，暫時無法傳送連結
+chat-too-long = 聊天訊息最多 { $max } 位元組
+recording-in-game = 遊戲中無法變更紀錄設定
+recording-off = 此房間不保留成績
+end-round-no-game = 目前沒有進行中的遊戲
+trust-no-encrypted-chat = 你的帳號太新，暫時無法傳送加密聊天
+notice-recording-public = 本房間的成績會被記錄並公開
+notice-recording-members = 本房間的成績會被記錄，僅供房間成員查看
+notice-spectated = 有觀眾正在即時觀看本房間
+merge-same-room = 不能將房間合併到其自身
+move-quickplay = 無法將玩家移入或移出快速遊戲房間
+move-game-ongoing = 只有在選擇譜面時才能移動玩家
+split-not-member = 使用者 { $user } 不在此房間中
+split-no-host = 被移動的玩家中沒有人可以擔任房主
+merge-password = 無法合併到設有密碼的房間
+spectator-cannot-play = 觀戰者無法參與遊戲
+kick-self = 無法踢出自己
+kick-not-member = 使用者 { $user } 不在此房間中
+join-banned = 你已被此房間封鎖
+merge-banned = { $user } 已被該房間封鎖
+transfer-self = 你已經是房主了
+transfer-not-player = 使用者 { $user } 不是此房間的玩家
+transfer-untrusted = 該帳號註冊時間過短，無法成為房主
+duel-not-started = 目前沒有進行中的對決
+duel-invalid-best-of = 對決局數須為奇數，且不超過 { $max }
+duel-needs-two-players = 對決需要房間中恰好有兩名玩家
+duel-not-your-pick = 現在輪到對手選擇譜面
+join-duel = 對決進行中，只能以觀戰者身分加入
+move-duel = 無法將玩家移入或移出對決
+draft-in-progress = 正在透過禁選決定下一張譜面
+draft-not-started = 目前沒有進行中的禁選
+draft-invalid-pool = 譜面池中不同譜面的數量須多於禁用數，且不超過 { $max }
+draft-invalid-turn-time = 每輪時限須為 { $min } 到 { $max } 秒
+draft-needs-two-players = 禁選需要至少兩名玩家
+draft-chart-unavailable = 譜面 { $chart } 無法使用
+draft-not-your-turn = 現在不是你的回合
+draft-wrong-action = 本回合不能進行此操作
+draft-not-in-pool = 譜面 { $chart } 不在剩餘的譜面池中
+max-players-invalid = 房間人數上限須在 1 到 { $max } 之間
+max-players-too-low = 房間內的玩家已超過該人數
+mute-self = 無法禁言自己
+mute-not-muted = 使用者 { $user } 未被禁言
+moderation-invalid-duration = 禁言和暫時移出的時長須在 1 秒到 { $max } 小時之間
+chat-muted = 你已被禁言，剩餘 { $secs } 秒
+join-timed-out = 你已被暫時移出此房間，請在 { $secs } 秒後重試
+whisper-self = 無法私訊自己
+whisper-not-in-room = 使用者 { $user } 不在此房間中
+chart-played-recently = 該譜面在 { $rounds } 輪前剛玩過，請選擇其他譜面
+random-chart-none = 沒有找到符合條件的譜面
+random-chart-no-length = 該伺服器無法取得譜面時長
+random-chart-fetch-failed = 取得譜面失敗，請稍後再試
+duel-needs-versus = 對決只能在對戰模式的房間中進行
+mode-duel-in-progress = 請先取消對決再變更房間模式
+queue-quickplay = 快速配對房間會自行選擇譜面
+queue-duplicate = 譜面 { $chart } 已在佇列中
+queue-full = 佇列最多只能容納 { $max } 張譜面
+queue-user-full = 你最多只能在佇列中加入 { $max } 張譜面
+queue-not-queued = 譜面 { $chart } 不在佇列中
+bot-round-summary = 第 { $round } 輪「{ $chart }」結果：
+bot-round-line = { $rank }. { $name } { $score }（{ $accuracy }%）
+bot-queue-next = 佇列中的下一張譜面：{ $chart }（{ $votes } 票）
+bot-room-closing = 房間將在 { $secs } 秒後關閉
+bot-room-closing-idle = 如果沒有任何動靜，房間將在 { $secs } 秒後關閉
+bot-quickplay-countdown = 「{ $chart }」將在 { $secs } 秒後開始，請準備！
+ready-check-invalid-time = 準備時間需在 { $min } 到 { $max } 秒之間
+
+auth-banned = 你已被此伺服器封鎖
@@ -0,0 +1,548 @@
+use crate::{
+    announce, close_room, kick_user, l10n::LANGUAGE, merge_rooms, split_room, Ban, ChartCacheStats,
+    InternalRoomState, LogLevels, RegistryStats, Report, Room, RoomUsageStats, RoundRecord,
+    ServerState, Subsystem, NAME_MAX_CHARS,
+};
+use anyhow::Result;
+use axum::{
+    extract::{Path, State},
+    http::{header, Request, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse, Response,
+    },
+    routing::{get, post, put},
+    Json, Router,
+};
+use phira_mp_common::{Recording, RoomId, RoomMode};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+};
+use subtle::ConstantTimeEq;
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+use tracing::{error, info, metadata::LevelFilter};
+
+const DASHBOARD: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/assets/dashboard.html"
+));
+
+type AppState = State<Arc<ServerState>>;
+
+pub async fn serve(state: Arc<ServerState>, addr: SocketAddr) -> Result<()> {
+    if state.config.admin_token.is_none() {
+        error!("admin API requires a token, not starting it");
+        return Ok(());
+    }
+    let api = Router::new()
+        .route("/stats", get(stats))
+        .route("/rooms", get(rooms))
+        .route("/rooms/:id/history.csv", get(room_history))
+        .route("/rooms/:id/events", get(room_events))
+        .route("/rooms/:id/cancel-start", post(cancel_start))
+        .route("/rooms/:id/merge", post(merge_room))
+        .route("/rooms/:id/split", post(split_room_into))
+        .route("/rooms/:id/close", post(close))
+        .route("/players", get(players))
+        .route("/users/:id/kick", post(kick))
+        .route("/bans", get(bans))
+        .route("/users/:id/ban", put(ban).delete(unban))
+        .route("/announce", post(announcement))
+        .route("/rounds", get(rounds))
+        .route("/names", get(name_overrides))
+        .route(
+            "/users/:id/name",
+            put(set_name_override).delete(remove_name_override),
+        )
+        .route("/reports", get(reports))
+        .route("/reports/:id/resolve", post(resolve_report))
+        .route("/log", get(log_levels))
+        .route(
+            "/log/:subsystem",
+            put(set_log_level).delete(reset_log_level),
+        )
+        .route_layer(middleware::from_fn_with_state(Arc::clone(&state), auth));
+    let app = Router::new()
+        .route("/", get(|| async { Html(DASHBOARD) }))
+        .nest("/api", api)
+        .with_state(state);
+    info!("admin API listening on {addr}");
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await?;
+    Ok(())
+}
+
+async fn auth<B>(State(state): AppState, req: Request<B>, next: Next<B>) -> Response {
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|it| it.to_str().ok())
+        .and_then(|it| it.strip_prefix("Bearer "));
+    // In constant time, so that response times don't give the token away
+    let authorized = match (token, state.config.admin_token.as_deref()) {
+        (Some(token), Some(expected)) => token.as_bytes().ct_eq(expected.as_bytes()).into(),
+        _ => false,
+    };
+    if !authorized {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(req).await
+}
+
+async fn all_rooms(state: &ServerState) -> Vec<Arc<Room>> {
+    state.rooms.values()
+}
+
+#[derive(Serialize)]
+struct Stats {
+    sessions: usize,
+    users: usize,
+    rooms: usize,
+    playing_rooms: usize,
+    /// Relayed by all rooms within the last second
+    relay_bytes_per_sec: u64,
+    registries: Registries,
+    chart_cache: ChartCacheStats,
+}
+
+#[derive(Serialize)]
+struct Registries {
+    sessions: RegistryStats,
+    users: RegistryStats,
+    rooms: RegistryStats,
+}
+
+async fn stats(State(state): AppState) -> Json<Stats> {
+    let rooms = all_rooms(&state).await;
+    let mut playing_rooms = 0;
+    let mut relay_bytes_per_sec = 0;
+    for room in &rooms {
+        relay_bytes_per_sec += room.usage.stats().bytes_per_sec;
+        if matches!(*room.state.read().await, InternalRoomState::Playing { .. }) {
+            playing_rooms += 1;
+        }
+    }
+    Json(Stats {
+        sessions: state.sessions.len(),
+        users: state.users.len(),
+        rooms: rooms.len(),
+        playing_rooms,
+        relay_bytes_per_sec,
+        registries: Registries {
+            sessions: state.sessions.stats(),
+            users: state.users.stats(),
+            rooms: state.rooms.stats(),
+        },
+        chart_cache: state.charts.stats(),
+    })
+}
+
+/// Server-sent events of the room's messages, named by their kind. Ends
+/// when the room is dropped.
+async fn room_events(State(state): AppState, Path(id): Path<String>) -> Response {
+    let Ok(id) = RoomId::try_from(id) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let Some(events) = state.rooms.get(&id).map(|it| it.subscribe()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let stream = BroadcastStream::new(events).filter_map(|msg| {
+        // Lagging behind only loses events
+        let msg = msg.ok()?;
+        Some(Event::default().event(msg.kind().as_str()).json_data(&msg))
+    });
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// For rooms stuck waiting for players to get ready.
+async fn cancel_start(State(state): AppState, Path(id): Path<String>) -> StatusCode {
+    let Ok(id) = RoomId::try_from(id) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    let room = state.rooms.get(&id);
+    match room {
+        Some(room) if room.force_cancel_start().await => StatusCode::NO_CONTENT,
+        Some(_) => StatusCode::CONFLICT,
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+#[derive(Deserialize)]
+struct MergeRoom {
+    into: String,
+}
+
+/// Moves everyone in the room into another one, closing it.
+async fn merge_room(
+    State(state): AppState,
+    Path(id): Path<String>,
+    Json(body): Json<MergeRoom>,
+) -> Response {
+    let (Ok(id), Ok(into)) = (RoomId::try_from(id), RoomId::try_from(body.into)) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let (Some(from), Some(into)) = (state.rooms.get(&id), state.rooms.get(&into)) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let res = LANGUAGE
+        .scope(Arc::default(), merge_rooms(&state, &from, &into))
+        .await;
+    match res {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => (StatusCode::CONFLICT, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct SplitRoom {
+    id: String,
+    users: Vec<i32>,
+}
+
+/// Moves some members of the room into a new one.
+async fn split_room_into(
+    State(state): AppState,
+    Path(id): Path<String>,
+    Json(body): Json<SplitRoom>,
+) -> Response {
+    let (Ok(id), Ok(new_id)) = (RoomId::try_from(id), RoomId::try_from(body.id)) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let Some(room) = state.rooms.get(&id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let res = LANGUAGE
+        .scope(
+            Arc::default(),
+            split_room(&state, &room, new_id, &body.users),
+        )
+        .await;
+    match res {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => (StatusCode::CONFLICT, err.to_string()).into_response(),
+    }
+}
+
+/// Kicks everyone out of the room, dropping it.
+async fn close(State(state): AppState, Path(id): Path<String>) -> StatusCode {
+    let Ok(id) = RoomId::try_from(id) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    match state.rooms.get(&id) {
+        Some(room) => {
+            close_room(&state, &room).await;
+            StatusCode::NO_CONTENT
+        }
+        None => StatusCode::NOT_FOUND,
+    }
+}
+
+#[derive(Serialize)]
+struct Member {
+    id: i32,
+    name: String,
+}
+
+#[derive(Serialize)]
+struct RoomInfo {
+    id: String,
+    state: &'static str,
+    chart: Option<i32>,
+    host: Option<i32>,
+    locked: bool,
+    password: bool,
+    max_players: usize,
+    mode: RoomMode,
+    live: bool,
+    recording: Recording,
+    players: Vec<Member>,
+    monitors: Vec<Member>,
+    rounds: usize,
+    usage: RoomUsageStats,
+}
+
+async fn room_info(room: &Room) -> RoomInfo {
+    let members = |users: Vec<Arc<crate::User>>| {
+        users
+            .into_iter()
+            .map(|it| Member {
+                id: it.id,
+                name: it.name(),
+            })
+            .collect()
+    };
+    RoomInfo {
+        id: room.id.to_string(),
+        state: room.client_room_state().await.kind().as_str(),
+        chart: room.chart.read().await.as_ref().map(|it| it.id),
+        host: room.host.read().await.upgrade().map(|it| it.id),
+        locked: room.is_locked(),
+        password: room.has_password(),
+        max_players: room.max_players(),
+        mode: room.mode(),
+        live: room.is_live(),
+        recording: room.recording(),
+        players: members(room.users().await),
+        monitors: members(room.monitors().await),
+        rounds: room.rounds.read().await.len(),
+        usage: room.usage.stats(),
+    }
+}
+
+async fn rooms(State(state): AppState) -> Json<Vec<RoomInfo>> {
+    let mut res = Vec::new();
+    for room in all_rooms(&state).await {
+        res.push(room_info(&room).await);
+    }
+    res.sort_by(|a, b| a.id.cmp(&b.id));
+    Json(res)
+}
+
+async fn room_history(State(state): AppState, Path(id): Path<String>) -> Response {
+    let Ok(id) = RoomId::try_from(id) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let room = state.rooms.get(&id);
+    match room {
+        Some(room) => (
+            [(header::CONTENT_TYPE, "text/csv; charset=utf-8")],
+            room.history_csv().await,
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[derive(Serialize)]
+struct Player {
+    id: i32,
+    name: String,
+    room: Option<String>,
+    monitor: bool,
+    /// Whether they're disconnected but may still come back
+    dangling: bool,
+    latency_ms: Option<u16>,
+}
+
+async fn players(State(state): AppState) -> Json<Vec<Player>> {
+    let mut res = Vec::new();
+    for user in state.users.values() {
+        res.push(Player {
+            id: user.id,
+            name: user.name(),
+            room: user.room.read().await.as_ref().map(|it| it.id.to_string()),
+            monitor: user.monitor.load(Ordering::SeqCst),
+            dangling: user.dangle_mark.lock().await.is_some(),
+            latency_ms: *user.latency_ms.lock().unwrap(),
+        });
+    }
+    res.sort_by_key(|it| it.id);
+    Json(res)
+}
+
+/// Disconnects the user, who may connect again right away.
+async fn kick(State(state): AppState, Path(id): Path<i32>) -> StatusCode {
+    if kick_user(&state, id, false).await {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn bans(State(state): AppState) -> Json<Vec<Ban>> {
+    Json(state.bans.list())
+}
+
+#[derive(Deserialize)]
+struct BanUser {
+    reason: Option<String>,
+}
+
+/// Bans the user from the server, disconnecting them if they're online. The
+/// body is optional.
+async fn ban(
+    State(state): AppState,
+    Path(id): Path<i32>,
+    body: Option<Json<BanUser>>,
+) -> StatusCode {
+    let reason = body.and_then(|it| it.0.reason);
+    if let Err(err) = state.bans.ban(id, reason).await {
+        error!("failed to save bans: {err:?}");
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    info!(user = id, "banned from the server");
+    kick_user(&state, id, true).await;
+    StatusCode::NO_CONTENT
+}
+
+async fn unban(State(state): AppState, Path(id): Path<i32>) -> StatusCode {
+    match state.bans.unban(id).await {
+        Ok(true) => {
+            info!(user = id, "unbanned");
+            StatusCode::NO_CONTENT
+        }
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(err) => {
+            error!("failed to save bans: {err:?}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Announcement {
+    message: String,
+}
+
+async fn announcement(State(state): AppState, Json(body): Json<Announcement>) -> StatusCode {
+    if body.message.trim().is_empty() {
+        return StatusCode::BAD_REQUEST;
+    }
+    announce(&state, body.message).await;
+    StatusCode::NO_CONTENT
+}
+
+#[derive(Serialize)]
+struct RecentRound {
+    room: String,
+    #[serde(flatten)]
+    record: RoundRecord,
+}
+
+/// Latest rounds across all open rooms, newest first.
+async fn rounds(State(state): AppState) -> Json<Vec<RecentRound>> {
+    let mut res = Vec::new();
+    for room in all_rooms(&state).await {
+        res.extend(
+            room.rounds
+                .read()
+                .await
+                .iter()
+                .rev()
+                .take(20)
+                .map(|record| RecentRound {
+                    room: room.id.to_string(),
+                    record: record.clone(),
+                }),
+        );
+    }
+    res.sort_by_key(|it| Reverse(it.record.finished_at));
+    res.truncate(20);
+    Json(res)
+}
+
+async fn name_overrides(State(state): AppState) -> Json<HashMap<i32, String>> {
+    Json(state.name_overrides.read().await.clone())
+}
+
+#[derive(Deserialize)]
+struct NameOverride {
+    name: String,
+}
+
+/// Applies at once to users online. Removing an override only takes effect
+/// when the user authenticates again.
+async fn set_name_override(
+    State(state): AppState,
+    Path(id): Path<i32>,
+    Json(body): Json<NameOverride>,
+) -> StatusCode {
+    let name = body.name.trim();
+    if name.is_empty() || name.chars().count() > NAME_MAX_CHARS {
+        return StatusCode::BAD_REQUEST;
+    }
+    info!(user = id, "display name overridden to {name}");
+    state
+        .name_overrides
+        .write()
+        .await
+        .insert(id, name.to_owned());
+    if let Some(user) = state.users.get(&id) {
+        user.rename(name.to_owned()).await;
+    }
+    StatusCode::NO_CONTENT
+}
+
+async fn remove_name_override(State(state): AppState, Path(id): Path<i32>) -> StatusCode {
+    if state.name_overrides.write().await.remove(&id).is_some() {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+/// All reports, unresolved first, newest first.
+async fn reports(State(state): AppState) -> Json<Vec<Report>> {
+    let mut res = state.reports.list();
+    res.sort_by_key(|it| (it.resolved, Reverse(it.id)));
+    Json(res)
+}
+
+async fn resolve_report(State(state): AppState, Path(id): Path<u32>) -> StatusCode {
+    match state.reports.resolve(id).await {
+        Ok(true) => {
+            info!("report #{id} resolved");
+            StatusCode::NO_CONTENT
+        }
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(err) => {
+            error!("failed to save reports: {err:?}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Current level override of each subsystem, `null` if it's the default.
+async fn log_levels() -> Response {
+    let Some(levels) = LogLevels::get() else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+    let overrides = levels.overrides();
+    Json(
+        Subsystem::ALL
+            .into_iter()
+            .map(|it| (it, overrides.get(&it).map(|it| it.to_string())))
+            .collect::<BTreeMap<_, _>>(),
+    )
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct LogLevel {
+    level: String,
+}
+
+async fn apply_log_level(subsystem: Subsystem, level: Option<LevelFilter>) -> StatusCode {
+    let Some(levels) = LogLevels::get() else {
+        return StatusCode::SERVICE_UNAVAILABLE;
+    };
+    match levels.set(subsystem, level) {
+        Ok(()) => {
+            info!("log level of {subsystem:?} set to {level:?}");
+            StatusCode::NO_CONTENT
+        }
+        Err(err) => {
+            error!("{err:?}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn set_log_level(Path(subsystem): Path<Subsystem>, Json(body): Json<LogLevel>) -> StatusCode {
+    match body.level.parse() {
+        Ok(level) => apply_log_level(subsystem, Some(level)).await,
+        Err(_) => StatusCode::BAD_REQUEST,
+    }
+}
+
+async fn reset_log_level(Path(subsystem): Path<Subsystem>) -> StatusCode {
+    apply_log_level(subsystem, None).await
+}
@@ -0,0 +1,77 @@
+use crate::{l10n::Language, Room, RoundRecord, ServerConfig, User};
+use phira_mp_common::{Features, Message, ServerCommand, BOT_USER_ID};
+
+/// Chats in `room` as the server's bot, if the operator named one and
+/// `enabled` says the option for this is on. `content` is formatted for every
+/// member in their language, so it isn't kept in the room's history.
+pub async fn bot_say(
+    room: &Room,
+    enabled: impl Fn(&ServerConfig) -> bool,
+    content: impl Fn(&Language) -> String,
+) {
+    let members: Vec<_> = room
+        .users()
+        .await
+        .into_iter()
+        .chain(room.monitors().await)
+        .collect();
+    let config = match members.first() {
+        Some(user) => &user.server.config,
+        None => return,
+    };
+    if config.bot_name.is_none() || !enabled(config) {
+        return;
+    }
+    for user in members {
+        bot_tell(&user, content(&user.lang)).await;
+    }
+}
+
+/// Chats to `user` alone as the server's bot.
+pub async fn bot_tell(user: &User, content: String) {
+    let msg = if user.features().await.contains(Features::BOT_MESSAGES) {
+        Message::Bot { content }
+    } else {
+        Message::Chat {
+            user: BOT_USER_ID,
+            content,
+        }
+    };
+    user.try_send(ServerCommand::Message(msg)).await;
+}
+
+/// Greets `user`, who just joined a room, if the operator configured it.
+pub async fn bot_welcome(user: &User) {
+    let config = &user.server.config;
+    if let (Some(_), Some(welcome)) = (&config.bot_name, &config.bot_welcome) {
+        bot_tell(user, welcome.replace("{name}", &user.name())).await;
+    }
+}
+
+/// One line per standing under a header.
+pub fn round_summary(lang: &Language, record: &RoundRecord) -> String {
+    let mut lines = vec![lang
+        .format(
+            "bot-round-summary",
+            Some(&fluent::fluent_args![
+                "round" => record.round,
+                "chart" => record.chart_name.as_str()
+            ]),
+        )
+        .into_owned()];
+    for standing in &record.standings {
+        lines.push(
+            lang.format(
+                "bot-round-line",
+                Some(&fluent::fluent_args![
+                    "rank" => standing.rank,
+                    "name" => standing.name.as_str(),
+                    "score" => standing.record.score,
+                    "accuracy" => format!("{:.2}", standing.record.accuracy * 100.)
+                ]),
+            )
+            .into_owned(),
+        );
+    }
+    lines.join("\n")
+}
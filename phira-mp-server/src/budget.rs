@@ -0,0 +1,130 @@
+use crate::ServerConfig;
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Budgets are checked against what a room used within windows this long.
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Relay traffic and handling time spent on a room, counted so that one busy
+/// room can be told apart from (and kept from slowing down) the others.
+pub struct RoomUsage {
+    relay_bytes: AtomicU64,
+    handler_nanos: AtomicU64,
+    throttled: AtomicU64,
+    window: Mutex<Window>,
+}
+
+struct Window {
+    start: Instant,
+    bytes: u64,
+    handler: Duration,
+    /// Totals of the last complete window
+    last: (u64, Duration),
+}
+
+impl Window {
+    fn roll(&mut self) {
+        let elapsed = self.start.elapsed();
+        if elapsed < WINDOW {
+            return;
+        }
+        // A window without any activity in between leaves nothing to report
+        self.last = if elapsed < WINDOW * 2 {
+            (self.bytes, self.handler)
+        } else {
+            (0, Duration::ZERO)
+        };
+        self.start = Instant::now();
+        self.bytes = 0;
+        self.handler = Duration::ZERO;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RoomUsageStats {
+    /// Bytes of touches and judges relayed since the room was created
+    pub relay_bytes: u64,
+    /// Time spent handling the room's commands and relays, in milliseconds
+    pub handler_ms: u64,
+    /// Touch relays dropped for exceeding the budget
+    pub throttled_relays: u64,
+    /// Relayed bytes within the last second
+    pub bytes_per_sec: u64,
+    /// Handling time within the last second, in milliseconds
+    pub handler_ms_per_sec: f64,
+}
+
+impl Default for RoomUsage {
+    fn default() -> Self {
+        Self {
+            relay_bytes: AtomicU64::default(),
+            handler_nanos: AtomicU64::default(),
+            throttled: AtomicU64::default(),
+            window: Mutex::new(Window {
+                start: Instant::now(),
+                bytes: 0,
+                handler: Duration::ZERO,
+                last: (0, Duration::ZERO),
+            }),
+        }
+    }
+}
+
+impl RoomUsage {
+    /// Counts `bytes` of touches or judges as written to a monitor.
+    pub fn add_relay(&self, bytes: u64) {
+        self.relay_bytes.fetch_add(bytes, Ordering::Relaxed);
+        let mut window = self.window.lock().unwrap();
+        window.roll();
+        window.bytes += bytes;
+    }
+
+    pub fn add_handler_time(&self, time: Duration) {
+        self.handler_nanos
+            .fetch_add(time.as_nanos() as u64, Ordering::Relaxed);
+        let mut window = self.window.lock().unwrap();
+        window.roll();
+        window.handler += time;
+    }
+
+    /// Whether the room has used up either of its budgets in the current
+    /// window. Always `false` if the server sets none.
+    pub fn over_budget(&self, config: &ServerConfig) -> bool {
+        if config.room_bandwidth_budget.is_none() && config.room_handler_budget.is_none() {
+            return false;
+        }
+        let mut window = self.window.lock().unwrap();
+        window.roll();
+        config
+            .room_bandwidth_budget
+            .is_some_and(|it| window.bytes >= it)
+            || config
+                .room_handler_budget
+                .is_some_and(|it| window.handler >= it)
+    }
+
+    pub fn add_throttled(&self) {
+        self.throttled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> RoomUsageStats {
+        let (bytes, handler) = {
+            let mut window = self.window.lock().unwrap();
+            window.roll();
+            window.last
+        };
+        RoomUsageStats {
+            relay_bytes: self.relay_bytes.load(Ordering::Relaxed),
+            handler_ms: self.handler_nanos.load(Ordering::Relaxed) / 1_000_000,
+            throttled_relays: self.throttled.load(Ordering::Relaxed),
+            bytes_per_sec: bytes,
+            handler_ms_per_sec: handler.as_secs_f64() * 1000.,
+        }
+    }
+}
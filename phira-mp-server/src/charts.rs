@@ -0,0 +1,421 @@
+use crate::HOST;
+use anyhow::{bail, Context, Result};
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+    mem::size_of,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::task::spawn_blocking;
+use tracing::{debug, warn};
+
+/// Chart packages larger than this aren't downloaded.
+const MAX_PACKAGE_SIZE: usize = 64 * 1024 * 1024;
+/// Most bytes extracted from a package, which may well decompress to far
+/// more than it takes.
+const MAX_EXTRACTED_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoteKind {
+    Tap,
+    Drag,
+    Hold,
+    Flick,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoteTiming {
+    /// Index of the judge line in the chart
+    pub line: u32,
+    pub kind: NoteKind,
+    /// In seconds from the start of the chart
+    pub time: f32,
+    /// Same as `time` except for holds
+    pub end_time: f32,
+}
+
+/// When a chart's notes are to be hit. Fake notes are left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoteData {
+    pub chart: i32,
+    /// Delay of the chart relative to its music, in seconds
+    pub offset: f32,
+    /// Sorted by `time`
+    pub notes: Vec<NoteTiming>,
+}
+
+impl NoteData {
+    /// Roughly how much memory this takes.
+    pub fn size(&self) -> usize {
+        size_of::<Self>() + self.notes.len() * size_of::<NoteTiming>()
+    }
+
+    /// In seconds, up to the end of the last note.
+    pub fn length(&self) -> f32 {
+        self.notes.iter().map(|it| it.end_time).fold(0., f32::max)
+    }
+}
+
+/// Counters of the [`ChartCache`] since the server started.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ChartCacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    /// Lookups served from the storage directory
+    pub disk_hits: u64,
+    pub downloads: u64,
+    pub failures: u64,
+    pub evictions: u64,
+}
+
+struct Entries {
+    lru: LruCache<i32, Arc<NoteData>>,
+    bytes: usize,
+}
+
+/// Note timings of recently selected charts, downloaded from the Phira API
+/// and kept in memory up to `capacity` bytes, least recently used first out.
+///
+/// With a storage directory, parsed data is also written there and read back
+/// instead of downloading the chart again, also across restarts. Files in it
+/// are never removed by the server.
+pub struct ChartCache {
+    dir: Option<PathBuf>,
+    capacity: usize,
+    entries: Mutex<Entries>,
+    /// Held while loading a chart, so that it's only downloaded once. Other
+    /// charts load meanwhile.
+    loading: Mutex<HashMap<i32, Arc<tokio::sync::Mutex<()>>>>,
+    hits: AtomicU64,
+    disk_hits: AtomicU64,
+    downloads: AtomicU64,
+    failures: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl ChartCache {
+    /// A `capacity` of zero disables the cache.
+    pub fn new(capacity: usize, dir: Option<PathBuf>) -> Self {
+        Self {
+            dir,
+            capacity,
+            entries: Mutex::new(Entries {
+                lru: LruCache::unbounded(),
+                bytes: 0,
+            }),
+            loading: Mutex::default(),
+            hits: AtomicU64::default(),
+            disk_hits: AtomicU64::default(),
+            downloads: AtomicU64::default(),
+            failures: AtomicU64::default(),
+            evictions: AtomicU64::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub async fn get(&self, chart: i32) -> Result<Arc<NoteData>> {
+        if !self.is_enabled() {
+            bail!("chart cache disabled");
+        }
+        if let Some(data) = self.cached(chart) {
+            return Ok(data);
+        }
+        let lock = Arc::clone(self.loading.lock().unwrap().entry(chart).or_default());
+        let res = async {
+            let _guard = lock.lock().await;
+            if let Some(data) = self.cached(chart) {
+                return Ok(data);
+            }
+            let data = match self.load(chart).await {
+                Ok(data) => Arc::new(data),
+                Err(err) => {
+                    self.failures.fetch_add(1, Ordering::Relaxed);
+                    return Err(err);
+                }
+            };
+            self.insert(Arc::clone(&data));
+            Ok(data)
+        }
+        .await;
+        // The last one done with the chart cleans up
+        let mut loading = self.loading.lock().unwrap();
+        if Arc::strong_count(&lock) == 2 {
+            loading.remove(&chart);
+        }
+        res
+    }
+
+    /// Loads `chart` in the background, e.g. as soon as it's selected.
+    pub fn prefetch(self: &Arc<Self>, chart: i32) {
+        if !self.is_enabled() {
+            return;
+        }
+        let this = Arc::clone(self);
+        tokio::spawn(async move {
+            if let Err(err) = this.get(chart).await {
+                warn!(chart, "failed to load note data: {err:?}");
+            }
+        });
+    }
+
+    pub fn stats(&self) -> ChartCacheStats {
+        let entries = self.entries.lock().unwrap();
+        ChartCacheStats {
+            entries: entries.lru.len(),
+            bytes: entries.bytes,
+            capacity: self.capacity,
+            hits: self.hits.load(Ordering::Relaxed),
+            disk_hits: self.disk_hits.load(Ordering::Relaxed),
+            downloads: self.downloads.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    fn cached(&self, chart: i32) -> Option<Arc<NoteData>> {
+        let data = self.entries.lock().unwrap().lru.get(&chart).map(Arc::clone);
+        if data.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        data
+    }
+
+    fn insert(&self, data: Arc<NoteData>) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.bytes += data.size();
+        if let Some(old) = entries.lru.put(data.chart, data) {
+            entries.bytes -= old.size();
+        }
+        // The newest entry is kept even if it alone exceeds the capacity
+        while entries.bytes > self.capacity && entries.lru.len() > 1 {
+            let (_, old) = entries.lru.pop_lru().unwrap();
+            entries.bytes -= old.size();
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    async fn load(&self, chart: i32) -> Result<NoteData> {
+        let path = self.dir.as_ref().map(|it| it.join(format!("{chart}.json")));
+        if let Some(path) = path.clone() {
+            if let Some(data) = spawn_blocking(move || read_stored(&path)).await? {
+                self.disk_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(data);
+            }
+        }
+
+        debug!(chart, "downloading chart");
+        self.downloads.fetch_add(1, Ordering::Relaxed);
+        let package = download(chart).await?;
+        let data = spawn_blocking(move || parse_package(chart, &package)).await??;
+
+        if let Some(path) = path {
+            let json = serde_json::to_vec(&data)?;
+            let res: Result<()> = spawn_blocking(move || {
+                std::fs::create_dir_all(path.parent().unwrap())?;
+                std::fs::write(path, json)?;
+                Ok(())
+            })
+            .await?;
+            if let Err(err) = res {
+                warn!("failed to store note data of {chart}: {err:?}");
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// Note data kept in the storage directory, if it's there and valid.
+fn read_stored(path: &Path) -> Option<NoteData> {
+    let bytes = std::fs::read(path).ok()?;
+    serde_json::from_slice(&bytes)
+        .map_err(|err| warn!("invalid note data in {}: {err:?}", path.display()))
+        .ok()
+}
+
+async fn download(chart: i32) -> Result<Vec<u8>> {
+    #[derive(Deserialize)]
+    struct ChartFile {
+        file: String,
+    }
+
+    let info: ChartFile = reqwest::get(format!("{HOST}/chart/{chart}"))
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let mut resp = reqwest::get(&info.file).await?.error_for_status()?;
+    if resp
+        .content_length()
+        .is_some_and(|it| it as usize > MAX_PACKAGE_SIZE)
+    {
+        bail!("chart package too large");
+    }
+    let mut package = Vec::new();
+    while let Some(chunk) = resp.chunk().await? {
+        if package.len() + chunk.len() > MAX_PACKAGE_SIZE {
+            bail!("chart package too large");
+        }
+        package.extend_from_slice(&chunk);
+    }
+    Ok(package)
+}
+
+/// Reads the chart named in the package's `info.yml`. Official and RPE
+/// charts are understood, PEC ones aren't.
+fn parse_package(chart: i32, package: &[u8]) -> Result<NoteData> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(package))?;
+    let mut budget = MAX_EXTRACTED_SIZE;
+    let mut read = |name: &str| -> Result<String> {
+        let mut content = String::new();
+        // One more byte tells whether there was more
+        zip.by_name(name)?
+            .take(budget + 1)
+            .read_to_string(&mut content)?;
+        if content.len() as u64 > budget {
+            bail!("chart package extracts to more than {MAX_EXTRACTED_SIZE} bytes");
+        }
+        budget -= content.len() as u64;
+        Ok(content)
+    };
+    let info = read("info.yml").context("missing info.yml")?;
+    let name = info
+        .lines()
+        .find_map(|it| it.strip_prefix("chart:"))
+        .map(|it| it.trim().trim_matches(|c| c == '"' || c == '\''))
+        .context("no chart in info.yml")?
+        .to_owned();
+    if name.ends_with(".pec") {
+        bail!("unsupported chart format: {name}");
+    }
+    let json: Value = serde_json::from_str(&read(&name)?)?;
+    let (offset, mut notes) = if json.get("META").is_some() {
+        parse_rpe(&json)?
+    } else if json.get("formatVersion").is_some() {
+        parse_official(&json)?
+    } else {
+        bail!("unknown chart format: {name}");
+    };
+    notes.sort_by(|a, b| a.time.total_cmp(&b.time));
+    Ok(NoteData {
+        chart,
+        offset,
+        notes,
+    })
+}
+
+fn lines(json: &Value) -> Result<&Vec<Value>> {
+    json["judgeLineList"]
+        .as_array()
+        .context("missing judgeLineList")
+}
+
+fn parse_official(json: &Value) -> Result<(f32, Vec<NoteTiming>)> {
+    let mut notes = Vec::new();
+    for (index, line) in lines(json)?.iter().enumerate() {
+        let bpm = line["bpm"].as_f64().context("missing bpm")?;
+        // Times are counted in 32nd notes
+        let secs = |time: &Value| (time.as_f64().unwrap_or_default() * 1.875 / bpm) as f32;
+        for note in ["notesAbove", "notesBelow"]
+            .iter()
+            .filter_map(|key| line[key].as_array())
+            .flatten()
+        {
+            let kind = match note["type"].as_u64() {
+                Some(1) => NoteKind::Tap,
+                Some(2) => NoteKind::Drag,
+                Some(3) => NoteKind::Hold,
+                Some(4) => NoteKind::Flick,
+                other => bail!("unknown note type {other:?}"),
+            };
+            let time = secs(&note["time"]);
+            let end_time = if kind == NoteKind::Hold {
+                time + secs(&note["holdTime"])
+            } else {
+                time
+            };
+            notes.push(NoteTiming {
+                line: index as u32,
+                kind,
+                time,
+                end_time,
+            });
+        }
+    }
+    Ok((json["offset"].as_f64().unwrap_or_default() as f32, notes))
+}
+
+fn parse_rpe(json: &Value) -> Result<(f32, Vec<NoteTiming>)> {
+    // Beats are written as `[whole, numerator, denominator]`
+    fn beats(value: &Value) -> f64 {
+        let part = |index: usize| value[index].as_f64().unwrap_or_default();
+        if part(2) == 0. {
+            part(0)
+        } else {
+            part(0) + part(1) / part(2)
+        }
+    }
+
+    let mut bpms: Vec<(f64, f64)> = json["BPMList"]
+        .as_array()
+        .context("missing BPMList")?
+        .iter()
+        .map(|it| (beats(&it["startTime"]), it["bpm"].as_f64().unwrap_or(120.)))
+        .collect();
+    bpms.sort_by(|a, b| a.0.total_cmp(&b.0));
+    if bpms.is_empty() {
+        bail!("empty BPMList");
+    }
+    let secs = |beat: f64| {
+        let mut time = 0.;
+        for (index, &(start, bpm)) in bpms.iter().enumerate() {
+            let end = bpms.get(index + 1).map_or(f64::INFINITY, |it| it.0);
+            if beat <= end {
+                return time + (beat - start) * 60. / bpm;
+            }
+            time += (end - start) * 60. / bpm;
+        }
+        time
+    };
+
+    let mut notes = Vec::new();
+    for (index, line) in lines(json)?.iter().enumerate() {
+        // Lines may run at a fraction of the chart's BPM
+        let factor = line["bpmfactor"].as_f64().unwrap_or(1.);
+        let Some(line_notes) = line["notes"].as_array() else {
+            continue;
+        };
+        for note in line_notes {
+            if note["isFake"].as_u64().unwrap_or_default() != 0 {
+                continue;
+            }
+            let kind = match note["type"].as_u64() {
+                Some(1) => NoteKind::Tap,
+                Some(2) => NoteKind::Hold,
+                Some(3) => NoteKind::Flick,
+                Some(4) => NoteKind::Drag,
+                other => bail!("unknown note type {other:?}"),
+            };
+            notes.push(NoteTiming {
+                line: index as u32,
+                kind,
+                time: (secs(beats(&note["startTime"])) * factor) as f32,
+                end_time: (secs(beats(&note["endTime"])) * factor) as f32,
+            });
+        }
+    }
+    let offset = json["META"]["offset"].as_f64().unwrap_or_default() / 1000.;
+    Ok((offset as f32, notes))
+}
@@ -0,0 +1,566 @@
+use anyhow::{bail, Context, Result};
+use phira_mp_common::{
+    ServerLimits, HEARTBEAT_DISCONNECT_TIMEOUT, HEARTBEAT_INTERVAL, HEARTBEAT_TIMEOUT,
+    ROOM_MAX_PLAYERS,
+};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Address the game listener binds to, all interfaces if `None`.
+    pub bind_addr: Option<IpAddr>,
+    pub port: u16,
+    /// Region tag advertised to clients probing this server, e.g. `cn-east`.
+    pub region: Option<String>,
+    /// Filter of what's logged to stdout, like `RUST_LOG`, which takes
+    /// precedence. Errors only if `None`.
+    pub log_level: Option<String>,
+    /// Largest payload accepted by `EchoTest`, in bytes.
+    pub echo_max_payload: usize,
+    /// Commands each user may send per second, realtime data excluded.
+    pub command_rate: f64,
+    /// How many commands may be sent in a burst above `command_rate`.
+    pub command_burst: f64,
+    /// Chat messages each user may send per second, on top of `command_rate`.
+    pub chat_rate: f64,
+    /// How many chat messages may be sent in a burst above `chat_rate`.
+    pub chat_burst: f64,
+    /// Commands announced to the whole room or reaching the Phira API, like
+    /// joining rooms or selecting charts, each user may send per second, on
+    /// top of `command_rate`.
+    pub flood_rate: f64,
+    /// How many of those may be sent in a burst above `flood_rate`.
+    pub flood_burst: f64,
+    /// Longest chat message accepted, in bytes of UTF-8. The protocol caps
+    /// this at 200.
+    pub chat_max_len: usize,
+    /// Most touch frames or judge events in a single batch. Larger batches
+    /// are dropped.
+    pub realtime_batch_max: usize,
+    /// Where the admin HTTP API listens, disabled if `None`.
+    pub admin_addr: Option<SocketAddr>,
+    /// Where `GET /healthz` is served, e.g. for load balancers, disabled if
+    /// `None`. Needs no token.
+    pub health_addr: Option<SocketAddr>,
+    /// Listened on only while the server is healthy, for probes that just
+    /// open a TCP connection. Disabled if `None`.
+    pub health_tcp_addr: Option<SocketAddr>,
+    /// Bearer token required by the admin HTTP API.
+    pub admin_token: Option<String>,
+    /// Path of the Unix domain control socket, disabled if `None`.
+    pub control_socket: Option<PathBuf>,
+    /// PEM certificate chain clients are served over TLS with, together
+    /// with `tls_key`. Needs the `tls` feature.
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of `tls_cert`.
+    pub tls_key: Option<PathBuf>,
+    /// Where clients speaking the protocol over WebSocket, e.g. web builds,
+    /// are accepted, disabled if `None`. Needs the `websocket` feature.
+    pub ws_addr: Option<SocketAddr>,
+    /// Chart pool of the quickplay room, which is only opened if non-empty.
+    pub quickplay_charts: Vec<i32>,
+    /// How long each quickplay chart stays before rotating.
+    pub quickplay_interval: Duration,
+    /// How long players have to get ready after each rotation.
+    pub quickplay_ready_time: Duration,
+    /// Words not allowed in display names, matched case-insensitively.
+    pub name_blocklist: Vec<String>,
+    /// Refuse to authenticate users with blocklisted names instead of masking
+    /// the offending words.
+    pub reject_bad_names: bool,
+    /// Where reports and other state are kept across restarts. Nothing is
+    /// written to disk if `None`.
+    pub data_dir: Option<PathBuf>,
+    /// Accounts registered more recently than this are treated as new and
+    /// can't host rooms or post links. Zero turns the check off.
+    pub new_account_age: Duration,
+    /// Accounts with less experience than this are treated as new as well.
+    pub new_account_min_exp: i64,
+    /// Phira API endpoint finished rounds are submitted to, disabled if `None`.
+    pub submit_url: Option<String>,
+    /// Bearer token used for result submission.
+    pub submit_token: Option<String>,
+    /// Identifies this server among clustered ones, given to clients as
+    /// their routing token so that proxies can send their reconnects back
+    /// here. Up to 64 URL-safe characters; no token is given if `None`.
+    pub node_id: Option<String>,
+    /// Name the server is advertised under on the local network via mDNS,
+    /// not advertised if `None`.
+    pub lan_name: Option<String>,
+    /// Most rooms that may exist at once, unlimited if `None`.
+    pub max_rooms: Option<usize>,
+    /// Most players a room may be created for or raised to.
+    pub room_max_players: usize,
+    /// How long a disconnected user keeps their place in a room that isn't
+    /// playing, so that they can come back by authenticating again.
+    pub reconnect_grace: Duration,
+    /// How long a player who lost connection mid-round keeps their place in
+    /// the game, so that they can come back, e.g. on a new connection after
+    /// switching networks, before they're dropped from it. Never longer
+    /// than `reconnect_grace`.
+    pub playing_grace: Duration,
+    /// Rooms without activity for this long are closed, never if `None`.
+    pub room_idle_timeout: Option<Duration>,
+    /// Tell room members when their rounds are recorded or spectators are
+    /// watching, on joining and whenever that changes.
+    pub recording_notice: bool,
+    /// Added to the recording notice, e.g. a link to the privacy policy.
+    pub recording_notice_text: Option<String>,
+    /// Memory for note data of charts, in bytes. Zero disables the cache.
+    pub chart_cache_size: usize,
+    /// Where parsed note data is kept across restarts. Defaults to `charts`
+    /// in `data_dir`.
+    pub chart_cache_dir: Option<PathBuf>,
+    /// Bytes of touches and judges a room may have relayed per second before
+    /// its touches are dropped, unlimited if `None`.
+    pub room_bandwidth_budget: Option<u64>,
+    /// Time handling a room's commands and relays may take per second before
+    /// its touches are dropped, unlimited if `None`. This is wall-clock time,
+    /// so waiting on a busy runtime counts too.
+    pub room_handler_budget: Option<Duration>,
+    /// Time between two heartbeats of clients, who are told when they
+    /// authenticate.
+    pub heartbeat_interval: Duration,
+    /// How long clients wait for the response to a heartbeat.
+    pub heartbeat_timeout: Duration,
+    /// Connections silent for this long are considered lost. Should be a
+    /// few times `heartbeat_interval`.
+    pub heartbeat_disconnect_timeout: Duration,
+    /// How far ahead of `StartPlaying` rounds are scheduled to begin for
+    /// clients that synchronize their clock, which should cover their
+    /// latency.
+    pub start_delay: Duration,
+    /// Public test server mode for client developers: any token is accepted,
+    /// results aren't submitted and rooms are labelled as test rooms.
+    pub sandbox: bool,
+    /// How long rooms may exist on a sandbox server, activity or not.
+    pub sandbox_room_lifetime: Duration,
+    /// Name of the bot chatting in rooms on behalf of the server, which is
+    /// quiet if `None`. See [`crate::bot_say`].
+    pub bot_name: Option<String>,
+    /// What the bot tells members joining a room, `{name}` replaced by
+    /// theirs.
+    pub bot_welcome: Option<String>,
+    /// The bot sums up the results of every round.
+    pub bot_round_summaries: bool,
+    /// The bot counts down to quickplay rounds and to rooms closing.
+    pub bot_countdowns: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: None,
+            port: 12346,
+            region: None,
+            log_level: None,
+            echo_max_payload: 64 * 1024,
+            command_rate: 5.,
+            command_burst: 20.,
+            chat_rate: 1.,
+            chat_burst: 5.,
+            flood_rate: 1.,
+            flood_burst: 10.,
+            chat_max_len: 200,
+            realtime_batch_max: 256,
+            admin_addr: None,
+            health_addr: None,
+            health_tcp_addr: None,
+            admin_token: None,
+            control_socket: None,
+            tls_cert: None,
+            tls_key: None,
+            ws_addr: None,
+            quickplay_charts: Vec::new(),
+            quickplay_interval: Duration::from_secs(300),
+            quickplay_ready_time: Duration::from_secs(30),
+            name_blocklist: Vec::new(),
+            reject_bad_names: false,
+            data_dir: None,
+            new_account_age: Duration::ZERO,
+            new_account_min_exp: 0,
+            submit_url: None,
+            submit_token: None,
+            node_id: None,
+            lan_name: None,
+            max_rooms: None,
+            room_max_players: ROOM_MAX_PLAYERS as usize,
+            reconnect_grace: Duration::from_secs(10),
+            playing_grace: Duration::from_secs(5),
+            room_idle_timeout: None,
+            recording_notice: false,
+            recording_notice_text: None,
+            chart_cache_size: 0,
+            chart_cache_dir: None,
+            room_bandwidth_budget: None,
+            room_handler_budget: None,
+            heartbeat_interval: HEARTBEAT_INTERVAL,
+            heartbeat_timeout: HEARTBEAT_TIMEOUT,
+            heartbeat_disconnect_timeout: HEARTBEAT_DISCONNECT_TIMEOUT,
+            start_delay: Duration::from_millis(1500),
+            sandbox: false,
+            sandbox_room_lifetime: Duration::from_secs(30 * 60),
+            bot_name: None,
+            bot_welcome: None,
+            bot_round_summaries: false,
+            bot_countdowns: false,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Options set through environment variables, named in the README.
+    /// Values that don't parse are errors, as with [`Self::load`].
+    pub fn from_env() -> Result<Self> {
+        Self::load(None)
+    }
+
+    /// Like [`Self::from_env`], but also reading options not set in the
+    /// environment from the TOML `file`, under the environment variables'
+    /// names lowercased and without the `PHIRA_MP_` prefix, e.g.
+    /// `reconnect_grace = 10`. Unknown options, values that don't parse and
+    /// invalid combinations are errors.
+    pub fn load(file: Option<&Path>) -> Result<Self> {
+        let source = match file {
+            Some(path) => Source::file(path)
+                .with_context(|| format!("failed to read config file {}", path.display()))?,
+            None => Source::default(),
+        };
+        let config = Self::from_source(&source);
+        let mut errors = source.errors.into_inner();
+        let read = source.read.into_inner();
+        errors.extend(
+            source
+                .file
+                .keys()
+                .filter(|it| !read.contains(*it))
+                .map(|it| format!("unknown option `{it}`")),
+        );
+        if !errors.is_empty() {
+            bail!("invalid configuration:\n{}", errors.join("\n"));
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks options that are fine on their own but not together, or that
+    /// the server can't work with.
+    pub fn validate(&self) -> Result<()> {
+        let mut errors = Vec::new();
+        let mut check = |ok: bool, err: &str| {
+            if !ok {
+                errors.push(err.to_owned());
+            }
+        };
+        check(self.port != 0, "port must not be 0");
+        check(
+            (1..=ROOM_MAX_PLAYERS as usize).contains(&self.room_max_players),
+            &format!("room_max_players must be between 1 and {ROOM_MAX_PLAYERS}"),
+        );
+        check(
+            (1..=200).contains(&self.chat_max_len),
+            "chat_max_len must be between 1 and 200",
+        );
+        for (name, rate) in [
+            ("command_rate", self.command_rate),
+            ("command_burst", self.command_burst),
+            ("chat_rate", self.chat_rate),
+            ("chat_burst", self.chat_burst),
+            ("flood_rate", self.flood_rate),
+            ("flood_burst", self.flood_burst),
+        ] {
+            check(rate > 0., &format!("{name} must be positive"));
+        }
+        check(
+            !self.heartbeat_interval.is_zero(),
+            "heartbeat_interval_ms must be positive",
+        );
+        check(
+            self.heartbeat_disconnect_timeout > self.heartbeat_interval,
+            "heartbeat_disconnect_ms must be longer than heartbeat_interval_ms",
+        );
+        check(
+            !matches!(&self.node_id, Some(it) if it.len() > 64),
+            "node_id must be at most 64 characters",
+        );
+        check(
+            self.admin_addr.is_none() || self.admin_token.is_some(),
+            "admin_addr is set, but admin_token is not",
+        );
+        check(
+            self.tls_cert.is_some() == self.tls_key.is_some(),
+            "tls_cert and tls_key must be set together",
+        );
+        for path in self.tls_cert.iter().chain(&self.tls_key) {
+            check(path.is_file(), &format!("{} is not a file", path.display()));
+        }
+        check(
+            self.bot_name.is_some()
+                || (self.bot_welcome.is_none()
+                    && !self.bot_round_summaries
+                    && !self.bot_countdowns),
+            "bot options are set, but bot_name is not",
+        );
+        check(
+            cfg!(feature = "tls") || self.tls_cert.is_none(),
+            "tls_cert is set, but the server was built without the `tls` feature",
+        );
+        check(
+            cfg!(feature = "websocket") || self.ws_addr.is_none(),
+            "ws_addr is set, but the server was built without the `websocket` feature",
+        );
+        if let Some(level) = &self.log_level {
+            check(
+                EnvFilter::try_new(level).is_ok(),
+                &format!("invalid log_level `{level}`"),
+            );
+        }
+        if !errors.is_empty() {
+            bail!("invalid configuration:\n{}", errors.join("\n"));
+        }
+        Ok(())
+    }
+
+    /// Where the game listener binds, on all interfaces unless `bind_addr`
+    /// is set.
+    pub fn listen_addrs(&self) -> Vec<SocketAddr> {
+        match self.bind_addr {
+            Some(ip) => vec![SocketAddr::new(ip, self.port)],
+            None => vec![
+                SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), self.port),
+                SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), self.port),
+            ],
+        }
+    }
+
+    fn from_source(source: &Source) -> Self {
+        let default = Self::default();
+        let data_dir = source.path("PHIRA_MP_DATA_DIR");
+        Self {
+            bind_addr: source.parse("PHIRA_MP_BIND_ADDR"),
+            port: source.or("PHIRA_MP_PORT", default.port),
+            region: source.string("PHIRA_MP_REGION"),
+            log_level: source.string("PHIRA_MP_LOG_LEVEL"),
+            echo_max_payload: source.or("PHIRA_MP_ECHO_MAX_PAYLOAD", default.echo_max_payload),
+            command_rate: source.or("PHIRA_MP_COMMAND_RATE", default.command_rate),
+            command_burst: source.or("PHIRA_MP_COMMAND_BURST", default.command_burst),
+            chat_rate: source.or("PHIRA_MP_CHAT_RATE", default.chat_rate),
+            chat_burst: source.or("PHIRA_MP_CHAT_BURST", default.chat_burst),
+            flood_rate: source.or("PHIRA_MP_FLOOD_RATE", default.flood_rate),
+            flood_burst: source.or("PHIRA_MP_FLOOD_BURST", default.flood_burst),
+            chat_max_len: source.or("PHIRA_MP_CHAT_MAX_LEN", default.chat_max_len),
+            realtime_batch_max: source
+                .or("PHIRA_MP_REALTIME_BATCH_MAX", default.realtime_batch_max),
+            admin_addr: source.parse("PHIRA_MP_ADMIN_ADDR"),
+            health_addr: source.parse("PHIRA_MP_HEALTH_ADDR"),
+            health_tcp_addr: source.parse("PHIRA_MP_HEALTH_TCP_ADDR"),
+            admin_token: source.string("PHIRA_MP_ADMIN_TOKEN"),
+            control_socket: source.path("PHIRA_MP_CONTROL_SOCKET"),
+            tls_cert: source.path("PHIRA_MP_TLS_CERT"),
+            tls_key: source.path("PHIRA_MP_TLS_KEY"),
+            ws_addr: source.parse("PHIRA_MP_WS_ADDR"),
+            quickplay_charts: source.list("PHIRA_MP_QUICKPLAY_CHARTS"),
+            quickplay_interval: Duration::from_secs(source.or(
+                "PHIRA_MP_QUICKPLAY_INTERVAL",
+                default.quickplay_interval.as_secs(),
+            )),
+            quickplay_ready_time: Duration::from_secs(source.or(
+                "PHIRA_MP_QUICKPLAY_READY_TIME",
+                default.quickplay_ready_time.as_secs(),
+            )),
+            name_blocklist: source.list("PHIRA_MP_NAME_BLOCKLIST"),
+            reject_bad_names: source.or("PHIRA_MP_REJECT_BAD_NAMES", default.reject_bad_names),
+            chart_cache_dir: source
+                .path("PHIRA_MP_CHART_CACHE_DIR")
+                .or_else(|| data_dir.as_ref().map(|it| it.join("charts"))),
+            data_dir,
+            new_account_age: Duration::from_secs(
+                source.or("PHIRA_MP_NEW_ACCOUNT_DAYS", 0u64) * 24 * 60 * 60,
+            ),
+            new_account_min_exp: source
+                .or("PHIRA_MP_NEW_ACCOUNT_MIN_EXP", default.new_account_min_exp),
+            submit_url: source.string("PHIRA_MP_SUBMIT_URL"),
+            submit_token: source.string("PHIRA_MP_SUBMIT_TOKEN"),
+            node_id: source.string("PHIRA_MP_NODE_ID"),
+            lan_name: source.string("PHIRA_MP_LAN_NAME"),
+            max_rooms: source.parse("PHIRA_MP_MAX_ROOMS"),
+            room_max_players: source.or("PHIRA_MP_ROOM_MAX_PLAYERS", default.room_max_players),
+            reconnect_grace: Duration::from_secs(source.or(
+                "PHIRA_MP_RECONNECT_GRACE",
+                default.reconnect_grace.as_secs(),
+            )),
+            playing_grace: Duration::from_secs(
+                source.or("PHIRA_MP_PLAYING_GRACE", default.playing_grace.as_secs()),
+            ),
+            room_idle_timeout: source
+                .parse("PHIRA_MP_ROOM_IDLE_TIMEOUT")
+                .filter(|it| *it > 0)
+                .map(Duration::from_secs),
+            recording_notice: source.or("PHIRA_MP_RECORDING_NOTICE", default.recording_notice),
+            recording_notice_text: source.string("PHIRA_MP_RECORDING_NOTICE_TEXT"),
+            chart_cache_size: source.or("PHIRA_MP_CHART_CACHE_MB", 0usize) * 1024 * 1024,
+            room_bandwidth_budget: source
+                .parse::<u64>("PHIRA_MP_ROOM_BANDWIDTH_KB")
+                .filter(|it| *it > 0)
+                .map(|it| it * 1024),
+            room_handler_budget: source
+                .parse("PHIRA_MP_ROOM_HANDLER_MS")
+                .filter(|it| *it > 0)
+                .map(Duration::from_millis),
+            heartbeat_interval: source
+                .millis("PHIRA_MP_HEARTBEAT_INTERVAL_MS", default.heartbeat_interval),
+            heartbeat_timeout: source
+                .millis("PHIRA_MP_HEARTBEAT_TIMEOUT_MS", default.heartbeat_timeout),
+            heartbeat_disconnect_timeout: source.millis(
+                "PHIRA_MP_HEARTBEAT_DISCONNECT_MS",
+                default.heartbeat_disconnect_timeout,
+            ),
+            start_delay: source.millis("PHIRA_MP_START_DELAY_MS", default.start_delay),
+            sandbox: source.or("PHIRA_MP_SANDBOX", default.sandbox),
+            sandbox_room_lifetime: Duration::from_secs(source.or(
+                "PHIRA_MP_SANDBOX_ROOM_LIFETIME",
+                default.sandbox_room_lifetime.as_secs(),
+            )),
+            bot_name: source.string("PHIRA_MP_BOT_NAME"),
+            bot_welcome: source.string("PHIRA_MP_BOT_WELCOME"),
+            bot_round_summaries: source
+                .or("PHIRA_MP_BOT_ROUND_SUMMARIES", default.bot_round_summaries),
+            bot_countdowns: source.or("PHIRA_MP_BOT_COUNTDOWNS", default.bot_countdowns),
+        }
+    }
+
+    /// Limits clients are told about when they authenticate.
+    pub fn limits(&self) -> ServerLimits {
+        ServerLimits {
+            chat_max_len: self.chat_max_len as u16,
+            realtime_batch_max: self.realtime_batch_max as u32,
+            command_rate: self.command_rate as f32,
+            command_burst: self.command_burst as f32,
+            chat_rate: self.chat_rate as f32,
+            chat_burst: self.chat_burst as f32,
+            heartbeat_interval_ms: self.heartbeat_interval.as_millis() as u32,
+            heartbeat_timeout_ms: self.heartbeat_timeout.as_millis() as u32,
+            sandbox: self.sandbox,
+            bot_name: self.bot_name.clone(),
+        }
+    }
+}
+
+/// Where options are read from: the environment first, then the config
+/// file, if any.
+#[derive(Default)]
+struct Source {
+    /// Values of the config file by option name, lists joined with commas
+    file: HashMap<String, String>,
+    /// Option names looked up so far, any other in the file is unknown
+    read: RefCell<HashSet<String>>,
+    errors: RefCell<Vec<String>>,
+}
+
+impl Source {
+    fn file(path: &Path) -> Result<Self> {
+        let table: toml::Table = toml::from_str(&std::fs::read_to_string(path)?)?;
+        let mut file = HashMap::new();
+        for (key, value) in table {
+            let value = match value {
+                toml::Value::Array(items) => items
+                    .iter()
+                    .map(file_value)
+                    .collect::<Result<Vec<_>>>()
+                    .with_context(|| format!("invalid value for `{key}`"))?
+                    .join(","),
+                value => {
+                    file_value(&value).with_context(|| format!("invalid value for `{key}`"))?
+                }
+            };
+            file.insert(key, value);
+        }
+        Ok(Self {
+            file,
+            ..Self::default()
+        })
+    }
+
+    /// The option's name in the config file, marked as known.
+    fn name(&self, key: &str) -> String {
+        let name = key
+            .strip_prefix("PHIRA_MP_")
+            .unwrap_or(key)
+            .to_ascii_lowercase();
+        self.read.borrow_mut().insert(name.clone());
+        name
+    }
+
+    /// The raw value of the option, and where it's from.
+    fn get(&self, key: &str) -> Option<(String, String)> {
+        let name = self.name(key);
+        if let Ok(value) = std::env::var(key) {
+            return Some((key.to_owned(), value));
+        }
+        self.file.get(&name).map(|it| (name, it.clone()))
+    }
+
+    /// Unset if empty.
+    fn string(&self, key: &str) -> Option<String> {
+        self.get(key).map(|it| it.1).filter(|it| !it.is_empty())
+    }
+
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        // Those from the environment don't need to be valid UTF-8
+        if let Some(path) = std::env::var_os(key) {
+            self.name(key);
+            return Some(PathBuf::from(path)).filter(|it| !it.as_os_str().is_empty());
+        }
+        self.string(key).map(PathBuf::from)
+    }
+
+    fn parse<T: FromStr>(&self, key: &str) -> Option<T> {
+        let (name, value) = self.get(key)?;
+        match value.parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                self.errors
+                    .borrow_mut()
+                    .push(format!("invalid value for {name}: `{value}`"));
+                None
+            }
+        }
+    }
+
+    fn or<T: FromStr>(&self, key: &str, default: T) -> T {
+        self.parse(key).unwrap_or(default)
+    }
+
+    fn millis(&self, key: &str, default: Duration) -> Duration {
+        Duration::from_millis(self.or(key, default.as_millis() as u64))
+    }
+
+    /// Comma-separated list, invalid items are skipped.
+    fn list<T: FromStr>(&self, key: &str) -> Vec<T> {
+        self.get(key)
+            .map(|(_, it)| {
+                it.split(',')
+                    .filter_map(|it| it.trim().parse().ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+fn file_value(value: &toml::Value) -> Result<String> {
+    Ok(match value {
+        toml::Value::String(it) => it.clone(),
+        toml::Value::Integer(it) => it.to_string(),
+        toml::Value::Float(it) => it.to_string(),
+        toml::Value::Boolean(it) => it.to_string(),
+        _ => bail!("expected a string, number, boolean or a list of those"),
+    })
+}
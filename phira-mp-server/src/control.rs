@@ -0,0 +1,87 @@
+use crate::{announce, kick_user, ServerState};
+use anyhow::{bail, Result};
+use phira_mp_common::{
+    read_control_frame, write_control_frame, ControlCommand, ControlResponse, ControlRoom,
+};
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    sync::{atomic::Ordering, Arc},
+};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::{debug, info, warn};
+
+/// Serves the local control socket at `path`, see [`ControlCommand`]. Only
+/// the user running the server may connect.
+pub async fn serve_control(state: Arc<ServerState>, path: &Path) -> Result<()> {
+    // Left over if the server wasn't shut down cleanly
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    info!("control socket listening on {}", path.display());
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn({
+            let state = Arc::clone(&state);
+            async move {
+                if let Err(err) = handle(&state, stream).await {
+                    warn!("control connection failed: {err:?}");
+                }
+            }
+        });
+    }
+}
+
+async fn handle(state: &ServerState, stream: UnixStream) -> Result<()> {
+    let (mut read, mut write) = stream.into_split();
+    while let Some(cmd) = read_control_frame::<ControlCommand>(&mut read).await? {
+        debug!("control command: {cmd:?}");
+        let resp = execute(state, cmd)
+            .await
+            .unwrap_or_else(|err| ControlResponse::Error(err.to_string()));
+        write_control_frame(&mut write, &resp).await?;
+    }
+    Ok(())
+}
+
+async fn execute(state: &ServerState, cmd: ControlCommand) -> Result<ControlResponse> {
+    Ok(match cmd {
+        ControlCommand::ListRooms => {
+            let rooms: Vec<_> = state.rooms.values();
+            let mut result = Vec::with_capacity(rooms.len());
+            for room in rooms {
+                result.push(ControlRoom {
+                    id: room.id.clone(),
+                    state: room.client_room_state().await,
+                    host: room.host.read().await.upgrade().map(|it| it.id),
+                    locked: room.is_locked(),
+                    users: room
+                        .users()
+                        .await
+                        .into_iter()
+                        .chain(room.monitors().await)
+                        .map(|it| it.to_info())
+                        .collect(),
+                });
+            }
+            ControlResponse::Rooms(result)
+        }
+        ControlCommand::Kick { user: id } => {
+            if !kick_user(state, id, false).await {
+                bail!("user not found");
+            }
+            ControlResponse::Ok
+        }
+        ControlCommand::Announce { message } => {
+            announce(state, message).await;
+            ControlResponse::Ok
+        }
+        ControlCommand::Drain => {
+            if !state.draining.swap(true, Ordering::SeqCst) {
+                info!("draining, no more rooms can be created");
+            }
+            ControlResponse::Ok
+        }
+    })
+}
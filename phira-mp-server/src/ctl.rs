@@ -0,0 +1,67 @@
+//! `phira-mp-server ctl <command>`: controls the running server through the
+//! control socket configured in the environment or the `--config` file.
+
+use anyhow::{bail, Context, Result};
+use phira_mp_common::{read_control_frame, write_control_frame, ControlCommand, ControlResponse};
+use phira_mp_server::ServerConfig;
+use std::path::Path;
+use tokio::net::UnixStream;
+
+const USAGE: &str = "usage: phira-mp-server ctl rooms | kick <user> | announce <message> | drain";
+
+pub async fn ctl(config: Option<&Path>, mut args: impl Iterator<Item = String>) -> Result<()> {
+    let cmd = match args.next().as_deref() {
+        Some("rooms") => ControlCommand::ListRooms,
+        Some("kick") => ControlCommand::Kick {
+            user: args
+                .next()
+                .context(USAGE)?
+                .parse()
+                .context("invalid user id")?,
+        },
+        Some("announce") => {
+            let message = args.collect::<Vec<_>>().join(" ");
+            if message.is_empty() {
+                bail!(USAGE);
+            }
+            ControlCommand::Announce { message }
+        }
+        Some("drain") => ControlCommand::Drain,
+        _ => bail!(USAGE),
+    };
+
+    let path = ServerConfig::load(config)?
+        .control_socket
+        .context("PHIRA_MP_CONTROL_SOCKET must be set to the server's control socket")?;
+    let mut stream = UnixStream::connect(&path)
+        .await
+        .with_context(|| format!("failed to connect to {}", path.display()))?;
+    write_control_frame(&mut stream, &cmd).await?;
+    let resp: ControlResponse = read_control_frame(&mut stream)
+        .await?
+        .context("server closed the connection")?;
+    match resp {
+        ControlResponse::Ok => println!("ok"),
+        ControlResponse::Rooms(rooms) => {
+            for room in rooms {
+                let users: Vec<_> = room
+                    .users
+                    .iter()
+                    .map(|it| {
+                        let host = if Some(it.id) == room.host { "*" } else { "" };
+                        format!("{host}{} ({})", it.name, it.id)
+                    })
+                    .collect();
+                println!(
+                    "{}{} [{:?}] {}",
+                    room.id,
+                    if room.locked { " (locked)" } else { "" },
+                    room.state,
+                    users.join(", ")
+                );
+            }
+        }
+        ControlResponse::Error(err) => bail!(err),
+    }
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use crate::{tl, Chart};
+use anyhow::{bail, Result};
+use chrono::Utc;
+use phira_mp_common::{DraftAction, DraftState};
+use rand::{seq::IteratorRandom, thread_rng};
+use std::{ops::RangeInclusive, time::Duration};
+
+/// Charts a draft pool may have at most.
+pub const DRAFT_MAX_POOL: usize = 16;
+/// Time players may be given per turn, in seconds.
+pub const DRAFT_TURN_SECS: RangeInclusive<u16> = 10..=300;
+
+/// A pick/ban phase in progress, see [`Room::start_draft`](crate::Room::start_draft).
+pub struct Draft {
+    pub id: u32,
+    pub state: DraftState,
+    /// Fetched when the draft started
+    charts: Vec<Chart>,
+    turn_time: Duration,
+}
+
+impl Draft {
+    /// `charts` is the pool, which [`check_pool`] accepted.
+    pub fn new(
+        id: u32,
+        charts: Vec<Chart>,
+        order: Vec<i32>,
+        bans: u8,
+        turn_time: Duration,
+    ) -> Self {
+        Self {
+            id,
+            state: DraftState {
+                pool: charts.iter().map(|it| it.id).collect(),
+                banned: Vec::new(),
+                order,
+                bans,
+                deadline: Utc::now() + chrono::Duration::from_std(turn_time).unwrap(),
+            },
+            charts,
+            turn_time,
+        }
+    }
+
+    /// Takes the current turn on behalf of `user`. Returns the chart if it
+    /// was picked, which ends the draft.
+    pub fn take_turn(
+        &mut self,
+        user: i32,
+        action: DraftAction,
+        chart: i32,
+    ) -> Result<Option<Chart>> {
+        let (expected_user, expected_action) = self.state.turn();
+        if user != expected_user {
+            bail!(tl!("draft-not-your-turn"));
+        }
+        if action != expected_action {
+            bail!(tl!("draft-wrong-action"));
+        }
+        if !self.state.remaining().any(|it| it == chart) {
+            bail!(tl!("draft-not-in-pool", "chart" => chart));
+        }
+        Ok(self.apply(chart))
+    }
+
+    /// Takes the current turn with a random chart, for a player who ran out
+    /// of time. Returns who that was, what was done to which chart, and the
+    /// chart if it was picked.
+    pub fn take_turn_auto(&mut self) -> (i32, DraftAction, i32, Option<Chart>) {
+        let (user, action) = self.state.turn();
+        // At least one chart remains as long as the draft goes on
+        let chart = self.state.remaining().choose(&mut thread_rng()).unwrap();
+        (user, action, chart, self.apply(chart))
+    }
+
+    fn apply(&mut self, chart: i32) -> Option<Chart> {
+        match self.state.turn().1 {
+            DraftAction::Ban => {
+                self.state.banned.push(chart);
+                self.state.deadline =
+                    Utc::now() + chrono::Duration::from_std(self.turn_time).unwrap();
+                None
+            }
+            DraftAction::Pick => self.charts.iter().find(|it| it.id == chart).cloned(),
+        }
+    }
+}
+
+/// Checks that a draft can be held with `pool`, `bans` and `turn_secs`.
+pub fn check_pool(pool: &[i32], bans: u8, turn_secs: u16) -> Result<()> {
+    let mut distinct = pool.to_vec();
+    distinct.sort_unstable();
+    distinct.dedup();
+    if pool.is_empty()
+        || pool.len() > DRAFT_MAX_POOL
+        || distinct.len() != pool.len()
+        || bans as usize >= pool.len()
+    {
+        bail!(tl!("draft-invalid-pool", "max" => DRAFT_MAX_POOL));
+    }
+    if !DRAFT_TURN_SECS.contains(&turn_secs) {
+        bail!(tl!(
+            "draft-invalid-turn-time",
+            "min" => *DRAFT_TURN_SECS.start(),
+            "max" => *DRAFT_TURN_SECS.end()
+        ));
+    }
+    Ok(())
+}
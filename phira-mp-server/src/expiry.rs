@@ -0,0 +1,60 @@
+use crate::{InternalRoomState, Room, ServerState};
+use std::{sync::Arc, time::Duration};
+use tokio::time;
+use tracing::info;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// How long before closing members are warned.
+const WARNING: Duration = Duration::from_secs(60);
+
+/// Closes rooms that have been idle for `timeout`, or that have existed for
+/// `lifetime`, warning their members beforehand. Quickplay rooms are never
+/// closed, and rooms in game only when their lifetime is up.
+pub async fn run_room_expiry(
+    state: Arc<ServerState>,
+    timeout: Option<Duration>,
+    lifetime: Option<Duration>,
+) {
+    let mut interval = time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let rooms = state.rooms.values();
+        for room in rooms {
+            if room.quickplay {
+                continue;
+            }
+            if let Some(lifetime) = lifetime {
+                let age = room.age();
+                if age >= lifetime {
+                    close(&state, &room, "closing room at end of lifetime").await;
+                    continue;
+                }
+                if age + WARNING.min(lifetime / 2) >= lifetime {
+                    room.announce_expiry(lifetime - age, true).await;
+                }
+            }
+            let Some(timeout) = timeout else {
+                continue;
+            };
+            if matches!(*room.state.read().await, InternalRoomState::Playing { .. }) {
+                room.touch().await;
+                continue;
+            }
+            let idle = room.idle_time();
+            if idle >= timeout {
+                close(&state, &room, "closing idle room").await;
+            } else if idle + WARNING.min(timeout / 2) >= timeout {
+                room.announce_expiry(timeout - idle, false).await;
+            }
+        }
+    }
+}
+
+async fn close(state: &ServerState, room: &Room, reason: &str) {
+    info!(room = room.id.to_string(), "{reason}");
+    for user in room.monitors().await.into_iter().chain(room.users().await) {
+        // The room is removed below regardless
+        let _ = room.on_user_leave(&user).await;
+    }
+    state.rooms.remove(&room.id);
+}
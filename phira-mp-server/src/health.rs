@@ -0,0 +1,141 @@
+use crate::ServerState;
+use anyhow::Result;
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use serde::Serialize;
+use std::{
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    net::TcpListener,
+    sync::Mutex,
+    task,
+    time::{self, Instant},
+};
+use tracing::{info, warn};
+
+/// How often the TCP probe listener checks whether it should be open.
+const TCP_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// How long the outcome of writing to the data directory is reused, so
+/// frequent probes don't touch the disk every time.
+const STORAGE_CHECK_TTL: Duration = Duration::from_secs(10);
+
+#[derive(Serialize)]
+struct Health {
+    healthy: bool,
+    /// Whether the game listener accepted its last connection, or has yet to
+    /// fail
+    listening: bool,
+    /// New rooms are refused, but those open are still served
+    draining: bool,
+    rooms: usize,
+    sessions: usize,
+    /// `disabled` without a data directory, or why it can't be written to
+    storage: String,
+}
+
+struct Checker {
+    state: Arc<ServerState>,
+    /// When storage was last checked, whether it's fine, and the
+    /// [`Health::storage`] to report
+    storage: Mutex<Option<(Instant, bool, String)>>,
+}
+
+impl Checker {
+    fn new(state: Arc<ServerState>) -> Self {
+        Self {
+            state,
+            storage: Mutex::default(),
+        }
+    }
+
+    async fn check(&self) -> Health {
+        let state = &self.state;
+        let listening = state.listening.load(Ordering::SeqCst);
+        let (storage_ok, storage) = self.check_storage().await;
+        Health {
+            healthy: listening && storage_ok,
+            listening,
+            draining: state.draining.load(Ordering::SeqCst),
+            rooms: state.rooms.len(),
+            sessions: state.sessions.len(),
+            storage,
+        }
+    }
+
+    async fn check_storage(&self) -> (bool, String) {
+        // Held while checking, so concurrent probes share one check
+        let mut cached = self.storage.lock().await;
+        if let Some((at, ok, storage)) = &*cached {
+            if at.elapsed() < STORAGE_CHECK_TTL {
+                return (*ok, storage.clone());
+            }
+        }
+        let store = self.state.store.clone();
+        let (ok, storage) = match task::spawn_blocking(move || store.check()).await {
+            Ok(Ok(true)) => (true, "ok".to_owned()),
+            Ok(Ok(false)) => (true, "disabled".to_owned()),
+            Ok(Err(err)) => (false, format!("{err:#}")),
+            Err(err) => (false, err.to_string()),
+        };
+        *cached = Some((Instant::now(), ok, storage.clone()));
+        (ok, storage)
+    }
+}
+
+/// Serves `GET /healthz` on `addr`, answering 503 when unhealthy.
+pub async fn serve(state: Arc<ServerState>, addr: SocketAddr) -> Result<()> {
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .with_state(Arc::new(Checker::new(state)));
+    info!("health check listening on {addr}");
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await?;
+    Ok(())
+}
+
+async fn healthz(State(checker): State<Arc<Checker>>) -> (StatusCode, Json<Health>) {
+    let health = checker.check().await;
+    let status = if health.healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(health))
+}
+
+/// Listens on `addr` only while the server is healthy, for probes that can
+/// do no more than open a connection. Those that get in are told `ok`.
+/// Failing to reopen it is retried on the next check.
+pub async fn serve_tcp(state: Arc<ServerState>, addr: SocketAddr) -> Result<()> {
+    let checker = Checker::new(state);
+    let mut listener = None;
+    let mut interval = time::interval(TCP_CHECK_INTERVAL);
+    loop {
+        let healthy = checker.check().await.healthy;
+        if healthy && listener.is_none() {
+            match TcpListener::bind(addr).await {
+                Ok(it) => {
+                    listener = Some(it);
+                    info!("TCP health probe listening on {addr}");
+                }
+                Err(err) => warn!("failed to open TCP health probe on {addr}: {err}"),
+            }
+        } else if !healthy && listener.take().is_some() {
+            warn!("unhealthy, TCP health probe closed");
+        }
+        let Some(probe) = &listener else {
+            interval.tick().await;
+            continue;
+        };
+        tokio::select! {
+            _ = interval.tick() => {}
+            Ok((mut stream, _)) = probe.accept() => {
+                let _ = stream.write_all(b"ok\n").await;
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use crate::ServerConfig;
+use anyhow::Result;
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use phira_mp_common::LAN_SERVICE_TYPE;
+use tracing::info;
+
+/// Keeps the server advertised on the local network until dropped.
+pub struct LanAdvertisement {
+    daemon: ServiceDaemon,
+}
+
+impl LanAdvertisement {
+    pub fn new(config: &ServerConfig, name: &str, port: u16) -> Result<Self> {
+        let daemon = ServiceDaemon::new()?;
+        let host: String = name
+            .chars()
+            .map(|it| if it.is_ascii_alphanumeric() { it } else { '-' })
+            .collect();
+        let mut properties = vec![("version", env!("CARGO_PKG_VERSION").to_owned())];
+        if let Some(region) = &config.region {
+            properties.push(("region", region.clone()));
+        }
+        let service = ServiceInfo::new(
+            LAN_SERVICE_TYPE,
+            name,
+            &format!("{host}.local."),
+            "",
+            port,
+            &properties[..],
+        )?
+        .enable_addr_auto();
+        daemon.register(service)?;
+        info!("advertising on the local network as {name}");
+        Ok(Self { daemon })
+    }
+}
+
+impl Drop for LanAdvertisement {
+    fn drop(&mut self) {
+        let _ = self.daemon.shutdown();
+    }
+}
@@ -0,0 +1,89 @@
+mod admin;
+
+mod bot;
+pub use bot::*;
+
+mod budget;
+pub use budget::*;
+
+mod charts;
+pub use charts::*;
+
+mod config;
+pub use config::*;
+
+#[cfg(unix)]
+mod control;
+#[cfg(unix)]
+pub use control::*;
+
+mod draft;
+pub use draft::*;
+
+mod expiry;
+pub use expiry::*;
+
+mod health;
+
+mod l10n;
+
+mod lan;
+pub use lan::*;
+
+mod logging;
+pub use logging::*;
+
+mod middleware;
+pub use middleware::*;
+
+mod moderation;
+pub use moderation::*;
+
+mod moves;
+pub use moves::*;
+
+mod names;
+pub use names::*;
+
+mod queue;
+pub use queue::*;
+
+mod quickplay;
+pub use quickplay::*;
+
+mod registry;
+pub use registry::*;
+
+mod reports;
+pub use reports::*;
+
+mod room;
+pub use room::*;
+
+mod server;
+pub use server::*;
+
+mod session;
+pub use session::*;
+
+mod stats;
+pub use stats::*;
+
+mod store;
+pub use store::*;
+
+mod submit;
+pub use submit::*;
+
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "tls")]
+pub use tls::*;
+
+mod trust;
+pub use trust::*;
+
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+
+pub type SafeMap<K, V> = RwLock<HashMap<K, V>>;
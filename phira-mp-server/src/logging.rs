@@ -0,0 +1,151 @@
+use anyhow::{anyhow, Result};
+use phira_mp_common::{LOG_HEARTBEAT, LOG_PROTOCOL};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+use tracing::{metadata::LevelFilter, Level};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{filter::Targets, EnvFilter};
+
+/// Parts of the server whose log verbosity can be changed at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Subsystem {
+    /// Every frame sent or received.
+    Protocol,
+    /// Room state changes and membership.
+    Room,
+    Heartbeat,
+}
+
+impl Subsystem {
+    pub const ALL: [Self; 3] = [Self::Protocol, Self::Room, Self::Heartbeat];
+
+    fn targets(self) -> &'static [&'static str] {
+        match self {
+            Self::Protocol => &[LOG_PROTOCOL],
+            Self::Room => &["phira_mp_server::room", "phira_mp_server::quickplay"],
+            Self::Heartbeat => &[LOG_HEARTBEAT],
+        }
+    }
+}
+
+type Overrides = BTreeMap<Subsystem, LevelFilter>;
+type ApplyFn = Box<dyn Fn(&Overrides) -> Result<()> + Send + Sync>;
+
+/// Per-subsystem levels applied on top of the defaults, for both the log
+/// files and stdout. Only available once [`init_log`] has been called.
+pub struct LogLevels {
+    overrides: Mutex<Overrides>,
+    apply: ApplyFn,
+}
+
+static LOG_LEVELS: OnceLock<LogLevels> = OnceLock::new();
+
+impl LogLevels {
+    pub fn get() -> Option<&'static Self> {
+        LOG_LEVELS.get()
+    }
+
+    pub fn overrides(&self) -> Overrides {
+        self.overrides.lock().unwrap().clone()
+    }
+
+    /// `None` restores the default level.
+    pub fn set(&self, subsystem: Subsystem, level: Option<LevelFilter>) -> Result<()> {
+        let mut overrides = self.overrides.lock().unwrap();
+        match level {
+            Some(level) => overrides.insert(subsystem, level),
+            None => overrides.remove(&subsystem),
+        };
+        (self.apply)(&overrides)
+    }
+}
+
+fn file_filter(overrides: &Overrides) -> Targets {
+    let mut targets = Targets::new().with_default(LevelFilter::DEBUG);
+    for (subsystem, level) in overrides {
+        for target in subsystem.targets() {
+            targets = targets.with_target(*target, *level);
+        }
+    }
+    targets
+}
+
+fn stdout_filter(level: Option<&str>, overrides: &Overrides) -> EnvFilter {
+    let mut filter = match level {
+        Some(level) if std::env::var_os(EnvFilter::DEFAULT_ENV).is_none() => EnvFilter::new(level),
+        _ => EnvFilter::from_default_env(),
+    };
+    for (subsystem, level) in overrides {
+        for target in subsystem.targets() {
+            if let Ok(directive) = format!("{target}={level}").parse() {
+                filter = filter.add_directive(directive);
+            }
+        }
+    }
+    filter
+}
+
+/// Logs to hourly files named `file` in `log`, and to stdout filtered by
+/// `RUST_LOG`, or `level` without it.
+pub fn init_log(file: &str, level: Option<&str>) -> Result<WorkerGuard> {
+    use tracing_log::LogTracer;
+    use tracing_subscriber::{filter, fmt, prelude::*, reload};
+
+    let log_dir = Path::new("log");
+    if log_dir.exists() {
+        if !log_dir.is_dir() {
+            panic!("log exists and is not a folder");
+        }
+    } else {
+        std::fs::create_dir(log_dir).expect("failed to create log folder");
+    }
+
+    LogTracer::init()?;
+
+    let (non_blocking, guard) =
+        tracing_appender::non_blocking(tracing_appender::rolling::hourly(log_dir, file));
+
+    let (file_filter_layer, file_handle) = reload::Layer::new(file_filter(&Overrides::new()));
+    let (stdout_filter_layer, stdout_handle) =
+        reload::Layer::new(stdout_filter(level, &Overrides::new()));
+
+    let subscriber = tracing_subscriber::registry()
+        .with(
+            fmt::layer()
+                .with_writer(non_blocking)
+                .with_filter(file_filter_layer),
+        )
+        .with(
+            fmt::layer()
+                .with_writer(std::io::stdout)
+                .with_filter(stdout_filter_layer),
+        )
+        .with(
+            filter::Targets::new()
+                .with_target("hyper", Level::INFO)
+                .with_target("rustls", Level::INFO)
+                .with_target("isahc", Level::INFO)
+                .with_default(Level::TRACE),
+        );
+
+    tracing::subscriber::set_global_default(subscriber).expect("unable to set global subscriber");
+    let level = level.map(str::to_owned);
+    let _ = LOG_LEVELS.set(LogLevels {
+        overrides: Mutex::default(),
+        apply: Box::new(move |overrides| {
+            file_handle
+                .reload(file_filter(overrides))
+                .map_err(|err| anyhow!("failed to reload log filter: {err}"))?;
+            stdout_handle
+                .reload(stdout_filter(level.as_deref(), overrides))
+                .map_err(|err| anyhow!("failed to reload log filter: {err}"))?;
+            Ok(())
+        }),
+    });
+    Ok(guard)
+}
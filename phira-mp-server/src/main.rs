@@ -1,94 +1,44 @@
-mod l10n;
-
-mod room;
-pub use room::*;
-
-mod server;
-pub use server::*;
-
-mod session;
-pub use session::*;
-
-use anyhow::Result;
-use std::{
-    collections::{
-        hash_map::{Entry, VacantEntry},
-        HashMap,
-    },
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
-    path::Path,
-};
-use tokio::{net::TcpListener, sync::RwLock};
+#[cfg(unix)]
+mod ctl;
+mod watch;
+
+use anyhow::{bail, Context, Result};
+use phira_mp_server::{init_log, Server, ServerConfig};
+use std::path::PathBuf;
+use tokio::net::TcpListener;
 use tracing::warn;
-use tracing_appender::non_blocking::WorkerGuard;
-use uuid::Uuid;
-
-pub type SafeMap<K, V> = RwLock<HashMap<K, V>>;
-pub type IdMap<V> = SafeMap<Uuid, V>;
-
-fn vacant_entry<V>(map: &mut HashMap<Uuid, V>) -> VacantEntry<'_, Uuid, V> {
-    let mut id = Uuid::new_v4();
-    while map.contains_key(&id) {
-        id = Uuid::new_v4();
-    }
-    match map.entry(id) {
-        Entry::Vacant(entry) => entry,
-        _ => unreachable!(),
-    }
-}
-
-pub fn init_log(file: &str) -> Result<WorkerGuard> {
-    use tracing::{metadata::LevelFilter, Level};
-    use tracing_log::LogTracer;
-    use tracing_subscriber::{filter, fmt, prelude::*, EnvFilter};
 
-    let log_dir = Path::new("log");
-    if log_dir.exists() {
-        if !log_dir.is_dir() {
-            panic!("log exists and is not a folder");
-        }
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1).peekable();
+    // Applies to the commands below as well
+    let config = if args.peek().map(String::as_str) == Some("--config") {
+        args.next();
+        let path = args
+            .next()
+            .context("usage: phira-mp-server --config <file> [command]")?;
+        Some(PathBuf::from(path))
     } else {
-        std::fs::create_dir(log_dir).expect("failed to create log folder");
+        None
+    };
+    match args.next().as_deref() {
+        None => serve(config).await,
+        Some("watch") => {
+            let room = args.next().context("usage: phira-mp-server watch <room>")?;
+            watch::watch(config.as_deref(), &room).await
+        }
+        #[cfg(unix)]
+        Some("ctl") => ctl::ctl(config.as_deref(), args).await,
+        Some(command) => bail!("unknown command: {command}"),
     }
-
-    LogTracer::init()?;
-
-    let (non_blocking, guard) =
-        tracing_appender::non_blocking(tracing_appender::rolling::hourly(log_dir, file));
-
-    let subscriber = tracing_subscriber::registry()
-        .with(
-            fmt::layer()
-                .with_writer(non_blocking)
-                .with_filter(LevelFilter::DEBUG),
-        )
-        .with(
-            fmt::layer()
-                .with_writer(std::io::stdout)
-                .with_filter(EnvFilter::from_default_env()),
-        )
-        .with(
-            filter::Targets::new()
-                .with_target("hyper", Level::INFO)
-                .with_target("rustls", Level::INFO)
-                .with_target("isahc", Level::INFO)
-                .with_default(Level::TRACE),
-        );
-
-    tracing::subscriber::set_global_default(subscriber).expect("unable to set global subscriber");
-    Ok(guard)
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let _guard = init_log("phira-mp")?;
+async fn serve(config: Option<PathBuf>) -> Result<()> {
+    let config = ServerConfig::load(config.as_deref())?;
+    let _guard = init_log("phira-mp", config.log_level.as_deref())?;
 
-    let port = 12346;
-    let addrs: &[SocketAddr] = &[
-        SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port),
-        SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port),
-    ];
-    let listener: Server = TcpListener::bind(addrs).await?.into();
+    let addrs = config.listen_addrs();
+    let listener = Server::new(config, TcpListener::bind(&addrs[..]).await?)?;
     loop {
         if let Err(err) = listener.accept().await {
             warn!("failed to accept: {err:?}");
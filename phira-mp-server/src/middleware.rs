@@ -0,0 +1,303 @@
+//! Layers wrapped around command handling.
+//!
+//! Commands only enter the chain once their session is authenticated. Layers
+//! run in insertion order and each decides whether to pass the command on by
+//! calling [`Next::run`]; the innermost step is the actual command handler.
+//!
+//! Authentication itself isn't a layer: every layer is handed the [`User`]
+//! the command came from, and before `Authenticate` there is none. The
+//! session answers `Hello`, `Authenticate` and `Resume` itself and drops
+//! anything else sent before them.
+
+use crate::{process, tl, ServerConfig, User};
+use phira_mp_common::{ClientCommand, ServerCommand, ServerError};
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{debug, trace};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+pub trait Middleware: Send + Sync {
+    fn handle<'a>(
+        &'a self,
+        user: Arc<User>,
+        cmd: ClientCommand,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Option<ServerCommand>>;
+}
+
+pub struct Next<'a> {
+    chain: &'a [Arc<dyn Middleware>],
+}
+
+impl<'a> Next<'a> {
+    pub fn new(chain: &'a [Arc<dyn Middleware>]) -> Self {
+        Self { chain }
+    }
+
+    pub async fn run(self, user: Arc<User>, cmd: ClientCommand) -> Option<ServerCommand> {
+        match self.chain.split_first() {
+            Some((layer, chain)) => layer.handle(user, cmd, Next { chain }).await,
+            None => process(user, cmd).await,
+        }
+    }
+}
+
+/// The response a rejected command should get, or `None` for commands that
+/// aren't answered (realtime data).
+pub fn reject(cmd: &ClientCommand, err: ServerError) -> Option<ServerCommand> {
+    Some(match cmd {
+        ClientCommand::Ping
+        | ClientCommand::Region
+        | ClientCommand::Route { .. }
+        | ClientCommand::Resume { .. }
+        | ClientCommand::Bye
+        | ClientCommand::Touches { .. }
+        | ClientCommand::PhasedTouches { .. }
+        | ClientCommand::PackedTouches { .. }
+        | ClientCommand::Judges { .. }
+        | ClientCommand::Reliable { .. }
+        | ClientCommand::UnknownCommand { .. }
+        | ClientCommand::SyncClock { .. }
+        | ClientCommand::ResendJudges { .. }
+        | ClientCommand::Prefetch { .. }
+        | ClientCommand::ChartProgress { .. }
+        | ClientCommand::ChartReady { .. }
+        | ClientCommand::ReportLatency { .. }
+        | ClientCommand::SetTouchSpace { .. }
+        | ClientCommand::AckStart { .. }
+        | ClientCommand::ScoreUpdate { .. } => return None,
+        ClientCommand::Authenticate { .. } => ServerCommand::Authenticate(Err(err)),
+        ClientCommand::Hello { .. } => ServerCommand::Hello(Err(err)),
+        ClientCommand::SetDuel { .. } => ServerCommand::SetDuel(Err(err)),
+        ClientCommand::SetMaxPlayers { .. } => ServerCommand::SetMaxPlayers(Err(err)),
+        ClientCommand::StartDraft { .. } => ServerCommand::StartDraft(Err(err)),
+        ClientCommand::CancelDraft => ServerCommand::CancelDraft(Err(err)),
+        ClientCommand::DraftTurn { .. } => ServerCommand::DraftTurn(Err(err)),
+        ClientCommand::Chat { .. }
+        | ClientCommand::EncryptedChat { .. }
+        | ClientCommand::Whisper { .. } => ServerCommand::Chat(Err(err)),
+        ClientCommand::CreateRoom { .. } => ServerCommand::CreateRoom(Err(err)),
+        ClientCommand::JoinRoom { .. } => ServerCommand::JoinRoom(Err(err)),
+        ClientCommand::LeaveRoom => ServerCommand::LeaveRoom(Err(err)),
+        ClientCommand::LockRoom { .. } => ServerCommand::LockRoom(Err(err)),
+        ClientCommand::CycleRoom { .. } => ServerCommand::CycleRoom(Err(err)),
+        ClientCommand::SelectChart { .. } => ServerCommand::SelectChart(Err(err)),
+        ClientCommand::RequestStart => ServerCommand::RequestStart(Err(err)),
+        ClientCommand::Ready => ServerCommand::Ready(Err(err)),
+        ClientCommand::CancelReady => ServerCommand::CancelReady(Err(err)),
+        ClientCommand::Played { .. } => ServerCommand::Played(Err(err)),
+        ClientCommand::Abort => ServerCommand::Abort(Err(err)),
+        ClientCommand::EchoTest { .. } => ServerCommand::EchoTest(Err(err)),
+        ClientCommand::WatchPlayer { .. } => ServerCommand::WatchPlayer(Err(err)),
+        ClientCommand::ExportResults { .. } => ServerCommand::ExportResults(Err(err)),
+        ClientCommand::SlowMode { .. } => ServerCommand::SlowMode(Err(err)),
+        ClientCommand::ReportPlayer { .. } => ServerCommand::ReportPlayer(Err(err)),
+        ClientCommand::SyncState => ServerCommand::SyncState(Err(err)),
+        ClientCommand::BridgeSpectatorChat { .. } => ServerCommand::BridgeSpectatorChat(Err(err)),
+        ClientCommand::ChatWithId { id, .. } => ServerCommand::ChatAck {
+            id: *id,
+            result: Err(err),
+        },
+        ClientCommand::ForceCancelStart => ServerCommand::ForceCancelStart(Err(err)),
+        ClientCommand::SetRecording { .. } => ServerCommand::SetRecording(Err(err)),
+        ClientCommand::FetchRoomHistory => ServerCommand::FetchRoomHistory(Err(err)),
+        ClientCommand::SetRepeatPolicy { .. } => ServerCommand::SetRepeatPolicy(Err(err)),
+        ClientCommand::SetRoomMode { .. } => ServerCommand::SetRoomMode(Err(err)),
+        ClientCommand::QueueChart { .. } => ServerCommand::QueueChart(Err(err)),
+        ClientCommand::UnqueueChart { .. } => ServerCommand::UnqueueChart(Err(err)),
+        ClientCommand::VoteChart { .. } => ServerCommand::VoteChart(Err(err)),
+        ClientCommand::SetReadyCheck { .. } => ServerCommand::SetReadyCheck(Err(err)),
+        ClientCommand::SelectRandomChart { .. } => ServerCommand::SelectRandomChart(Err(err)),
+        ClientCommand::EndRound { .. } => ServerCommand::EndRound(Err(err)),
+        ClientCommand::SplitRoom { .. } => ServerCommand::SplitRoom(Err(err)),
+        ClientCommand::MergeRoom { .. } => ServerCommand::MergeRoom(Err(err)),
+        ClientCommand::ListRooms => ServerCommand::RoomList(Err(err)),
+        ClientCommand::KickPlayer { .. } => ServerCommand::KickPlayer(Err(err)),
+        ClientCommand::BanPlayer { .. } => ServerCommand::BanPlayer(Err(err)),
+        ClientCommand::MutePlayer { .. } => ServerCommand::MutePlayer(Err(err)),
+        ClientCommand::TimeoutPlayer { .. } => ServerCommand::TimeoutPlayer(Err(err)),
+        ClientCommand::TransferHost { .. } => ServerCommand::TransferHost(Err(err)),
+    })
+}
+
+pub fn default_chain(config: &ServerConfig) -> Vec<Arc<dyn Middleware>> {
+    vec![
+        Arc::new(RateLimit::new(config.command_rate, config.command_burst)),
+        Arc::new(RateLimit::chat(config.chat_rate, config.chat_burst)),
+        Arc::new(RateLimit::flood(config.flood_rate, config.flood_burst)),
+        Arc::new(Logging),
+    ]
+}
+
+pub struct Logging;
+
+impl Middleware for Logging {
+    fn handle<'a>(
+        &'a self,
+        user: Arc<User>,
+        cmd: ClientCommand,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Option<ServerCommand>> {
+        Box::pin(async move {
+            let id = user.id;
+            trace!(user = id, "handling {cmd:?}");
+            let start = Instant::now();
+            let resp = next.run(user, cmd).await;
+            if let Some(resp) = &resp {
+                debug!(user = id, "handled in {:?}: {resp:?}", start.elapsed());
+            }
+            resp
+        })
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+/// Per-user token bucket over the commands it applies to.
+pub struct RateLimit {
+    rate: f64,
+    burst: f64,
+    applies: fn(&ClientCommand) -> bool,
+    buckets: Mutex<HashMap<i32, TokenBucket>>,
+}
+
+impl RateLimit {
+    /// Limits all commands except realtime data and state reports. Neither is
+    /// answered, so a rejected one would be lost without the client knowing,
+    /// and a lost report (a start ack, say) is never sent again.
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self::with_filter(rate, burst, |cmd| {
+            !matches!(
+                cmd,
+                ClientCommand::Touches { .. }
+                    | ClientCommand::PhasedTouches { .. }
+                    | ClientCommand::PackedTouches { .. }
+                    | ClientCommand::Judges { .. }
+                    | ClientCommand::ScoreUpdate { .. }
+                    | ClientCommand::AckStart { .. }
+                    | ClientCommand::ChartReady { .. }
+                    | ClientCommand::ChartProgress { .. }
+                    | ClientCommand::ReportLatency { .. }
+                    | ClientCommand::SetTouchSpace { .. }
+                    | ClientCommand::ResendJudges { .. }
+                    | ClientCommand::Prefetch { .. }
+            )
+        })
+    }
+
+    /// Limits chat messages only.
+    pub fn chat(rate: f64, burst: f64) -> Self {
+        Self::with_filter(rate, burst, |cmd| {
+            matches!(
+                cmd,
+                ClientCommand::Chat { .. }
+                    | ClientCommand::ChatWithId { .. }
+                    | ClientCommand::EncryptedChat { .. }
+                    | ClientCommand::Whisper { .. }
+            )
+        })
+    }
+
+    /// Limits commands announced to the whole room or reaching the Phira
+    /// API, which flood others or cost the most to handle.
+    pub fn flood(rate: f64, burst: f64) -> Self {
+        Self::with_filter(rate, burst, |cmd| {
+            matches!(
+                cmd,
+                ClientCommand::CreateRoom { .. }
+                    | ClientCommand::JoinRoom { .. }
+                    | ClientCommand::LeaveRoom
+                    | ClientCommand::LockRoom { .. }
+                    | ClientCommand::CycleRoom { .. }
+                    | ClientCommand::SetRoomMode { .. }
+                    | ClientCommand::QueueChart { .. }
+                    | ClientCommand::UnqueueChart { .. }
+                    | ClientCommand::VoteChart { .. }
+                    | ClientCommand::SelectChart { .. }
+                    | ClientCommand::SelectRandomChart { .. }
+                    | ClientCommand::Ready
+                    | ClientCommand::CancelReady
+                    | ClientCommand::ReportPlayer { .. }
+                    | ClientCommand::ExportResults { .. }
+                    | ClientCommand::ListRooms
+                    | ClientCommand::EchoTest { .. }
+            )
+        })
+    }
+
+    /// # Panics
+    ///
+    /// If `rate` isn't positive, commands would never be let through again.
+    pub fn with_filter(rate: f64, burst: f64, applies: fn(&ClientCommand) -> bool) -> Self {
+        assert!(rate > 0., "rate must be positive, got {rate}");
+        Self {
+            rate,
+            burst,
+            applies,
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// How long until the next command would be let through, if not now.
+    fn acquire(&self, user: i32) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > 1024 {
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.retain(|_, it| {
+                it.tokens + now.duration_since(it.last).as_secs_f64() * rate < burst
+            });
+        }
+        let bucket = buckets.entry(user).or_insert(TokenBucket {
+            tokens: self.burst,
+            last: now,
+        });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.last).as_secs_f64() * self.rate)
+            .min(self.burst);
+        bucket.last = now;
+        if bucket.tokens >= 1. {
+            bucket.tokens -= 1.;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1. - bucket.tokens) / self.rate))
+        }
+    }
+}
+
+impl Middleware for RateLimit {
+    fn handle<'a>(
+        &'a self,
+        user: Arc<User>,
+        cmd: ClientCommand,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Option<ServerCommand>> {
+        Box::pin(async move {
+            if !(self.applies)(&cmd) {
+                return next.run(user, cmd).await;
+            }
+            match self.acquire(user.id) {
+                Ok(()) => next.run(user, cmd).await,
+                Err(retry_after) => {
+                    debug!(user = user.id, "rate limited: {cmd:?}");
+                    let retry_after_ms = retry_after.as_millis().min(u32::MAX as u128) as u32;
+                    reject(
+                        &cmd,
+                        ServerError::RateLimited {
+                            retry_after_ms,
+                            message: tl!("rate-limited").into_owned(),
+                        },
+                    )
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{Room, ServerState, Store};
+use anyhow::Result;
+use phira_mp_common::{Message, ServerCommand};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, Weak},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::info;
+
+const BANS_FILE: &str = "bans.json";
+
+/// A user banned from the whole server by the operator.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    pub user: i32,
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+    pub reason: Option<String>,
+}
+
+pub struct Bans {
+    store: Store,
+    list: Mutex<BTreeMap<i32, Ban>>,
+    /// Held from changing the list until it's saved, so that saves land in
+    /// order
+    saving: tokio::sync::Mutex<()>,
+}
+
+impl Bans {
+    pub fn new(store: Store) -> Self {
+        Self {
+            list: store.load::<BTreeMap<i32, Ban>>(BANS_FILE).into(),
+            store,
+            saving: tokio::sync::Mutex::default(),
+        }
+    }
+
+    pub fn list(&self) -> Vec<Ban> {
+        self.list.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn is_banned(&self, user: i32) -> bool {
+        self.list.lock().unwrap().contains_key(&user)
+    }
+
+    /// Replaces the reason if the user is banned already.
+    pub async fn ban(&self, user: i32, reason: Option<String>) -> Result<()> {
+        let _saving = self.saving.lock().await;
+        let data = {
+            let mut list = self.list.lock().unwrap();
+            list.insert(
+                user,
+                Ban {
+                    user,
+                    created_at: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |it| it.as_secs()),
+                    reason,
+                },
+            );
+            serde_json::to_vec(&*list)?
+        };
+        self.store.save_serialized(BANS_FILE, data).await
+    }
+
+    /// Returns whether the user was banned.
+    pub async fn unban(&self, user: i32) -> Result<bool> {
+        let _saving = self.saving.lock().await;
+        let data = {
+            let mut list = self.list.lock().unwrap();
+            if list.remove(&user).is_none() {
+                return Ok(false);
+            }
+            serde_json::to_vec(&*list)?
+        };
+        self.store.save_serialized(BANS_FILE, data).await?;
+        Ok(true)
+    }
+}
+
+/// Disconnects the user from the server, leaving their room. Returns whether
+/// they were online.
+pub async fn kick_user(server: &ServerState, id: i32, banned: bool) -> bool {
+    let Some(user) = server.users.remove(&id) else {
+        return false;
+    };
+    info!(user = id, banned, "kicked off the server");
+    user.revoke_resume_token();
+    let room = user.room.read().await.as_ref().map(Arc::clone);
+    if let Some(room) = room {
+        if room.on_user_leave(&user).await {
+            server.rooms.remove(&room.id);
+        }
+    }
+    let session = user.session.read().await.as_ref().and_then(Weak::upgrade);
+    if let Some(session) = session {
+        session
+            .try_send(ServerCommand::Kicked { room: None, banned })
+            .await;
+        // Dropping the session closes the connection
+        server.sessions.remove(&session.id);
+    }
+    true
+}
+
+/// Shows a message to every connected user.
+pub async fn announce(server: &ServerState, message: String) {
+    info!("announcement: {message}");
+    let cmd = ServerCommand::Message(Message::Announcement { content: message });
+    for user in server.users.values() {
+        user.try_send(cmd.clone()).await;
+    }
+}
+
+/// Removes everyone from the room and drops it. Members stay connected.
+pub async fn close_room(server: &ServerState, room: &Room) {
+    info!(room = room.id.to_string(), "room closed by the operator");
+    for user in room.monitors().await.into_iter().chain(room.users().await) {
+        // The room is removed below regardless
+        let _ = room.on_user_leave(&user).await;
+        user.try_send(ServerCommand::Kicked {
+            room: Some(room.id.clone()),
+            banned: false,
+        })
+        .await;
+    }
+    server.rooms.remove(&room.id);
+}
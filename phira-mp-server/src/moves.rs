@@ -0,0 +1,124 @@
+use crate::{tl, InternalRoomState, Room, ServerState, TrustLevel, User};
+use anyhow::{anyhow, bail, Result};
+use phira_mp_common::{Message, RoomId, ServerCommand, ServerError};
+use std::sync::{atomic::Ordering, Arc, Weak};
+use tracing::info;
+
+/// Moves everyone in `from` into `into`, then closes `from`. Both rooms must
+/// be choosing a chart, and `into` needs space for all players.
+pub async fn merge_rooms(server: &ServerState, from: &Arc<Room>, into: &Arc<Room>) -> Result<()> {
+    if Arc::ptr_eq(from, into) {
+        bail!(tl!("merge-same-room"));
+    }
+    check_movable(from).await?;
+    check_movable(into).await?;
+    let users = from.users().await;
+    if users.len() + into.users().await.len() > into.max_players() {
+        bail!(ServerError::RoomFull {
+            message: tl!("join-room-full").into_owned(),
+        });
+    }
+    if let Some(user) = users.iter().find(|it| into.is_banned(it.id)) {
+        bail!(tl!("merge-banned", "user" => user.name()));
+    }
+    info!(
+        from = from.id.to_string(),
+        into = into.id.to_string(),
+        "merge rooms"
+    );
+    for user in from.monitors().await.into_iter().chain(users) {
+        move_user(server, from, into, &user).await;
+    }
+    server.rooms.remove(&from.id);
+    Ok(())
+}
+
+/// Moves `users` of `room` into a new room `id`, hosted by the first of them
+/// who may host. `room` must be choosing a chart, and the new room starts
+/// with its chart and recording policy.
+pub async fn split_room(
+    server: &ServerState,
+    room: &Arc<Room>,
+    id: RoomId,
+    users: &[i32],
+) -> Result<Arc<Room>> {
+    check_movable(room).await?;
+    let members: Vec<_> = room
+        .users()
+        .await
+        .into_iter()
+        .chain(room.monitors().await)
+        .collect();
+    let mut moved: Vec<Arc<User>> = Vec::new();
+    for id in users {
+        if moved.iter().any(|it| it.id == *id) {
+            continue;
+        }
+        let user = members
+            .iter()
+            .find(|it| it.id == *id)
+            .ok_or_else(|| anyhow!(tl!("split-not-member", "user" => *id)))?;
+        moved.push(Arc::clone(user));
+    }
+    let host = moved
+        .iter()
+        .find(|it| !it.monitor.load(Ordering::SeqCst) && it.trust != TrustLevel::New)
+        .ok_or_else(|| anyhow!(tl!("split-no-host")))?;
+
+    // Members are added one by one below, the host included
+    let new = Arc::new(Room::new(
+        id.clone(),
+        Weak::new(),
+        Weak::clone(&room.server),
+    ));
+    *new.host.write().await = Arc::downgrade(host);
+    new.inherit(room).await;
+    match server
+        .rooms
+        .try_insert_bounded(id.clone(), Arc::clone(&new), server.config.max_rooms)
+    {
+        Some(true) => {}
+        Some(false) => bail!(tl!("create-id-occupied")),
+        None => bail!(tl!("create-too-many-rooms")),
+    }
+    info!(
+        from = room.id.to_string(),
+        room = id.to_string(),
+        users = moved.len(),
+        "split room"
+    );
+    new.send(Message::CreateRoom { user: host.id }).await;
+    for user in moved {
+        move_user(server, room, &new, &user).await;
+    }
+    Ok(new)
+}
+
+async fn check_movable(room: &Room) -> Result<()> {
+    if room.quickplay {
+        bail!(tl!("move-quickplay"));
+    }
+    if !matches!(*room.state.read().await, InternalRoomState::SelectChart) {
+        bail!(tl!("move-game-ongoing"));
+    }
+    if room.duel().is_some() {
+        bail!(tl!("move-duel"));
+    }
+    if room.is_drafting() {
+        bail!(tl!("draft-in-progress"));
+    }
+    Ok(())
+}
+
+/// `to` must have space for `user`.
+async fn move_user(server: &ServerState, from: &Room, to: &Arc<Room>, user: &Arc<User>) {
+    if from.on_user_leave(user).await {
+        server.rooms.remove(&from.id);
+    }
+    to.add_user(Arc::downgrade(user), user.monitor.load(Ordering::SeqCst))
+        .await;
+    *user.room.write().await = Some(Arc::clone(to));
+    to.welcome(user).await;
+    user.try_send(ServerCommand::MovedRoom(to.client_state(user).await))
+        .await;
+}
@@ -0,0 +1,61 @@
+use crate::ServerConfig;
+use anyhow::{bail, Result};
+
+pub const NAME_MAX_CHARS: usize = 32;
+
+/// Cleans up a display name from the Phira API before it's shown to anyone:
+/// control characters are dropped, overlong names are cut, and blocklisted
+/// words are either masked or rejected depending on the config.
+pub fn sanitize_name(config: &ServerConfig, id: i32, name: &str) -> Result<String> {
+    let mut name: String = name
+        .chars()
+        .filter(|it| !it.is_control())
+        .take(NAME_MAX_CHARS)
+        .collect::<String>()
+        .trim()
+        .to_owned();
+    if name.is_empty() {
+        return Ok(format!("Player{id}"));
+    }
+    for word in &config.name_blocklist {
+        let Some(range) = find_ignore_case(&name, word) else {
+            continue;
+        };
+        if config.reject_bad_names {
+            bail!("inappropriate user name");
+        }
+        let mut masked = String::with_capacity(name.len());
+        let mut rest = name.as_str();
+        let mut range = Some(range);
+        while let Some((start, end)) = range {
+            masked.push_str(&rest[..start]);
+            masked.push_str(&"*".repeat(rest[start..end].chars().count()));
+            rest = &rest[end..];
+            range = find_ignore_case(rest, word);
+        }
+        masked.push_str(rest);
+        name = masked;
+    }
+    Ok(name)
+}
+
+/// Byte range of the first case-insensitive occurrence of `needle`.
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<(usize, usize)> {
+    if needle.is_empty() {
+        return None;
+    }
+    let needle: Vec<char> = needle.chars().collect();
+    haystack.char_indices().find_map(|(start, _)| {
+        let mut chars = haystack[start..].char_indices();
+        for expected in &needle {
+            let (_, c) = chars.next()?;
+            if !c.to_lowercase().eq(expected.to_lowercase()) {
+                return None;
+            }
+        }
+        Some((
+            start,
+            start + chars.next().map_or(haystack.len() - start, |(i, _)| i),
+        ))
+    })
+}
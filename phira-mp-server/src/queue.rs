@@ -0,0 +1,94 @@
+use crate::{tl, Chart};
+use anyhow::{bail, Result};
+use phira_mp_common::{QueuedChart, CHART_QUEUE_MAX, CHART_QUEUE_PER_USER};
+
+/// Charts members would like to play next, see
+/// [`Room::queue_chart`](crate::Room::queue_chart).
+#[derive(Default)]
+pub struct ChartQueue {
+    /// Fetched when queued, oldest first
+    entries: Vec<(Chart, QueuedChart)>,
+}
+
+impl ChartQueue {
+    pub fn state(&self) -> Vec<QueuedChart> {
+        self.entries.iter().map(|it| it.1.clone()).collect()
+    }
+
+    /// Who queued `chart`, if it's queued.
+    pub fn queued_by(&self, chart: i32) -> Option<i32> {
+        self.entries
+            .iter()
+            .find(|it| it.0.id == chart)
+            .map(|it| it.1.user)
+    }
+
+    /// Checks that `user` can queue `chart`, before it's fetched.
+    pub fn check_push(&self, user: i32, chart: i32) -> Result<()> {
+        if self.queued_by(chart).is_some() {
+            bail!(tl!("queue-duplicate", "chart" => chart));
+        }
+        if self.entries.len() >= CHART_QUEUE_MAX {
+            bail!(tl!("queue-full", "max" => CHART_QUEUE_MAX));
+        }
+        if self.entries.iter().filter(|it| it.1.user == user).count() >= CHART_QUEUE_PER_USER {
+            bail!(tl!("queue-user-full", "max" => CHART_QUEUE_PER_USER));
+        }
+        Ok(())
+    }
+
+    pub fn push(&mut self, user: i32, chart: Chart) -> Result<QueuedChart> {
+        self.check_push(user, chart.id)?;
+        let queued = QueuedChart {
+            chart: chart.id,
+            name: chart.name.clone(),
+            user,
+            votes: Vec::new(),
+        };
+        self.entries.push((chart, queued.clone()));
+        Ok(queued)
+    }
+
+    /// Returns whether `chart` was queued.
+    pub fn remove(&mut self, chart: i32) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|it| it.0.id != chart);
+        self.entries.len() != len
+    }
+
+    /// Moves the vote of `user` to `chart`, or withdraws it.
+    pub fn vote(&mut self, user: i32, chart: Option<i32>) -> Result<()> {
+        if let Some(chart) = chart {
+            if self.queued_by(chart).is_none() {
+                bail!(tl!("queue-not-queued", "chart" => chart));
+            }
+        }
+        for (it, queued) in &mut self.entries {
+            queued.votes.retain(|it| *it != user);
+            if Some(it.id) == chart {
+                queued.votes.push(user);
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the votes of `user`, who left. Their charts stay queued.
+    pub fn forget(&mut self, user: i32) {
+        for (_, queued) in &mut self.entries {
+            queued.votes.retain(|it| *it != user);
+        }
+    }
+
+    /// Takes the chart with the most votes, the oldest on ties, leaving out
+    /// those `skip` returns `true` for.
+    pub fn take_next(&mut self, skip: impl Fn(i32) -> bool) -> Option<(Chart, QueuedChart)> {
+        let index = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, it)| !skip(it.0.id))
+            .max_by_key(|(index, it)| (it.1.votes.len(), std::cmp::Reverse(*index)))?
+            .0;
+        Some(self.entries.remove(index))
+    }
+}
@@ -0,0 +1,73 @@
+use crate::{bot_say, Chart, InternalRoomState, Room};
+use anyhow::{bail, Result};
+use phira_mp_common::GameEndReason;
+use rand::{seq::SliceRandom, thread_rng};
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use tokio::time;
+use tracing::{info, warn};
+
+/// Drives a quickplay room: every `interval` a random chart from `charts` is
+/// picked and players get `ready_time` to get ready. Whoever is ready by then
+/// plays, and the round is forcibly ended when the next rotation comes.
+pub async fn run_quickplay(
+    room: Arc<Room>,
+    charts: Vec<i32>,
+    interval: Duration,
+    ready_time: Duration,
+) {
+    let ready_time = ready_time.min(interval);
+    loop {
+        if room.users().await.is_empty() {
+            time::sleep(interval).await;
+            continue;
+        }
+        if let Err(err) = rotate(&room, &charts, ready_time).await {
+            warn!(
+                room = room.id.to_string(),
+                "failed to rotate quickplay chart: {err:?}"
+            );
+            time::sleep(interval).await;
+            continue;
+        }
+        time::sleep(ready_time).await;
+        room.force_start().await;
+        time::sleep(interval - ready_time).await;
+        room.force_end(GameEndReason::TimedOut).await;
+    }
+}
+
+async fn rotate(room: &Room, charts: &[i32], ready_time: Duration) -> Result<()> {
+    let Some(&id) = charts.choose(&mut thread_rng()) else {
+        bail!("empty chart pool");
+    };
+    let chart = Chart::fetch(id).await?;
+    info!(
+        room = room.id.to_string(),
+        chart = chart.id,
+        "quickplay rotates to {}",
+        chart.name
+    );
+    let name = chart.name.clone();
+    *room.chart.write().await = Some(chart);
+    room.on_state_change().await;
+
+    room.reset_game_time().await;
+    *room.state.write().await = InternalRoomState::WaitForReady {
+        started: HashSet::new(),
+    };
+    room.on_state_change().await;
+    let secs = ready_time.as_secs();
+    bot_say(
+        room,
+        |it| it.bot_countdowns,
+        |lang| {
+            lang.format(
+                "bot-quickplay-countdown",
+                Some(&fluent::fluent_args!["chart" => name.as_str(), "secs" => secs]),
+            )
+            .into_owned()
+        },
+    )
+    .await;
+    Ok(())
+}
@@ -0,0 +1,165 @@
+use dashmap::{mapref::entry::Entry, try_result::TryResult, DashMap};
+use serde::Serialize;
+use std::{
+    hash::Hash,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+use uuid::Uuid;
+
+/// Sharded map for the server's sessions, users and rooms.
+///
+/// Each operation only locks the shard of its key, so lookups don't wait
+/// behind unrelated writes. Values are handed out as clones (they're `Arc`s),
+/// so no shard lock is ever held across an `.await`.
+pub struct Registry<K, V> {
+    map: DashMap<K, V>,
+    /// Entries, plus those reserved by [`Self::try_insert_bounded`] but not
+    /// inserted yet
+    len: AtomicUsize,
+    reads: AtomicU64,
+    writes: AtomicU64,
+    contended: AtomicU64,
+}
+
+/// Counters of a [`Registry`] since the server started.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RegistryStats {
+    pub len: usize,
+    pub reads: u64,
+    pub writes: u64,
+    /// Reads that found their shard locked by a writer
+    pub contended_reads: u64,
+}
+
+impl<K: Eq + Hash, V> Default for Registry<K, V> {
+    fn default() -> Self {
+        Self {
+            map: DashMap::new(),
+            len: AtomicUsize::default(),
+            reads: AtomicU64::default(),
+            writes: AtomicU64::default(),
+            contended: AtomicU64::default(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V: Clone> Registry<K, V> {
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        match self.map.try_get(key) {
+            TryResult::Present(value) => Some(value.clone()),
+            TryResult::Absent => None,
+            TryResult::Locked => {
+                self.contended.fetch_add(1, Ordering::Relaxed);
+                self.map.get(key).map(|it| it.clone())
+            }
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.map.contains_key(key)
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        let old = self.map.insert(key, value);
+        if old.is_none() {
+            self.len.fetch_add(1, Ordering::SeqCst);
+        }
+        old
+    }
+
+    /// Inserts `value` unless `key` is taken. Returns whether it was inserted.
+    pub fn try_insert(&self, key: K, value: V) -> bool {
+        self.len.fetch_add(1, Ordering::SeqCst);
+        self.insert_reserved(key, value)
+    }
+
+    /// Like [`Self::try_insert`], but fails with `None` if there are `max`
+    /// entries already. Concurrent inserts never exceed it.
+    pub fn try_insert_bounded(&self, key: K, value: V, max: Option<usize>) -> Option<bool> {
+        let Some(max) = max else {
+            return Some(self.try_insert(key, value));
+        };
+        self.len
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |len| {
+                (len < max).then_some(len + 1)
+            })
+            .ok()?;
+        Some(self.insert_reserved(key, value))
+    }
+
+    /// Inserts into a slot already counted in `len`, giving it back if `key`
+    /// is taken.
+    fn insert_reserved(&self, key: K, value: V) -> bool {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        match self.map.entry(key) {
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+                true
+            }
+            Entry::Occupied(_) => {
+                self.len.fetch_sub(1, Ordering::SeqCst);
+                false
+            }
+        }
+    }
+
+    /// Returns the value of `key`, inserting `value` first if there's none.
+    pub fn get_or_insert(&self, key: K, value: V) -> V {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        match self.map.entry(key) {
+            Entry::Vacant(entry) => {
+                self.len.fetch_add(1, Ordering::SeqCst);
+                entry.insert(value).clone()
+            }
+            Entry::Occupied(entry) => entry.get().clone(),
+        }
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        let removed = self.map.remove(key).map(|it| it.1);
+        if removed.is_some() {
+            self.len.fetch_sub(1, Ordering::SeqCst);
+        }
+        removed
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// A snapshot of all values. Shards are visited one at a time, so it
+    /// may miss concurrent changes.
+    pub fn values(&self) -> Vec<V> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.map.iter().map(|it| it.value().clone()).collect()
+    }
+
+    pub fn stats(&self) -> RegistryStats {
+        RegistryStats {
+            len: self.map.len(),
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            contended_reads: self.contended.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl<V: Clone> Registry<Uuid, V> {
+    /// A random id not in use yet.
+    pub fn vacant_id(&self) -> Uuid {
+        loop {
+            let id = Uuid::new_v4();
+            if !self.contains_key(&id) {
+                break id;
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{tl, ChatLine, Room, RoundRecord, Store, User};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const REPORTS_FILE: &str = "reports.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportedUser {
+    pub id: i32,
+    pub name: String,
+}
+
+impl From<&User> for ReportedUser {
+    fn from(user: &User) -> Self {
+        Self {
+            id: user.id,
+            name: user.name(),
+        }
+    }
+}
+
+/// A player report along with what was going on in the reporter's room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub id: u32,
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+    pub reporter: ReportedUser,
+    pub target: ReportedUser,
+    pub reason: String,
+    pub room: Option<String>,
+    pub chart: Option<i32>,
+    pub last_round: Option<RoundRecord>,
+    pub chat: Vec<ChatLine>,
+    pub resolved: bool,
+}
+
+pub struct Reports {
+    store: Store,
+    list: Mutex<Vec<Report>>,
+    /// Held from changing the list until it's saved, so that saves land in
+    /// order
+    saving: tokio::sync::Mutex<()>,
+}
+
+impl Reports {
+    pub fn new(store: Store) -> Self {
+        Self {
+            list: store.load::<Vec<Report>>(REPORTS_FILE).into(),
+            store,
+            saving: tokio::sync::Mutex::default(),
+        }
+    }
+
+    pub fn list(&self) -> Vec<Report> {
+        self.list.lock().unwrap().clone()
+    }
+
+    pub async fn submit(
+        &self,
+        reporter: &User,
+        target: &User,
+        reason: String,
+        room: Option<&Room>,
+    ) -> Result<u32> {
+        let (chart, last_round, chat) = match room {
+            Some(room) => (
+                room.chart.read().await.as_ref().map(|it| it.id),
+                room.rounds.read().await.last().cloned(),
+                room.chat_log(),
+            ),
+            None => (None, None, Vec::new()),
+        };
+        let _saving = self.saving.lock().await;
+        let (id, data) = {
+            let mut list = self.list.lock().unwrap();
+            if list.iter().any(|it| {
+                !it.resolved && it.reporter.id == reporter.id && it.target.id == target.id
+            }) {
+                bail!(tl!("report-duplicate"));
+            }
+            let id = list.last().map_or(1, |it| it.id + 1);
+            list.push(Report {
+                id,
+                created_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |it| it.as_secs()),
+                reporter: reporter.into(),
+                target: target.into(),
+                reason,
+                room: room.map(|it| it.id.to_string()),
+                chart,
+                last_round,
+                chat,
+                resolved: false,
+            });
+            (id, serde_json::to_vec(&*list)?)
+        };
+        self.store.save_serialized(REPORTS_FILE, data).await?;
+        Ok(id)
+    }
+
+    /// Returns whether the report exists.
+    pub async fn resolve(&self, id: u32) -> Result<bool> {
+        let _saving = self.saving.lock().await;
+        let data = {
+            let mut list = self.list.lock().unwrap();
+            let Some(report) = list.iter_mut().find(|it| it.id == id) else {
+                return Ok(false);
+            };
+            report.resolved = true;
+            serde_json::to_vec(&*list)?
+        };
+        self.store.save_serialized(REPORTS_FILE, data).await?;
+        Ok(true)
+    }
+}
@@ -1,19 +1,104 @@
-use crate::{Chart, Record, User};
+use crate::{
+    bot_say, bot_welcome, check_pool, l10n::Language, round_summary, tl, Chart, ChartQueue, Draft,
+    Record, RoomUsage, ServerConfig, ServerState, TrustLevel, User,
+};
 use anyhow::{bail, Result};
-use phira_mp_common::{ClientRoomState, Message, RoomId, RoomState, ServerCommand};
-use rand::{seq::SliceRandom, thread_rng};
+use chrono::{DateTime, Utc};
+use phira_mp_common::{
+    unix_millis, ChatChannel, ClientRoomState, DraftAction, DraftState, DuelSeries, Features,
+    GameEndReason, JudgeBatch, JudgeEvent, MemberPhase, MemberStatus, Message, PlayedChart,
+    PlayerResult, PlayerScore, QueuedChart, ReadyCheck, ReadyTimeoutAction, Recording,
+    RepeatPolicy, RoomId, RoomListing, RoomMode, RoomState, RoundPhase, ServerCommand, ServerError,
+    SyncStateResponse, TouchBatch, TouchFrame, RECENT_CHARTS, RESEND_JUDGES_MAX, ROOM_MAX_PLAYERS,
+};
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
-    collections::{HashMap, HashSet},
-    ops::Deref,
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Write,
+    ops::{Deref, RangeInclusive},
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Weak,
+        atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU8, Ordering},
+        Arc, Mutex, Weak,
     },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use tokio::sync::RwLock;
-use tracing::{debug, info};
+use subtle::ConstantTimeEq;
+use tokio::{
+    sync::{broadcast, RwLock},
+    time,
+};
+use tracing::{debug, info, warn};
+
+pub const ROOM_MAX_SPECTATORS: usize = 16;
+const CHAT_LOG_SIZE: usize = 50;
+/// Messages sent to those joining, see [`ServerCommand::MessageHistory`].
+const MESSAGE_HISTORY_SIZE: usize = 30;
+/// Rounds kept for [`Room::chart_history`].
+const CHART_HISTORY_SIZE: usize = 100;
+const ROOM_EVENTS_CAPACITY: usize = 64;
+/// Judges arriving within this long are relayed together.
+const JUDGE_RELAY_INTERVAL: Duration = Duration::from_millis(50);
+/// Relayed judge batches kept per player for [`Room::resend_judges`].
+const JUDGE_HISTORY: usize = 256;
+/// How long players have to acknowledge the start of a round before it's
+/// rolled back.
+const START_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest duel series a host can start.
+const DUEL_MAX_BEST_OF: u8 = 9;
+/// Score updates of a player closer together than this are dropped. Below
+/// [`phira_mp_common::SCORE_UPDATE_INTERVAL`] so that jitter doesn't drop
+/// regular ones.
+const SCORE_UPDATE_MIN_INTERVAL: Duration = Duration::from_millis(400);
+/// Highest score of a chart
+pub const SCORE_MAX: i32 = 1_000_000;
+/// Longest mute or timeout a host can give, in seconds.
+const MODERATION_MAX_SECS: u32 = 24 * 60 * 60;
+/// Time a [`ReadyCheck`] may give players, in seconds.
+const READY_CHECK_SECS: RangeInclusive<u16> = 5..=120;
+/// How long a burst of joins and leaves lasts, from its first one.
+const CHURN_WINDOW: Duration = Duration::from_secs(2);
+/// Joins and leaves of a burst announced one by one, see [`Room::announce`].
+const CHURN_ANNOUNCED: u32 = 3;
+
+/// Scores reported in a round and when, by player.
+#[derive(Default)]
+struct RoundScores {
+    round: u32,
+    scores: HashMap<i32, (Instant, PlayerScore)>,
+}
+
+/// Joins and leaves of the current burst, see [`Room::announce`].
+#[derive(Default)]
+struct Churn {
+    since: Option<Instant>,
+    count: u32,
+    joined: Vec<i32>,
+    left: Vec<i32>,
+}
 
-const ROOM_MAX_USERS: usize = 8;
+impl Churn {
+    fn take(&mut self) -> Option<Message> {
+        if self.joined.is_empty() && self.left.is_empty() {
+            return None;
+        }
+        Some(Message::MembersChurn {
+            joined: std::mem::take(&mut self.joined),
+            left: std::mem::take(&mut self.left),
+        })
+    }
+}
+
+/// Sequence numbers of a player's relayed frames in a round.
+#[derive(Default)]
+struct RelayState {
+    round: u32,
+    touch_seq: u32,
+    judge_seq: u32,
+    judges: VecDeque<(u32, Arc<JudgeBatch>)>,
+}
 
 #[derive(Default, Debug)]
 pub enum InternalRoomState {
@@ -23,6 +108,8 @@ pub enum InternalRoomState {
         started: HashSet<i32>,
     },
     Playing {
+        /// Same as the round of the resulting [`RoundRecord`]
+        round: u32,
         results: HashMap<i32, Record>,
         aborted: HashSet<i32>,
     },
@@ -36,36 +123,244 @@ impl InternalRoomState {
             Self::Playing { .. } => RoomState::Playing,
         }
     }
+
+    /// Phase of player `user`, monitors are always idle.
+    pub fn phase(&self, user: i32) -> MemberPhase {
+        match self {
+            Self::SelectChart => MemberPhase::Idle,
+            Self::WaitForReady { started } if started.contains(&user) => MemberPhase::Ready,
+            Self::WaitForReady { .. } => MemberPhase::Idle,
+            Self::Playing { results, .. } if results.contains_key(&user) => MemberPhase::Finished,
+            Self::Playing { aborted, .. } if aborted.contains(&user) => MemberPhase::Aborted,
+            Self::Playing { .. } => MemberPhase::Playing,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Standing {
+    pub rank: u32,
+    pub name: String,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+impl Standing {
+    pub fn to_result(&self) -> PlayerResult {
+        let record = &self.record;
+        PlayerResult {
+            user: record.player,
+            rank: self.rank,
+            score: record.score,
+            accuracy: record.accuracy,
+            full_combo: record.full_combo,
+            max_combo: record.max_combo as u32,
+            perfect: record.perfect as u32,
+            good: record.good as u32,
+            bad: record.bad as u32,
+            miss: record.miss as u32,
+        }
+    }
+}
+
+/// Results of a finished round, kept for the lifetime of the room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoundRecord {
+    pub round: u32,
+    pub chart: i32,
+    pub chart_name: String,
+    /// Unix timestamp in seconds
+    pub finished_at: u64,
+    pub standings: Vec<Standing>,
+    pub aborted: Vec<i32>,
+}
+
+impl RoundRecord {
+    fn new(
+        round: u32,
+        chart: Option<&Chart>,
+        results: &HashMap<i32, Record>,
+        aborted: &HashSet<i32>,
+        names: &HashMap<i32, String>,
+        ranked: bool,
+    ) -> Self {
+        let mut records: Vec<_> = results.values().cloned().collect();
+        records.sort_by(|a, b| {
+            b.score
+                .cmp(&a.score)
+                .then(b.accuracy.total_cmp(&a.accuracy))
+        });
+        let mut standings: Vec<Standing> = Vec::with_capacity(records.len());
+        for (index, record) in records.into_iter().enumerate() {
+            // Ties share the same rank
+            let rank = match standings.last() {
+                _ if !ranked => 1,
+                Some(last) if last.record.score == record.score => last.rank,
+                _ => index as u32 + 1,
+            };
+            standings.push(Standing {
+                rank,
+                name: names.get(&record.player).cloned().unwrap_or_default(),
+                record,
+            });
+        }
+        Self {
+            round,
+            chart: chart.map_or(-1, |it| it.id),
+            chart_name: chart.map(|it| it.name.clone()).unwrap_or_default(),
+            finished_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |it| it.as_secs()),
+            standings,
+            aborted: aborted.iter().copied().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatLine {
+    pub user: i32,
+    pub name: String,
+    pub content: String,
+    /// Unix timestamp in seconds
+    pub sent_at: u64,
 }
 
 pub struct Room {
     pub id: RoomId,
     pub host: RwLock<Weak<User>>,
     pub state: RwLock<InternalRoomState>,
+    /// The server the room was opened on
+    pub(crate) server: Weak<ServerState>,
 
     pub live: AtomicBool,
     pub locked: AtomicBool,
-    pub cycle: AtomicBool,
+    mode: Mutex<RoomMode>,
+    /// Whether spectator chat is shown to players too.
+    pub bridge_spectator_chat: AtomicBool,
+    /// Server-managed room without a host, see [`crate::run_quickplay`].
+    pub quickplay: bool,
+    /// Seconds between two chat messages from the same user, 0 if off.
+    pub slow_mode: AtomicU16,
+    /// See [`Self::set_max_players`]
+    max_players: AtomicU8,
+    recording: Mutex<Recording>,
+    /// Salt and salted hash of the password, if the room has one
+    password: Mutex<Option<([u8; 16], [u8; 32])>>,
+    /// Users the host banned, see [`Self::kick`]
+    banned: Mutex<HashSet<i32>>,
+    /// Users who can't chat until then, see [`Self::mute`]
+    muted: Mutex<HashMap<i32, Instant>>,
+    /// Users who can't join until then, see [`Self::timeout`]
+    timeouts: Mutex<HashMap<i32, Instant>>,
+    /// Series being played, see [`Self::set_duel`]
+    duel: Mutex<Option<DuelSeries>>,
+    /// See [`Self::start_draft`]
+    draft: Mutex<Option<Draft>>,
+    /// Drafts started so far, telling timers of past ones apart
+    drafts: AtomicU32,
+    last_chat: Mutex<HashMap<i32, Instant>>,
+    chat_log: Mutex<VecDeque<ChatLine>>,
+    /// Latest messages every member was sent, see [`Self::send`]
+    history: Mutex<VecDeque<Message>>,
+    last_activity: Mutex<Instant>,
+    created: Instant,
+    /// Announced time of closing, and whether activity can no longer put it
+    /// off.
+    expiry: Mutex<Option<(DateTime<Utc>, bool)>>,
+    events: broadcast::Sender<Message>,
+    /// Host who lost connection during a game, and when that game ended.
+    /// See [`Self::reclaim_host`].
+    former_host: Mutex<Option<(i32, Option<Instant>)>>,
+    /// Why the current game was ended early, see [`Self::force_end`]
+    end_reason: Mutex<Option<GameEndReason>>,
+    /// Round just started and its players yet to acknowledge it, see
+    /// [`Self::ack_start`]
+    start_acks: Mutex<Option<(u32, HashSet<i32>)>>,
+    /// Judges waiting to be relayed, with their round, by player
+    pending_judges: Mutex<HashMap<i32, (u32, Vec<JudgeEvent>)>>,
+    /// By player, cleared when a round starts
+    relay: Mutex<HashMap<i32, RelayState>>,
+    /// See [`Self::update_score`]
+    scores: Mutex<RoundScores>,
+    /// Of the last round, kept even when rounds aren't recorded
+    last_results: Mutex<Vec<PlayerResult>>,
+    repeat_policy: Mutex<RepeatPolicy>,
+    /// See [`Self::chart_history`]
+    chart_history: Mutex<VecDeque<PlayedChart>>,
+    /// See [`Self::queue_chart`]
+    queue: Mutex<ChartQueue>,
+    ready_check: Mutex<Option<ReadyCheck>>,
+    /// Starts requested so far, telling countdowns of past ones apart
+    starts: AtomicU32,
+    churn: Mutex<Churn>,
+    pub usage: RoomUsage,
 
     users: RwLock<Vec<Weak<User>>>,
     monitors: RwLock<Vec<Weak<User>>>,
     pub chart: RwLock<Option<Chart>>,
+    pub rounds: RwLock<Vec<RoundRecord>>,
+    /// Rounds started so far, also counting those not kept in `rounds`.
+    started_rounds: AtomicU32,
 }
 
 impl Room {
-    pub fn new(id: RoomId, host: Weak<User>) -> Self {
+    pub fn new(id: RoomId, host: Weak<User>, server: Weak<ServerState>) -> Self {
         Self {
             id,
             host: host.clone().into(),
             state: RwLock::default(),
+            server,
 
             live: AtomicBool::new(false),
             locked: AtomicBool::new(false),
-            cycle: AtomicBool::new(false),
+            mode: Mutex::default(),
+            bridge_spectator_chat: AtomicBool::new(false),
+            quickplay: false,
+            slow_mode: AtomicU16::new(0),
+            max_players: AtomicU8::new(ROOM_MAX_PLAYERS),
+            recording: Mutex::default(),
+            password: Mutex::default(),
+            banned: Mutex::default(),
+            muted: Mutex::default(),
+            timeouts: Mutex::default(),
+            duel: Mutex::default(),
+            draft: Mutex::default(),
+            drafts: AtomicU32::new(0),
+            last_chat: Mutex::default(),
+            chat_log: Mutex::default(),
+            history: Mutex::default(),
+            last_activity: Mutex::new(Instant::now()),
+            created: Instant::now(),
+            expiry: Mutex::default(),
+            events: broadcast::channel(ROOM_EVENTS_CAPACITY).0,
+            former_host: Mutex::default(),
+            end_reason: Mutex::default(),
+            start_acks: Mutex::default(),
+            pending_judges: Mutex::default(),
+            relay: Mutex::default(),
+            scores: Mutex::default(),
+            last_results: Mutex::default(),
+            repeat_policy: Mutex::default(),
+            chart_history: Mutex::default(),
+            queue: Mutex::default(),
+            ready_check: Mutex::default(),
+            starts: AtomicU32::new(0),
+            churn: Mutex::default(),
+            usage: RoomUsage::default(),
 
             users: vec![host].into(),
             monitors: Vec::new().into(),
             chart: RwLock::default(),
+            rounds: RwLock::default(),
+            started_rounds: AtomicU32::new(0),
+        }
+    }
+
+    pub fn new_quickplay(id: RoomId, server: Weak<ServerState>) -> Self {
+        Self {
+            quickplay: true,
+            ..Self::new(id, Weak::new(), server)
         }
     }
 
@@ -77,8 +372,864 @@ impl Room {
         self.locked.load(Ordering::SeqCst)
     }
 
+    /// This room as registered on its server, for tasks that outlive the
+    /// call. `None` once it was closed, even if another room took its id.
+    fn this(&self) -> Option<Arc<Room>> {
+        let room = self.server.upgrade()?.rooms.get(&self.id)?;
+        std::ptr::eq(Arc::as_ptr(&room), self).then_some(room)
+    }
+
+    pub fn mode(&self) -> RoomMode {
+        *self.mode.lock().unwrap()
+    }
+
     pub fn is_cycle(&self) -> bool {
-        self.cycle.load(Ordering::SeqCst)
+        self.mode() == RoomMode::Relay
+    }
+
+    /// On behalf of `by`, who must be the host. Doesn't tell the room.
+    pub async fn set_mode(&self, by: &User, mode: RoomMode) -> Result<()> {
+        self.check_host(by).await?;
+        if mode != RoomMode::Versus && self.duel().is_some() {
+            bail!(tl!("mode-duel-in-progress"));
+        }
+        info!(
+            user = by.id,
+            room = self.id.to_string(),
+            "room mode: {mode:?}"
+        );
+        *self.mode.lock().unwrap() = mode;
+        Ok(())
+    }
+
+    pub fn recording(&self) -> Recording {
+        *self.recording.lock().unwrap()
+    }
+
+    /// Changes the recording policy of future rounds. Rounds already kept
+    /// are dropped when recording is turned off.
+    pub async fn set_recording(&self, recording: Recording) -> Result<()> {
+        if matches!(*self.state.read().await, InternalRoomState::Playing { .. }) {
+            bail!(tl!("recording-in-game"));
+        }
+        *self.recording.lock().unwrap() = recording;
+        if recording == Recording::Off {
+            self.rounds.write().await.clear();
+        }
+        self.send(Message::Recording { recording }).await;
+        Ok(())
+    }
+
+    pub fn repeat_policy(&self) -> RepeatPolicy {
+        *self.repeat_policy.lock().unwrap()
+    }
+
+    pub fn set_repeat_policy(&self, policy: RepeatPolicy) {
+        *self.repeat_policy.lock().unwrap() = policy;
+    }
+
+    pub fn ready_check(&self) -> Option<ReadyCheck> {
+        *self.ready_check.lock().unwrap()
+    }
+
+    /// On behalf of `by`, who must be the host. Doesn't tell the room.
+    pub async fn set_ready_check(&self, by: &User, check: Option<ReadyCheck>) -> Result<()> {
+        self.check_host(by).await?;
+        if check.is_some_and(|it| !READY_CHECK_SECS.contains(&it.secs)) {
+            bail!(tl!(
+                "ready-check-invalid-time",
+                "min" => *READY_CHECK_SECS.start(),
+                "max" => *READY_CHECK_SECS.end()
+            ));
+        }
+        info!(
+            user = by.id,
+            room = self.id.to_string(),
+            "ready check: {check:?}"
+        );
+        *self.ready_check.lock().unwrap() = check;
+        Ok(())
+    }
+
+    /// Counts down the ready check of the start the host just requested,
+    /// if the room has one.
+    pub fn start_ready_check(self: &Arc<Self>) {
+        let id = self.starts.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(check) = self.ready_check() {
+            tokio::spawn(run_ready_check(Arc::clone(self), id, check));
+        }
+    }
+
+    /// Whether the room still waits for players to get ready for start `id`.
+    async fn is_waiting_for(&self, id: u32) -> bool {
+        self.starts.load(Ordering::SeqCst) == id
+            && matches!(
+                *self.state.read().await,
+                InternalRoomState::WaitForReady { .. }
+            )
+    }
+
+    /// Charts of the latest rounds, oldest first. Unlike [`Self::rounds`],
+    /// kept whatever the recording policy.
+    pub fn chart_history(&self) -> Vec<PlayedChart> {
+        self.chart_history.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// How many rounds ago `chart` was last played, if within the last
+    /// [`RECENT_CHARTS`].
+    pub fn played_recently(&self, chart: i32) -> Option<u32> {
+        self.chart_history
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .take(RECENT_CHARTS)
+            .position(|it| it.chart == chart)
+            .map(|it| it as u32 + 1)
+    }
+
+    /// Only a hash of `password` is kept.
+    pub fn set_password(&self, password: Option<&str>) {
+        *self.password.lock().unwrap() = password.map(|password| {
+            let salt: [u8; 16] = thread_rng().gen();
+            (salt, hash_password(&salt, password))
+        });
+    }
+
+    pub fn has_password(&self) -> bool {
+        self.password.lock().unwrap().is_some()
+    }
+
+    pub fn max_players(&self) -> usize {
+        self.max_players.load(Ordering::SeqCst) as usize
+    }
+
+    /// Limits the room to `max_players`, which must leave room for everyone
+    /// already in it and be at most `limit`, the server's. Monitors and
+    /// spectators aren't counted.
+    pub async fn set_max_players(&self, max_players: u8, limit: usize) -> Result<()> {
+        if !(1..=limit).contains(&(max_players as usize)) {
+            bail!(tl!("max-players-invalid", "max" => limit));
+        }
+        // Held so nobody joins in between
+        let users = self.users.write().await;
+        if users.iter().filter(|it| it.strong_count() > 0).count() > max_players as usize {
+            bail!(tl!("max-players-too-low"));
+        }
+        self.max_players.store(max_players, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn check_password(&self, password: Option<&str>) -> Result<(), ServerError> {
+        let Some((salt, hash)) = *self.password.lock().unwrap() else {
+            return Ok(());
+        };
+        match password {
+            None => Err(ServerError::PasswordRequired),
+            Some(password) if hash_password(&salt, password).ct_eq(&hash).into() => Ok(()),
+            Some(_) => Err(ServerError::WrongPassword),
+        }
+    }
+
+    pub fn is_banned(&self, user: i32) -> bool {
+        self.banned.lock().unwrap().contains(&user)
+    }
+
+    /// How long `user` stays muted, if they are.
+    pub fn muted_for(&self, user: i32) -> Option<Duration> {
+        let until = *self.muted.lock().unwrap().get(&user)?;
+        until.checked_duration_since(Instant::now())
+    }
+
+    /// How long `user` is kept from joining, if they are.
+    pub fn timed_out_for(&self, user: i32) -> Option<Duration> {
+        let until = *self.timeouts.lock().unwrap().get(&user)?;
+        until.checked_duration_since(Instant::now())
+    }
+
+    pub async fn member(&self, id: i32) -> Option<Arc<User>> {
+        self.users()
+            .await
+            .into_iter()
+            .chain(self.monitors().await)
+            .find(|it| it.id == id)
+    }
+
+    /// Keeps `target` from chatting for `secs` seconds on behalf of `by`,
+    /// who must be the host, or lets them chat again if `secs` is 0. The
+    /// mute is lifted by itself once it runs out.
+    pub async fn mute(self: &Arc<Self>, by: &User, target: i32, secs: u32) -> Result<()> {
+        self.check_host(by).await?;
+        if target == by.id {
+            bail!(tl!("mute-self"));
+        }
+        if self.member(target).await.is_none() {
+            bail!(tl!("kick-not-member", "user" => target));
+        }
+        if secs == 0 {
+            if self.muted.lock().unwrap().remove(&target).is_none() {
+                bail!(tl!("mute-not-muted", "user" => target));
+            }
+            info!(
+                user = by.id,
+                room = self.id.to_string(),
+                target,
+                "unmute player"
+            );
+            self.send(Message::Muted {
+                user: target,
+                until: None,
+            })
+            .await;
+            return Ok(());
+        }
+        let duration = moderation_duration(secs)?;
+        let until = Instant::now() + duration;
+        self.muted.lock().unwrap().insert(target, until);
+        info!(
+            user = by.id,
+            room = self.id.to_string(),
+            target,
+            secs,
+            "mute player"
+        );
+        self.send(Message::Muted {
+            user: target,
+            until: Some(Utc::now() + chrono::Duration::from_std(duration).unwrap()),
+        })
+        .await;
+        let room = Arc::downgrade(self);
+        tokio::spawn(async move {
+            time::sleep(duration).await;
+            let Some(room) = room.upgrade() else {
+                return;
+            };
+            let expired = {
+                let mut muted = room.muted.lock().unwrap();
+                // Not if it was lifted or changed in the meantime
+                muted.get(&target) == Some(&until) && muted.remove(&target).is_some()
+            };
+            if expired {
+                room.send(Message::Muted {
+                    user: target,
+                    until: None,
+                })
+                .await;
+            }
+        });
+        Ok(())
+    }
+
+    /// Removes `target` from the room like [`Self::kick`], and keeps them
+    /// from joining again for `secs` seconds.
+    pub async fn timeout(&self, by: &User, target: i32, secs: u32) -> Result<bool> {
+        self.check_host(by).await?;
+        if target == by.id {
+            bail!(tl!("kick-self"));
+        }
+        let duration = moderation_duration(secs)?;
+        if self.member(target).await.is_none() {
+            bail!(tl!("kick-not-member", "user" => target));
+        }
+        {
+            let now = Instant::now();
+            let mut timeouts = self.timeouts.lock().unwrap();
+            timeouts.retain(|_, it| *it > now);
+            timeouts.insert(target, now + duration);
+        }
+        info!(
+            user = by.id,
+            room = self.id.to_string(),
+            target,
+            secs,
+            "timeout player"
+        );
+        self.send(Message::TimedOut {
+            user: target,
+            until: Utc::now() + chrono::Duration::from_std(duration).unwrap(),
+        })
+        .await;
+        self.kick(by, target, false).await
+    }
+
+    /// Removes `target` from the room on behalf of `by`, who must be the
+    /// host. With `ban`, they can't join again while the room exists.
+    /// Returns whether the room is empty now and should be dropped.
+    pub async fn kick(&self, by: &User, target: i32, ban: bool) -> Result<bool> {
+        self.check_host(by).await?;
+        if target == by.id {
+            bail!(tl!("kick-self"));
+        }
+        let Some(user) = self.member(target).await else {
+            bail!(tl!("kick-not-member", "user" => target));
+        };
+        info!(
+            user = by.id,
+            room = self.id.to_string(),
+            target,
+            ban,
+            "kick player"
+        );
+        if ban {
+            self.banned.lock().unwrap().insert(target);
+        }
+        let empty = self.on_user_leave(&user).await;
+        user.try_send(ServerCommand::Kicked {
+            room: Some(self.id.clone()),
+            banned: ban,
+        })
+        .await;
+        Ok(empty)
+    }
+
+    /// Makes the player `target` host on behalf of `by`, the current host.
+    pub async fn transfer_host(&self, by: &User, target: i32) -> Result<()> {
+        self.check_host(by).await?;
+        if target == by.id {
+            bail!(tl!("transfer-self"));
+        }
+        let Some(user) = self.users().await.into_iter().find(|it| it.id == target) else {
+            bail!(tl!("transfer-not-player", "user" => target));
+        };
+        if user.trust == TrustLevel::New {
+            bail!(tl!("transfer-untrusted"));
+        }
+        info!(
+            user = by.id,
+            room = self.id.to_string(),
+            target,
+            "transfer host"
+        );
+        *self.host.write().await = Arc::downgrade(&user);
+        self.send(Message::HostTransferred {
+            from: by.id,
+            to: target,
+        })
+        .await;
+        by.try_send(ServerCommand::ChangeHost(false)).await;
+        user.try_send(ServerCommand::ChangeHost(true)).await;
+        Ok(())
+    }
+
+    pub fn duel(&self) -> Option<DuelSeries> {
+        self.duel.lock().unwrap().clone()
+    }
+
+    /// Starts a best-of-`best_of` duel between the room's two players on
+    /// behalf of `by`, the host, who picks the first chart. Calls off the
+    /// current duel if `best_of` is `None`.
+    pub async fn set_duel(&self, by: &User, best_of: Option<u8>) -> Result<()> {
+        self.check_host(by).await?;
+        let Some(best_of) = best_of else {
+            if self.duel.lock().unwrap().take().is_none() {
+                bail!(tl!("duel-not-started"));
+            }
+            info!(user = by.id, room = self.id.to_string(), "duel called off");
+            self.send(Message::Duel { series: None }).await;
+            return Ok(());
+        };
+        if self.is_drafting() {
+            bail!(tl!("draft-in-progress"));
+        }
+        if self.mode() != RoomMode::Versus {
+            bail!(tl!("duel-needs-versus"));
+        }
+        if best_of % 2 == 0 || !(1..=DUEL_MAX_BEST_OF).contains(&best_of) {
+            bail!(tl!("duel-invalid-best-of", "max" => DUEL_MAX_BEST_OF));
+        }
+        let users = self.users().await;
+        let [a, b] = users.as_slice() else {
+            bail!(tl!("duel-needs-two-players"));
+        };
+        let opponent = if a.id == by.id { b } else { a };
+        let series = DuelSeries {
+            best_of,
+            wins: vec![(by.id, 0), (opponent.id, 0)],
+            picker: by.id,
+        };
+        info!(
+            user = by.id,
+            room = self.id.to_string(),
+            opponent = opponent.id,
+            best_of,
+            "duel started"
+        );
+        *self.duel.lock().unwrap() = Some(series.clone());
+        self.send(Message::Duel {
+            series: Some(series),
+        })
+        .await;
+        Ok(())
+    }
+
+    /// Scores a finished round of the duel, if there's one, and hands the
+    /// pick to the other player. Ties and rounds both players aborted score
+    /// nobody.
+    async fn on_duel_round(&self, record: &RoundRecord) {
+        let (series, winner) = {
+            let mut guard = self.duel.lock().unwrap();
+            let Some(series) = guard.as_mut() else {
+                return;
+            };
+            let top: Vec<_> = record
+                .standings
+                .iter()
+                .filter(|it| it.rank == 1)
+                .map(|it| it.record.player)
+                .collect();
+            if let [winner] = top.as_slice() {
+                if let Some(entry) = series.wins.iter_mut().find(|it| it.0 == *winner) {
+                    entry.1 += 1;
+                }
+            }
+            if let Some(other) = series.wins.iter().find(|it| it.0 != series.picker) {
+                series.picker = other.0;
+            }
+            let winner = series.winner();
+            let series = series.clone();
+            if winner.is_some() {
+                *guard = None;
+            }
+            (series, winner)
+        };
+        if let Some(winner) = winner {
+            info!(room = self.id.to_string(), winner, "duel won");
+            self.send(Message::DuelWon { winner, series }).await;
+        } else {
+            self.send(Message::Duel {
+                series: Some(series),
+            })
+            .await;
+        }
+    }
+
+    pub fn draft(&self) -> Option<DraftState> {
+        self.draft
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|it| it.state.clone())
+    }
+
+    pub fn is_drafting(&self) -> bool {
+        self.draft.lock().unwrap().is_some()
+    }
+
+    /// Starts a draft for the next chart on behalf of `by`, the host. The
+    /// host takes the first turn, or the duel's picker during a duel.
+    pub async fn start_draft(
+        &self,
+        by: &User,
+        pool: Vec<i32>,
+        bans: u8,
+        turn_secs: u16,
+    ) -> Result<()> {
+        self.check_host(by).await?;
+        if self.is_drafting() {
+            bail!(tl!("draft-in-progress"));
+        }
+        check_pool(&pool, bans, turn_secs)?;
+        let mut order: Vec<i32> = match self.duel() {
+            Some(duel) => {
+                let mut order: Vec<_> = duel.wins.iter().map(|it| it.0).collect();
+                order.sort_by_key(|it| *it != duel.picker);
+                order
+            }
+            None => {
+                let mut order: Vec<_> = self.users().await.iter().map(|it| it.id).collect();
+                order.sort_by_key(|it| *it != by.id);
+                order
+            }
+        };
+        order.dedup();
+        if order.len() < 2 {
+            bail!(tl!("draft-needs-two-players"));
+        }
+        let mut charts = Vec::with_capacity(pool.len());
+        for id in pool {
+            match Chart::fetch(id).await {
+                Ok(chart) => charts.push(chart),
+                Err(err) => {
+                    debug!("failed to fetch chart {id}: {err:?}");
+                    bail!(tl!("draft-chart-unavailable", "chart" => id));
+                }
+            }
+        }
+        let turn_time = Duration::from_secs(turn_secs as u64);
+        let id = self.drafts.fetch_add(1, Ordering::SeqCst) + 1;
+        let draft = Draft::new(id, charts, order, bans, turn_time);
+        let state = draft.state.clone();
+        {
+            // Fetching took a while
+            let mut guard = self.draft.lock().unwrap();
+            if guard.is_some() {
+                bail!(tl!("draft-in-progress"));
+            }
+            *guard = Some(draft);
+        }
+        info!(
+            user = by.id,
+            room = self.id.to_string(),
+            pool = state.pool.len(),
+            bans,
+            "draft started"
+        );
+        self.send(Message::DraftStarted { draft: state }).await;
+        if let Some(room) = self.this() {
+            tokio::spawn(run_draft_timer(room, id));
+        }
+        Ok(())
+    }
+
+    /// Calls off the draft on behalf of `by`, the host.
+    pub async fn cancel_draft(&self, by: &User) -> Result<()> {
+        self.check_host(by).await?;
+        if self.draft.lock().unwrap().take().is_none() {
+            bail!(tl!("draft-not-started"));
+        }
+        info!(user = by.id, room = self.id.to_string(), "draft cancelled");
+        self.send(Message::DraftCancelled).await;
+        Ok(())
+    }
+
+    pub async fn draft_turn(&self, user: &User, action: DraftAction, chart: i32) -> Result<()> {
+        let picked = {
+            let mut guard = self.draft.lock().unwrap();
+            let Some(draft) = guard.as_mut() else {
+                bail!(tl!("draft-not-started"));
+            };
+            draft.take_turn(user.id, action, chart)?
+        };
+        self.after_draft_turn(user.id, action, chart, false, picked)
+            .await;
+        Ok(())
+    }
+
+    async fn after_draft_turn(
+        &self,
+        user: i32,
+        action: DraftAction,
+        chart: i32,
+        auto: bool,
+        picked: Option<Chart>,
+    ) {
+        debug!(
+            room = self.id.to_string(),
+            user, chart, auto, "draft {action:?}"
+        );
+        let deadline = if picked.is_some() {
+            *self.draft.lock().unwrap() = None;
+            None
+        } else {
+            self.draft().map(|it| it.deadline)
+        };
+        self.send(Message::DraftTurn {
+            user,
+            action,
+            chart,
+            auto,
+            deadline,
+        })
+        .await;
+        if let Some(chart) = picked {
+            if let Some(picker) = self.users().await.into_iter().find(|it| it.id == user) {
+                self.select_chart(&picker, chart).await;
+            }
+        }
+    }
+
+    pub fn queue(&self) -> Vec<QueuedChart> {
+        self.queue.lock().unwrap().state()
+    }
+
+    /// Adds chart `id` to the end of the queue on behalf of `user`.
+    pub async fn queue_chart(&self, user: &User, id: i32) -> Result<()> {
+        if self.quickplay {
+            bail!(tl!("queue-quickplay"));
+        }
+        self.queue.lock().unwrap().check_push(user.id, id)?;
+        if let (Some(rounds), RepeatPolicy::Forbid) =
+            (self.played_recently(id), self.repeat_policy())
+        {
+            bail!(tl!("chart-played-recently", "rounds" => rounds));
+        }
+        let chart = Chart::fetch(id).await.map_err(|err| {
+            debug!("failed to fetch chart {id}: {err:?}");
+            ServerError::ChartNotFound
+        })?;
+        // Fetching took a while
+        let queued = self.queue.lock().unwrap().push(user.id, chart)?;
+        debug!(
+            user = user.id,
+            room = self.id.to_string(),
+            chart = id,
+            "chart queued"
+        );
+        self.send(Message::ChartQueued { chart: queued }).await;
+        Ok(())
+    }
+
+    /// Removes chart `id` from the queue on behalf of `by`, the host or who
+    /// queued it.
+    pub async fn unqueue_chart(&self, by: &User, id: i32) -> Result<()> {
+        let queued_by = self.queue.lock().unwrap().queued_by(id);
+        match queued_by {
+            None => bail!(tl!("queue-not-queued", "chart" => id)),
+            Some(user) if user != by.id => self.check_host(by).await?,
+            Some(_) => {}
+        }
+        if self.queue.lock().unwrap().remove(id) {
+            self.send(Message::ChartUnqueued {
+                user: by.id,
+                chart: id,
+            })
+            .await;
+        }
+        Ok(())
+    }
+
+    pub async fn vote_chart(&self, user: &User, chart: Option<i32>) -> Result<()> {
+        self.queue.lock().unwrap().vote(user.id, chart)?;
+        self.send(Message::ChartVoted {
+            user: user.id,
+            chart,
+        })
+        .await;
+        Ok(())
+    }
+
+    /// Selects the next chart of the queue after a round, unless the duel
+    /// or draft picks it instead.
+    async fn advance_queue(&self) {
+        if self.duel().is_some() || self.is_drafting() {
+            return;
+        }
+        let policy = self.repeat_policy();
+        let Some((chart, queued)) =
+            self.queue.lock().unwrap().take_next(|id| {
+                policy == RepeatPolicy::Forbid && self.played_recently(id).is_some()
+            })
+        else {
+            return;
+        };
+        // Attributed to who queued it, if they're still around
+        let users = self.users().await;
+        let host = self.host.read().await.upgrade();
+        let Some(user) = users
+            .iter()
+            .find(|it| it.id == queued.user)
+            .or(host.as_ref())
+            .or(users.first())
+        else {
+            return;
+        };
+        debug!(
+            room = self.id.to_string(),
+            chart = chart.id,
+            votes = queued.votes.len(),
+            "queue advances"
+        );
+        let repeated = self.played_recently(chart.id);
+        self.select_chart(user, chart).await;
+        bot_say(
+            self,
+            |_| true,
+            |lang| {
+                lang.format(
+                    "bot-queue-next",
+                    Some(&fluent::fluent_args![
+                        "chart" => queued.name.as_str(),
+                        "votes" => queued.votes.len()
+                    ]),
+                )
+                .into_owned()
+            },
+        )
+        .await;
+        if let (Some(rounds_ago), RepeatPolicy::Warn) = (repeated, policy) {
+            self.send(Message::ChartRepeated {
+                user: user.id,
+                chart: queued.chart,
+                rounds_ago,
+            })
+            .await;
+        }
+    }
+
+    /// Makes `chart` the room's chart, selected by `user`. Takes it off the
+    /// queue.
+    pub async fn select_chart(&self, user: &User, chart: Chart) {
+        self.queue.lock().unwrap().remove(chart.id);
+        self.send(Message::SelectChart {
+            user: user.id,
+            name: chart.name.clone(),
+            id: chart.id,
+        })
+        .await;
+        let id = chart.id;
+        *self.chart.write().await = Some(chart);
+        user.server.charts.prefetch(id);
+        self.on_state_change().await;
+    }
+
+    /// Takes over the chart and recording policy of `other`, for a room split
+    /// off from it.
+    pub async fn inherit(&self, other: &Room) {
+        *self.chart.write().await = other.chart.read().await.clone();
+        *self.recording.lock().unwrap() = other.recording();
+        self.set_repeat_policy(other.repeat_policy());
+        *self.ready_check.lock().unwrap() = other.ready_check();
+        *self.mode.lock().unwrap() = other.mode();
+        *self.chart_history.lock().unwrap() = other.chart_history.lock().unwrap().clone();
+        self.max_players
+            .store(other.max_players() as u8, Ordering::SeqCst);
+    }
+
+    /// Tells `user` (or every member) what of the room is recorded or
+    /// watched, if the server is configured to and there's anything to tell.
+    pub async fn send_recording_notice(&self, config: &ServerConfig, user: Option<&User>) {
+        if !config.recording_notice {
+            return;
+        }
+        match user {
+            Some(user) => self.send_recording_notice_to(config, user).await,
+            None => {
+                for user in self.users().await.into_iter().chain(self.monitors().await) {
+                    self.send_recording_notice_to(config, &user).await;
+                }
+            }
+        }
+    }
+
+    async fn send_recording_notice_to(&self, config: &ServerConfig, user: &User) {
+        if let Some(content) = self.recording_notice(config, &user.lang) {
+            user.try_send(ServerCommand::Message(Message::Announcement { content }))
+                .await;
+        }
+    }
+
+    fn recording_notice(&self, config: &ServerConfig, lang: &Language) -> Option<String> {
+        let mut lines = Vec::new();
+        match self.recording() {
+            Recording::Off => {}
+            Recording::Members => lines.push(lang.format("notice-recording-members", None)),
+            Recording::Public => lines.push(lang.format("notice-recording-public", None)),
+        }
+        if self.is_live() {
+            lines.push(lang.format("notice-spectated", None));
+        }
+        if lines.is_empty() {
+            return None;
+        }
+        if let Some(text) = &config.recording_notice_text {
+            lines.push(text.into());
+        }
+        Some(lines.join("\n"))
+    }
+
+    /// Postpones closing the room for inactivity, telling members if they
+    /// were warned about it.
+    pub async fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+        let announced = {
+            let mut expiry = self.expiry.lock().unwrap();
+            let announced = expiry.is_some_and(|(_, fixed)| !fixed);
+            if announced {
+                *expiry = None;
+            }
+            announced
+        };
+        if announced {
+            self.broadcast(ServerCommand::RoomExpiry { at: None }).await;
+        }
+    }
+
+    pub fn idle_time(&self) -> Duration {
+        self.last_activity.lock().unwrap().elapsed()
+    }
+
+    pub fn age(&self) -> Duration {
+        self.created.elapsed()
+    }
+
+    /// Warns members that the room closes in `remaining` unless there's
+    /// activity, once. A `fixed` closing can't be put off by activity and
+    /// takes over an announced one that can.
+    pub async fn announce_expiry(&self, remaining: Duration, fixed: bool) {
+        let at = Utc::now() + chrono::Duration::from_std(remaining).unwrap();
+        {
+            let mut expiry = self.expiry.lock().unwrap();
+            if expiry.is_some_and(|(_, announced)| announced || !fixed) {
+                return;
+            }
+            *expiry = Some((at, fixed));
+        }
+        debug!(room = self.id.to_string(), "room expires at {at}");
+        self.broadcast(ServerCommand::RoomExpiry { at: Some(at) })
+            .await;
+        let secs = remaining.as_secs();
+        bot_say(
+            self,
+            |it| it.bot_countdowns,
+            |lang| {
+                let key = if fixed {
+                    "bot-room-closing"
+                } else {
+                    "bot-room-closing-idle"
+                };
+                lang.format(key, Some(&fluent::fluent_args!["secs" => secs]))
+                    .into_owned()
+            },
+        )
+        .await;
+    }
+
+    /// Records a chat message from `user`, or returns how long they have to
+    /// wait if slow mode doesn't allow it yet. The host is exempt.
+    pub async fn chat_cooldown(&self, user: &User) -> Option<Duration> {
+        let interval = Duration::from_secs(self.slow_mode.load(Ordering::SeqCst) as u64);
+        if interval.is_zero() || self.check_host(user).await.is_ok() {
+            return None;
+        }
+        let now = Instant::now();
+        let mut last_chat = self.last_chat.lock().unwrap();
+        if let Some(last) = last_chat.get(&user.id) {
+            let elapsed = now.duration_since(*last);
+            if elapsed < interval {
+                return Some(interval - elapsed);
+            }
+        }
+        last_chat.insert(user.id, now);
+        None
+    }
+
+    /// The latest chat messages, oldest first.
+    pub fn chat_log(&self) -> Vec<ChatLine> {
+        self.chat_log.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Round of the game being played, if any.
+    pub async fn current_round(&self) -> Option<u32> {
+        match &*self.state.read().await {
+            InternalRoomState::Playing { round, .. } => Some(*round),
+            _ => None,
+        }
+    }
+
+    pub async fn listing(&self, config: &ServerConfig) -> RoomListing {
+        RoomListing {
+            id: self.id.clone(),
+            host: match self.quickplay {
+                true => None,
+                false => self.host.read().await.upgrade().map(|it| it.name()),
+            },
+            players: self.users().await.len() as u8,
+            max_players: self.max_players() as u8,
+            state: self.client_room_state().await,
+            locked: self.is_locked(),
+            password: self.has_password(),
+            test: config.sandbox,
+        }
     }
 
     pub async fn client_room_state(&self) -> RoomState {
@@ -95,8 +1246,10 @@ impl Room {
             live: self.is_live(),
             locked: self.is_locked(),
             cycle: self.is_cycle(),
+            recording: self.recording(),
             is_host: self.check_host(user).await.is_ok(),
             is_ready: matches!(&*self.state.read().await, InternalRoomState::WaitForReady { started } if started.contains(&user.id)),
+            is_spectator: user.spectator.load(Ordering::SeqCst),
             users: self
                 .users
                 .read()
@@ -105,7 +1258,96 @@ impl Room {
                 .chain(self.monitors.read().await.iter())
                 .filter_map(|it| it.upgrade().map(|it| (it.id, it.to_info())))
                 .collect(),
+            test: user.server.config.sandbox,
+            duel: self.duel(),
+            draft: self.draft(),
+            max_players: self.max_players() as u8,
+            last_results: self.last_results(),
+            repeat_policy: self.repeat_policy(),
+            mode: self.mode(),
+            queue: self.queue(),
+            ready_check: self.ready_check(),
+        }
+    }
+
+    pub fn last_results(&self) -> Vec<PlayerResult> {
+        self.last_results.lock().unwrap().clone()
+    }
+
+    pub async fn sync_state(&self, user: &User) -> SyncStateResponse {
+        let phase = match &*self.state.read().await {
+            InternalRoomState::SelectChart => RoundPhase::SelectChart,
+            InternalRoomState::WaitForReady { started } => RoundPhase::WaitingForReady {
+                ready: started.iter().copied().collect(),
+            },
+            InternalRoomState::Playing {
+                round,
+                results,
+                aborted,
+            } => RoundPhase::Playing {
+                round: *round,
+                finished: results.keys().copied().collect(),
+                aborted: aborted.iter().copied().collect(),
+            },
+        };
+        SyncStateResponse {
+            room: self.client_state(user).await,
+            host: self.host.read().await.upgrade().map(|it| it.id),
+            phase,
+        }
+    }
+
+    /// Standings of `round` (the latest one if `None`) as JSON.
+    pub async fn export_results(&self, round: Option<u32>) -> Result<String> {
+        if self.recording() == Recording::Off {
+            bail!(tl!("recording-off"));
         }
+        let rounds = self.rounds.read().await;
+        let record = match round {
+            Some(round) => rounds.iter().find(|it| it.round == round),
+            None => rounds.last(),
+        };
+        let Some(record) = record else {
+            bail!(tl!("export-round-not-found"))
+        };
+        Ok(serde_json::to_string(record)?)
+    }
+
+    /// Every finished round of this room as CSV, one row per player.
+    pub async fn history_csv(&self) -> String {
+        fn escape(s: &str) -> String {
+            if s.contains([',', '"', '\n']) {
+                format!("\"{}\"", s.replace('"', "\"\""))
+            } else {
+                s.to_owned()
+            }
+        }
+        let mut csv = "round,chart,chart_name,finished_at,rank,user,name,score,accuracy,full_combo,perfect,good,bad,miss,max_combo\n".to_owned();
+        for round in self.rounds.read().await.iter() {
+            for it in &round.standings {
+                let r = &it.record;
+                let _ = writeln!(
+                    csv,
+                    "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                    round.round,
+                    round.chart,
+                    escape(&round.chart_name),
+                    round.finished_at,
+                    it.rank,
+                    r.player,
+                    escape(&it.name),
+                    r.score,
+                    r.accuracy,
+                    r.full_combo,
+                    r.perfect,
+                    r.good,
+                    r.bad,
+                    r.miss,
+                    r.max_combo
+                );
+            }
+        }
+        csv
     }
 
     pub async fn on_state_change(&self) {
@@ -122,7 +1364,7 @@ impl Room {
         } else {
             let mut guard = self.users.write().await;
             guard.retain(|it| it.strong_count() > 0);
-            if guard.len() >= ROOM_MAX_USERS {
+            if guard.len() >= self.max_players() {
                 false
             } else {
                 guard.push(user);
@@ -131,6 +1373,97 @@ impl Room {
         }
     }
 
+    /// Announces `user`, just added with [`Self::add_user`], to the room.
+    pub async fn welcome(&self, user: &User) {
+        let went_live =
+            user.monitor.load(Ordering::SeqCst) && !self.live.fetch_or(true, Ordering::SeqCst);
+        if went_live {
+            info!(room = self.id.to_string(), "room goes live");
+        }
+        let churn = user.features().await.contains(Features::BATCHED_CHURN);
+        let history: Vec<_> = self
+            .history
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|it| churn || !matches!(it, Message::MembersChurn { .. }))
+            .cloned()
+            .collect();
+        if !history.is_empty() {
+            user.try_send(ServerCommand::MessageHistory(history)).await;
+        }
+        self.broadcast(ServerCommand::OnJoinRoom(user.to_info()))
+            .await;
+        self.announce(
+            user,
+            Message::JoinRoom {
+                user: user.id,
+                name: user.name(),
+            },
+        )
+        .await;
+        // Everyone learns about the first spectator
+        let notified = if went_live { None } else { Some(user) };
+        self.send_recording_notice(&user.server.config, notified)
+            .await;
+        bot_welcome(user).await;
+        user.try_send(ServerCommand::Members(self.members().await))
+            .await;
+        self.exchange_touch_spaces(user).await;
+        self.touch().await;
+    }
+
+    /// Tells `user`, who just joined, the touch spaces of the other members,
+    /// and them theirs.
+    async fn exchange_touch_spaces(&self, user: &User) {
+        for other in self.users().await.into_iter().chain(self.monitors().await) {
+            if other.id == user.id {
+                continue;
+            }
+            let space = *other.touch_space.lock().unwrap();
+            if let Some(space) = space {
+                user.try_send(ServerCommand::TouchSpace {
+                    player: other.id,
+                    space,
+                })
+                .await;
+            }
+        }
+        let space = *user.touch_space.lock().unwrap();
+        if let Some(space) = space {
+            self.broadcast_except(
+                user.id,
+                ServerCommand::TouchSpace {
+                    player: user.id,
+                    space,
+                },
+            )
+            .await;
+        }
+    }
+
+    pub async fn member_status(&self, user: &User) -> MemberStatus {
+        let phase = if user.monitor.load(Ordering::SeqCst) {
+            MemberPhase::Idle
+        } else {
+            self.state.read().await.phase(user.id)
+        };
+        MemberStatus {
+            user: user.id,
+            phase,
+            connected: user.is_connected().await,
+            latency_ms: *user.latency_ms.lock().unwrap(),
+        }
+    }
+
+    pub async fn members(&self) -> Vec<MemberStatus> {
+        let mut members = Vec::new();
+        for user in self.users().await.into_iter().chain(self.monitors().await) {
+            members.push(self.member_status(&user).await);
+        }
+        members
+    }
+
     pub async fn users(&self) -> Vec<Arc<User>> {
         self.users
             .read()
@@ -140,6 +1473,14 @@ impl Room {
             .collect()
     }
 
+    pub async fn spectator_count(&self) -> usize {
+        self.monitors()
+            .await
+            .iter()
+            .filter(|it| it.spectator.load(Ordering::SeqCst))
+            .count()
+    }
+
     pub async fn monitors(&self) -> Vec<Arc<User>> {
         self.monitors
             .read()
@@ -151,28 +1492,375 @@ impl Room {
 
     pub async fn check_host(&self, user: &User) -> Result<()> {
         if self.host.read().await.upgrade().map(|it| it.id) != Some(user.id) {
-            bail!("only host can do this");
+            bail!(ServerError::NotHost);
         }
         Ok(())
     }
 
-    #[inline]
+    /// Whether `user` may select the chart: the host, or during a duel the
+    /// player whose pick it is. Not possible while drafting.
+    pub async fn check_picker(&self, user: &User) -> Result<()> {
+        if self.is_drafting() {
+            bail!(tl!("draft-in-progress"));
+        }
+        // Players of a duel take turns instead
+        match self.duel() {
+            Some(duel) if duel.picker != user.id => bail!(tl!("duel-not-your-pick")),
+            Some(_) => Ok(()),
+            None => self.check_host(user).await,
+        }
+    }
+
+    /// Remembers `user` as the host to give the room back to, should they
+    /// reconnect after losing connection during a game.
+    pub fn hold_host(&self, user: i32) {
+        *self.former_host.lock().unwrap() = Some((user, None));
+    }
+
+    /// Whether `user` is a former host who may take the room back, i.e. the
+    /// game they lost connection in ended less than `grace` ago.
+    pub fn is_former_host(&self, user: i32, grace: Duration) -> bool {
+        match *self.former_host.lock().unwrap() {
+            Some((id, None)) => id == user,
+            Some((id, Some(ended_at))) => id == user && ended_at.elapsed() < grace,
+            None => false,
+        }
+    }
+
+    /// Makes `user`, who just rejoined, host again if they're the former
+    /// host. Returns whether they were.
+    pub async fn reclaim_host(&self, user: &Arc<User>, grace: Duration) -> bool {
+        if !self.is_former_host(user.id, grace) {
+            return false;
+        }
+        *self.former_host.lock().unwrap() = None;
+        info!(
+            user = user.id,
+            room = self.id.to_string(),
+            "host reclaims room"
+        );
+        let previous = std::mem::replace(&mut *self.host.write().await, Arc::downgrade(user));
+        if let Some(previous) = previous.upgrade() {
+            previous.try_send(ServerCommand::ChangeHost(false)).await;
+        }
+        self.send(Message::NewHost { user: user.id }).await;
+        true
+    }
+
+    /// Every message sent in this room from now on, regardless of channel.
+    pub fn subscribe(&self) -> broadcast::Receiver<Message> {
+        self.events.subscribe()
+    }
+
     pub async fn send(&self, msg: Message) {
-        self.broadcast(ServerCommand::Message(msg)).await;
+        let _ = self.events.send(msg.clone());
+        // Spectator chat isn't for everyone, and encrypted chat is never kept
+        if msg.channel() == ChatChannel::Room && !matches!(msg, Message::EncryptedChat { .. }) {
+            self.record(msg.clone());
+        }
+        match msg.channel() {
+            ChatChannel::Spectators if !self.bridge_spectator_chat.load(Ordering::SeqCst) => {
+                self.broadcast_monitors(ServerCommand::Message(msg)).await;
+            }
+            _ => self.broadcast(ServerCommand::Message(msg)).await,
+        }
+    }
+
+    fn record(&self, msg: Message) {
+        let mut history = self.history.lock().unwrap();
+        if history.len() == MESSAGE_HISTORY_SIZE {
+            history.pop_front();
+        }
+        history.push_back(msg);
+    }
+
+    /// Sends `msg`, `user` joining or leaving, like [`Self::send`]. Beyond
+    /// the first few of a burst, e.g. during a raid, members who understand
+    /// it get them summed up in [`Message::MembersChurn`] once the burst is
+    /// over instead. `user` always learns about leaving right away.
+    ///
+    /// The history keeps only the summary of those, so that a raid doesn't
+    /// push out all the chat before it.
+    async fn announce(&self, user: &User, msg: Message) {
+        let now = Instant::now();
+        let (since, batched, first_batched, flushed) = {
+            let mut churn = self.churn.lock().unwrap();
+            let flushed = if churn.since.is_some_and(|it| now - it <= CHURN_WINDOW) {
+                None
+            } else {
+                churn.since = Some(now);
+                churn.count = 0;
+                churn.take()
+            };
+            churn.count += 1;
+            let batched = churn.count > CHURN_ANNOUNCED;
+            if batched {
+                match msg {
+                    Message::JoinRoom { user, .. } => churn.joined.push(user),
+                    _ => churn.left.push(user.id),
+                }
+            }
+            let first_batched = churn.count == CHURN_ANNOUNCED + 1;
+            (churn.since.unwrap(), batched, first_batched, flushed)
+        };
+        if let Some(flushed) = flushed {
+            self.send_churn(flushed).await;
+        }
+        if !batched {
+            self.send(msg).await;
+            return;
+        }
+        let _ = self.events.send(msg.clone());
+        let leaving = matches!(msg, Message::LeaveRoom { .. });
+        for session in self.users().await.into_iter().chain(self.monitors().await) {
+            if (leaving && session.id == user.id)
+                || !session.features().await.contains(Features::BATCHED_CHURN)
+            {
+                session.try_send(ServerCommand::Message(msg.clone())).await;
+            }
+        }
+        if first_batched {
+            if let Some(room) = self.this() {
+                tokio::spawn(async move {
+                    time::sleep_until((since + CHURN_WINDOW).into()).await;
+                    let flushed = {
+                        let mut churn = room.churn.lock().unwrap();
+                        if churn.since == Some(since) {
+                            churn.take()
+                        } else {
+                            None
+                        }
+                    };
+                    if let Some(flushed) = flushed {
+                        room.send_churn(flushed).await;
+                    }
+                });
+            }
+        }
+    }
+
+    async fn send_churn(&self, msg: Message) {
+        let _ = self.events.send(msg.clone());
+        self.record(msg.clone());
+        for session in self.users().await.into_iter().chain(self.monitors().await) {
+            if session.features().await.contains(Features::BATCHED_CHURN) {
+                session.try_send(ServerCommand::Message(msg.clone())).await;
+            }
+        }
     }
 
     pub async fn broadcast(&self, cmd: ServerCommand) {
         debug!("broadcast {cmd:?}");
-        for session in self
-            .users()
-            .await
-            .into_iter()
-            .chain(self.monitors().await.into_iter())
-        {
+        for session in self.users().await.into_iter().chain(self.monitors().await) {
             session.try_send(cmd.clone()).await;
         }
     }
 
+    pub async fn broadcast_except(&self, user: i32, cmd: ServerCommand) {
+        for session in self.users().await.into_iter().chain(self.monitors().await) {
+            if session.id != user {
+                session.try_send(cmd.clone()).await;
+            }
+        }
+    }
+
+    /// Relays touches to monitors watching `player` (or everyone). They're
+    /// never sent back to `player`.
+    ///
+    /// Sequence numbers are shared by all monitors, so those watching someone
+    /// else also see the frames they were never sent as lost. The same goes
+    /// for frames dropped while the room is over its budget.
+    pub async fn broadcast_touches(
+        &self,
+        config: &ServerConfig,
+        player: i32,
+        round: u32,
+        frames: Arc<Vec<TouchFrame>>,
+    ) {
+        let start = Instant::now();
+        let seq = {
+            let mut relay = self.relay.lock().unwrap();
+            let state = relay.entry(player).or_default();
+            if state.round != round {
+                *state = RelayState {
+                    round,
+                    ..RelayState::default()
+                };
+            }
+            state.touch_seq += 1;
+            state.touch_seq
+        };
+        if self.usage.over_budget(config) {
+            debug!(room = self.id.to_string(), "over budget, dropping touches");
+            self.usage.add_throttled();
+            return;
+        }
+        // Only converted if someone needs it
+        let mut legacy = None;
+        let mut packed = [None, None];
+        for session in self.monitors().await {
+            if session.id == player
+                || matches!(*session.watching.read().await, Some(it) if it != player)
+            {
+                continue;
+            }
+            let features = session.features().await;
+            if features.contains(Features::TOUCH_DELTA) {
+                let compress = features.contains(Features::TOUCH_ZSTD);
+                let cmd = packed[compress as usize]
+                    .get_or_insert_with(|| ServerCommand::PackedTouches {
+                        player,
+                        round,
+                        seq,
+                        frames: Arc::new(TouchBatch::new(frames.to_vec(), compress)),
+                    })
+                    .clone();
+                session.try_send(cmd).await;
+            } else if features.contains(Features::TOUCH_PHASES) {
+                session
+                    .try_send(ServerCommand::PhasedTouches {
+                        player,
+                        round,
+                        seq,
+                        frames: Arc::clone(&frames),
+                    })
+                    .await;
+            } else {
+                let cmd = legacy
+                    .get_or_insert_with(|| ServerCommand::Touches {
+                        player,
+                        round,
+                        seq,
+                        frames: Arc::new(frames.iter().map(Into::into).collect()),
+                    })
+                    .clone();
+                session.try_send(cmd).await;
+            }
+        }
+        self.usage.add_handler_time(start.elapsed());
+    }
+
+    /// Queues judges to be relayed by [`Self::broadcast_judges`]. Those
+    /// arriving within `JUDGE_RELAY_INTERVAL` are merged into one batch per
+    /// player, which smooths out dense sections of a chart.
+    pub fn queue_judges(self: &Arc<Self>, player: i32, round: u32, judges: &[JudgeEvent]) {
+        let mut pending = self.pending_judges.lock().unwrap();
+        let schedule = pending.is_empty();
+        let entry = pending.entry(player).or_insert((round, Vec::new()));
+        if entry.0 != round {
+            *entry = (round, Vec::new());
+        }
+        entry.1.extend_from_slice(judges);
+        if schedule {
+            let room = Arc::clone(self);
+            tokio::spawn(async move {
+                time::sleep(JUDGE_RELAY_INTERVAL).await;
+                let pending = std::mem::take(&mut *room.pending_judges.lock().unwrap());
+                for (player, (round, judges)) in pending {
+                    room.broadcast_judges(player, round, Arc::new(judges.into()))
+                        .await;
+                }
+            });
+        }
+    }
+
+    /// Takes a score reported for `round`, returning everyone's to
+    /// broadcast, or `None` if it came too soon after the player's last one.
+    pub fn update_score(&self, round: u32, score: PlayerScore) -> Option<Vec<PlayerScore>> {
+        let now = Instant::now();
+        let mut guard = self.scores.lock().unwrap();
+        if guard.round != round {
+            *guard = RoundScores {
+                round,
+                scores: HashMap::new(),
+            };
+        }
+        if let Some((last, _)) = guard.scores.get(&score.user) {
+            if now.duration_since(*last) < SCORE_UPDATE_MIN_INTERVAL {
+                return None;
+            }
+        }
+        guard.scores.insert(score.user, (now, score));
+        let mut scores: Vec<_> = guard.scores.values().map(|it| it.1.clone()).collect();
+        scores.sort_by_key(|it| Reverse(it.score));
+        Some(scores)
+    }
+
+    /// Relays judges to all monitors, except `player` themselves. The batch
+    /// is kept for [`Self::resend_judges`].
+    pub async fn broadcast_judges(&self, player: i32, round: u32, judges: Arc<JudgeBatch>) {
+        let start = Instant::now();
+        let seq = {
+            let mut relay = self.relay.lock().unwrap();
+            let state = relay.entry(player).or_default();
+            if state.round != round {
+                *state = RelayState {
+                    round,
+                    ..RelayState::default()
+                };
+            }
+            state.judge_seq += 1;
+            if state.judges.len() == JUDGE_HISTORY {
+                state.judges.pop_front();
+            }
+            state
+                .judges
+                .push_back((state.judge_seq, Arc::clone(&judges)));
+            state.judge_seq
+        };
+        let cmd = ServerCommand::Judges {
+            player,
+            round,
+            seq,
+            judges,
+        };
+        for session in self.monitors().await {
+            if session.id != player {
+                session.try_send(cmd.clone()).await;
+            }
+        }
+        self.usage.add_handler_time(start.elapsed());
+    }
+
+    /// Sends judge batches of `player` with the given `seqs` again, only to
+    /// `user`. Batches no longer kept, or of another round, are skipped.
+    pub async fn resend_judges(&self, user: &User, player: i32, round: u32, seqs: &[u32]) {
+        if self.current_round().await != Some(round) {
+            return;
+        }
+        let batches: Vec<_> = {
+            let relay = self.relay.lock().unwrap();
+            let Some(state) = relay.get(&player).filter(|it| it.round == round) else {
+                return;
+            };
+            seqs.iter()
+                .take(RESEND_JUDGES_MAX)
+                .filter_map(|seq| {
+                    state
+                        .judges
+                        .iter()
+                        .find(|it| it.0 == *seq)
+                        .map(|(seq, judges)| (*seq, Arc::clone(judges)))
+                })
+                .collect()
+        };
+        debug!(
+            user = user.id,
+            player,
+            count = batches.len(),
+            "resending judge events"
+        );
+        for (seq, judges) in batches {
+            let cmd = ServerCommand::Judges {
+                player,
+                round,
+                seq,
+                judges,
+            };
+            user.try_send(cmd).await;
+        }
+    }
+
     pub async fn broadcast_monitors(&self, cmd: ServerCommand) {
         for session in self.monitors().await {
             session.try_send(cmd.clone()).await;
@@ -181,22 +1869,47 @@ impl Room {
 
     #[inline]
     pub async fn send_as(&self, user: &User, content: String) {
-        self.send(Message::Chat {
-            user: user.id,
-            content,
-        })
-        .await;
+        {
+            let mut chat_log = self.chat_log.lock().unwrap();
+            if chat_log.len() == CHAT_LOG_SIZE {
+                chat_log.pop_front();
+            }
+            chat_log.push_back(ChatLine {
+                user: user.id,
+                name: user.name(),
+                content: content.clone(),
+                sent_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |it| it.as_secs()),
+            });
+        }
+        let msg = if user.monitor.load(Ordering::SeqCst) {
+            Message::SpectatorChat {
+                user: user.id,
+                content,
+            }
+        } else {
+            Message::Chat {
+                user: user.id,
+                content,
+            }
+        };
+        self.send(msg).await;
     }
 
     /// Return: should the room be dropped
     #[must_use]
     pub async fn on_user_leave(&self, user: &User) -> bool {
-        self.send(Message::LeaveRoom {
-            user: user.id,
-            name: user.name.clone(),
-        })
+        self.announce(
+            user,
+            Message::LeaveRoom {
+                user: user.id,
+                name: user.name(),
+            },
+        )
         .await;
         *user.room.write().await = None;
+        *user.watching.write().await = None;
         (if user.monitor.load(Ordering::SeqCst) {
             &self.monitors
         } else {
@@ -204,7 +1917,38 @@ impl Room {
         })
         .write()
         .await
-        .retain(|it| it.upgrade().map_or(false, |it| it.id != user.id));
+        .retain(|it| it.upgrade().is_some_and(|it| it.id != user.id));
+        // Monitors watching them would otherwise never see touches again
+        for monitor in self.monitors().await {
+            let mut watching = monitor.watching.write().await;
+            if *watching == Some(user.id) {
+                *watching = None;
+            }
+        }
+        self.queue.lock().unwrap().forget(user.id);
+        let in_duel = self
+            .duel
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|it| it.wins.iter().any(|it| it.0 == user.id));
+        if in_duel {
+            *self.duel.lock().unwrap() = None;
+            self.send(Message::Duel { series: None }).await;
+        }
+        let in_draft = {
+            let mut guard = self.draft.lock().unwrap();
+            let in_draft = guard
+                .as_ref()
+                .is_some_and(|it| it.state.order.contains(&user.id));
+            if in_draft {
+                *guard = None;
+            }
+            in_draft
+        };
+        if in_draft {
+            self.send(Message::DraftCancelled).await;
+        }
         if self.check_host(user).await.is_ok() {
             info!("host disconnected!");
             let users = self.users().await;
@@ -212,7 +1956,15 @@ impl Room {
                 info!("room users all disconnected, dropping room");
                 return true;
             } else {
-                let user = users.choose(&mut thread_rng()).unwrap();
+                // New accounts only get to host if nobody else is left
+                let trusted: Vec<_> = users
+                    .iter()
+                    .filter(|it| it.trust != TrustLevel::New)
+                    .collect();
+                let user = match trusted.choose(&mut thread_rng()) {
+                    Some(user) => *user,
+                    None => users.choose(&mut thread_rng()).unwrap(),
+                };
                 debug!("selected {} as host", user.id);
                 *self.host.write().await = Arc::downgrade(user);
                 self.send(Message::NewHost { user: user.id }).await;
@@ -230,64 +1982,553 @@ impl Room {
         }
     }
 
+    async fn start_playing(&self, aborted: HashSet<i32>) {
+        let round = self.started_rounds.fetch_add(1, Ordering::SeqCst) + 1;
+        *self.end_reason.lock().unwrap() = None;
+        self.relay.lock().unwrap().clear();
+        info!(room = self.id.to_string(), round, "game start");
+        let users = self.users().await;
+        let mut pending = HashSet::new();
+        for user in &users {
+            if aborted.contains(&user.id) {
+                continue;
+            }
+            // Clients that can't acknowledge are trusted to have started
+            if !user.is_connected().await || user.features().await.contains(Features::START_ACK) {
+                pending.insert(user.id);
+            }
+        }
+        *self.start_acks.lock().unwrap() = Some((round, pending));
+        self.send(Message::StartPlaying { round }).await;
+        if let Some(server) = self.server.upgrade() {
+            let start_at = unix_millis() + server.config.start_delay.as_millis() as u64;
+            for user in &users {
+                if user.features().await.contains(Features::CLOCK_SYNC) {
+                    user.try_send(ServerCommand::GameStart { round, start_at })
+                        .await;
+                }
+            }
+        }
+        self.reset_game_time().await;
+        *self.state.write().await = InternalRoomState::Playing {
+            round,
+            results: HashMap::new(),
+            aborted,
+        };
+        self.on_state_change().await;
+
+        if let Some(room) = self.this() {
+            tokio::spawn(async move {
+                time::sleep(START_ACK_TIMEOUT).await;
+                room.check_start_acks(round).await;
+            });
+        }
+    }
+
+    pub fn ack_start(&self, user: i32, round: u32) {
+        let mut acks = self.start_acks.lock().unwrap();
+        if let Some((current, pending)) = acks.as_mut() {
+            if *current == round && pending.remove(&user) && pending.is_empty() {
+                *acks = None;
+            }
+        }
+    }
+
+    /// Rolls `round` back to getting ready if any of its players who are
+    /// still here didn't acknowledge its start, so that nobody plays alone
+    /// while the others never started.
+    async fn check_start_acks(&self, round: u32) {
+        let mut missing = {
+            let mut acks = self.start_acks.lock().unwrap();
+            match acks.take() {
+                Some((current, missing)) if current == round => missing,
+                other => {
+                    *acks = other;
+                    return;
+                }
+            }
+        };
+        let users = self.users().await;
+        missing.retain(|id| users.iter().any(|it| it.id == *id));
+        if missing.is_empty() {
+            return;
+        }
+        let mut guard = self.state.write().await;
+        if !matches!(*guard, InternalRoomState::Playing { round: current, .. } if current == round)
+        {
+            return;
+        }
+        let host = self.host.read().await.upgrade().map(|it| it.id);
+        *guard = InternalRoomState::WaitForReady {
+            started: host.into_iter().collect(),
+        };
+        drop(guard);
+        warn!(
+            room = self.id.to_string(),
+            round, "start not acknowledged by {missing:?}, rolling back"
+        );
+        self.relay.lock().unwrap().clear();
+        self.reset_game_time().await;
+        let mut users: Vec<_> = missing.into_iter().collect();
+        users.sort_unstable();
+        self.send(Message::StartFailed { users }).await;
+        self.on_state_change().await;
+        self.broadcast(ServerCommand::Members(self.members().await))
+            .await;
+    }
+
+    /// Starts the game with whoever is ready, the rest sit this round out. If
+    /// nobody is ready, goes back to chart selection.
+    pub async fn force_start(&self) {
+        let guard = self.state.read().await;
+        let InternalRoomState::WaitForReady { started } = &*guard else {
+            return;
+        };
+        let aborted: HashSet<_> = self
+            .users()
+            .await
+            .into_iter()
+            .map(|it| it.id)
+            .filter(|it| !started.contains(it))
+            .collect();
+        let nobody_ready = started.is_empty();
+        drop(guard);
+        if nobody_ready {
+            *self.state.write().await = InternalRoomState::SelectChart;
+            self.on_state_change().await;
+        } else {
+            self.start_playing(aborted).await;
+            self.check_all_ready().await;
+        }
+    }
+
+    /// Goes back to chart selection from a start that can't complete, e.g.
+    /// because a player crashed before getting ready. Returns whether there
+    /// was a start to cancel.
+    pub async fn force_cancel_start(&self) -> bool {
+        let mut guard = self.state.write().await;
+        if !matches!(*guard, InternalRoomState::WaitForReady { .. }) {
+            return false;
+        }
+        info!(room = self.id.to_string(), "start cancelled");
+        *guard = InternalRoomState::SelectChart;
+        drop(guard);
+        self.send(Message::StartCancelled).await;
+        self.on_state_change().await;
+        true
+    }
+
+    /// Players who haven't uploaded a result or aborted yet, or `None` if
+    /// there's no game.
+    pub async fn still_playing(&self) -> Option<Vec<i32>> {
+        let guard = self.state.read().await;
+        let InternalRoomState::Playing {
+            results, aborted, ..
+        } = &*guard
+        else {
+            return None;
+        };
+        Some(
+            self.users()
+                .await
+                .into_iter()
+                .map(|it| it.id)
+                .filter(|it| !results.contains_key(it) && !aborted.contains(it))
+                .collect(),
+        )
+    }
+
+    /// Ends the current game, treating everyone who hasn't uploaded a result
+    /// as aborted. `reason` is told to members if anyone was still playing.
+    pub async fn force_end(&self, reason: GameEndReason) {
+        let mut guard = self.state.write().await;
+        match &mut *guard {
+            InternalRoomState::WaitForReady { .. } => {
+                *guard = InternalRoomState::SelectChart;
+                drop(guard);
+                self.on_state_change().await;
+            }
+            InternalRoomState::Playing {
+                results, aborted, ..
+            } => {
+                for user in self.users().await {
+                    if !results.contains_key(&user.id) && aborted.insert(user.id) {
+                        *self.end_reason.lock().unwrap() = Some(reason);
+                    }
+                }
+                drop(guard);
+                self.check_all_ready().await;
+            }
+            InternalRoomState::SelectChart => {}
+        }
+    }
+
+    async fn on_round_end(&self, record: &RoundRecord) {
+        let recording = self.recording();
+        if recording == Recording::Off {
+            return;
+        }
+        let Some(server) = self.server.upgrade() else {
+            warn!(
+                room = self.id.to_string(),
+                "server gone, round not recorded"
+            );
+            return;
+        };
+        let players: Vec<_> = self.users().await.iter().map(|it| it.id).collect();
+        let ranked = self.mode() != RoomMode::Coop;
+        for (user, achievement) in server
+            .stats
+            .record_round(&self.id, record, &players, ranked)
+            .await
+        {
+            info!(user, "achievement unlocked: {achievement:?}");
+            self.broadcast(ServerCommand::Achievement { user, achievement })
+                .await;
+        }
+        if recording == Recording::Public && server.submitter.is_some() {
+            let room = self.id.to_string();
+            let record = record.clone();
+            tokio::spawn(async move {
+                server
+                    .submitter
+                    .as_ref()
+                    .unwrap()
+                    .submit(&room, &record)
+                    .await;
+            });
+        }
+    }
+
     pub async fn check_all_ready(&self) {
         let guard = self.state.read().await;
         match guard.deref() {
-            InternalRoomState::WaitForReady { started } => {
-                if self
-                    .users()
-                    .await
-                    .into_iter()
-                    .chain(self.monitors().await.into_iter())
-                    .all(|it| started.contains(&it.id))
-                {
-                    drop(guard);
-                    info!(room = self.id.to_string(), "game start");
-                    self.send(Message::StartPlaying).await;
-                    self.reset_game_time().await;
-                    *self.state.write().await = InternalRoomState::Playing {
-                        results: HashMap::new(),
-                        aborted: HashSet::new(),
-                    };
-                    self.on_state_change().await;
-                }
+            InternalRoomState::WaitForReady { started }
+                if !started.is_empty()
+                    && self
+                        .users()
+                        .await
+                        .into_iter()
+                        .chain(self.monitors().await)
+                        .filter(|it| !it.spectator.load(Ordering::SeqCst))
+                        .all(|it| started.contains(&it.id)) =>
+            {
+                drop(guard);
+                self.start_playing(HashSet::new()).await;
             }
-            InternalRoomState::Playing { results, aborted } => {
-                if self
+            InternalRoomState::Playing {
+                round,
+                results,
+                aborted,
+            } if self
+                .users()
+                .await
+                .into_iter()
+                .all(|it| results.contains_key(&it.id) || aborted.contains(&it.id)) =>
+            {
+                let names = self
                     .users()
                     .await
                     .into_iter()
-                    .all(|it| results.contains_key(&it.id) || aborted.contains(&it.id))
-                {
-                    drop(guard);
-                    // TODO print results
-                    self.send(Message::GameEnd).await;
-                    // dbg!(2);
-                    *self.state.write().await = InternalRoomState::SelectChart;
-                    // dbg!(3);
-                    if self.is_cycle() {
-                        debug!(room = self.id.to_string(), "cycling");
-                        let host = Weak::clone(&*self.host.read().await);
-                        let new_host = {
-                            let users = self.users().await;
-                            let index = users
-                                .iter()
-                                .position(|it| host.ptr_eq(&Arc::downgrade(it)))
-                                .map(|it| (it + 1) % users.len())
-                                .unwrap_or_default();
-                            users.into_iter().nth(index).unwrap()
-                        };
-                        *self.host.write().await = Arc::downgrade(&new_host);
-                        self.send(Message::NewHost { user: new_host.id }).await;
-                        if let Some(old) = host.upgrade() {
-                            old.try_send(ServerCommand::ChangeHost(false)).await;
-                        }
-                        new_host.try_send(ServerCommand::ChangeHost(true)).await;
+                    .map(|it| (it.id, it.name()))
+                    .collect();
+                let record = {
+                    let mut rounds = self.rounds.write().await;
+                    let record = RoundRecord::new(
+                        *round,
+                        self.chart.read().await.as_ref(),
+                        results,
+                        aborted,
+                        &names,
+                        self.mode() != RoomMode::Coop,
+                    );
+                    if self.recording() != Recording::Off {
+                        rounds.push(record.clone());
+                    }
+                    record
+                };
+                drop(guard);
+                if record.chart != -1 {
+                    let mut history = self.chart_history.lock().unwrap();
+                    if history.len() == CHART_HISTORY_SIZE {
+                        history.pop_front();
+                    }
+                    history.push_back(PlayedChart {
+                        round: record.round,
+                        chart: record.chart,
+                        name: record.chart_name.clone(),
+                        finished_at: Utc::now(),
+                    });
+                }
+                self.on_round_end(&record).await;
+                let reason = self.end_reason.lock().unwrap().take().unwrap_or(
+                    if record.standings.is_empty() {
+                        GameEndReason::Aborted
+                    } else {
+                        GameEndReason::AllPlayed
+                    },
+                );
+                let results: Vec<_> = record.standings.iter().map(Standing::to_result).collect();
+                *self.last_results.lock().unwrap() = results.clone();
+                self.send(Message::GameEnd {
+                    round: record.round,
+                    reason,
+                    results,
+                })
+                .await;
+                if !record.standings.is_empty() {
+                    bot_say(
+                        self,
+                        |it| it.bot_round_summaries,
+                        |lang| round_summary(lang, &record),
+                    )
+                    .await;
+                }
+                self.on_duel_round(&record).await;
+                // dbg!(2);
+                *self.state.write().await = InternalRoomState::SelectChart;
+                if let Some((_, ended_at)) = self.former_host.lock().unwrap().as_mut() {
+                    ended_at.get_or_insert_with(Instant::now);
+                }
+                // dbg!(3);
+                if self.is_cycle() {
+                    debug!(room = self.id.to_string(), "cycling");
+                    let host = Weak::clone(&*self.host.read().await);
+                    let new_host = {
+                        let users = self.users().await;
+                        let index = users
+                            .iter()
+                            .position(|it| host.ptr_eq(&Arc::downgrade(it)))
+                            .map(|it| (it + 1) % users.len())
+                            .unwrap_or_default();
+                        users.into_iter().nth(index).unwrap()
+                    };
+                    *self.host.write().await = Arc::downgrade(&new_host);
+                    self.send(Message::NewHost { user: new_host.id }).await;
+                    if let Some(old) = host.upgrade() {
+                        old.try_send(ServerCommand::ChangeHost(false)).await;
                     }
-                    self.on_state_change().await;
+                    new_host.try_send(ServerCommand::ChangeHost(true)).await;
                 }
+                self.advance_queue().await;
+                self.on_state_change().await;
             }
             _ => {}
         }
     }
 }
+
+/// Takes turns of draft `id` at random once they run out of time, until the
+/// draft is over.
+async fn run_draft_timer(room: Arc<Room>, id: u32) {
+    loop {
+        let Some((turn, deadline)) = room
+            .draft
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|it| it.id == id)
+            .map(|it| (it.state.banned.len(), it.state.deadline))
+        else {
+            return;
+        };
+        time::sleep((deadline - Utc::now()).to_std().unwrap_or_default()).await;
+        let taken = {
+            let mut guard = room.draft.lock().unwrap();
+            match guard.as_mut() {
+                Some(draft) if draft.id == id && draft.state.banned.len() == turn => {
+                    Some(draft.take_turn_auto())
+                }
+                // Taken in time, or the draft is over
+                _ => None,
+            }
+        };
+        if let Some((user, action, chart, picked)) = taken {
+            room.after_draft_turn(user, action, chart, true, picked)
+                .await;
+        }
+    }
+}
+
+/// Ticks every second until players ran out of time to get ready for start
+/// `id`, then starts without those who aren't or cancels the start.
+async fn run_ready_check(room: Arc<Room>, id: u32, check: ReadyCheck) {
+    for secs in (1..=check.secs).rev() {
+        if !room.is_waiting_for(id).await {
+            return;
+        }
+        room.broadcast(ServerCommand::StartCountdown { secs }).await;
+        time::sleep(Duration::from_secs(1)).await;
+    }
+    if !room.is_waiting_for(id).await {
+        return;
+    }
+    info!(
+        room = room.id.to_string(),
+        "ready check ran out: {:?}", check.on_timeout
+    );
+    match check.on_timeout {
+        ReadyTimeoutAction::Start => room.force_start().await,
+        ReadyTimeoutAction::Cancel => {
+            room.force_cancel_start().await;
+        }
+    }
+}
+
+fn moderation_duration(secs: u32) -> Result<Duration> {
+    if !(1..=MODERATION_MAX_SECS).contains(&secs) {
+        bail!(tl!("moderation-invalid-duration", "max" => MODERATION_MAX_SECS / 3600));
+    }
+    Ok(Duration::from_secs(secs as u64))
+}
+
+fn hash_password(salt: &[u8; 16], password: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(password.as_bytes());
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Server, Session};
+    use phira_mp_common::{
+        ClientCommand, CompactPos, Judgement, Stream, TouchPhase, TouchPoint, PROTOCOL_VERSION,
+    };
+    use tokio::{net::TcpListener, sync::mpsc};
+    use uuid::Uuid;
+
+    /// Connects `user` through an in-memory pipe by resuming, returning
+    /// everything the server sends to them.
+    async fn connect(
+        user: &Arc<User>,
+    ) -> (
+        Arc<Session>,
+        Stream<ClientCommand, ServerCommand>,
+        mpsc::UnboundedReceiver<ServerCommand>,
+    ) {
+        let (client, server) = tokio::io::duplex(1 << 16);
+        let token = user.issue_resume_token();
+        let session = tokio::spawn(Session::new(
+            Uuid::new_v4(),
+            server,
+            Arc::clone(&user.server),
+        ));
+        let (tx, rx) = mpsc::unbounded_channel();
+        let stream = Stream::<ClientCommand, ServerCommand>::new(
+            Some(1),
+            client,
+            Box::new(move |_send_tx, cmd| {
+                let _ = tx.send(cmd);
+                async {}
+            }),
+        )
+        .await
+        .unwrap();
+        stream
+            .send(ClientCommand::Hello {
+                version: PROTOCOL_VERSION,
+                features: Features::ALL,
+            })
+            .await
+            .unwrap();
+        stream
+            .send(ClientCommand::Resume { session: token })
+            .await
+            .unwrap();
+        (session.await.unwrap().unwrap(), stream, rx)
+    }
+
+    /// The player whose relayed frames `cmd` carries, if it is one.
+    fn relayed_from(cmd: &ServerCommand) -> Option<i32> {
+        match cmd {
+            ServerCommand::Touches { player, .. }
+            | ServerCommand::PhasedTouches { player, .. }
+            | ServerCommand::PackedTouches { player, .. }
+            | ServerCommand::Judges { player, .. } => Some(*player),
+            _ => None,
+        }
+    }
+
+    async fn next_relayed(rx: &mut mpsc::UnboundedReceiver<ServerCommand>) -> i32 {
+        time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(player) = relayed_from(&rx.recv().await.unwrap()) {
+                    break player;
+                }
+            }
+        })
+        .await
+        .expect("nothing relayed in time")
+    }
+
+    #[tokio::test]
+    async fn frames_are_not_relayed_to_their_sender() {
+        let server = Server::new(
+            ServerConfig::default(),
+            TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        )
+        .unwrap();
+        let state = Arc::clone(&server.state);
+        let users: Vec<_> = [1, 2]
+            .into_iter()
+            .map(|id| {
+                let user = Arc::new(User::new(
+                    id,
+                    format!("player {id}"),
+                    Language::default(),
+                    TrustLevel::Regular,
+                    Arc::clone(&state),
+                ));
+                state.users.insert(id, Arc::clone(&user));
+                user
+            })
+            .collect();
+        let room = Room::new(
+            "echo".to_owned().try_into().unwrap(),
+            Arc::downgrade(&users[0]),
+            Arc::downgrade(&state),
+        );
+        for user in &users {
+            assert!(room.add_user(Arc::downgrade(user), true).await);
+        }
+        let mut sender = connect(&users[0]).await;
+        let mut other = connect(&users[1]).await;
+
+        let frames = Arc::new(vec![TouchFrame {
+            time: 1.,
+            points: vec![TouchPoint {
+                id: 0,
+                phase: TouchPhase::Down,
+                pos: CompactPos::new(0.5, 0.5),
+            }],
+        }]);
+        room.broadcast_touches(&state.config, 1, 0, frames).await;
+        let judges = vec![JudgeEvent {
+            time: 1.,
+            line_id: 0,
+            note_id: 0,
+            judgement: Judgement::Perfect,
+        }];
+        room.broadcast_judges(1, 0, Arc::new(judges.into())).await;
+
+        assert_eq!(next_relayed(&mut other.2).await, 1);
+        assert_eq!(next_relayed(&mut other.2).await, 1);
+        // The pong is queued after anything relayed to the sender, so
+        // everything before it has arrived once it does
+        sender.1.send(ClientCommand::Ping).await.unwrap();
+        let before_pong = time::timeout(Duration::from_secs(5), async {
+            let mut cmds = Vec::new();
+            loop {
+                match sender.2.recv().await.unwrap() {
+                    ServerCommand::Pong => break cmds,
+                    cmd => cmds.push(cmd),
+                }
+            }
+        })
+        .await
+        .expect("no pong in time");
+        assert!(before_pong.iter().all(|it| relayed_from(it).is_none()));
+    }
+}
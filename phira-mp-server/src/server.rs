@@ -1,19 +1,130 @@
-use crate::{vacant_entry, IdMap, Room, SafeMap, Session, User};
-use anyhow::Result;
-use phira_mp_common::RoomId;
-use serde::Deserialize;
-use std::sync::Arc;
-use tokio::{net::TcpListener, sync::mpsc, task::JoinHandle};
-use tracing::{info, warn};
+use crate::{
+    default_chain, run_quickplay, run_room_expiry, Bans, ChartCache, LanAdvertisement, Middleware,
+    Registry, Reports, ResultSubmitter, Room, SafeMap, ServerConfig, Session, Stats, Store, User,
+    HOST,
+};
+use anyhow::{Context, Result};
+#[cfg(feature = "websocket")]
+use phira_mp_common::{accept_ws, WsTransport};
+use phira_mp_common::{ChartFilter, RoomId, Transport, ROUTE_PREFIX};
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock as StdRwLock,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinHandle,
+    time,
+};
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-#[derive(Debug, Deserialize)]
+/// Charts per catalog page fetched by [`Chart::random`].
+const CATALOG_PAGE_SIZE: u32 = 30;
+/// Catalog pages [`Chart::random`] looks through before giving up.
+const RANDOM_CHART_PAGES: usize = 3;
+/// Charts [`Chart::random`] may download to tell their length.
+const RANDOM_CHART_LENGTH_CHECKS: usize = 2;
+/// How long a new connection may take to finish the TLS and WebSocket
+/// handshakes, so that stalled ones don't pile up.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Chart {
     pub id: i32,
     pub name: String,
+    #[serde(default)]
+    pub difficulty: f32,
+    #[serde(default)]
+    pub ranked: bool,
+}
+
+#[derive(Deserialize)]
+struct Page<T> {
+    count: u32,
+    results: Vec<T>,
 }
 
-#[derive(Debug, Deserialize)]
+impl Chart {
+    pub async fn fetch(id: i32) -> Result<Self> {
+        Ok(reqwest::get(format!("{HOST}/chart/{id}"))
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    async fn fetch_page(page: u32, size: u32) -> Result<Page<Self>> {
+        Ok(
+            reqwest::get(format!("{HOST}/chart?page={page}&pageNum={size}"))
+                .await?
+                .error_for_status()?
+                .json()
+                .await?,
+        )
+    }
+
+    fn matches(&self, filter: &ChartFilter) -> bool {
+        !matches!(filter.min_difficulty, Some(min) if self.difficulty < min)
+            && !matches!(filter.max_difficulty, Some(max) if self.difficulty > max)
+            && (!filter.ranked_only || self.ranked)
+    }
+
+    /// A random chart of the Phira catalog matching `filter` and not
+    /// `excluded`, looking through a few random pages of it. Lengths are
+    /// told by loading the chart into `cache`. `None` if nothing was found.
+    pub async fn random(
+        filter: &ChartFilter,
+        cache: &ChartCache,
+        excluded: impl Fn(i32) -> bool,
+    ) -> Result<Option<Self>> {
+        let count = Self::fetch_page(1, 1).await?.count;
+        if count == 0 {
+            return Ok(None);
+        }
+        let mut pages: Vec<u32> = (0..RANDOM_CHART_PAGES)
+            .map(|_| thread_rng().gen_range(0..count) / CATALOG_PAGE_SIZE + 1)
+            .collect();
+        pages.sort_unstable();
+        pages.dedup();
+        let mut length_checks = RANDOM_CHART_LENGTH_CHECKS;
+        for page in pages {
+            let mut charts = Self::fetch_page(page, CATALOG_PAGE_SIZE).await?.results;
+            charts.shuffle(&mut thread_rng());
+            for chart in charts {
+                if !chart.matches(filter) || excluded(chart.id) {
+                    continue;
+                }
+                if let Some(max) = filter.max_length {
+                    if length_checks == 0 {
+                        return Ok(None);
+                    }
+                    length_checks -= 1;
+                    match cache.get(chart.id).await {
+                        Ok(data) if data.length() <= max as f32 => {}
+                        Ok(_) => continue,
+                        Err(err) => {
+                            debug!(chart = chart.id, "failed to tell length: {err:?}");
+                            continue;
+                        }
+                    }
+                }
+                return Ok(Some(chart));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Record {
     pub id: i32,
     pub player: i32,
@@ -29,30 +140,128 @@ pub struct Record {
     pub std_score: f32,
 }
 
+pub const QUICKPLAY_ROOM: &str = "quickplay";
+
 pub struct ServerState {
-    pub sessions: IdMap<Arc<Session>>,
-    pub users: SafeMap<i32, Arc<User>>,
+    pub config: ServerConfig,
+    pub middlewares: StdRwLock<Vec<Arc<dyn Middleware>>>,
 
-    pub rooms: SafeMap<RoomId, Arc<Room>>,
+    pub sessions: Registry<Uuid, Arc<Session>>,
+    pub users: Registry<i32, Arc<User>>,
+    /// User of each resume token, see [`User::issue_resume_token`]
+    pub resume_tokens: Registry<Uuid, i32>,
+
+    pub rooms: Registry<RoomId, Arc<Room>>,
+    pub stats: Stats,
+    /// Display names set by operators, applied on authentication.
+    pub name_overrides: SafeMap<i32, String>,
+    pub store: Store,
+    pub reports: Reports,
+    pub bans: Bans,
+    pub submitter: Option<ResultSubmitter>,
+    pub charts: Arc<ChartCache>,
+    /// Set through the control socket to stop new rooms from being created.
+    pub draining: AtomicBool,
+    /// Whether the game listener accepted its last connection, see
+    /// [`Server::accept`].
+    pub listening: AtomicBool,
+    /// Wraps every accepted connection if TLS is configured
+    #[cfg(feature = "tls")]
+    pub tls: Option<tokio_rustls::TlsAcceptor>,
 
     pub lost_con_tx: mpsc::Sender<Uuid>,
 }
 
 pub struct Server {
-    state: Arc<ServerState>,
+    pub(crate) state: Arc<ServerState>,
     listener: TcpListener,
 
     lost_con_handle: JoinHandle<()>,
+    admin_handle: Option<JoinHandle<()>>,
+    health_handle: Option<JoinHandle<()>>,
+    health_tcp_handle: Option<JoinHandle<()>>,
+    quickplay_handle: Option<JoinHandle<()>>,
+    expiry_handle: Option<JoinHandle<()>>,
+    control_handle: Option<JoinHandle<()>>,
+    ws_handle: Option<JoinHandle<()>>,
+    _lan: Option<LanAdvertisement>,
 }
 
 impl From<TcpListener> for Server {
     fn from(listener: TcpListener) -> Self {
+        Self::new(ServerConfig::default(), listener)
+            .expect("the default config needs neither TLS nor WebSocket")
+    }
+}
+
+impl Server {
+    /// Fails if the config isn't [valid](ServerConfig::validate), TLS is
+    /// configured but the certificate can't be loaded, the WebSocket address
+    /// can't be listened on, or TLS or WebSocket is configured without its
+    /// feature enabled.
+    pub fn new(config: ServerConfig, listener: TcpListener) -> Result<Self> {
+        config.validate()?;
+        #[cfg(feature = "tls")]
+        let tls = match (&config.tls_cert, &config.tls_key) {
+            (Some(cert), Some(key)) => {
+                Some(crate::load_tls(cert, key).context("failed to load TLS certificate")?)
+            }
+            (None, None) => None,
+            _ => anyhow::bail!("TLS needs both a certificate and a key"),
+        };
+        #[cfg(not(feature = "tls"))]
+        anyhow::ensure!(
+            config.tls_cert.is_none() && config.tls_key.is_none(),
+            "TLS support isn't compiled in, enable the `tls` feature"
+        );
+        #[cfg(not(feature = "websocket"))]
+        anyhow::ensure!(
+            config.ws_addr.is_none(),
+            "WebSocket support isn't compiled in, enable the `websocket` feature"
+        );
+        // Bound right away so that a taken address fails startup
+        let ws_listener = config
+            .ws_addr
+            .map(|addr| {
+                let listener = std::net::TcpListener::bind(addr)?;
+                listener.set_nonblocking(true)?;
+                TcpListener::from_std(listener)
+            })
+            .transpose()
+            .context("failed to listen for WebSocket connections")?;
         let (lost_con_tx, mut lost_con_rx) = mpsc::channel(16);
+        let store = Store::new(config.data_dir.clone());
+        // Anyone can sign in to a sandbox, so nothing played there counts
+        let submitter = config
+            .submit_url
+            .clone()
+            .zip(config.submit_token.clone())
+            .filter(|_| !config.sandbox)
+            .map(|(url, token)| ResultSubmitter::new(url, token));
+        let charts = Arc::new(ChartCache::new(
+            config.chart_cache_size,
+            config.chart_cache_dir.clone(),
+        ));
         let state = Arc::new(ServerState {
-            sessions: IdMap::default(),
-            users: SafeMap::default(),
+            middlewares: default_chain(&config).into(),
+            config,
+
+            sessions: Registry::default(),
+            users: Registry::default(),
+            resume_tokens: Registry::default(),
 
-            rooms: SafeMap::default(),
+            rooms: Registry::default(),
+            stats: Stats::default(),
+            name_overrides: SafeMap::default(),
+            reports: Reports::new(store.clone()),
+            bans: Bans::new(store.clone()),
+            submitter,
+            charts,
+            store,
+            draining: AtomicBool::new(false),
+            listening: AtomicBool::new(true),
+            #[cfg(feature = "tls")]
+            tls,
 
             lost_con_tx,
         });
@@ -61,49 +270,277 @@ impl From<TcpListener> for Server {
             async move {
                 while let Some(id) = lost_con_rx.recv().await {
                     warn!("lost connection with {id}");
-                    if let Some(session) = state.sessions.write().await.remove(&id) {
+                    if let Some(session) = state.sessions.remove(&id) {
+                        if session.said_bye() {
+                            session.stream.close();
+                        }
                         if session
                             .user
                             .session
                             .read()
                             .await
                             .as_ref()
-                            .map_or(false, |it| it.ptr_eq(&Arc::downgrade(&session)))
+                            .is_some_and(|it| it.ptr_eq(&Arc::downgrade(&session)))
                         {
-                            Arc::clone(&session.user).dangle().await;
+                            if session.said_bye() {
+                                Arc::clone(&session.user).leave().await;
+                            } else {
+                                Arc::clone(&session.user).dangle().await;
+                            }
                         }
                     }
                 }
             }
         });
 
-        Self {
+        let admin_handle = state.config.admin_addr.map(|addr| {
+            let state = Arc::clone(&state);
+            tokio::spawn(async move {
+                if let Err(err) = crate::admin::serve(state, addr).await {
+                    error!("admin API failed: {err:?}");
+                }
+            })
+        });
+
+        let health_handle = state.config.health_addr.map(|addr| {
+            let state = Arc::clone(&state);
+            tokio::spawn(async move {
+                if let Err(err) = crate::health::serve(state, addr).await {
+                    error!("health check failed: {err:?}");
+                }
+            })
+        });
+        let health_tcp_handle = state.config.health_tcp_addr.map(|addr| {
+            let state = Arc::clone(&state);
+            tokio::spawn(async move {
+                if let Err(err) = crate::health::serve_tcp(state, addr).await {
+                    error!("TCP health probe failed: {err:?}");
+                }
+            })
+        });
+
+        let quickplay_handle = (!state.config.quickplay_charts.is_empty()).then(|| {
+            let id: RoomId = QUICKPLAY_ROOM.to_owned().try_into().unwrap();
+            let room = Arc::new(Room::new_quickplay(id.clone(), Arc::downgrade(&state)));
+            // Nobody can have created a room yet
+            state.rooms.insert(id, Arc::clone(&room));
+            info!("quickplay room opened");
+            let config = &state.config;
+            tokio::spawn(run_quickplay(
+                room,
+                config.quickplay_charts.clone(),
+                config.quickplay_interval,
+                config.quickplay_ready_time,
+            ))
+        });
+
+        let lifetime = state
+            .config
+            .sandbox
+            .then_some(state.config.sandbox_room_lifetime);
+        let expiry_handle =
+            (state.config.room_idle_timeout.is_some() || lifetime.is_some()).then(|| {
+                tokio::spawn(run_room_expiry(
+                    Arc::clone(&state),
+                    state.config.room_idle_timeout,
+                    lifetime,
+                ))
+            });
+
+        #[cfg(unix)]
+        let control_handle = state.config.control_socket.clone().map(|path| {
+            let state = Arc::clone(&state);
+            tokio::spawn(async move {
+                if let Err(err) = crate::serve_control(state, &path).await {
+                    error!("control socket failed: {err:?}");
+                }
+            })
+        });
+        #[cfg(not(unix))]
+        let control_handle = state.config.control_socket.as_ref().and_then(|_| {
+            error!("the control socket is only supported on Unix");
+            None
+        });
+
+        let ws_handle = ws_listener.map(|listener| {
+            let state = Arc::clone(&state);
+            tokio::spawn(async move {
+                if let Err(err) = serve_ws(state, listener).await {
+                    error!("WebSocket listener failed: {err:?}");
+                }
+            })
+        });
+
+        let lan = state.config.lan_name.as_deref().and_then(|name| {
+            let port = listener.local_addr().ok()?.port();
+            LanAdvertisement::new(&state.config, name, port)
+                .map_err(|err| error!("failed to advertise on the local network: {err:?}"))
+                .ok()
+        });
+
+        Ok(Self {
             listener,
             state,
 
             lost_con_handle,
+            admin_handle,
+            health_handle,
+            health_tcp_handle,
+            quickplay_handle,
+            expiry_handle,
+            control_handle,
+            ws_handle,
+            _lan: lan,
+        })
+    }
+
+    /// Appends a layer to the end of the command handling chain, right before
+    /// the command handler itself.
+    pub fn add_middleware(&self, middleware: Arc<dyn Middleware>) {
+        self.state.middlewares.write().unwrap().push(middleware);
+    }
+
+    pub async fn accept(&self) -> Result<()> {
+        let res = self.listener.accept().await;
+        self.state.listening.store(res.is_ok(), Ordering::SeqCst);
+        let (stream, addr) = res?;
+        spawn_session(Arc::clone(&self.state), stream, addr, false)
+    }
+}
+
+/// Accepts clients speaking the protocol over WebSocket on `listener`.
+async fn serve_ws(state: Arc<ServerState>, listener: TcpListener) -> Result<()> {
+    info!("WebSocket listener on {}", listener.local_addr()?);
+    loop {
+        let res = match listener.accept().await {
+            Ok((stream, peer)) => spawn_session(Arc::clone(&state), stream, peer, true),
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = res {
+            warn!("failed to accept WebSocket connection: {err:?}");
         }
     }
 }
 
-impl Server {
-    pub async fn accept(&self) -> Result<()> {
-        let (stream, addr) = self.listener.accept().await?;
-        let mut guard = self.state.sessions.write().await;
-        let entry = vacant_entry(&mut guard);
-        let session = Session::new(*entry.key(), stream, Arc::clone(&self.state)).await?;
+fn spawn_session(
+    state: Arc<ServerState>,
+    stream: TcpStream,
+    addr: SocketAddr,
+    websocket: bool,
+) -> Result<()> {
+    stream.set_nodelay(true)?;
+    let id = state.sessions.vacant_id();
+    // Session creation waits for authentication, up to the heartbeat
+    // timeout. Probing clients never authenticate, so this must not block
+    // the accept loop.
+    tokio::spawn(async move {
+        let res = time::timeout(HANDSHAKE_TIMEOUT, handshake(&state, stream, websocket)).await;
+        let stream = match res.context("timeout").and_then(|it| it) {
+            Ok(stream) => stream,
+            Err(err) => {
+                debug!("handshake with {addr} ({id}) failed: {err:?}");
+                return;
+            }
+        };
+        let session = match Session::new(id, stream, Arc::clone(&state)).await {
+            Ok(session) => session,
+            Err(err) => {
+                debug!("connection from {addr} ({id}) closed before authentication: {err:?}");
+                return;
+            }
+        };
         info!(
             "received connections from {addr} ({}), version: {}",
             session.id,
             session.version()
         );
-        entry.insert(session);
-        Ok(())
+        state.sessions.insert(id, session);
+    });
+    Ok(())
+}
+
+/// Sets up TLS and WebSocket on a new connection, as far as configured.
+async fn handshake(
+    state: &ServerState,
+    mut stream: TcpStream,
+    websocket: bool,
+) -> Result<Box<dyn Transport>> {
+    if !websocket {
+        if let Some(token) = read_route(&mut stream).await? {
+            if state.config.node_id.as_deref() == Some(token.as_str()) {
+                debug!("connection routed back to this node");
+            } else {
+                warn!("connection routed here with the token {token}");
+            }
+        }
+    }
+    #[cfg(feature = "tls")]
+    if let Some(tls) = &state.tls {
+        return upgrade(tls.accept(stream).await?, websocket).await;
+    }
+    #[cfg(not(feature = "tls"))]
+    let _ = state;
+    upgrade(stream, websocket).await
+}
+
+/// Takes the routing token off the start of the connection, if it comes
+/// with one, see [`ROUTE_PREFIX`].
+async fn read_route(stream: &mut TcpStream) -> Result<Option<String>> {
+    let mut first = [0; 1];
+    if stream.peek(&mut first).await? == 0 || first[0] != ROUTE_PREFIX[0] {
+        return Ok(None);
+    }
+    // Read byte by byte so that nothing after it is consumed
+    let mut line = Vec::new();
+    loop {
+        match stream.read_u8().await? {
+            b'\n' => break,
+            byte => line.push(byte),
+        }
+        anyhow::ensure!(
+            line.len() <= ROUTE_PREFIX.len() + 64,
+            "routing token too long"
+        );
+    }
+    let token = line
+        .strip_prefix(ROUTE_PREFIX)
+        .context("invalid routing line")?;
+    Ok(Some(String::from_utf8(token.to_vec())?))
+}
+
+async fn upgrade(stream: impl Transport, websocket: bool) -> Result<Box<dyn Transport>> {
+    #[cfg(feature = "websocket")]
+    if websocket {
+        return Ok(Box::new(WsTransport::new(accept_ws(stream).await?)));
     }
+    #[cfg(not(feature = "websocket"))]
+    let _ = websocket;
+    Ok(Box::new(stream))
 }
 
 impl Drop for Server {
     fn drop(&mut self) {
         self.lost_con_handle.abort();
+        if let Some(handle) = &self.admin_handle {
+            handle.abort();
+        }
+        if let Some(handle) = &self.health_handle {
+            handle.abort();
+        }
+        if let Some(handle) = &self.health_tcp_handle {
+            handle.abort();
+        }
+        if let Some(handle) = &self.quickplay_handle {
+            handle.abort();
+        }
+        if let Some(handle) = &self.expiry_handle {
+            handle.abort();
+        }
+        if let Some(handle) = &self.ws_handle {
+            handle.abort();
+        }
+        if let Some(handle) = &self.control_handle {
+            handle.abort();
+        }
     }
 }
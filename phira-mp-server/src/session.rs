@@ -1,24 +1,30 @@
 use crate::{
+    contains_link,
     l10n::{Language, LANGUAGE},
-    tl, Chart, InternalRoomState, Record, Room, ServerState,
+    merge_rooms, sanitize_name, split_room, tl, Chart, InternalRoomState, Next, Record, Room,
+    ServerState, TrustLevel, ROOM_MAX_SPECTATORS, SCORE_MAX,
 };
 use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
 use phira_mp_common::{
-    ClientCommand, JoinRoomResponse, Message, ServerCommand, Stream, UserInfo,
-    HEARTBEAT_DISCONNECT_TIMEOUT,
+    unix_millis, ChatChannel, ClientCommand, Features, GameEndReason, JoinRoomResponse, Message,
+    PlayerScore, RepeatPolicy, RoomMode, ServerCommand, ServerError, Stream, TouchFrame,
+    TouchSpace, Transport, UserInfo, Varchar, ENCRYPTED_CHAT_OVERHEAD, LOG_HEARTBEAT,
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, RELIABLE_PENDING_MAX,
 };
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use std::{
-    collections::{hash_map::Entry, HashSet},
+    cmp::Reverse,
+    collections::{BTreeSet, HashSet, VecDeque},
     ops::DerefMut,
     sync::{
         atomic::{AtomicBool, AtomicU32, Ordering},
-        Arc, Weak,
+        Arc, Mutex as StdMutex, RwLock as StdRwLock, Weak,
     },
-    time::{Duration, Instant},
+    time::Instant,
 };
 use tokio::{
-    net::TcpStream,
     sync::{oneshot, Mutex, Notify, OnceCell, RwLock},
     task::JoinHandle,
     time,
@@ -26,57 +32,165 @@ use tokio::{
 use tracing::{debug, debug_span, error, info, trace, warn, Instrument};
 use uuid::Uuid;
 
-const HOST: &str = "https://api.phira.cn";
+pub const HOST: &str = "https://api.phira.cn";
 const MONITORS: &[i32] = &[2, 143245];
 
+/// Chart hints beyond this are dropped.
+const PREFETCH_MAX_CHARTS: usize = 8;
+const CHAT_IDS_KEPT: usize = 64;
+/// Rooms beyond this are left out of `RoomList`.
+const ROOM_LIST_MAX: usize = 100;
+
 pub struct User {
     pub id: i32,
-    pub name: String,
+    name: StdRwLock<String>,
     pub lang: Language,
+    pub trust: TrustLevel,
 
     pub server: Arc<ServerState>,
     pub session: RwLock<Option<Weak<Session>>>,
     pub room: RwLock<Option<Arc<Room>>>,
 
     pub monitor: AtomicBool,
+    /// Also a monitor, only watching
+    pub spectator: AtomicBool,
     pub game_time: AtomicU32,
+    /// The player whose touches this monitor wants, `None` for everyone.
+    pub watching: RwLock<Option<i32>>,
 
     pub dangle_mark: Mutex<Option<Arc<()>>>,
+    /// See [`ClientCommand::ReportLatency`]
+    pub latency_ms: StdMutex<Option<u16>>,
+    /// See [`ClientCommand::SetTouchSpace`]
+    pub touch_space: StdMutex<Option<TouchSpace>>,
+    /// Ids of the latest delivered chat messages, so that retries aren't
+    /// delivered again.
+    chat_ids: Mutex<VecDeque<Uuid>>,
+    /// See [`Self::issue_resume_token`]
+    resume_token: StdMutex<Option<Uuid>>,
 }
 
 impl User {
-    pub fn new(id: i32, name: String, lang: Language, server: Arc<ServerState>) -> Self {
+    pub fn new(
+        id: i32,
+        name: String,
+        lang: Language,
+        trust: TrustLevel,
+        server: Arc<ServerState>,
+    ) -> Self {
         Self {
             id,
-            name,
+            name: name.into(),
             lang,
+            trust,
 
             server,
             session: RwLock::default(),
             room: RwLock::default(),
 
             monitor: AtomicBool::default(),
+            spectator: AtomicBool::default(),
             game_time: AtomicU32::default(),
+            watching: RwLock::default(),
 
             dangle_mark: Mutex::default(),
+            latency_ms: StdMutex::default(),
+            touch_space: StdMutex::default(),
+            chat_ids: Mutex::default(),
+            resume_token: StdMutex::default(),
+        }
+    }
+
+    /// A new token for [`ClientCommand::Resume`]. The previous one no longer
+    /// works.
+    pub fn issue_resume_token(&self) -> Uuid {
+        let tokens = &self.server.resume_tokens;
+        let token = tokens.vacant_id();
+        tokens.insert(token, self.id);
+        if let Some(old) = self.resume_token.lock().unwrap().replace(token) {
+            tokens.remove(&old);
+        }
+        token
+    }
+
+    pub fn revoke_resume_token(&self) {
+        if let Some(old) = self.resume_token.lock().unwrap().take() {
+            self.server.resume_tokens.remove(&old);
         }
     }
 
     pub fn to_info(&self) -> UserInfo {
         UserInfo {
             id: self.id,
-            name: self.name.clone(),
+            name: self.name(),
             monitor: self.monitor.load(Ordering::SeqCst),
         }
     }
 
+    /// `false` while waiting for the user to reconnect.
+    pub async fn is_connected(&self) -> bool {
+        self.dangle_mark.lock().await.is_none()
+    }
+
+    pub fn name(&self) -> String {
+        self.name.read().unwrap().clone()
+    }
+
+    /// Changes the display name, telling the room if it's a different one.
+    /// Users are always told apart by their id, the name is only shown.
+    pub async fn rename(&self, name: String) {
+        {
+            let mut guard = self.name.write().unwrap();
+            if *guard == name {
+                return;
+            }
+            info!(user = self.id, "renamed from {} to {name}", *guard);
+            *guard = name.clone();
+        }
+        let room = self.room.read().await.as_ref().map(Arc::clone);
+        if let Some(room) = room {
+            room.broadcast(ServerCommand::UserRenamed {
+                user: self.id,
+                name,
+            })
+            .await;
+        }
+    }
+
     pub fn can_monitor(&self) -> bool {
         MONITORS.contains(&self.id)
     }
 
+    /// Moves the user onto `session`. A connection that's still open, e.g.
+    /// the old one of a client that switched networks, is closed, while the
+    /// room membership stays untouched.
     pub async fn set_session(&self, session: Weak<Session>) {
-        *self.session.write().await = Some(session);
-        *self.dangle_mark.lock().await = None;
+        let old = self.session.write().await.replace(session.clone());
+        let reconnected = self.dangle_mark.lock().await.take().is_some();
+        if reconnected {
+            if let Some(room) = self.room.read().await.as_ref() {
+                room.broadcast(ServerCommand::MemberUpdate(room.member_status(self).await))
+                    .await;
+            }
+        }
+        if let Some(old) = old.as_ref().and_then(Weak::upgrade) {
+            if !Weak::ptr_eq(&Arc::downgrade(&old), &session) {
+                info!(user = self.id, "session {} migrated", old.id);
+                if let Err(err) = self.server.lost_con_tx.send(old.id).await {
+                    error!("failed to mark lost connection ({}): {err:?}", old.id);
+                }
+            }
+        }
+    }
+
+    /// Features of the user's current connection, none while dangling.
+    pub async fn features(&self) -> Features {
+        self.session
+            .read()
+            .await
+            .as_ref()
+            .and_then(Weak::upgrade)
+            .map_or(Features::EMPTY, |it| it.features())
     }
 
     pub async fn try_send(&self, cmd: ServerCommand) {
@@ -87,35 +201,55 @@ impl User {
         }
     }
 
+    /// Lets the user go for good after they said [`ClientCommand::Bye`],
+    /// leaving their room right away.
+    pub async fn leave(self: Arc<Self>) {
+        info!(user = self.id, "user left");
+        self.server.users.remove(&self.id);
+        self.revoke_resume_token();
+        let room = self.room.read().await.as_ref().map(Arc::clone);
+        if let Some(room) = room {
+            if room.on_user_leave(&self).await {
+                self.server.rooms.remove(&room.id);
+            }
+        }
+    }
+
     pub async fn dangle(self: Arc<Self>) {
         warn!(user = self.id, "user dangling");
-        let guard = self.room.read().await;
-        let room = guard.as_ref().map(Arc::clone);
-        drop(guard);
-        if let Some(room) = room {
-            let guard = room.state.read().await;
-            if matches!(*guard, InternalRoomState::Playing { .. }) {
-                warn!(user = self.id, "lost connection on playing, aborting");
-                self.server.users.write().await.remove(&self.id);
-                drop(guard);
-                if room.on_user_leave(&self).await {
-                    self.server.rooms.write().await.remove(&room.id);
-                }
-                return;
+        let room = self.room.read().await.as_ref().map(Arc::clone);
+        let mut grace = self.server.config.reconnect_grace;
+        if let Some(room) = &room {
+            if matches!(*room.state.read().await, InternalRoomState::Playing { .. }) {
+                grace = grace.min(self.server.config.playing_grace);
             }
         }
         let dangle_mark = Arc::new(());
         *self.dangle_mark.lock().await = Some(Arc::clone(&dangle_mark));
+        if let Some(room) = &room {
+            room.broadcast_except(
+                self.id,
+                ServerCommand::MemberUpdate(room.member_status(&self).await),
+            )
+            .await;
+        }
         tokio::spawn(async move {
-            time::sleep(Duration::from_secs(10)).await;
+            time::sleep(grace).await;
             if Arc::strong_count(&dangle_mark) > 1 {
                 let guard = self.room.read().await;
                 let room = guard.as_ref().map(Arc::clone);
                 drop(guard);
                 if let Some(room) = room {
-                    self.server.users.write().await.remove(&self.id);
+                    self.server.users.remove(&self.id);
+                    self.revoke_resume_token();
+                    if matches!(*room.state.read().await, InternalRoomState::Playing { .. }) {
+                        warn!(user = self.id, "lost connection on playing, aborting");
+                        if !room.is_cycle() && room.check_host(&self).await.is_ok() {
+                            room.hold_host(self.id);
+                        }
+                    }
                     if room.on_user_leave(&self).await {
-                        self.server.rooms.write().await.remove(&room.id);
+                        self.server.rooms.remove(&room.id);
                     }
                 }
             }
@@ -127,55 +261,172 @@ pub struct Session {
     pub id: Uuid,
     pub stream: Stream<ServerCommand, ClientCommand>,
     pub user: Arc<User>,
+    /// Negotiated in `Hello`, which always comes before authentication
+    protocol: Arc<StdMutex<Option<(u16, Features)>>>,
+    /// See [`ClientCommand::Bye`]
+    said_bye: Arc<AtomicBool>,
 
     monitor_task_handle: JoinHandle<()>,
 }
 
 impl Session {
-    pub async fn new(id: Uuid, stream: TcpStream, server: Arc<ServerState>) -> Result<Arc<Self>> {
-        stream.set_nodelay(true)?;
+    pub async fn new(
+        id: Uuid,
+        stream: impl Transport,
+        server: Arc<ServerState>,
+    ) -> Result<Arc<Self>> {
         let this = Arc::new(OnceCell::<Arc<Session>>::new());
         let this_inited = Arc::new(Notify::new());
         let (tx, rx) = oneshot::channel::<Arc<User>>();
         let last_recv: Arc<Mutex<Instant>> = Arc::new(Mutex::new(Instant::now()));
+        let protocol: Arc<StdMutex<Option<(u16, Features)>>> = Arc::default();
+        let said_bye: Arc<AtomicBool> = Arc::default();
         let stream = Stream::<ServerCommand, ClientCommand>::new(
             None,
             stream,
             Box::new({
                 let this = Arc::clone(&this);
                 let this_inited = Arc::clone(&this_inited);
-                let mut tx = Some(tx);
+                // Taken by the first `Authenticate`
+                let tx = Arc::new(StdMutex::new(Some(tx)));
                 let server = Arc::clone(&server);
                 let last_recv = Arc::clone(&last_recv);
+                let protocol = Arc::clone(&protocol);
+                let said_bye = Arc::clone(&said_bye);
                 let waiting_for_authenticate = Arc::new(AtomicBool::new(true));
                 let panicked = Arc::new(AtomicBool::new(false));
+                let received = Arc::new(StdMutex::new(ReceivedSeqs::default()));
                 move |send_tx, cmd| {
                     let this = Arc::clone(&this);
                     let this_inited = Arc::clone(&this_inited);
-                    let tx = tx.take();
+                    let tx = Arc::clone(&tx);
                     let server = Arc::clone(&server);
                     let last_recv = Arc::clone(&last_recv);
+                    let protocol = Arc::clone(&protocol);
+                    let said_bye = Arc::clone(&said_bye);
                     let waiting_for_authenticate = Arc::clone(&waiting_for_authenticate);
                     let panicked = Arc::clone(&panicked);
+                    let received = Arc::clone(&received);
                     async move {
                         *last_recv.lock().await = Instant::now();
                         if panicked.load(Ordering::SeqCst) {
                             return;
                         }
-                        if matches!(cmd, ClientCommand::Ping) {
-                            let _ = send_tx.send(ServerCommand::Pong).await;
-                            return;
+                        let cmd = match cmd {
+                            ClientCommand::Reliable { seq, cmd } => {
+                                let _ = send_tx.send(ServerCommand::Ack { seq }).await;
+                                if !received.lock().unwrap().insert(seq) {
+                                    trace!("session {id}: reliable packet {seq} again");
+                                    return;
+                                }
+                                cmd.into()
+                            }
+                            cmd => cmd,
+                        };
+                        match cmd {
+                            ClientCommand::Ping => {
+                                trace!(target: LOG_HEARTBEAT, "session {id}: ping");
+                                let _ = send_tx.send(ServerCommand::Pong).await;
+                                return;
+                            }
+                            ClientCommand::SyncClock { client_time } => {
+                                let _ = send_tx
+                                    .send(ServerCommand::SyncClock {
+                                        client_time,
+                                        server_time: unix_millis(),
+                                    })
+                                    .await;
+                                return;
+                            }
+                            ClientCommand::Region => {
+                                let _ = send_tx
+                                    .send(ServerCommand::Region(server.config.region.clone()))
+                                    .await;
+                                return;
+                            }
+                            ClientCommand::Route { token } => {
+                                let token = token.into_inner();
+                                if server.config.node_id.as_deref() == Some(token.as_str()) {
+                                    debug!("session {id}: routed back to this node");
+                                } else {
+                                    warn!("session {id}: routed here with the token {token}");
+                                }
+                                return;
+                            }
+                            ClientCommand::Bye => {
+                                debug!("session {id}: bye");
+                                said_bye.store(true, Ordering::SeqCst);
+                                if let Err(err) = server.lost_con_tx.send(id).await {
+                                    error!("failed to mark lost connection ({id}): {err:?}");
+                                }
+                                return;
+                            }
+                            ClientCommand::UnknownCommand { tag } => {
+                                debug!("session {id}: skipped unknown command {tag}");
+                                return;
+                            }
+                            _ => {}
                         }
                         if waiting_for_authenticate.load(Ordering::SeqCst) {
-                            if let ClientCommand::Authenticate { token } = cmd {
-                                let Some(tx) = tx else { return };
+                            if let ClientCommand::Hello { version, features } = cmd {
+                                let res = negotiate(version, features);
+                                debug!("session {id}: hello {version} {features:?} -> {res:?}");
+                                *protocol.lock().unwrap() = res.as_ref().ok().copied();
+                                let _ = send_tx.send(ServerCommand::Hello(res.clone())).await;
+                                if let (Ok((_, features)), Some(node)) =
+                                    (res, &server.config.node_id)
+                                {
+                                    if features.contains(Features::ROUTING) {
+                                        let token = node.clone();
+                                        let _ =
+                                            send_tx.send(ServerCommand::Routing { token }).await;
+                                    }
+                                }
+                                return;
+                            }
+                            if matches!(
+                                cmd,
+                                ClientCommand::Authenticate { .. } | ClientCommand::Resume { .. }
+                            ) {
+                                let Some(tx) = tx.lock().unwrap().take() else {
+                                    return;
+                                };
                                 let res: Result<()> = {
                                     let this = Arc::clone(&this);
                                     let server = Arc::clone(&server);
+                                    let protocol = *protocol.lock().unwrap();
                                     async move {
-                                        let token = token.into_inner();
-                                        if token.len() != 32 {
-                                            bail!("invalid token");
+                                        // Clients from before `Hello` end up here
+                                        if protocol.is_none() {
+                                            bail!(ServerError::UnsupportedVersion);
+                                        }
+                                        let token = match cmd {
+                                            ClientCommand::Authenticate { token } => {
+                                                token.into_inner()
+                                            }
+                                            ClientCommand::Resume { session } => {
+                                                let user = server
+                                                    .resume_tokens
+                                                    .get(&session)
+                                                    .and_then(|it| server.users.get(&it))
+                                                    .ok_or(ServerError::InvalidToken)?;
+                                                if server.bans.is_banned(user.id) {
+                                                    bail!(user.lang.format("auth-banned", None));
+                                                }
+                                                info!("session {id}: resume {}", user.id);
+                                                let _ = tx.send(Arc::clone(&user));
+                                                this_inited.notified().await;
+                                                user.set_session(Arc::downgrade(
+                                                    this.get().unwrap(),
+                                                ))
+                                                .await;
+                                                return Ok(());
+                                            }
+                                            _ => unreachable!(),
+                                        };
+                                        let sandbox = server.config.sandbox;
+                                        if token.is_empty() || (!sandbox && token.len() != 32) {
+                                            bail!(ServerError::InvalidToken);
                                         }
                                         debug!("session {id}: authenticate {token}");
                                         #[derive(Debug, Deserialize)]
@@ -183,6 +434,10 @@ impl Session {
                                             id: i32,
                                             name: String,
                                             language: String,
+                                            #[serde(default)]
+                                            joined: Option<DateTime<Utc>>,
+                                            #[serde(default)]
+                                            exp: Option<i64>,
                                         }
                                         let resp: Result<UserInfo> = async {
                                             Ok(reqwest::Client::new()
@@ -200,34 +455,77 @@ impl Session {
                                         .await;
                                         let resp = match resp {
                                             Ok(resp) => resp,
+                                            // Real accounts still work, for testing
+                                            // things like trust levels
+                                            Err(err) if sandbox => {
+                                                debug!("session {id}: sandbox user ({err})");
+                                                let id = sandbox_user_id(&token);
+                                                UserInfo {
+                                                    id,
+                                                    name: format!(
+                                                        "Tester {:05}",
+                                                        id.unsigned_abs() % 100000
+                                                    ),
+                                                    language: "en-US".to_owned(),
+                                                    joined: None,
+                                                    exp: None,
+                                                }
+                                            }
                                             Err(err) => {
                                                 warn!("failed to fetch info: {err:?}");
                                                 bail!("failed to fetch info");
                                             }
                                         };
                                         debug!("session {id} <- {resp:?}");
-                                        let mut users_guard = server.users.write().await;
-                                        if let Some(user) = users_guard.get(&resp.id) {
+                                        if server.bans.is_banned(resp.id) {
+                                            let lang = resp
+                                                .language
+                                                .parse()
+                                                .map(Language)
+                                                .unwrap_or_default();
+                                            bail!(lang.format("auth-banned", None));
+                                        }
+                                        let name = match server
+                                            .name_overrides
+                                            .read()
+                                            .await
+                                            .get(&resp.id)
+                                        {
+                                            Some(name) => name.clone(),
+                                            None => {
+                                                sanitize_name(&server.config, resp.id, &resp.name)?
+                                            }
+                                        };
+                                        if let Some(user) = server.users.get(&resp.id) {
                                             info!("reconnect");
-                                            let _ = tx.send(Arc::clone(user));
+                                            let _ = tx.send(Arc::clone(&user));
                                             this_inited.notified().await;
                                             user.set_session(Arc::downgrade(this.get().unwrap()))
                                                 .await;
+                                            // Renamed on the Phira backend meanwhile
+                                            user.rename(name).await;
                                         } else {
                                             let user = Arc::new(User::new(
                                                 resp.id,
-                                                resp.name,
+                                                name,
                                                 resp.language
                                                     .parse()
                                                     .map(Language)
                                                     .unwrap_or_default(),
+                                                TrustLevel::of(
+                                                    &server.config,
+                                                    resp.joined,
+                                                    resp.exp,
+                                                ),
                                                 Arc::clone(&server),
                                             ));
+                                            // Another session of the same user may have
+                                            // registered meanwhile, take over theirs then
+                                            let user = server.users.get_or_insert(resp.id, user);
                                             let _ = tx.send(Arc::clone(&user));
                                             this_inited.notified().await;
                                             user.set_session(Arc::downgrade(this.get().unwrap()))
                                                 .await;
-                                            users_guard.insert(resp.id, user);
                                         }
                                         Ok(())
                                     }
@@ -236,7 +534,7 @@ impl Session {
                                 if let Err(err) = res {
                                     warn!("failed to authenticate: {err:?}");
                                     let _ = send_tx
-                                        .send(ServerCommand::Authenticate(Err(err.to_string())))
+                                        .send(ServerCommand::Authenticate(Err(server_error(err))))
                                         .await;
                                     panicked.store(true, Ordering::SeqCst);
                                     if let Err(err) = server.lost_con_tx.send(id).await {
@@ -244,16 +542,33 @@ impl Session {
                                     }
                                 } else {
                                     let user = &this.get().unwrap().user;
-                                    let room_state = match user.room.read().await.as_ref() {
+                                    let room = user.room.read().await.as_ref().map(Arc::clone);
+                                    let room_state = match &room {
                                         Some(room) => Some(room.client_state(user).await),
                                         None => None,
                                     };
+                                    let _ = send_tx
+                                        .send(ServerCommand::Limits(server.config.limits()))
+                                        .await;
                                     let _ = send_tx
                                         .send(ServerCommand::Authenticate(Ok((
                                             user.to_info(),
                                             room_state,
                                         ))))
                                         .await;
+                                    let features = user.features().await;
+                                    if let Some(room) = room {
+                                        if features.contains(Features::MEMBER_STATUS) {
+                                            let _ = send_tx
+                                                .send(ServerCommand::Members(room.members().await))
+                                                .await;
+                                        }
+                                    }
+                                    if features.contains(Features::RESUME) {
+                                        let token = user.issue_resume_token();
+                                        let _ =
+                                            send_tx.send(ServerCommand::ResumeToken(token)).await;
+                                    }
                                     waiting_for_authenticate.store(false, Ordering::SeqCst);
                                 }
                                 return;
@@ -263,10 +578,18 @@ impl Session {
                             }
                         }
                         let user = this.get().map(|it| Arc::clone(&it.user)).unwrap();
-                        if let Some(resp) = LANGUAGE
-                            .scope(Arc::new(user.lang.clone()), process(user, cmd))
-                            .await
-                        {
+                        let chain = server.middlewares.read().unwrap().clone();
+                        let start = Instant::now();
+                        let resp = LANGUAGE
+                            .scope(
+                                Arc::new(user.lang.clone()),
+                                Next::new(&chain).run(Arc::clone(&user), cmd),
+                            )
+                            .await;
+                        if let Some(room) = user.room.read().await.as_ref() {
+                            room.usage.add_handler_time(start.elapsed());
+                        }
+                        if let Some(resp) = resp {
                             if let Err(err) = send_tx.send(resp).await {
                                 error!(
                                     "failed to handle message, aborting connection {id}: {err:?}",
@@ -282,17 +605,20 @@ impl Session {
             }),
         )
         .await?;
+        let auth_timeout = server.config.heartbeat_disconnect_timeout;
         let monitor_task_handle = tokio::spawn({
             let last_recv = Arc::clone(&last_recv);
+            let timeout = server.config.heartbeat_disconnect_timeout;
             async move {
                 loop {
                     let recv = *last_recv.lock().await;
-                    time::sleep_until((recv + HEARTBEAT_DISCONNECT_TIMEOUT).into()).await;
+                    time::sleep_until((recv + timeout).into()).await;
 
-                    if *last_recv.lock().await + HEARTBEAT_DISCONNECT_TIMEOUT > Instant::now() {
+                    if *last_recv.lock().await + timeout > Instant::now() {
                         continue;
                     }
 
+                    warn!(target: LOG_HEARTBEAT, "session {id}: heartbeat timed out");
                     if let Err(err) = server.lost_con_tx.send(id).await {
                         error!("failed to mark lost connection ({id}): {err:?}");
                     }
@@ -301,12 +627,50 @@ impl Session {
             }
         });
 
-        let user = rx.await?;
+        // Before then, heartbeat timeouts are reported for a session that
+        // isn't registered yet, which does nothing
+        let user = match time::timeout(auth_timeout, rx).await {
+            Ok(Ok(user)) => user,
+            res => {
+                monitor_task_handle.abort();
+                bail!(match res {
+                    Ok(Err(err)) => anyhow!(err),
+                    _ => anyhow!("not authenticated in time"),
+                });
+            }
+        };
+        // Counted once written, towards the room the user is in by then
+        stream.on_sent({
+            let user = Arc::downgrade(&user);
+            move |cmd, bytes| {
+                if !matches!(
+                    cmd,
+                    ServerCommand::Touches { .. }
+                        | ServerCommand::PhasedTouches { .. }
+                        | ServerCommand::PackedTouches { .. }
+                        | ServerCommand::Judges { .. }
+                ) {
+                    return;
+                }
+                let Some(user) = user.upgrade() else {
+                    return;
+                };
+                let room = match user.room.try_read() {
+                    Ok(room) => room.clone(),
+                    Err(_) => return,
+                };
+                if let Some(room) = room {
+                    room.usage.add_relay(bytes as u64);
+                }
+            }
+        });
 
         let res = Arc::new(Self {
             id,
             stream,
             user,
+            protocol,
+            said_bye,
 
             monitor_task_handle,
         });
@@ -319,27 +683,107 @@ impl Session {
         self.stream.version()
     }
 
-    pub fn name(&self) -> &str {
-        &self.user.name
+    pub fn protocol_version(&self) -> u16 {
+        self.protocol.lock().unwrap().map_or(0, |it| it.0)
+    }
+
+    /// Whether the client closed the connection on purpose, see
+    /// [`ClientCommand::Bye`].
+    pub fn said_bye(&self) -> bool {
+        self.said_bye.load(Ordering::SeqCst)
+    }
+
+    /// Features both sides support on this connection.
+    pub fn features(&self) -> Features {
+        self.protocol
+            .lock()
+            .unwrap()
+            .map_or(Features::EMPTY, |it| it.1)
+    }
+
+    pub fn name(&self) -> String {
+        self.user.name()
     }
 
     pub async fn try_send(&self, cmd: ServerCommand) {
+        let needs = match cmd {
+            ServerCommand::Members(_) | ServerCommand::MemberUpdate(_) => Features::MEMBER_STATUS,
+            ServerCommand::Scores { .. } => Features::LIVE_SCORES,
+            ServerCommand::MessageHistory(_) => Features::MESSAGE_HISTORY,
+            ServerCommand::Routing { .. } => Features::ROUTING,
+            ServerCommand::ResumeToken(_) => Features::RESUME,
+            ServerCommand::TouchSpace { .. } => Features::TOUCH_SPACE,
+            ServerCommand::PhasedTouches { .. } => Features::TOUCH_PHASES,
+            ServerCommand::PackedTouches { .. } => Features::TOUCH_DELTA,
+            ServerCommand::Message(Message::Bot { .. }) => Features::BOT_MESSAGES,
+            _ => Features::EMPTY,
+        };
+        if !self.features().contains(needs) {
+            return;
+        }
         if let Err(err) = self.stream.send(cmd).await {
             error!("failed to deliver command to {}: {err:?}", self.id);
+            if self.stream.close_reason().is_some() {
+                // Don't wait for the heartbeat monitor, the writer is already gone
+                let _ = self.user.server.lost_con_tx.try_send(self.id);
+            }
+        }
+    }
+}
+
+/// Sequence numbers of [`ClientCommand::Reliable`] received on a connection.
+#[derive(Default)]
+struct ReceivedSeqs {
+    /// All below were received
+    next: u32,
+    /// Received out of order
+    ahead: BTreeSet<u32>,
+}
+
+impl ReceivedSeqs {
+    /// Returns whether `seq` wasn't received before.
+    fn insert(&mut self, seq: u32) -> bool {
+        if seq < self.next || !self.ahead.insert(seq) {
+            return false;
+        }
+        if self.ahead.len() > RELIABLE_PENDING_MAX {
+            // The client gave up on the missing ones by now
+            self.next = self.ahead.pop_first().unwrap() + 1;
+        }
+        while self.ahead.remove(&self.next) {
+            self.next += 1;
         }
+        true
     }
 }
 
+/// The protocol version and features to use with a client sending `Hello`.
+fn negotiate(version: u16, features: Features) -> Result<(u16, Features), ServerError> {
+    if version < MIN_PROTOCOL_VERSION {
+        return Err(ServerError::UnsupportedVersion);
+    }
+    Ok((
+        version.min(PROTOCOL_VERSION),
+        features.intersection(Features::ALL),
+    ))
+}
+
+/// The error to answer with, typed if it was raised as a [`ServerError`].
+fn server_error(err: anyhow::Error) -> ServerError {
+    err.downcast()
+        .unwrap_or_else(|err| ServerError::Other(err.to_string()))
+}
+
 impl Drop for Session {
     fn drop(&mut self) {
         self.monitor_task_handle.abort();
     }
 }
 
-async fn process(user: Arc<User>, cmd: ClientCommand) -> Option<ServerCommand> {
+pub(crate) async fn process(user: Arc<User>, cmd: ClientCommand) -> Option<ServerCommand> {
     #[inline]
-    fn err_to_str<T>(result: Result<T>) -> Result<T, String> {
-        result.map_err(|it| it.to_string())
+    fn err_to_server<T>(result: Result<T>) -> Result<T, ServerError> {
+        result.map_err(server_error)
     }
 
     macro_rules! get_room {
@@ -359,7 +803,7 @@ async fn process(user: Arc<User>, cmd: ClientCommand) -> Option<ServerCommand> {
                 .await
                 .as_ref()
                 .map(Arc::clone)
-                .ok_or_else(|| anyhow!("no room"))?;
+                .ok_or(ServerError::NotInRoom)?;
         };
         ($d:ident, $($pt:tt)*) => {
             let $d = user
@@ -368,140 +812,278 @@ async fn process(user: Arc<User>, cmd: ClientCommand) -> Option<ServerCommand> {
                 .await
                 .as_ref()
                 .map(Arc::clone)
-                .ok_or_else(|| anyhow!("no room"))?;
+                .ok_or(ServerError::NotInRoom)?;
             if !matches!(&*$d.state.read().await, $($pt)*) {
-                bail!("invalid state");
+                bail!(ServerError::InvalidState);
             }
         };
     }
+    if !matches!(
+        cmd,
+        ClientCommand::Touches { .. }
+            | ClientCommand::PhasedTouches { .. }
+            | ClientCommand::PackedTouches { .. }
+            | ClientCommand::Judges { .. }
+    ) {
+        let room = user.room.read().await.as_ref().map(Arc::clone);
+        if let Some(room) = room {
+            room.touch().await;
+        }
+    }
     match cmd {
-        ClientCommand::Ping => unreachable!(),
-        ClientCommand::Authenticate { .. } => Some(ServerCommand::Authenticate(Err(
-            "repeated authenticate".to_owned(),
-        ))),
+        ClientCommand::Ping
+        | ClientCommand::Region
+        | ClientCommand::Route { .. }
+        | ClientCommand::Reliable { .. }
+        | ClientCommand::UnknownCommand { .. }
+        | ClientCommand::SyncClock { .. }
+        | ClientCommand::Bye => unreachable!(),
+        ClientCommand::Authenticate { .. } | ClientCommand::Resume { .. } => {
+            Some(ServerCommand::Authenticate(Err(ServerError::InvalidState)))
+        }
+        ClientCommand::Hello { .. } => Some(ServerCommand::Hello(Err(ServerError::InvalidState))),
         ClientCommand::Chat { message } => {
+            let res = chat(&user, message.into_inner()).await;
+            Some(ServerCommand::Chat(err_to_server(res)))
+        }
+        ClientCommand::EncryptedChat { payload } => {
+            let res = encrypted_chat(&user, payload).await;
+            Some(ServerCommand::Chat(err_to_server(res)))
+        }
+        ClientCommand::Whisper { target, message } => {
+            let res = whisper(&user, target, message.into_inner()).await;
+            Some(ServerCommand::Chat(err_to_server(res)))
+        }
+        ClientCommand::ChatWithId { id, message } => {
             let res: Result<()> = async move {
-                get_room!(room);
-                room.send_as(&user, message.into_inner()).await;
+                if user.chat_ids.lock().await.contains(&id) {
+                    debug!(user = user.id, "chat {id} already delivered");
+                    return Ok(());
+                }
+                chat(&user, message.into_inner()).await?;
+                let mut chat_ids = user.chat_ids.lock().await;
+                if chat_ids.len() == CHAT_IDS_KEPT {
+                    chat_ids.pop_front();
+                }
+                chat_ids.push_back(id);
                 Ok(())
             }
             .await;
-            Some(ServerCommand::Chat(err_to_str(res)))
+            Some(ServerCommand::ChatAck {
+                id,
+                result: err_to_server(res),
+            })
+        }
+        ClientCommand::Touches { round, frames } => {
+            let frames = Arc::new(frames.iter().map(Into::into).collect());
+            relay_touches(user, round, frames).await;
+            None
+        }
+        ClientCommand::PhasedTouches { round, frames } => {
+            relay_touches(user, round, frames).await;
+            None
+        }
+        ClientCommand::PackedTouches { round, frames } => {
+            let frames = Arc::try_unwrap(frames).unwrap_or_else(|it| (*it).clone());
+            relay_touches(user, round, Arc::new(frames.into_inner())).await;
+            None
         }
-        ClientCommand::Touches { frames } => {
+        ClientCommand::Judges { round, judges } => {
             get_room!(~ room);
             if room.is_live() {
-                debug!("received {} touch events from {}", frames.len(), user.id);
-                if let Some(frame) = frames.last() {
-                    user.game_time.store(frame.time.to_bits(), Ordering::SeqCst);
+                if room.current_round().await != Some(round) {
+                    debug!("dropped judge events of round {round} from {}", user.id);
+                    return None;
                 }
-                tokio::spawn(async move {
-                    room.broadcast_monitors(ServerCommand::Touches {
-                        player: user.id,
-                        frames,
-                    })
-                    .await;
-                });
+                if judges.len() > user.server.config.realtime_batch_max {
+                    warn!("dropped {} judge events from {}", judges.len(), user.id);
+                    return None;
+                }
+                debug!("received {} judge events from {}", judges.len(), user.id);
+                room.queue_judges(user.id, round, &judges);
             } else {
-                warn!("received touch events in non-live mode");
+                warn!("received judge events in non-live mode");
             }
             None
         }
-        ClientCommand::Judges { judges } => {
+        ClientCommand::ScoreUpdate {
+            round,
+            score,
+            accuracy,
+            combo,
+        } => {
             get_room!(~ room);
-            if room.is_live() {
-                debug!("received {} judge events from {}", judges.len(), user.id);
-                tokio::spawn(async move {
-                    room.broadcast_monitors(ServerCommand::Judges {
-                        player: user.id,
-                        judges,
-                    })
+            if user.monitor.load(Ordering::SeqCst) || room.current_round().await != Some(round) {
+                debug!("dropped score update of round {round} from {}", user.id);
+                return None;
+            }
+            if !(0..=SCORE_MAX).contains(&score) || !(0.0..=1.0).contains(&accuracy) {
+                warn!("dropped invalid score update from {}", user.id);
+                return None;
+            }
+            let score = PlayerScore {
+                user: user.id,
+                score,
+                accuracy,
+                combo,
+            };
+            let Some(scores) = room.update_score(round, score) else {
+                debug!("dropped too frequent score update from {}", user.id);
+                return None;
+            };
+            tokio::spawn(async move {
+                room.broadcast(ServerCommand::Scores { round, scores })
                     .await;
-                });
-            } else {
-                warn!("received judge events in non-live mode");
+            });
+            None
+        }
+        ClientCommand::ResendJudges {
+            player,
+            round,
+            seqs,
+        } => {
+            get_room!(~ room);
+            if room.is_live() && user.monitor.load(Ordering::SeqCst) {
+                room.resend_judges(&user, player, round, &seqs).await;
             }
             None
         }
-        ClientCommand::CreateRoom { id } => {
+        ClientCommand::CreateRoom {
+            id,
+            password,
+            max_players,
+        } => {
             let res: Result<()> = async move {
                 let mut room_guard = user.room.write().await;
                 if room_guard.is_some() {
-                    bail!("already in room");
+                    bail!(ServerError::AlreadyInRoom);
+                }
+                if user.trust == TrustLevel::New {
+                    bail!(tl!("trust-cannot-host"));
+                }
+                if user.server.draining.load(Ordering::SeqCst) {
+                    bail!(tl!("create-draining"));
                 }
 
-                let mut map_guard = user.server.rooms.write().await;
-                let room = Arc::new(Room::new(id.clone(), Arc::downgrade(&user)));
-                match map_guard.entry(id.clone()) {
-                    Entry::Vacant(entry) => {
-                        entry.insert(Arc::clone(&room));
-                    }
-                    Entry::Occupied(_) => {
-                        bail!(tl!("create-id-occupied"));
-                    }
+                let room = Arc::new(Room::new(
+                    id.clone(),
+                    Arc::downgrade(&user),
+                    Arc::downgrade(&user.server),
+                ));
+                room.set_password(password.map(Varchar::into_inner).as_deref());
+                // Clients ask for the protocol's most by default
+                let limit = user.server.config.room_max_players;
+                room.set_max_players((max_players as usize).min(limit) as u8, limit)
+                    .await?;
+                let max = user.server.config.max_rooms;
+                match user
+                    .server
+                    .rooms
+                    .try_insert_bounded(id.clone(), Arc::clone(&room), max)
+                {
+                    Some(true) => {}
+                    Some(false) => bail!(tl!("create-id-occupied")),
+                    None => bail!(tl!("create-too-many-rooms")),
                 }
                 room.send(Message::CreateRoom { user: user.id }).await;
-                drop(map_guard);
+                room.send_recording_notice(&user.server.config, Some(&user))
+                    .await;
                 *room_guard = Some(room);
 
                 info!(user = user.id, room = id.to_string(), "user create room");
                 Ok(())
             }
             .await;
-            Some(ServerCommand::CreateRoom(err_to_str(res)))
+            Some(ServerCommand::CreateRoom(err_to_server(res)))
         }
-        ClientCommand::JoinRoom { id, monitor } => {
+        ClientCommand::JoinRoom {
+            id,
+            monitor,
+            spectator,
+            password,
+        } => {
             let res: Result<JoinRoomResponse> = async move {
                 let mut room_guard = user.room.write().await;
                 if room_guard.is_some() {
-                    bail!("already in room");
+                    bail!(ServerError::AlreadyInRoom);
+                }
+                let room = user.server.rooms.get(&id);
+                let Some(room) = room else {
+                    bail!(ServerError::RoomNotFound)
+                };
+                let grace = user.server.config.reconnect_grace;
+                if room.is_banned(user.id) {
+                    bail!(tl!("join-banned"));
+                }
+                if let Some(left) = room.timed_out_for(user.id) {
+                    bail!(tl!("join-timed-out", "secs" => left.as_secs() + 1));
                 }
-                let room = user.server.rooms.read().await.get(&id).map(Arc::clone);
-                let Some(room) = room else { bail!("room not found") };
-                if room.locked.load(Ordering::SeqCst) {
+                let former_host = room.is_former_host(user.id, grace);
+                if room.locked.load(Ordering::SeqCst) && !former_host {
                     bail!(tl!("join-room-locked"));
                 }
-                if !matches!(*room.state.read().await, InternalRoomState::SelectChart) {
-                    bail!(tl!("join-game-ongoing"));
+                if !former_host {
+                    room.check_password(password.map(Varchar::into_inner).as_deref())?;
+                }
+                match *room.state.read().await {
+                    InternalRoomState::SelectChart => {}
+                    // Quickplay players may drop in until the game starts
+                    InternalRoomState::WaitForReady { .. } if room.quickplay => {}
+                    _ => bail!(tl!("join-game-ongoing")),
                 }
-                if monitor && !user.can_monitor() {
+                if monitor && !spectator && !user.can_monitor() {
                     bail!(tl!("join-cant-monitor"));
                 }
+                if spectator && room.spectator_count().await >= ROOM_MAX_SPECTATORS {
+                    bail!(ServerError::RoomFull {
+                        message: tl!("join-room-full").into_owned(),
+                    });
+                }
+                let monitor = monitor || spectator;
+                if !monitor && room.duel().is_some() {
+                    bail!(tl!("join-duel"));
+                }
                 if !room.add_user(Arc::downgrade(&user), monitor).await {
-                    bail!(tl!("join-room-full"));
+                    bail!(ServerError::RoomFull {
+                        message: tl!("join-room-full").into_owned(),
+                    });
                 }
                 info!(
                     user = user.id,
                     room = id.to_string(),
                     monitor,
+                    spectator,
                     "user join room"
                 );
                 user.monitor.store(monitor, Ordering::SeqCst);
-                if monitor && !room.live.fetch_or(true, Ordering::SeqCst) {
-                    info!(room = id.to_string(), "room goes live");
-                }
-                room.broadcast(ServerCommand::OnJoinRoom(user.to_info()))
-                    .await;
-                room.send(Message::JoinRoom {
-                    user: user.id,
-                    name: user.name.clone(),
-                })
-                .await;
+                user.spectator.store(spectator, Ordering::SeqCst);
                 *room_guard = Some(Arc::clone(&room));
+                room.welcome(&user).await;
+                let is_host = !monitor && room.reclaim_host(&user, grace).await;
                 Ok(JoinRoomResponse {
                     state: room.client_room_state().await,
                     users: room
                         .users()
                         .await
                         .into_iter()
-                        .chain(room.monitors().await.into_iter())
+                        .chain(room.monitors().await)
                         .map(|it| it.to_info())
                         .collect(),
                     live: room.is_live(),
+                    recording: room.recording(),
+                    is_host,
+                    duel: room.duel(),
+                    draft: room.draft(),
+                    max_players: room.max_players() as u8,
+                    last_results: room.last_results(),
+                    repeat_policy: room.repeat_policy(),
+                    mode: room.mode(),
+                    queue: room.queue(),
+                    ready_check: room.ready_check(),
                 })
             }
             .await;
-            Some(ServerCommand::JoinRoom(err_to_str(res)))
+            Some(ServerCommand::JoinRoom(err_to_server(res)))
         }
         ClientCommand::LeaveRoom => {
             let res: Result<()> = async move {
@@ -516,12 +1098,43 @@ async fn process(user: Arc<User>, cmd: ClientCommand) -> Option<ServerCommand> {
                     "user leave room"
                 );
                 if room.on_user_leave(&user).await {
-                    user.server.rooms.write().await.remove(&room.id);
+                    user.server.rooms.remove(&room.id);
                 }
                 Ok(())
             }
             .await;
-            Some(ServerCommand::LeaveRoom(err_to_str(res)))
+            Some(ServerCommand::LeaveRoom(err_to_server(res)))
+        }
+        ClientCommand::SplitRoom { id, users } => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                room.check_host(&user).await?;
+                if user.server.draining.load(Ordering::SeqCst) {
+                    bail!(tl!("create-draining"));
+                }
+                split_room(&user.server, &room, id, &users).await?;
+                Ok(())
+            }
+            .await;
+            Some(ServerCommand::SplitRoom(err_to_server(res)))
+        }
+        ClientCommand::MergeRoom { into } => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                room.check_host(&user).await?;
+                let Some(target) = user.server.rooms.get(&into) else {
+                    bail!(ServerError::RoomNotFound);
+                };
+                if target.is_locked() {
+                    bail!(tl!("join-room-locked"));
+                }
+                if target.has_password() {
+                    bail!(tl!("merge-password"));
+                }
+                merge_rooms(&user.server, &room, &target).await
+            }
+            .await;
+            Some(ServerCommand::MergeRoom(err_to_server(res)))
         }
         ClientCommand::LockRoom { lock } => {
             let res: Result<()> = async move {
@@ -538,7 +1151,7 @@ async fn process(user: Arc<User>, cmd: ClientCommand) -> Option<ServerCommand> {
                 Ok(())
             }
             .await;
-            Some(ServerCommand::LockRoom(err_to_str(res)))
+            Some(ServerCommand::LockRoom(err_to_server(res)))
         }
         ClientCommand::CycleRoom { cycle } => {
             let res: Result<()> = async move {
@@ -550,17 +1163,369 @@ async fn process(user: Arc<User>, cmd: ClientCommand) -> Option<ServerCommand> {
                     cycle,
                     "cycle room"
                 );
-                room.cycle.store(cycle, Ordering::SeqCst);
+                let mode = match (cycle, room.mode()) {
+                    (true, _) => RoomMode::Relay,
+                    (false, RoomMode::Relay) => RoomMode::Versus,
+                    (false, mode) => mode,
+                };
+                room.set_mode(&user, mode).await?;
                 room.send(Message::CycleRoom { cycle }).await;
                 Ok(())
             }
             .await;
-            Some(ServerCommand::CycleRoom(err_to_str(res)))
+            Some(ServerCommand::CycleRoom(err_to_server(res)))
         }
-        ClientCommand::SelectChart { id } => {
+        ClientCommand::SlowMode { secs } => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                room.check_host(&user).await?;
+                info!(
+                    user = user.id,
+                    room = room.id.to_string(),
+                    secs,
+                    "slow mode"
+                );
+                room.slow_mode.store(secs, Ordering::SeqCst);
+                room.send(Message::SlowMode { secs }).await;
+                Ok(())
+            }
+            .await;
+            Some(ServerCommand::SlowMode(err_to_server(res)))
+        }
+        ClientCommand::SetDuel { best_of } => {
+            let res: Result<()> = async move {
+                get_room!(room, InternalRoomState::SelectChart);
+                room.set_duel(&user, best_of).await
+            }
+            .await;
+            Some(ServerCommand::SetDuel(err_to_server(res)))
+        }
+        ClientCommand::StartDraft {
+            pool,
+            bans,
+            turn_secs,
+        } => {
+            let res: Result<()> = async move {
+                get_room!(room, InternalRoomState::SelectChart);
+                room.start_draft(&user, pool, bans, turn_secs).await
+            }
+            .await;
+            Some(ServerCommand::StartDraft(err_to_server(res)))
+        }
+        ClientCommand::CancelDraft => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                room.cancel_draft(&user).await
+            }
+            .await;
+            Some(ServerCommand::CancelDraft(err_to_server(res)))
+        }
+        ClientCommand::DraftTurn { action, chart } => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                room.draft_turn(&user, action, chart).await
+            }
+            .await;
+            Some(ServerCommand::DraftTurn(err_to_server(res)))
+        }
+        ClientCommand::SetMaxPlayers { max_players } => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                room.check_host(&user).await?;
+                room.set_max_players(max_players, user.server.config.room_max_players)
+                    .await?;
+                info!(
+                    user = user.id,
+                    room = room.id.to_string(),
+                    max_players,
+                    "max players changed"
+                );
+                room.send(Message::MaxPlayers { max_players }).await;
+                Ok(())
+            }
+            .await;
+            Some(ServerCommand::SetMaxPlayers(err_to_server(res)))
+        }
+        ClientCommand::FetchRoomHistory => {
+            let res: Result<_> = async move {
+                get_room!(room);
+                Ok(room.chart_history())
+            }
+            .await;
+            Some(ServerCommand::FetchRoomHistory(err_to_server(res)))
+        }
+        ClientCommand::SetRoomMode { mode } => {
             let res: Result<()> = async move {
                 get_room!(room, InternalRoomState::SelectChart);
+                room.set_mode(&user, mode).await?;
+                room.send(Message::RoomMode { mode }).await;
+                Ok(())
+            }
+            .await;
+            Some(ServerCommand::SetRoomMode(err_to_server(res)))
+        }
+        ClientCommand::QueueChart { id } => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                room.queue_chart(&user, id).await
+            }
+            .await;
+            Some(ServerCommand::QueueChart(err_to_server(res)))
+        }
+        ClientCommand::UnqueueChart { id } => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                room.unqueue_chart(&user, id).await
+            }
+            .await;
+            Some(ServerCommand::UnqueueChart(err_to_server(res)))
+        }
+        ClientCommand::VoteChart { id } => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                room.vote_chart(&user, id).await
+            }
+            .await;
+            Some(ServerCommand::VoteChart(err_to_server(res)))
+        }
+        ClientCommand::SetReadyCheck { check } => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                room.set_ready_check(&user, check).await?;
+                room.send(Message::ReadyCheck { check }).await;
+                Ok(())
+            }
+            .await;
+            Some(ServerCommand::SetReadyCheck(err_to_server(res)))
+        }
+        ClientCommand::SetRepeatPolicy { policy } => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                room.check_host(&user).await?;
+                room.set_repeat_policy(policy);
+                info!(
+                    user = user.id,
+                    room = room.id.to_string(),
+                    "repeat policy: {policy:?}"
+                );
+                room.send(Message::RepeatPolicy { policy }).await;
+                Ok(())
+            }
+            .await;
+            Some(ServerCommand::SetRepeatPolicy(err_to_server(res)))
+        }
+        ClientCommand::SetRecording { recording } => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                room.check_host(&user).await?;
+                info!(
+                    user = user.id,
+                    room = room.id.to_string(),
+                    "recording: {recording:?}"
+                );
+                room.set_recording(recording).await?;
+                room.send_recording_notice(&user.server.config, None).await;
+                Ok(())
+            }
+            .await;
+            Some(ServerCommand::SetRecording(err_to_server(res)))
+        }
+        ClientCommand::ReportPlayer { user_id, reason } => {
+            let res: Result<()> = async move {
+                if user_id == user.id {
+                    bail!(tl!("report-self"));
+                }
+                let target = user.server.users.get(&user_id);
+                let Some(target) = target else {
+                    bail!(tl!("report-unknown-user"));
+                };
+                let room = user.room.read().await.as_ref().map(Arc::clone);
+                let id = user
+                    .server
+                    .reports
+                    .submit(&user, &target, reason.into_inner(), room.as_deref())
+                    .await?;
+                warn!(user = user.id, target = user_id, "player reported (#{id})");
+                Ok(())
+            }
+            .await;
+            Some(ServerCommand::ReportPlayer(err_to_server(res)))
+        }
+        ClientCommand::KickPlayer { id } | ClientCommand::BanPlayer { id } => {
+            let ban = matches!(cmd, ClientCommand::BanPlayer { .. });
+            let res: Result<()> = async {
+                get_room!(room);
+                if room.kick(&user, id, ban).await? {
+                    user.server.rooms.remove(&room.id);
+                }
+                Ok(())
+            }
+            .await;
+            let res = err_to_server(res);
+            Some(match ban {
+                true => ServerCommand::BanPlayer(res),
+                false => ServerCommand::KickPlayer(res),
+            })
+        }
+        ClientCommand::MutePlayer { user_id, secs } => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                room.mute(&user, user_id, secs).await
+            }
+            .await;
+            Some(ServerCommand::MutePlayer(err_to_server(res)))
+        }
+        ClientCommand::TimeoutPlayer { user_id, secs } => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                if room.timeout(&user, user_id, secs).await? {
+                    user.server.rooms.remove(&room.id);
+                }
+                Ok(())
+            }
+            .await;
+            Some(ServerCommand::TimeoutPlayer(err_to_server(res)))
+        }
+        ClientCommand::TransferHost { target } => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                room.transfer_host(&user, target).await
+            }
+            .await;
+            Some(ServerCommand::TransferHost(err_to_server(res)))
+        }
+        ClientCommand::ListRooms => {
+            let mut rooms = Vec::new();
+            for room in user.server.rooms.values() {
+                rooms.push(room.listing(&user.server.config).await);
+            }
+            // Rooms with players first, so that there's someone to play with
+            rooms.sort_by_cached_key(|it| (Reverse(it.players), it.id.to_string()));
+            rooms.truncate(ROOM_LIST_MAX);
+            Some(ServerCommand::RoomList(Ok(rooms)))
+        }
+        ClientCommand::SyncState => {
+            let room = user.room.read().await.as_ref().map(Arc::clone);
+            Some(ServerCommand::SyncState(Ok(match room {
+                Some(room) => {
+                    user.try_send(ServerCommand::Members(room.members().await))
+                        .await;
+                    Some(room.sync_state(&user).await)
+                }
+                None => None,
+            })))
+        }
+        ClientCommand::BridgeSpectatorChat { bridge } => {
+            let res: Result<()> = async move {
+                get_room!(room);
                 room.check_host(&user).await?;
+                info!(
+                    user = user.id,
+                    room = room.id.to_string(),
+                    bridge,
+                    "bridge spectator chat"
+                );
+                room.bridge_spectator_chat.store(bridge, Ordering::SeqCst);
+                room.send(Message::BridgeSpectatorChat { bridge }).await;
+                Ok(())
+            }
+            .await;
+            Some(ServerCommand::BridgeSpectatorChat(err_to_server(res)))
+        }
+        ClientCommand::Prefetch { mut chart_ids } => {
+            get_room!(~ room);
+            if room.check_host(&user).await.is_err() {
+                warn!("prefetch hint from non-host {}", user.id);
+                return None;
+            }
+            chart_ids.truncate(PREFETCH_MAX_CHARTS);
+            tokio::spawn(async move {
+                room.broadcast_except(user.id, ServerCommand::Prefetch { chart_ids })
+                    .await;
+            });
+            None
+        }
+        ClientCommand::ChartProgress { id, progress } => {
+            get_room!(~ room);
+            if room.chart.read().await.as_ref().map(|it| it.id) != Some(id) {
+                return None;
+            }
+            let progress = if progress.is_nan() {
+                0.
+            } else {
+                progress.clamp(0., 1.)
+            };
+            tokio::spawn(async move {
+                room.broadcast_except(
+                    user.id,
+                    ServerCommand::ChartProgress {
+                        user: user.id,
+                        id,
+                        progress,
+                    },
+                )
+                .await;
+            });
+            None
+        }
+        ClientCommand::AckStart { round } => {
+            get_room!(~ room);
+            room.ack_start(user.id, round);
+            None
+        }
+        ClientCommand::ReportLatency { ms } => {
+            get_room!(~ room);
+            *user.latency_ms.lock().unwrap() = Some(ms);
+            tokio::spawn(async move {
+                room.broadcast(ServerCommand::MemberUpdate(room.member_status(&user).await))
+                    .await;
+            });
+            None
+        }
+        ClientCommand::SetTouchSpace { space } => {
+            if !(0.1..=10.).contains(&space.aspect_ratio) {
+                warn!(user = user.id, "invalid touch space: {space:?}");
+                return None;
+            }
+            *user.touch_space.lock().unwrap() = Some(space);
+            get_room!(~ room);
+            tokio::spawn(async move {
+                room.broadcast_except(
+                    user.id,
+                    ServerCommand::TouchSpace {
+                        player: user.id,
+                        space,
+                    },
+                )
+                .await;
+            });
+            None
+        }
+        ClientCommand::ChartReady { id } => {
+            get_room!(~ room);
+            if room.chart.read().await.as_ref().map(|it| it.id) != Some(id) {
+                return None;
+            }
+            debug!(
+                user = user.id,
+                room = room.id.to_string(),
+                "chart {id} ready"
+            );
+            tokio::spawn(async move {
+                room.broadcast_except(user.id, ServerCommand::ChartReady { user: user.id, id })
+                    .await;
+            });
+            None
+        }
+        ClientCommand::SelectChart { id } => {
+            let res: Result<()> = async move {
+                get_room!(room, InternalRoomState::SelectChart);
+                room.check_picker(&user).await?;
+                let repeated = room.played_recently(id);
+                let policy = room.repeat_policy();
+                if let (Some(rounds), RepeatPolicy::Forbid) = (repeated, policy) {
+                    bail!(tl!("chart-played-recently", "rounds" => rounds));
+                }
                 let span = debug_span!(
                     "select chart",
                     user = user.id,
@@ -569,33 +1534,59 @@ async fn process(user: Arc<User>, cmd: ClientCommand) -> Option<ServerCommand> {
                 );
                 async move {
                     trace!("fetch");
-                    let res: Chart = reqwest::get(format!("{HOST}/chart/{id}"))
-                        .await?
-                        .error_for_status()?
-                        .json()
-                        .await?;
+                    let res = Chart::fetch(id).await.map_err(|err| {
+                        debug!("failed to fetch chart: {err:?}");
+                        ServerError::ChartNotFound
+                    })?;
                     debug!("chart is {res:?}");
-                    room.send(Message::SelectChart {
-                        user: user.id,
-                        name: res.name.clone(),
-                        id: res.id,
-                    })
-                    .await;
-                    *room.chart.write().await = Some(res);
-                    room.on_state_change().await;
+                    room.select_chart(&user, res).await;
+                    if let (Some(rounds_ago), RepeatPolicy::Warn) = (repeated, policy) {
+                        room.send(Message::ChartRepeated {
+                            user: user.id,
+                            chart: id,
+                            rounds_ago,
+                        })
+                        .await;
+                    }
                     Ok(())
                 }
                 .instrument(span)
                 .await
             }
             .await;
-            Some(ServerCommand::SelectChart(err_to_str(res)))
+            Some(ServerCommand::SelectChart(err_to_server(res)))
+        }
+        ClientCommand::SelectRandomChart { filter } => {
+            let res: Result<i32> = async move {
+                get_room!(room, InternalRoomState::SelectChart);
+                room.check_picker(&user).await?;
+                let charts = &user.server.charts;
+                if filter.max_length.is_some() && !charts.is_enabled() {
+                    bail!(tl!("random-chart-no-length"));
+                }
+                let chart = Chart::random(&filter, charts, |id| room.played_recently(id).is_some())
+                    .await
+                    .map_err(|err| {
+                        warn!("failed to pick random chart: {err:?}");
+                        anyhow!(tl!("random-chart-fetch-failed"))
+                    })?
+                    .ok_or_else(|| anyhow!(tl!("random-chart-none")))?;
+                debug!(user = user.id, "random chart is {chart:?}");
+                let id = chart.id;
+                room.select_chart(&user, chart).await;
+                Ok(id)
+            }
+            .await;
+            Some(ServerCommand::SelectRandomChart(err_to_server(res)))
         }
 
         ClientCommand::RequestStart => {
             let res: Result<()> = async move {
                 get_room!(room, InternalRoomState::SelectChart);
                 room.check_host(&user).await?;
+                if room.is_drafting() {
+                    bail!(tl!("draft-in-progress"));
+                }
                 if room.chart.read().await.is_none() {
                     bail!(tl!("start-no-chart-selected"));
                 }
@@ -607,14 +1598,18 @@ async fn process(user: Arc<User>, cmd: ClientCommand) -> Option<ServerCommand> {
                 };
                 room.on_state_change().await;
                 room.check_all_ready().await;
+                room.start_ready_check();
                 Ok(())
             }
             .await;
-            Some(ServerCommand::RequestStart(err_to_str(res)))
+            Some(ServerCommand::RequestStart(err_to_server(res)))
         }
         ClientCommand::Ready => {
             let res: Result<()> = async move {
                 get_room!(room);
+                if user.spectator.load(Ordering::SeqCst) {
+                    bail!(tl!("spectator-cannot-play"));
+                }
                 let mut guard = room.state.write().await;
                 if let InternalRoomState::WaitForReady { started } = guard.deref_mut() {
                     if !started.insert(user.id) {
@@ -627,7 +1622,7 @@ async fn process(user: Arc<User>, cmd: ClientCommand) -> Option<ServerCommand> {
                 Ok(())
             }
             .await;
-            Some(ServerCommand::Ready(err_to_str(res)))
+            Some(ServerCommand::Ready(err_to_server(res)))
         }
         ClientCommand::CancelReady => {
             let res: Result<()> = async move {
@@ -649,7 +1644,39 @@ async fn process(user: Arc<User>, cmd: ClientCommand) -> Option<ServerCommand> {
                 Ok(())
             }
             .await;
-            Some(ServerCommand::CancelReady(err_to_str(res)))
+            Some(ServerCommand::CancelReady(err_to_server(res)))
+        }
+        ClientCommand::ForceCancelStart => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                room.check_host(&user).await?;
+                if !room.force_cancel_start().await {
+                    bail!(tl!("start-nothing-to-cancel"));
+                }
+                Ok(())
+            }
+            .await;
+            Some(ServerCommand::ForceCancelStart(err_to_server(res)))
+        }
+        ClientCommand::EndRound { confirm } => {
+            let res: Result<Vec<i32>> = async move {
+                get_room!(room);
+                room.check_host(&user).await?;
+                let Some(playing) = room.still_playing().await else {
+                    bail!(tl!("end-round-no-game"));
+                };
+                if confirm {
+                    info!(
+                        user = user.id,
+                        room = room.id.to_string(),
+                        "host ends round, still playing: {playing:?}"
+                    );
+                    room.force_end(GameEndReason::HostEnded).await;
+                }
+                Ok(playing)
+            }
+            .await;
+            Some(ServerCommand::EndRound(err_to_server(res)))
         }
         ClientCommand::Played { id } => {
             let res: Result<()> = async move {
@@ -675,7 +1702,10 @@ async fn process(user: Arc<User>, cmd: ClientCommand) -> Option<ServerCommand> {
                 })
                 .await;
                 let mut guard = room.state.write().await;
-                if let InternalRoomState::Playing { results, aborted } = guard.deref_mut() {
+                if let InternalRoomState::Playing {
+                    results, aborted, ..
+                } = guard.deref_mut()
+                {
                     if aborted.contains(&user.id) {
                         bail!("aborted");
                     }
@@ -688,13 +1718,16 @@ async fn process(user: Arc<User>, cmd: ClientCommand) -> Option<ServerCommand> {
                 Ok(())
             }
             .await;
-            Some(ServerCommand::Played(err_to_str(res)))
+            Some(ServerCommand::Played(err_to_server(res)))
         }
         ClientCommand::Abort => {
             let res: Result<()> = async move {
                 get_room!(room);
                 let mut guard = room.state.write().await;
-                if let InternalRoomState::Playing { results, aborted } = guard.deref_mut() {
+                if let InternalRoomState::Playing {
+                    results, aborted, ..
+                } = guard.deref_mut()
+                {
                     if results.contains_key(&user.id) {
                         bail!("already uploaded");
                     }
@@ -708,7 +1741,159 @@ async fn process(user: Arc<User>, cmd: ClientCommand) -> Option<ServerCommand> {
                 Ok(())
             }
             .await;
-            Some(ServerCommand::Abort(err_to_str(res)))
+            Some(ServerCommand::Abort(err_to_server(res)))
+        }
+        ClientCommand::EchoTest { payload } => {
+            let max = user.server.config.echo_max_payload;
+            if payload.len() > max {
+                Some(ServerCommand::EchoTest(Err(ServerError::Other(
+                    tl!("echo-payload-too-large", "max" => max),
+                ))))
+            } else {
+                Some(ServerCommand::EchoTest(Ok(payload)))
+            }
+        }
+        ClientCommand::WatchPlayer { user_id } => {
+            let res: Result<()> = async move {
+                get_room!(room);
+                if let Some(target) = user_id {
+                    if !room.users().await.iter().any(|it| it.id == target) {
+                        bail!(tl!("watch-not-player"));
+                    }
+                }
+                debug!(user = user.id, target = user_id, "watch player");
+                *user.watching.write().await = user_id;
+                Ok(())
+            }
+            .await;
+            Some(ServerCommand::WatchPlayer(err_to_server(res)))
+        }
+        ClientCommand::ExportResults { round } => {
+            let res: Result<String> = async move {
+                get_room!(room);
+                room.export_results(round).await
+            }
+            .await;
+            Some(ServerCommand::ExportResults(err_to_server(res)))
         }
     }
 }
+
+async fn relay_touches(user: Arc<User>, round: u32, frames: Arc<Vec<TouchFrame>>) {
+    let Some(room) = user.room.read().await.as_ref().map(Arc::clone) else {
+        warn!("no room");
+        return;
+    };
+    if !room.is_live() {
+        warn!("received touch events in non-live mode");
+        return;
+    }
+    if room.current_round().await != Some(round) {
+        debug!("dropped touch events of round {round} from {}", user.id);
+        return;
+    }
+    if frames.len() > user.server.config.realtime_batch_max {
+        warn!("dropped {} touch events from {}", frames.len(), user.id);
+        return;
+    }
+    debug!("received {} touch events from {}", frames.len(), user.id);
+    if let Some(frame) = frames.last() {
+        user.game_time.store(frame.time.to_bits(), Ordering::SeqCst);
+    }
+    tokio::spawn(async move {
+        room.broadcast_touches(&user.server.config, user.id, round, frames)
+            .await;
+    });
+}
+
+async fn chat(user: &User, message: String) -> Result<()> {
+    check_message(user, &message)?;
+    let room = chat_room(user).await?;
+    room.send_as(user, message).await;
+    Ok(())
+}
+
+/// Delivers chat to `target` alone, who must be in the same room. The
+/// sender gets it back as well, so that both sides show the conversation.
+async fn whisper(user: &User, target: i32, message: String) -> Result<()> {
+    check_message(user, &message)?;
+    if target == user.id {
+        bail!(tl!("whisper-self"));
+    }
+    let room = chat_room(user).await?;
+    let Some(target) = room.member(target).await else {
+        bail!(tl!("whisper-not-in-room", "user" => target));
+    };
+    let msg = ServerCommand::Message(Message::Whisper {
+        from: user.id,
+        to: target.id,
+        content: message,
+    });
+    target.try_send(msg.clone()).await;
+    user.try_send(msg).await;
+    Ok(())
+}
+
+fn check_message(user: &User, message: &str) -> Result<()> {
+    let max = user.server.config.chat_max_len;
+    if message.len() > max {
+        bail!(tl!("chat-too-long", "max" => max));
+    }
+    if user.trust == TrustLevel::New && contains_link(message) {
+        bail!(tl!("trust-no-links"));
+    }
+    Ok(())
+}
+
+/// Relays chat the server can't read. Its length shows through the
+/// encryption, but as links can't be told apart, new accounts may not send it.
+async fn encrypted_chat(user: &User, payload: Vec<u8>) -> Result<()> {
+    let max = user.server.config.chat_max_len;
+    if payload.len() > max + ENCRYPTED_CHAT_OVERHEAD {
+        bail!(tl!("chat-too-long", "max" => max));
+    }
+    if user.trust == TrustLevel::New {
+        bail!(tl!("trust-no-encrypted-chat"));
+    }
+    let room = chat_room(user).await?;
+    let channel = if user.monitor.load(Ordering::SeqCst) {
+        ChatChannel::Spectators
+    } else {
+        ChatChannel::Room
+    };
+    room.send(Message::EncryptedChat {
+        user: user.id,
+        channel,
+        payload,
+    })
+    .await;
+    Ok(())
+}
+
+/// The room `user` may chat in right now.
+async fn chat_room(user: &User) -> Result<Arc<Room>> {
+    let room = user
+        .room
+        .read()
+        .await
+        .as_ref()
+        .map(Arc::clone)
+        .ok_or(ServerError::NotInRoom)?;
+    if let Some(left) = room.muted_for(user.id) {
+        bail!(tl!("chat-muted", "secs" => left.as_secs() + 1));
+    }
+    if let Some(retry_after) = room.chat_cooldown(user).await {
+        bail!(ServerError::RateLimited {
+            retry_after_ms: retry_after.as_millis() as u32,
+            message: tl!("chat-slow-mode", "secs" => retry_after.as_secs() + 1),
+        });
+    }
+    Ok(room)
+}
+
+/// Stable id for a token the Phira API doesn't know, on sandbox servers.
+/// Negative so that it never collides with a real account.
+fn sandbox_user_id(token: &str) -> i32 {
+    let hash = Sha256::digest(token.as_bytes());
+    -(i32::from_le_bytes(hash[..4].try_into().unwrap()) & i32::MAX) - 1
+}
@@ -0,0 +1,97 @@
+use crate::RoundRecord;
+use phira_mp_common::{Achievement, RoomId};
+use std::collections::HashMap;
+use tokio::sync::Mutex;
+
+/// Consecutive rounds finished without aborting that make a streak.
+pub const STREAK_ROUNDS: u32 = 10;
+/// Rounds played in the same room that make a marathon.
+pub const MARATHON_ROUNDS: u32 = 20;
+
+#[derive(Debug, Default, Clone)]
+pub struct UserStats {
+    pub rounds_played: u32,
+    pub wins: u32,
+    pub streak: u32,
+    /// Days since the Unix epoch (UTC)
+    pub last_win_day: Option<u64>,
+    pub room: Option<RoomId>,
+    pub room_rounds: u32,
+}
+
+/// In-memory per-user statistics, kept across rooms but not across restarts.
+#[derive(Default)]
+pub struct Stats {
+    users: Mutex<HashMap<i32, UserStats>>,
+}
+
+impl Stats {
+    pub async fn get(&self, user: i32) -> UserStats {
+        self.users
+            .lock()
+            .await
+            .get(&user)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Records a finished round for `players`, returning the achievements it
+    /// unlocked.
+    pub async fn record_round(
+        &self,
+        room: &RoomId,
+        record: &RoundRecord,
+        players: &[i32],
+        ranked: bool,
+    ) -> Vec<(i32, Achievement)> {
+        let mut unlocked = Vec::new();
+        let mut users = self.users.lock().await;
+        for &player in players {
+            let stats = users.entry(player).or_default();
+            let Some(standing) = record
+                .standings
+                .iter()
+                .find(|it| it.record.player == player)
+            else {
+                stats.streak = 0;
+                continue;
+            };
+
+            stats.rounds_played += 1;
+            stats.streak += 1;
+            if stats.streak % STREAK_ROUNDS == 0 {
+                unlocked.push((
+                    player,
+                    Achievement::Streak {
+                        rounds: stats.streak,
+                    },
+                ));
+            }
+
+            if stats.room.as_ref() != Some(room) {
+                stats.room = Some(room.clone());
+                stats.room_rounds = 0;
+            }
+            stats.room_rounds += 1;
+            if stats.room_rounds % MARATHON_ROUNDS == 0 {
+                unlocked.push((
+                    player,
+                    Achievement::Marathon {
+                        rounds: stats.room_rounds,
+                    },
+                ));
+            }
+
+            // Winning alone doesn't count
+            if ranked && standing.rank == 1 && record.standings.len() > 1 {
+                stats.wins += 1;
+                let day = record.finished_at / 86400;
+                if stats.last_win_day != Some(day) {
+                    stats.last_win_day = Some(day);
+                    unlocked.push((player, Achievement::FirstWinOfDay));
+                }
+            }
+        }
+        unlocked
+    }
+}
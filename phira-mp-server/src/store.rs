@@ -0,0 +1,71 @@
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use std::path::PathBuf;
+use tracing::warn;
+
+/// JSON files kept across restarts in [`crate::ServerConfig::data_dir`].
+/// Without a data directory everything only lives in memory.
+#[derive(Debug, Clone, Default)]
+pub struct Store {
+    dir: Option<PathBuf>,
+}
+
+impl Store {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self { dir }
+    }
+
+    /// Falls back to the default value if the file is missing or invalid.
+    pub fn load<T: DeserializeOwned + Default>(&self, name: &str) -> T {
+        let Some(dir) = &self.dir else {
+            return T::default();
+        };
+        let path = dir.join(name);
+        if !path.exists() {
+            return T::default();
+        }
+        match std::fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|it| Ok(serde_json::from_slice(&it)?))
+        {
+            Ok(value) => value,
+            Err(err) => {
+                warn!("failed to load {}: {err:?}", path.display());
+                T::default()
+            }
+        }
+    }
+
+    /// Whether the data directory can be written to, `false` if there's none.
+    pub fn check(&self) -> Result<bool> {
+        let Some(dir) = &self.dir else {
+            return Ok(false);
+        };
+        std::fs::create_dir_all(dir)?;
+        let probe = dir.join(".health");
+        std::fs::write(&probe, b"")?;
+        std::fs::remove_file(probe)?;
+        Ok(true)
+    }
+
+    /// Writes a value serialized to JSON, on a blocking thread. Callers
+    /// serialize it themselves, so that they don't hold their locks while
+    /// waiting on the disk.
+    pub async fn save_serialized(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        let store = self.clone();
+        let name = name.to_owned();
+        tokio::task::spawn_blocking(move || store.write(&name, &data)).await?
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        std::fs::create_dir_all(dir)?;
+        // Write then rename so a crash never leaves a truncated file behind
+        let tmp = dir.join(format!("{name}.tmp"));
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(tmp, dir.join(name))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use crate::RoundRecord;
+use anyhow::Result;
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{info, warn};
+
+const SUBMIT_ATTEMPTS: u32 = 3;
+
+#[derive(Serialize)]
+struct Submission<'a> {
+    room: &'a str,
+    #[serde(flatten)]
+    record: &'a RoundRecord,
+}
+
+/// Forwards finished rounds to the Phira API so that multiplayer plays count
+/// toward players' records. Only records the server fetched and checked itself
+/// (see `ClientCommand::Played`) end up in a round, so nothing submitted comes
+/// straight from a client.
+pub struct ResultSubmitter {
+    client: reqwest::Client,
+    url: String,
+    token: String,
+}
+
+impl ResultSubmitter {
+    pub fn new(url: String, token: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url,
+            token,
+        }
+    }
+
+    async fn try_submit(&self, room: &str, record: &RoundRecord) -> Result<()> {
+        self.client
+            .post(&self.url)
+            .bearer_auth(&self.token)
+            .json(&Submission { room, record })
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    pub async fn submit(&self, room: &str, record: &RoundRecord) {
+        if record.standings.is_empty() {
+            return;
+        }
+        for attempt in 1..=SUBMIT_ATTEMPTS {
+            match self.try_submit(room, record).await {
+                Ok(()) => {
+                    info!(room, round = record.round, "round results submitted");
+                    return;
+                }
+                Err(err) => {
+                    warn!(
+                        room,
+                        round = record.round,
+                        "failed to submit round results (attempt {attempt}): {err:?}"
+                    );
+                    if attempt < SUBMIT_ATTEMPTS {
+                        tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+                    }
+                }
+            }
+        }
+    }
+}
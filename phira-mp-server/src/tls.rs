@@ -0,0 +1,35 @@
+use anyhow::{bail, Context, Result};
+use rustls_pemfile::Item;
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+use tokio_rustls::{
+    rustls::{self, Certificate, PrivateKey},
+    TlsAcceptor,
+};
+
+/// Reads a PEM certificate chain and the first private key found in `key`.
+pub fn load_tls(cert: &Path, key: &Path) -> Result<TlsAcceptor> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .with_context(|| format!("failed to open {}", path.display()))
+    };
+    let certs: Vec<_> = rustls_pemfile::certs(&mut open(cert)?)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        bail!("no certificate in {}", cert.display());
+    }
+    let key = rustls_pemfile::read_all(&mut open(key)?)?
+        .into_iter()
+        .find_map(|it| match it {
+            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .with_context(|| format!("no private key in {}", key.display()))?;
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -0,0 +1,47 @@
+use crate::ServerConfig;
+use chrono::{DateTime, Utc};
+
+const LINK_TLDS: &[&str] = &[
+    "com", "net", "org", "io", "gg", "cn", "me", "cc", "xyz", "top", "ly", "co",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrustLevel {
+    /// Looks like a throwaway account: can't host rooms or post links.
+    New,
+    Regular,
+}
+
+impl TrustLevel {
+    /// Judges an account by what the Phira API tells about it. Missing fields
+    /// never count against the user.
+    pub fn of(config: &ServerConfig, joined: Option<DateTime<Utc>>, exp: Option<i64>) -> Self {
+        let too_young = !config.new_account_age.is_zero()
+            && joined.is_some_and(|joined| {
+                (Utc::now() - joined)
+                    .to_std()
+                    .map_or(true, |age| age < config.new_account_age)
+            });
+        let too_little_exp = exp.is_some_and(|exp| exp < config.new_account_min_exp);
+        if too_young || too_little_exp {
+            Self::New
+        } else {
+            Self::Regular
+        }
+    }
+}
+
+/// Rough check for URLs and bare domains like `example.com/path`.
+pub fn contains_link(text: &str) -> bool {
+    text.split_whitespace().any(|word| {
+        let word = word.to_ascii_lowercase();
+        if word.contains("://") || word.starts_with("www.") {
+            return true;
+        }
+        let host = word.split('/').next().unwrap_or_default();
+        host.rsplit_once('.').is_some_and(|(name, tld)| {
+            !name.is_empty()
+                && LINK_TLDS.contains(&tld.trim_end_matches(|c: char| !c.is_ascii_alphanumeric()))
+        })
+    })
+}
@@ -0,0 +1,50 @@
+//! `phira-mp-server watch <room>`: prints a room's events as they happen,
+//! using the admin API of the server configured in the environment or the
+//! `--config` file.
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use phira_mp_server::ServerConfig;
+use std::path::Path;
+
+pub async fn watch(config: Option<&Path>, room: &str) -> Result<()> {
+    let config = ServerConfig::load(config)?;
+    let addr = config
+        .admin_addr
+        .context("PHIRA_MP_ADMIN_ADDR must be set to the server's admin address")?;
+    let token = config
+        .admin_token
+        .context("PHIRA_MP_ADMIN_TOKEN must be set")?;
+    let mut resp = reqwest::Client::new()
+        .get(format!("http://{addr}/api/rooms/{room}/events"))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()
+        .context("failed to attach to room")?;
+    println!("watching room {room}");
+
+    let mut buffer = Vec::new();
+    let mut event = String::new();
+    while let Some(chunk) = resp.chunk().await? {
+        buffer.extend_from_slice(&chunk);
+        while let Some(pos) = buffer.iter().position(|it| *it == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if let Some(name) = line.strip_prefix("event:") {
+                event = name.trim().to_owned();
+            } else if let Some(data) = line.strip_prefix("data:") {
+                println!(
+                    "{} {event:<16} {}",
+                    Local::now().format("%H:%M:%S"),
+                    data.trim()
+                );
+            } else if line.is_empty() {
+                event.clear();
+            }
+        }
+    }
+    println!("room {room} closed");
+    Ok(())
+}